@@ -6,16 +6,53 @@
 #include <cstdlib>
 #include "amxxmodule.h"
 
+/// Maximum number of items, which can be stored at once.
+constexpr static const uintptr_t MAX_ITEMS = (1 << INDEX_BITS);
+
+/// Close code reported, when connection was lost without close frame.
+constexpr static const uint16_t ABNORMAL_CLOSE_CODE = 1006;
+
 extern "C" {
 
 cell grip_body_from_json(const void *amx, cell value, bool pretty, cell recursion_limit);
 
+cell grip_body_from_response(const void *amx, cell response);
+
 cell grip_body_from_string(const void *amx, const char *str);
 
+cell grip_body_get_length(const void *amx, cell body);
+
+cell grip_body_get_string(const void *amx, cell body, char *buffer, cell size, cell offset);
+
+cell grip_body_read_line(const void *amx, cell body, cell *offset, char *buffer, cell size);
+
 cell grip_cancel_request(const void *amx, cell cancellation);
 
+/// Destroys the schedule and cancels its pending requests without calling the handler.
+cell grip_cancel_schedule(const void *amx, cell schedule);
+
+/// Executes `grip` server command. Every line of the output is passed to the `printer`.
+void grip_console_command(cell argc, const char *const *argv, void (*printer)(const char*));
+
 cell grip_create_default_options(const void *amx, double timeout);
 
+cell grip_create_queue(const void *amx,
+                       cell worker_threads,
+                       cell max_concurrent_requests,
+                       cell callbacks_per_frame);
+
+cell grip_create_request_chain(const void *amx);
+
+cell grip_create_request_group(const void *amx,
+                               cell forward_id,
+                               void (*handler)(cell forward_handle,
+                                               cell group,
+                                               cell succeeded,
+                                               cell failed,
+                                               cell user_data),
+                               void (*forward_releaser)(cell forward_handle),
+                               cell user_data);
+
 void grip_deinit();
 
 cell grip_destroy_body(const void *amx, cell body);
@@ -24,15 +61,82 @@ cell grip_destroy_json_value(const void *amx, cell json_value);
 
 cell grip_destroy_options(const void *amx, cell options_handle);
 
+cell grip_destroy_queue(const void *amx, cell queue);
+
+cell grip_destroy_request_chain(const void *amx, cell chain);
+
+cell grip_destroy_request_group(const void *amx, cell group);
+
+cell grip_destroy_response(const void *amx, cell response);
+
+/// Waits for the pending requests according to the `[shutdown]` section of the config.
+/// Called before plugins are unloaded, so that callbacks can still be executed.
+void grip_drain();
+
+/// Prints live handles of every plugin like the `grip handles` server command.
+/// Every line of the report is passed to the `printer`.
+cell grip_dump_handles(void (*printer)(const char*));
+
 cell grip_get_error_description(const void *amx, char *buffer, cell size);
 
-cell grip_get_response_body_string(const void *amx, char *buffer, cell size);
+cell grip_get_error_kind(const void *amx);
+
+cell grip_get_handle_count(const void *amx, cell handle_type);
+
+cell grip_get_handles(const void *amx, cell handle_type, cell *handles, cell size);
+
+cell grip_get_response_body_base64(const void *amx,
+                                   char *buffer,
+                                   cell size,
+                                   cell offset,
+                                   cell length,
+                                   cell response);
+
+cell grip_get_response_body_bytes(const void *amx,
+                                  uint8_t *buffer,
+                                  cell size,
+                                  cell offset,
+                                  cell response);
+
+cell grip_get_response_body_hash(const void *amx,
+                                 cell hash_type,
+                                 char *buffer,
+                                 cell size,
+                                 cell offset,
+                                 cell length,
+                                 cell response);
+
+cell grip_get_response_body_hex(const void *amx,
+                                char *buffer,
+                                cell size,
+                                cell offset,
+                                cell length,
+                                cell response);
+
+cell grip_get_response_body_length(const void *amx, cell response);
+
+cell grip_get_response_body_string(const void *amx, char *buffer, cell size, cell response);
+
+/// Index of the step, whose response or error is passed to the chain handler.
+cell grip_get_response_chain_step(const void *amx);
+
+cell grip_get_response_header(const void *amx,
+                              const char *name,
+                              char *buffer,
+                              cell size,
+                              cell response);
 
 cell grip_get_response_state(const void *amx);
 
-cell grip_get_response_status_code(const void *amx);
+cell grip_get_response_status_code(const void *amx, cell response);
+
+cell grip_get_response_url(const void *amx, char *buffer, cell size, cell response);
+
+cell grip_get_stats(const void *amx);
 
-void grip_init(void (*error_logger)(const void*, const char*), const char *config_file_path);
+void grip_init(void (*error_logger)(const void*, const char*),
+               const char *(*plugin_name_resolver)(const void*),
+               const char *config_file_path);
 
 cell grip_is_request_active(cell request_id);
 
@@ -94,17 +198,17 @@ cell grip_json_get_string(const void *amx, cell value, char *buffer, cell buffer
 
 cell grip_json_get_type(const void *amx, cell value);
 
-cell grip_json_init_array();
+cell grip_json_init_array(const void *amx);
 
-cell grip_json_init_bool(bool value);
+cell grip_json_init_bool(const void *amx, bool value);
 
-cell grip_json_init_float(double value);
+cell grip_json_init_float(const void *amx, double value);
 
-cell grip_json_init_null();
+cell grip_json_init_null(const void *amx);
 
-cell grip_json_init_number(cell value);
+cell grip_json_init_number(const void *amx, cell value);
 
-cell grip_json_init_object();
+cell grip_json_init_object(const void *amx);
 
 cell grip_json_init_string(const void *amx, char *string);
 
@@ -177,7 +281,10 @@ cell grip_json_object_set_value(const void *amx,
 
 cell grip_json_parse_file(const void *amx, char *file, char *error_buffer, cell error_buffer_size);
 
-cell grip_json_parse_response_body(const void *amx, char *error_buffer, cell error_buffer_size);
+cell grip_json_parse_response_body(const void *amx,
+                                   char *error_buffer,
+                                   cell error_buffer_size,
+                                   cell response);
 
 cell grip_json_parse_string(const void *amx,
                             char *string,
@@ -205,13 +312,52 @@ cell grip_json_serial_to_string(const void *amx,
 
 cell grip_json_validate(const void *amx, cell schema, cell value);
 
+cell grip_mock_add_failure(const void *amx,
+                           cell request_type,
+                           const char *url,
+                           cell error_kind,
+                           cell delay_milliseconds);
+
+cell grip_mock_add_response(const void *amx,
+                            cell request_type,
+                            const char *url,
+                            cell status,
+                            const char *body,
+                            cell delay_milliseconds,
+                            cell options_handle);
+
+cell grip_mock_get_request_count(const void *amx, cell request_type, const char *url);
+
+cell grip_mock_reset(const void *amx);
+
 cell grip_options_add_header(const void *amx,
                              cell options_handle,
                              const char *header_name,
                              const char *header_value);
 
+cell grip_options_set_max_response_body_size(const void *amx, cell options_handle, cell max_size);
+
+cell grip_options_set_must_complete(const void *amx, cell options_handle, bool must_complete);
+
+/// Requests aren't sent, until schedule is resumed. Already sent request isn't cancelled.
+cell grip_pause_schedule(const void *amx, cell schedule);
+
+/// Called by the wrapper of the `pause` native, after the plugin was paused.
+cell grip_plugin_paused(const void *amx);
+
 void grip_process_request();
 
+cell grip_release_handles(const void *amx);
+
+/// Destroys handles of every plugin before plugins are unloaded, so that they are never called again.
+cell grip_release_plugin_handles();
+
+cell grip_reload_config(const void *amx, char *error_buffer, cell error_buffer_size);
+
+/// Logs handles, which plugins didn't destroy, on behalf of every plugin.
+/// Called before plugins are unloaded, when enabled by "handles.warn-leaks-on-map-end".
+void grip_report_leaks();
+
 cell grip_request(const void *amx,
                   cell forward_id,
                   const char *uri,
@@ -219,7 +365,181 @@ cell grip_request(const void *amx,
                   cell request_type,
                   void (*handler)(cell forward_handle, cell user_data),
                   cell options_handle,
-                  cell user_data);
+                  cell user_data,
+                  cell queue);
+
+/// Appends step to the chain. URI, header values and body may contain `{{N/json/pointer}}` references.
+cell grip_request_chain_add(const void *amx,
+                            cell chain,
+                            const char *uri,
+                            cell body_handle,
+                            cell request_type,
+                            cell options_handle);
+
+/// Sends steps of the chain in the worker of the queue. Handler is called once, like the handler of `grip_request`.
+cell grip_request_chain_send(const void *amx,
+                             cell chain,
+                             cell forward_id,
+                             void (*handler)(cell forward_handle, cell user_data),
+                             cell user_data,
+                             cell queue);
+
+cell grip_request_group_add(const void *amx, cell group, cell cancellation);
+
+/// Cancels pending requests of the group the same way as `grip_cancel_request`.
+cell grip_request_group_cancel(const void *amx, cell group);
+
+/// Group completes after the last request, which was added before this.
+/// Group, whose requests are already finished, completes right away.
+cell grip_request_group_seal(const void *amx, cell group);
+
+cell grip_response_detach(const void *amx);
+
+/// Request, which became due during the pause, is sent on the next frame.
+cell grip_resume_schedule(const void *amx, cell schedule);
+
+cell grip_schedule_request(const void *amx,
+                           cell forward_id,
+                           const char *uri,
+                           cell body_handle,
+                           cell request_type,
+                           void (*handler)(cell forward_handle, cell schedule, cell user_data),
+                           void (*forward_releaser)(cell forward_handle),
+                           cell options_handle,
+                           cell user_data,
+                           double delay,
+                           double interval,
+                           double jitter,
+                           bool wait_for_previous,
+                           cell queue);
+
+cell grip_server_body_from_request(const void *amx, cell request);
+
+cell grip_server_get_body_length(const void *amx, cell request);
+
+cell grip_server_get_body_string(const void *amx,
+                                 cell request,
+                                 char *buffer,
+                                 cell size,
+                                 cell offset);
+
+cell grip_server_get_header(const void *amx,
+                            cell request,
+                            const char *name,
+                            char *buffer,
+                            cell size);
+
+cell grip_server_get_method(const void *amx, cell request, char *buffer, cell size);
+
+cell grip_server_get_path(const void *amx, cell request, char *buffer, cell size);
+
+cell grip_server_get_query(const void *amx, cell request, char *buffer, cell size);
+
+cell grip_server_get_query_param(const void *amx,
+                                 cell request,
+                                 const char *name,
+                                 char *buffer,
+                                 cell size);
+
+cell grip_server_get_remote_address(const void *amx, cell request, char *buffer, cell size);
+
+bool grip_server_is_running();
+
+cell grip_server_parse_body_json(const void *amx,
+                                 cell request,
+                                 char *error_buffer,
+                                 cell error_buffer_size);
+
+cell grip_server_register_route(const void *amx,
+                                cell forward_id,
+                                const char *pattern,
+                                void (*handler)(cell forward_handle, cell request, cell user_data),
+                                cell user_data);
+
+cell grip_server_reply(const void *amx, cell request, cell status, const char *body);
+
+cell grip_server_reply_json(const void *amx,
+                            cell request,
+                            cell status,
+                            cell value,
+                            bool pretty,
+                            cell recursion_limit);
+
+cell grip_server_set_reply_header(const void *amx,
+                                  cell request,
+                                  const char *name,
+                                  const char *value);
+
+cell grip_sse_get_error_description(const void *amx, char *buffer, cell size);
+
+cell grip_sse_get_error_kind(const void *amx);
+
+cell grip_sse_get_event_data(const void *amx, char *buffer, cell size);
+
+cell grip_sse_get_event_id(const void *amx, char *buffer, cell size);
+
+cell grip_sse_get_event_name(const void *amx, char *buffer, cell size);
+
+cell grip_sse_parse_event_json(const void *amx, char *error_buffer, cell error_buffer_size);
+
+cell grip_sse_subscribe(const void *amx,
+                        cell forward_id,
+                        const char *url,
+                        void (*handler)(cell forward_handle,
+                                        cell subscription,
+                                        cell event,
+                                        cell user_data),
+                        void (*forward_releaser)(cell forward_handle),
+                        cell options_handle,
+                        cell user_data);
+
+cell grip_sse_unsubscribe(const void *amx, cell subscription);
+
+cell grip_websocket_close(const void *amx, cell socket, cell code, const char *reason);
+
+cell grip_websocket_connect(const void *amx,
+                            cell forward_id,
+                            const char *url,
+                            void (*handler)(cell forward_handle,
+                                            cell socket,
+                                            cell event,
+                                            cell user_data),
+                            void (*forward_releaser)(cell forward_handle),
+                            cell options_handle,
+                            bool reconnect,
+                            cell user_data);
+
+cell grip_websocket_destroy(const void *amx, cell socket);
+
+cell grip_websocket_get_close_code(const void *amx);
+
+cell grip_websocket_get_close_reason(const void *amx, char *buffer, cell size);
+
+cell grip_websocket_get_error_description(const void *amx, char *buffer, cell size);
+
+cell grip_websocket_get_error_kind(const void *amx);
+
+cell grip_websocket_get_message_bytes(const void *amx, uint8_t *buffer, cell size, cell offset);
+
+cell grip_websocket_get_message_length(const void *amx);
+
+cell grip_websocket_get_message_string(const void *amx, char *buffer, cell size);
+
+cell grip_websocket_get_message_type(const void *amx);
+
+cell grip_websocket_is_connected(const void *amx, cell socket);
+
+cell grip_websocket_parse_message_json(const void *amx, char *error_buffer, cell error_buffer_size);
+
+cell grip_websocket_send_binary(const void *amx, cell socket, const uint8_t *data, cell length);
+
+cell grip_websocket_send_json(const void *amx,
+                              cell socket,
+                              cell value,
+                              bool pretty,
+                              cell recursion_limit);
+
+cell grip_websocket_send_string(const void *amx, cell socket, const char *message);
 
 } // extern "C"
 
@@ -37,6 +37,8 @@
 #include "amxxmodule.h"
 
 #include <unistd.h>
+#include <string>
+#include <vector>
 
 cell dummy;
 
@@ -45,10 +47,116 @@ char name[std::max(1, size + 1)]; \
 memset(&name[0], 0, std::max(1, size + 1) * sizeof(char))
 
 #define MF_SetAmxStringSafe(amx, amx_cell, addr, size) MF_SetAmxString(amx, amx_cell, addr, std::max(0,size) * sizeof(char))
+
+// Plugins compiled with older include may pass less parameters.
+#define OPTIONAL_PARAM(index, default_value) \
+(params[0] / static_cast<cell>(sizeof(cell)) >= (index) ? params[(index)] : (default_value))
+
+// Plugin can't pass an array, which is larger than its data, heap and stack.
+// Negative sizes are left as they are, so the module reports them.
+cell clamp_buffer_size(AMX *amx, cell size) {
+	return std::min(size, static_cast<cell>(amx->stp / sizeof(cell)));
+}
+
+// Same value as Current_GripResponse in Pawn.
+const cell CURRENT_RESPONSE = -1;
+
+// Same value as Default_GripQueue in Pawn.
+const cell DEFAULT_QUEUE = -1;
 void log_error(const void* amx, const char* string) {
 	MF_LogError((AMX*)amx, AMX_ERR_NATIVE, "%s", string);
 }
 
+const char* plugin_name(const void* amx) {
+	int id = MF_FindScriptByAmx((AMX*)amx);
+	return id == -1 ? nullptr : MF_GetScriptName(id);
+}
+
+// Beginning of the compiled plugin header from amx.h, which isn't part of the module SDK.
+#pragma pack(push, 1)
+struct amx_header {
+	int32_t size;
+	uint16_t magic;
+	char file_version;
+	char amx_version;
+	int16_t flags;
+	int16_t defsize;
+	int32_t cod;
+	int32_t dat;
+	int32_t hea;
+	int32_t stp;
+	int32_t cip;
+	int32_t publics;
+	int32_t natives;
+};
+#pragma pack(pop)
+
+// Function of the native, which is already bound in one of the loaded plugins.
+AMX_NATIVE find_bound_native(const char* name) {
+	for (int id = 0; AMX* amx = MF_GetScriptAmx(id); id++) {
+		int index;
+		if (MF_AmxFindNative(amx, name, &index) != AMX_ERR_NONE) {
+			continue;
+		}
+
+		auto header = reinterpret_cast<const amx_header*>(amx->base);
+		ucell address = *reinterpret_cast<const ucell*>(amx->base + header->natives + header->defsize * index);
+		if (address != 0) {
+			return reinterpret_cast<AMX_NATIVE>(address);
+		}
+	}
+
+	return nullptr;
+}
+
+// Pause native of the core. AMXX doesn't tell modules about paused plugins, so it is wrapped.
+AMX_NATIVE core_pause = nullptr;
+
+cell AMX_NATIVE_CALL pause_amxx(AMX *amx, cell *params) {
+	enum args_e { arg_count, arg_flags, arg_param1 };
+
+	int length;
+	std::string flags = MF_GetAmxString(amx, params[arg_flags], 0, &length);
+	std::string plugin = params[0] / static_cast<cell>(sizeof(cell)) >= arg_param1
+		? MF_GetAmxString(amx, params[arg_param1], 1, &length)
+		: "";
+
+	cell result = core_pause(amx, params);
+
+	// "a" pauses the whole plugin, "c" pauses the plugin with the given name instead of the calling one.
+	if (result && flags.find('a') != std::string::npos) {
+		AMX* paused = amx;
+		if (flags.find('c') != std::string::npos) {
+			int id = MF_FindScriptByName(plugin.c_str());
+			paused = id == -1 ? nullptr : MF_GetScriptAmx(id);
+		}
+
+		if (paused) {
+			grip_plugin_paused(paused);
+		}
+	}
+
+	return result;
+}
+
+AMX_NATIVE_INFO pause_natives[] = {
+	{"pause", pause_amxx},
+	{nullptr, nullptr},
+};
+
+void print_to_server_console(const char* line) {
+	MF_PrintSrvConsole("%s\n", line);
+}
+
+void grip_command() {
+	std::vector<const char*> args;
+	for (int i = 1; i < CMD_ARGC(); i++) {
+		args.push_back(CMD_ARGV(i));
+	}
+
+	grip_console_command(static_cast<cell>(args.size()), args.data(), print_to_server_console);
+}
+
 void request_handler(cell forward_handle, cell user_data) {
 	MF_ExecuteForward(
 			forward_handle,
@@ -74,7 +182,7 @@ cell AMX_NATIVE_CALL grip_destroy_body_amxx(AMX *amx, cell *params) {
 // native GripRequest:grip_request(const uri[], GripBodyHandle:body, GripRequestType:type, const handler[], GripRequestOptionsHandle:options = Invalid_GripRequestOptionsHandle, const userData);
 // public RequestHandler(GripResponseHandle:handle, const userData);
 cell AMX_NATIVE_CALL grip_request_amxx(AMX *amx, cell *params) {
-	enum { arg_count, arg_uri, arg_body_handle, arg_type, arg_handler, arg_options, arg_user_data };
+	enum { arg_count, arg_uri, arg_body_handle, arg_type, arg_handler, arg_options, arg_user_data, arg_queue };
 
 
 	const char* uri = MF_GetAmxString(amx, params[arg_uri], 2, &dummy);
@@ -86,7 +194,19 @@ cell AMX_NATIVE_CALL grip_request_amxx(AMX *amx, cell *params) {
 		return 0;
 	}
 
-	return grip_request(amx, handler_forward, uri, params[arg_body_handle], params[arg_type], request_handler, params[arg_options], params[arg_user_data]);
+	return grip_request(amx, handler_forward, uri, params[arg_body_handle], params[arg_type], request_handler, params[arg_options], params[arg_user_data], OPTIONAL_PARAM(arg_queue, DEFAULT_QUEUE));
+}
+
+cell AMX_NATIVE_CALL grip_create_queue_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_worker_threads, arg_max_concurrent_requests, arg_callbacks_per_frame };
+
+	return grip_create_queue(amx, params[arg_worker_threads], params[arg_max_concurrent_requests], params[arg_callbacks_per_frame]);
+}
+
+cell AMX_NATIVE_CALL grip_destroy_queue_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_queue };
+
+	return grip_destroy_queue(amx, params[arg_queue]);
 }
 
 cell AMX_NATIVE_CALL grip_cancel_request_amxx(AMX *amx, cell *params) {
@@ -98,8 +218,10 @@ cell AMX_NATIVE_CALL grip_get_response_state_amxx(AMX *amx, cell*) {
 	return grip_get_response_state(amx);
 }
 
-cell AMX_NATIVE_CALL grip_get_response_status_code_amxx(AMX *amx, cell *) {
-	return grip_get_response_status_code(amx);
+cell AMX_NATIVE_CALL grip_get_response_status_code_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_response };
+
+	return grip_get_response_status_code(amx, OPTIONAL_PARAM(arg_response, CURRENT_RESPONSE));
 }
 
 cell AMX_NATIVE_CALL grip_is_request_active_amxx(AMX *, cell *params) {
@@ -118,15 +240,73 @@ cell AMX_NATIVE_CALL grip_get_error_description_amxx(AMX *amx, cell *params) {
   return ret;
 }
 
+cell AMX_NATIVE_CALL grip_get_error_kind_amxx(AMX *amx, cell *) {
+	return grip_get_error_kind(amx);
+}
+
 cell AMX_NATIVE_CALL grip_get_response_body_string_amxx(AMX *amx, cell *params) {
-  enum { arg_count, arg_buffer, arg_buffer_size};
+	enum { arg_count, arg_buffer, arg_buffer_size, arg_response };
 
-  ZERO_INIT_STACK_BUFFER(buffer, params[arg_buffer_size]);
-  cell ret = grip_get_response_body_string(amx, &buffer[0], params[arg_buffer_size]);
+	ZERO_INIT_STACK_BUFFER(buffer, params[arg_buffer_size]);
+	cell ret = grip_get_response_body_string(amx, &buffer[0], params[arg_buffer_size], OPTIONAL_PARAM(arg_response, CURRENT_RESPONSE));
 
-  MF_SetAmxStringSafe(amx, params[arg_buffer], &buffer[0], params[arg_buffer_size]);
+	MF_SetAmxStringSafe(amx, params[arg_buffer], &buffer[0], params[arg_buffer_size]);
 
-  return ret;
+	return ret;
+}
+
+cell AMX_NATIVE_CALL grip_get_response_body_length_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_response };
+
+	return grip_get_response_body_length(amx, OPTIONAL_PARAM(arg_response, CURRENT_RESPONSE));
+}
+
+cell AMX_NATIVE_CALL grip_get_response_body_bytes_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_buffer, arg_buffer_size, arg_offset, arg_response };
+
+	cell buffer_size = clamp_buffer_size(amx, params[arg_buffer_size]);
+	std::vector<uint8_t> buffer(std::max(1, buffer_size));
+	cell ret = grip_get_response_body_bytes(amx, buffer.data(), buffer_size, params[arg_offset], OPTIONAL_PARAM(arg_response, CURRENT_RESPONSE));
+
+	cell *dest = MF_GetAmxAddr(amx, params[arg_buffer]);
+	for (cell i = 0; i < ret; ++i) {
+		dest[i] = buffer[i];
+	}
+
+	return ret;
+}
+
+cell AMX_NATIVE_CALL grip_get_response_body_hash_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_hash_type, arg_buffer, arg_buffer_size, arg_offset, arg_length, arg_response };
+
+	ZERO_INIT_STACK_BUFFER(buffer, params[arg_buffer_size]);
+	cell ret = grip_get_response_body_hash(amx, params[arg_hash_type], &buffer[0], params[arg_buffer_size], params[arg_offset], params[arg_length], OPTIONAL_PARAM(arg_response, CURRENT_RESPONSE));
+
+	MF_SetAmxStringSafe(amx, params[arg_buffer], &buffer[0], params[arg_buffer_size]);
+
+	return ret;
+}
+
+cell AMX_NATIVE_CALL grip_get_response_body_base64_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_buffer, arg_buffer_size, arg_offset, arg_length, arg_response };
+
+	ZERO_INIT_STACK_BUFFER(buffer, params[arg_buffer_size]);
+	cell ret = grip_get_response_body_base64(amx, &buffer[0], params[arg_buffer_size], params[arg_offset], params[arg_length], OPTIONAL_PARAM(arg_response, CURRENT_RESPONSE));
+
+	MF_SetAmxStringSafe(amx, params[arg_buffer], &buffer[0], params[arg_buffer_size]);
+
+	return ret;
+}
+
+cell AMX_NATIVE_CALL grip_get_response_body_hex_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_buffer, arg_buffer_size, arg_offset, arg_length, arg_response };
+
+	ZERO_INIT_STACK_BUFFER(buffer, params[arg_buffer_size]);
+	cell ret = grip_get_response_body_hex(amx, &buffer[0], params[arg_buffer_size], params[arg_offset], params[arg_length], OPTIONAL_PARAM(arg_response, CURRENT_RESPONSE));
+
+	MF_SetAmxStringSafe(amx, params[arg_buffer], &buffer[0], params[arg_buffer_size]);
+
+	return ret;
 }
 
 cell AMX_NATIVE_CALL grip_destroy_json_value_amxx(AMX *amx, cell *params) {
@@ -154,12 +334,24 @@ cell AMX_NATIVE_CALL grip_options_add_header_amxx(AMX *amx, cell *params) {
 			MF_GetAmxString(amx, params[arg_header_value], 1, &dummy));
 }
 
+cell AMX_NATIVE_CALL grip_options_set_max_response_body_size_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_options_handle, arg_max_size};
+
+	return grip_options_set_max_response_body_size(amx, params[arg_options_handle], params[arg_max_size]);
+}
+
+cell AMX_NATIVE_CALL grip_options_set_must_complete_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_options_handle, arg_must_complete };
+
+	return grip_options_set_must_complete(amx, params[arg_options_handle], params[arg_must_complete] != 0);
+}
+
 cell AMX_NATIVE_CALL grip_json_parse_response_body_amxx(AMX *amx, cell *params) {
-	enum { arg_count, arg_buffer, arg_buffer_size, arg_is_comment};
+	enum { arg_count, arg_buffer, arg_buffer_size, arg_response };
 
 	ZERO_INIT_STACK_BUFFER(buffer, params[arg_buffer_size]);
 
-	cell ret = grip_json_parse_response_body(amx, &buffer[0], params[arg_buffer_size]);
+	cell ret = grip_json_parse_response_body(amx, &buffer[0], params[arg_buffer_size], OPTIONAL_PARAM(arg_response, CURRENT_RESPONSE));
 
 	MF_SetAmxStringSafe(amx, params[arg_buffer], &buffer[0], params[arg_buffer_size]);
 
@@ -201,12 +393,12 @@ cell AMX_NATIVE_CALL grip_json_get_type_amxx(AMX *amx, cell *params) {
 	return grip_json_get_type(amx, params[arg_value]);
 }
 
-cell AMX_NATIVE_CALL grip_json_init_object_amxx(AMX *, cell *) {
-	return grip_json_init_object();
+cell AMX_NATIVE_CALL grip_json_init_object_amxx(AMX *amx, cell *) {
+	return grip_json_init_object(amx);
 }
 
-cell AMX_NATIVE_CALL grip_json_init_array_amxx(AMX *, cell *) {
-	return grip_json_init_array();
+cell AMX_NATIVE_CALL grip_json_init_array_amxx(AMX *amx, cell *) {
+	return grip_json_init_array(amx);
 }
 
 cell AMX_NATIVE_CALL grip_json_init_string_amxx(AMX *amx, cell *params) {
@@ -214,23 +406,23 @@ cell AMX_NATIVE_CALL grip_json_init_string_amxx(AMX *amx, cell *params) {
 	return grip_json_init_string(amx, MF_GetAmxString(amx, params[arg_string], 2, &dummy));
 }
 
-cell AMX_NATIVE_CALL grip_json_init_number_amxx(AMX *, cell *params) {
+cell AMX_NATIVE_CALL grip_json_init_number_amxx(AMX *amx, cell *params) {
 	enum { arg_count, arg_value };
-	return grip_json_init_number(params[arg_value]);
+	return grip_json_init_number(amx, params[arg_value]);
 }
 
-cell AMX_NATIVE_CALL grip_json_init_float_amxx(AMX *, cell *params) {
+cell AMX_NATIVE_CALL grip_json_init_float_amxx(AMX *amx, cell *params) {
 	enum { arg_count, arg_value };
-	return grip_json_init_float( (float) amx_ctof(params[arg_value]));
+	return grip_json_init_float(amx, (float) amx_ctof(params[arg_value]));
 }
 
-cell AMX_NATIVE_CALL grip_json_init_bool_amxx(AMX *, cell *params) {
+cell AMX_NATIVE_CALL grip_json_init_bool_amxx(AMX *amx, cell *params) {
 	enum { arg_count, arg_value };
-	return grip_json_init_bool(params[arg_value] != 0);
+	return grip_json_init_bool(amx, params[arg_value] != 0);
 }
 
-cell AMX_NATIVE_CALL grip_json_init_null_amxx(AMX *, cell *) {
-	return grip_json_init_null();
+cell AMX_NATIVE_CALL grip_json_init_null_amxx(AMX *amx, cell *) {
+	return grip_json_init_null(amx);
 }
 
 cell AMX_NATIVE_CALL grip_json_get_string_amxx(AMX *amx, cell *params) {
@@ -586,20 +778,800 @@ cell AMX_NATIVE_CALL grip_body_from_json_amxx(AMX *amx, cell *params) {
     return grip_body_from_json(amx, params[arg_value], params[arg_pretty] != 0, params[arg_recursion_limit]);
 }
 
+cell AMX_NATIVE_CALL grip_body_from_response_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_response };
+
+	return grip_body_from_response(amx, OPTIONAL_PARAM(arg_response, CURRENT_RESPONSE));
+}
+
+cell AMX_NATIVE_CALL grip_response_detach_amxx(AMX *amx, cell *) {
+	return grip_response_detach(amx);
+}
+
+cell AMX_NATIVE_CALL grip_destroy_response_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_response };
+
+	return grip_destroy_response(amx, params[arg_response]);
+}
+
+cell AMX_NATIVE_CALL grip_get_response_header_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_name, arg_buffer, arg_buffer_size, arg_response };
+
+	const char* name = MF_GetAmxString(amx, params[arg_name], 0, &dummy);
+
+	ZERO_INIT_STACK_BUFFER(buffer, params[arg_buffer_size]);
+	cell ret = grip_get_response_header(amx, name, &buffer[0], params[arg_buffer_size], OPTIONAL_PARAM(arg_response, CURRENT_RESPONSE));
+
+	MF_SetAmxStringSafe(amx, params[arg_buffer], &buffer[0], params[arg_buffer_size]);
+
+	return ret;
+}
+
+cell AMX_NATIVE_CALL grip_get_response_url_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_buffer, arg_buffer_size, arg_response };
+
+	ZERO_INIT_STACK_BUFFER(buffer, params[arg_buffer_size]);
+	cell ret = grip_get_response_url(amx, &buffer[0], params[arg_buffer_size], OPTIONAL_PARAM(arg_response, CURRENT_RESPONSE));
+
+	MF_SetAmxStringSafe(amx, params[arg_buffer], &buffer[0], params[arg_buffer_size]);
+
+	return ret;
+}
+
+cell AMX_NATIVE_CALL grip_body_get_length_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_body };
+
+	return grip_body_get_length(amx, params[arg_body]);
+}
+
+cell AMX_NATIVE_CALL grip_body_get_string_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_body, arg_buffer, arg_buffer_size, arg_offset };
+
+	ZERO_INIT_STACK_BUFFER(buffer, params[arg_buffer_size]);
+	cell ret = grip_body_get_string(amx, params[arg_body], &buffer[0], params[arg_buffer_size], params[arg_offset]);
+
+	MF_SetAmxStringSafe(amx, params[arg_buffer], &buffer[0], params[arg_buffer_size]);
+
+	return ret;
+}
+
+cell AMX_NATIVE_CALL grip_body_read_line_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_body, arg_offset, arg_buffer, arg_buffer_size };
+
+	cell *offset_addr = MF_GetAmxAddr(amx, params[arg_offset]);
+	cell offset = *offset_addr;
+
+	ZERO_INIT_STACK_BUFFER(buffer, params[arg_buffer_size]);
+	cell ret = grip_body_read_line(amx, params[arg_body], &offset, &buffer[0], params[arg_buffer_size]);
+
+	MF_SetAmxStringSafe(amx, params[arg_buffer], &buffer[0], params[arg_buffer_size]);
+	*offset_addr = offset;
+
+	return ret;
+}
+
+void event_handler(cell forward_handle, cell socket, cell event, cell user_data) {
+	MF_ExecuteForward(
+			forward_handle,
+			socket,
+			event,
+			user_data
+	);
+}
+
+void forward_releaser(cell forward_handle) {
+	MF_UnregisterSPForward(forward_handle);
+}
+
+// native GripWebSocket:grip_websocket_connect(const url[], const handler[], GripRequestOptions:options = Empty_GripRequestOptions, bool:reconnect = true, const any:userData = 0);
+// public WebSocketHandler(GripWebSocket:socket, GripWebSocketEvent:event, const userData);
+cell AMX_NATIVE_CALL grip_websocket_connect_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_url, arg_handler, arg_options, arg_reconnect, arg_user_data };
+
+	const char* url = MF_GetAmxString(amx, params[arg_url], 0, &dummy);
+	const char* handler_name = MF_GetAmxString(amx, params[arg_handler], 1, &dummy);
+	cell handler_forward = MF_RegisterSPForwardByName(amx, handler_name, FP_CELL, FP_CELL, FP_CELL, FP_DONE);
+	if (handler_forward < 1)
+	{
+		MF_LogError(amx, AMX_ERR_NATIVE, "Function not found: %s", handler_name);
+		return 0;
+	}
+
+	cell ret = grip_websocket_connect(amx, handler_forward, url, event_handler, forward_releaser, params[arg_options], params[arg_reconnect] != 0, params[arg_user_data]);
+	if (ret == 0) {
+		MF_UnregisterSPForward(handler_forward);
+	}
+
+	return ret;
+}
+
+cell AMX_NATIVE_CALL grip_websocket_send_string_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_socket, arg_message };
+
+	const char* message = MF_GetAmxString(amx, params[arg_message], 0, &dummy);
+	return grip_websocket_send_string(amx, params[arg_socket], message);
+}
+
+cell AMX_NATIVE_CALL grip_websocket_send_binary_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_socket, arg_data, arg_length };
+
+	cell length = clamp_buffer_size(amx, params[arg_length]);
+	std::vector<uint8_t> data(std::max(1, length));
+
+	cell *source = MF_GetAmxAddr(amx, params[arg_data]);
+	for (cell i = 0; i < length; ++i) {
+		data[i] = static_cast<uint8_t>(source[i]);
+	}
+
+	return grip_websocket_send_binary(amx, params[arg_socket], data.data(), length);
+}
+
+cell AMX_NATIVE_CALL grip_websocket_send_json_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_socket, arg_value, arg_pretty, arg_recursion_limit };
+
+	return grip_websocket_send_json(amx, params[arg_socket], params[arg_value], params[arg_pretty] != 0, params[arg_recursion_limit]);
+}
+
+cell AMX_NATIVE_CALL grip_websocket_close_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_socket, arg_code, arg_reason };
+
+	const char* reason = MF_GetAmxString(amx, params[arg_reason], 0, &dummy);
+	return grip_websocket_close(amx, params[arg_socket], params[arg_code], reason);
+}
+
+cell AMX_NATIVE_CALL grip_websocket_is_connected_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_socket };
+
+	return grip_websocket_is_connected(amx, params[arg_socket]);
+}
+
+cell AMX_NATIVE_CALL grip_websocket_destroy_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_socket };
+
+	return grip_websocket_destroy(amx, params[arg_socket]);
+}
+
+cell AMX_NATIVE_CALL grip_websocket_get_message_type_amxx(AMX *amx, cell *) {
+	return grip_websocket_get_message_type(amx);
+}
+
+cell AMX_NATIVE_CALL grip_websocket_get_message_length_amxx(AMX *amx, cell *) {
+	return grip_websocket_get_message_length(amx);
+}
+
+cell AMX_NATIVE_CALL grip_websocket_get_message_string_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_buffer, arg_buffer_size };
+
+	ZERO_INIT_STACK_BUFFER(buffer, params[arg_buffer_size]);
+	cell ret = grip_websocket_get_message_string(amx, &buffer[0], params[arg_buffer_size]);
+
+	MF_SetAmxStringSafe(amx, params[arg_buffer], &buffer[0], params[arg_buffer_size]);
+
+	return ret;
+}
+
+cell AMX_NATIVE_CALL grip_websocket_get_message_bytes_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_buffer, arg_buffer_size, arg_offset };
+
+	cell buffer_size = clamp_buffer_size(amx, params[arg_buffer_size]);
+	std::vector<uint8_t> buffer(std::max(1, buffer_size));
+	cell ret = grip_websocket_get_message_bytes(amx, buffer.data(), buffer_size, params[arg_offset]);
+
+	cell *dest = MF_GetAmxAddr(amx, params[arg_buffer]);
+	for (cell i = 0; i < ret; ++i) {
+		dest[i] = buffer[i];
+	}
+
+	return ret;
+}
+
+cell AMX_NATIVE_CALL grip_websocket_parse_message_json_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_buffer, arg_buffer_size };
+
+	ZERO_INIT_STACK_BUFFER(buffer, params[arg_buffer_size]);
+	cell ret = grip_websocket_parse_message_json(amx, &buffer[0], params[arg_buffer_size]);
+
+	MF_SetAmxStringSafe(amx, params[arg_buffer], &buffer[0], params[arg_buffer_size]);
+
+	return ret;
+}
+
+cell AMX_NATIVE_CALL grip_websocket_get_close_code_amxx(AMX *amx, cell *) {
+	return grip_websocket_get_close_code(amx);
+}
+
+cell AMX_NATIVE_CALL grip_websocket_get_close_reason_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_buffer, arg_buffer_size };
+
+	ZERO_INIT_STACK_BUFFER(buffer, params[arg_buffer_size]);
+	cell ret = grip_websocket_get_close_reason(amx, &buffer[0], params[arg_buffer_size]);
+
+	MF_SetAmxStringSafe(amx, params[arg_buffer], &buffer[0], params[arg_buffer_size]);
+
+	return ret;
+}
+
+cell AMX_NATIVE_CALL grip_websocket_get_error_description_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_buffer, arg_buffer_size };
+
+	ZERO_INIT_STACK_BUFFER(buffer, params[arg_buffer_size]);
+	cell ret = grip_websocket_get_error_description(amx, &buffer[0], params[arg_buffer_size]);
+
+	MF_SetAmxStringSafe(amx, params[arg_buffer], &buffer[0], params[arg_buffer_size]);
+
+	return ret;
+}
+
+cell AMX_NATIVE_CALL grip_websocket_get_error_kind_amxx(AMX *amx, cell *) {
+	return grip_websocket_get_error_kind(amx);
+}
+
+// native GripSSESubscription:grip_sse_subscribe(const url[], const handler[], GripRequestOptions:options = Empty_GripRequestOptions, const any:userData = 0);
+// public SSEHandler(GripSSESubscription:subscription, GripSSEEvent:event, const userData);
+cell AMX_NATIVE_CALL grip_sse_subscribe_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_url, arg_handler, arg_options, arg_user_data };
+
+	const char* url = MF_GetAmxString(amx, params[arg_url], 0, &dummy);
+	const char* handler_name = MF_GetAmxString(amx, params[arg_handler], 1, &dummy);
+	cell handler_forward = MF_RegisterSPForwardByName(amx, handler_name, FP_CELL, FP_CELL, FP_CELL, FP_DONE);
+	if (handler_forward < 1)
+	{
+		MF_LogError(amx, AMX_ERR_NATIVE, "Function not found: %s", handler_name);
+		return 0;
+	}
+
+	cell ret = grip_sse_subscribe(amx, handler_forward, url, event_handler, forward_releaser, params[arg_options], params[arg_user_data]);
+	if (ret == 0) {
+		MF_UnregisterSPForward(handler_forward);
+	}
+
+	return ret;
+}
+
+cell AMX_NATIVE_CALL grip_sse_unsubscribe_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_subscription };
+
+	return grip_sse_unsubscribe(amx, params[arg_subscription]);
+}
+
+void group_handler(cell forward_handle, cell group, cell succeeded, cell failed, cell user_data) {
+	MF_ExecuteForward(
+			forward_handle,
+			group,
+			succeeded,
+			failed,
+			user_data
+	);
+}
+
+// native GripRequestGroup:grip_create_request_group(const handler[], const any:userData = 0);
+// public GroupHandler(GripRequestGroup:group, succeeded, failed, const userData);
+cell AMX_NATIVE_CALL grip_create_request_group_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_handler, arg_user_data };
+
+	const char* handler_name = MF_GetAmxString(amx, params[arg_handler], 0, &dummy);
+	cell handler_forward = MF_RegisterSPForwardByName(amx, handler_name, FP_CELL, FP_CELL, FP_CELL, FP_CELL, FP_DONE);
+	if (handler_forward < 1)
+	{
+		MF_LogError(amx, AMX_ERR_NATIVE, "Function not found: %s", handler_name);
+		return 0;
+	}
+
+	cell ret = grip_create_request_group(amx, handler_forward, group_handler, forward_releaser, params[arg_user_data]);
+	if (ret == 0) {
+		MF_UnregisterSPForward(handler_forward);
+	}
+
+	return ret;
+}
+
+cell AMX_NATIVE_CALL grip_request_group_add_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_group, arg_request };
+
+	return grip_request_group_add(amx, params[arg_group], params[arg_request]);
+}
+
+cell AMX_NATIVE_CALL grip_request_group_seal_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_group };
+
+	return grip_request_group_seal(amx, params[arg_group]);
+}
+
+cell AMX_NATIVE_CALL grip_request_group_cancel_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_group };
+
+	return grip_request_group_cancel(amx, params[arg_group]);
+}
+
+cell AMX_NATIVE_CALL grip_destroy_request_group_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_group };
+
+	return grip_destroy_request_group(amx, params[arg_group]);
+}
+
+cell AMX_NATIVE_CALL grip_create_request_chain_amxx(AMX *amx, cell *) {
+	return grip_create_request_chain(amx);
+}
+
+// native grip_request_chain_add(GripRequestChain:chain, const uri[], GripBody:body, GripRequestType:type, GripRequestOptions:options = Empty_GripRequestOptions);
+cell AMX_NATIVE_CALL grip_request_chain_add_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_chain, arg_uri, arg_body_handle, arg_type, arg_options };
+
+	const char* uri = MF_GetAmxString(amx, params[arg_uri], 0, &dummy);
+	return grip_request_chain_add(amx, params[arg_chain], uri, params[arg_body_handle], params[arg_type], params[arg_options]);
+}
+
+// native GripRequestCancellation:grip_request_chain_send(GripRequestChain:chain, const handler[], const any:userData = 0, GripQueue:queue = Default_GripQueue);
+cell AMX_NATIVE_CALL grip_request_chain_send_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_chain, arg_handler, arg_user_data, arg_queue };
+
+	const char* handler_name = MF_GetAmxString(amx, params[arg_handler], 0, &dummy);
+	cell handler_forward = MF_RegisterSPForwardByName(amx, handler_name, FP_CELL, FP_DONE);
+	if (handler_forward < 1)
+	{
+		MF_LogError(amx, AMX_ERR_NATIVE, "Function not found: %s", handler_name);
+		return 0;
+	}
+
+	cell ret = grip_request_chain_send(amx, params[arg_chain], handler_forward, request_handler, params[arg_user_data], OPTIONAL_PARAM(arg_queue, DEFAULT_QUEUE));
+	if (ret == 0) {
+		MF_UnregisterSPForward(handler_forward);
+	}
+
+	return ret;
+}
+
+cell AMX_NATIVE_CALL grip_destroy_request_chain_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_chain };
+
+	return grip_destroy_request_chain(amx, params[arg_chain]);
+}
+
+cell AMX_NATIVE_CALL grip_get_response_chain_step_amxx(AMX *amx, cell *) {
+	return grip_get_response_chain_step(amx);
+}
+
+void schedule_handler(cell forward_handle, cell schedule, cell user_data) {
+	MF_ExecuteForward(
+			forward_handle,
+			schedule,
+			user_data
+	);
+}
+
+// native GripSchedule:grip_schedule_request(const uri[], GripBody:body, GripRequestType:type, const handler[], Float:delay, Float:interval = 0.0, Float:jitter = 0.0, bool:wait_for_previous = true, GripRequestOptions:options = Empty_GripRequestOptions, const any:userData = 0, GripQueue:queue = Default_GripQueue);
+// public ScheduleHandler(GripSchedule:schedule, const userData);
+cell AMX_NATIVE_CALL grip_schedule_request_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_uri, arg_body_handle, arg_type, arg_handler, arg_delay, arg_interval, arg_jitter, arg_wait_for_previous, arg_options, arg_user_data, arg_queue };
+
+	const char* uri = MF_GetAmxString(amx, params[arg_uri], 0, &dummy);
+	const char* handler_name = MF_GetAmxString(amx, params[arg_handler], 1, &dummy);
+	cell handler_forward = MF_RegisterSPForwardByName(amx, handler_name, FP_CELL, FP_CELL, FP_DONE);
+	if (handler_forward < 1)
+	{
+		MF_LogError(amx, AMX_ERR_NATIVE, "Function not found: %s", handler_name);
+		return 0;
+	}
+
+	cell ret = grip_schedule_request(
+			amx,
+			handler_forward,
+			uri,
+			params[arg_body_handle],
+			params[arg_type],
+			schedule_handler,
+			forward_releaser,
+			params[arg_options],
+			params[arg_user_data],
+			amx_ctof(params[arg_delay]),
+			amx_ctof(params[arg_interval]),
+			amx_ctof(params[arg_jitter]),
+			params[arg_wait_for_previous] != 0,
+			OPTIONAL_PARAM(arg_queue, DEFAULT_QUEUE)
+	);
+	if (ret == 0) {
+		MF_UnregisterSPForward(handler_forward);
+	}
+
+	return ret;
+}
+
+cell AMX_NATIVE_CALL grip_pause_schedule_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_schedule };
+
+	return grip_pause_schedule(amx, params[arg_schedule]);
+}
+
+cell AMX_NATIVE_CALL grip_resume_schedule_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_schedule };
+
+	return grip_resume_schedule(amx, params[arg_schedule]);
+}
+
+cell AMX_NATIVE_CALL grip_cancel_schedule_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_schedule };
+
+	return grip_cancel_schedule(amx, params[arg_schedule]);
+}
+
+cell AMX_NATIVE_CALL grip_sse_get_event_name_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_buffer, arg_buffer_size };
+
+	ZERO_INIT_STACK_BUFFER(buffer, params[arg_buffer_size]);
+	cell ret = grip_sse_get_event_name(amx, &buffer[0], params[arg_buffer_size]);
+
+	MF_SetAmxStringSafe(amx, params[arg_buffer], &buffer[0], params[arg_buffer_size]);
+
+	return ret;
+}
+
+cell AMX_NATIVE_CALL grip_sse_get_event_id_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_buffer, arg_buffer_size };
+
+	ZERO_INIT_STACK_BUFFER(buffer, params[arg_buffer_size]);
+	cell ret = grip_sse_get_event_id(amx, &buffer[0], params[arg_buffer_size]);
+
+	MF_SetAmxStringSafe(amx, params[arg_buffer], &buffer[0], params[arg_buffer_size]);
+
+	return ret;
+}
+
+cell AMX_NATIVE_CALL grip_sse_get_event_data_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_buffer, arg_buffer_size };
+
+	ZERO_INIT_STACK_BUFFER(buffer, params[arg_buffer_size]);
+	cell ret = grip_sse_get_event_data(amx, &buffer[0], params[arg_buffer_size]);
+
+	MF_SetAmxStringSafe(amx, params[arg_buffer], &buffer[0], params[arg_buffer_size]);
+
+	return ret;
+}
+
+cell AMX_NATIVE_CALL grip_sse_parse_event_json_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_buffer, arg_buffer_size };
+
+	ZERO_INIT_STACK_BUFFER(buffer, params[arg_buffer_size]);
+	cell ret = grip_sse_parse_event_json(amx, &buffer[0], params[arg_buffer_size]);
+
+	MF_SetAmxStringSafe(amx, params[arg_buffer], &buffer[0], params[arg_buffer_size]);
+
+	return ret;
+}
+
+cell AMX_NATIVE_CALL grip_sse_get_error_description_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_buffer, arg_buffer_size };
+
+	ZERO_INIT_STACK_BUFFER(buffer, params[arg_buffer_size]);
+	cell ret = grip_sse_get_error_description(amx, &buffer[0], params[arg_buffer_size]);
+
+	MF_SetAmxStringSafe(amx, params[arg_buffer], &buffer[0], params[arg_buffer_size]);
+
+	return ret;
+}
+
+cell AMX_NATIVE_CALL grip_sse_get_error_kind_amxx(AMX *amx, cell *) {
+	return grip_sse_get_error_kind(amx);
+}
+
+void route_handler(cell forward_handle, cell request, cell user_data) {
+	MF_ExecuteForward(
+			forward_handle,
+			request,
+			user_data
+	);
+}
+
+// native bool:grip_server_register_route(const route[], const handler[], const any:userData = 0);
+// public RouteHandler(GripServerRequest:request, const userData);
+cell AMX_NATIVE_CALL grip_server_register_route_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_route, arg_handler, arg_user_data };
+
+	const char* route = MF_GetAmxString(amx, params[arg_route], 0, &dummy);
+	const char* handler_name = MF_GetAmxString(amx, params[arg_handler], 1, &dummy);
+	cell handler_forward = MF_RegisterSPForwardByName(amx, handler_name, FP_CELL, FP_CELL, FP_DONE);
+	if (handler_forward < 1)
+	{
+		MF_LogError(amx, AMX_ERR_NATIVE, "Function not found: %s", handler_name);
+		return 0;
+	}
+
+	cell ret = grip_server_register_route(amx, handler_forward, route, route_handler, params[arg_user_data]);
+	if (ret == 0) {
+		MF_UnregisterSPForward(handler_forward);
+	}
+
+	return ret;
+}
+
+cell AMX_NATIVE_CALL grip_server_is_running_amxx(AMX *, cell *) {
+	return grip_server_is_running();
+}
+
+cell AMX_NATIVE_CALL grip_server_get_method_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_request, arg_buffer, arg_buffer_size };
+
+	ZERO_INIT_STACK_BUFFER(buffer, params[arg_buffer_size]);
+	cell ret = grip_server_get_method(amx, params[arg_request], &buffer[0], params[arg_buffer_size]);
+
+	MF_SetAmxStringSafe(amx, params[arg_buffer], &buffer[0], params[arg_buffer_size]);
+
+	return ret;
+}
+
+cell AMX_NATIVE_CALL grip_server_get_path_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_request, arg_buffer, arg_buffer_size };
+
+	ZERO_INIT_STACK_BUFFER(buffer, params[arg_buffer_size]);
+	cell ret = grip_server_get_path(amx, params[arg_request], &buffer[0], params[arg_buffer_size]);
+
+	MF_SetAmxStringSafe(amx, params[arg_buffer], &buffer[0], params[arg_buffer_size]);
+
+	return ret;
+}
+
+cell AMX_NATIVE_CALL grip_server_get_query_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_request, arg_buffer, arg_buffer_size };
+
+	ZERO_INIT_STACK_BUFFER(buffer, params[arg_buffer_size]);
+	cell ret = grip_server_get_query(amx, params[arg_request], &buffer[0], params[arg_buffer_size]);
+
+	MF_SetAmxStringSafe(amx, params[arg_buffer], &buffer[0], params[arg_buffer_size]);
+
+	return ret;
+}
+
+cell AMX_NATIVE_CALL grip_server_get_query_param_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_request, arg_name, arg_buffer, arg_buffer_size };
+
+	const char* name = MF_GetAmxString(amx, params[arg_name], 0, &dummy);
+
+	ZERO_INIT_STACK_BUFFER(buffer, params[arg_buffer_size]);
+	cell ret = grip_server_get_query_param(amx, params[arg_request], name, &buffer[0], params[arg_buffer_size]);
+
+	MF_SetAmxStringSafe(amx, params[arg_buffer], &buffer[0], params[arg_buffer_size]);
+
+	return ret;
+}
+
+cell AMX_NATIVE_CALL grip_server_get_header_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_request, arg_name, arg_buffer, arg_buffer_size };
+
+	const char* name = MF_GetAmxString(amx, params[arg_name], 0, &dummy);
+
+	ZERO_INIT_STACK_BUFFER(buffer, params[arg_buffer_size]);
+	cell ret = grip_server_get_header(amx, params[arg_request], name, &buffer[0], params[arg_buffer_size]);
+
+	MF_SetAmxStringSafe(amx, params[arg_buffer], &buffer[0], params[arg_buffer_size]);
+
+	return ret;
+}
+
+cell AMX_NATIVE_CALL grip_server_get_remote_address_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_request, arg_buffer, arg_buffer_size };
+
+	ZERO_INIT_STACK_BUFFER(buffer, params[arg_buffer_size]);
+	cell ret = grip_server_get_remote_address(amx, params[arg_request], &buffer[0], params[arg_buffer_size]);
+
+	MF_SetAmxStringSafe(amx, params[arg_buffer], &buffer[0], params[arg_buffer_size]);
+
+	return ret;
+}
+
+cell AMX_NATIVE_CALL grip_server_get_body_length_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_request };
+
+	return grip_server_get_body_length(amx, params[arg_request]);
+}
+
+cell AMX_NATIVE_CALL grip_server_get_body_string_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_request, arg_buffer, arg_buffer_size, arg_offset };
+
+	ZERO_INIT_STACK_BUFFER(buffer, params[arg_buffer_size]);
+	cell ret = grip_server_get_body_string(amx, params[arg_request], &buffer[0], params[arg_buffer_size], params[arg_offset]);
+
+	MF_SetAmxStringSafe(amx, params[arg_buffer], &buffer[0], params[arg_buffer_size]);
+
+	return ret;
+}
+
+cell AMX_NATIVE_CALL grip_server_body_from_request_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_request };
+
+	return grip_server_body_from_request(amx, params[arg_request]);
+}
+
+cell AMX_NATIVE_CALL grip_server_parse_body_json_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_request, arg_buffer, arg_buffer_size };
+
+	ZERO_INIT_STACK_BUFFER(buffer, params[arg_buffer_size]);
+	cell ret = grip_server_parse_body_json(amx, params[arg_request], &buffer[0], params[arg_buffer_size]);
+
+	MF_SetAmxStringSafe(amx, params[arg_buffer], &buffer[0], params[arg_buffer_size]);
+
+	return ret;
+}
+
+cell AMX_NATIVE_CALL grip_server_set_reply_header_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_request, arg_name, arg_value };
+
+	const char* name = MF_GetAmxString(amx, params[arg_name], 0, &dummy);
+	const char* value = MF_GetAmxString(amx, params[arg_value], 1, &dummy);
+
+	return grip_server_set_reply_header(amx, params[arg_request], name, value);
+}
+
+cell AMX_NATIVE_CALL grip_server_reply_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_request, arg_status, arg_body };
+
+	const char* body = MF_GetAmxString(amx, params[arg_body], 0, &dummy);
+
+	return grip_server_reply(amx, params[arg_request], params[arg_status], body);
+}
+
+cell AMX_NATIVE_CALL grip_server_reply_json_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_request, arg_status, arg_value, arg_pretty, arg_recursion_limit };
+
+	return grip_server_reply_json(amx, params[arg_request], params[arg_status], params[arg_value], params[arg_pretty] != 0, params[arg_recursion_limit]);
+}
+
+cell AMX_NATIVE_CALL grip_get_stats_amxx(AMX *amx, cell *) {
+	return grip_get_stats(amx);
+}
+
+cell AMX_NATIVE_CALL grip_get_handle_count_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_handle_type };
+	return grip_get_handle_count(amx, params[arg_handle_type]);
+}
+
+cell AMX_NATIVE_CALL grip_get_handles_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_handle_type, arg_handles, arg_size };
+	return grip_get_handles(amx, params[arg_handle_type], MF_GetAmxAddr(amx, params[arg_handles]), params[arg_size]);
+}
+
+cell AMX_NATIVE_CALL grip_release_handles_amxx(AMX *amx, cell *) {
+	return grip_release_handles(amx);
+}
+
+cell AMX_NATIVE_CALL grip_dump_handles_amxx(AMX *, cell *) {
+	return grip_dump_handles(print_to_server_console);
+}
+
+cell AMX_NATIVE_CALL grip_reload_config_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_error_buffer, arg_error_buffer_size };
+
+	ZERO_INIT_STACK_BUFFER(buffer, params[arg_error_buffer_size]);
+
+	cell ret = grip_reload_config(amx, &buffer[0], params[arg_error_buffer_size]);
+
+	MF_SetAmxStringSafe(amx, params[arg_error_buffer], &buffer[0], params[arg_error_buffer_size]);
+
+	return ret;
+}
+
+cell AMX_NATIVE_CALL grip_mock_add_response_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_type, arg_url, arg_status, arg_body, arg_delay, arg_options };
+
+	const char* url = MF_GetAmxString(amx, params[arg_url], 0, &dummy);
+	const char* body = MF_GetAmxString(amx, params[arg_body], 1, &dummy);
+
+	return grip_mock_add_response(amx, params[arg_type], url, params[arg_status], body, params[arg_delay], params[arg_options]);
+}
+
+cell AMX_NATIVE_CALL grip_mock_add_failure_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_type, arg_url, arg_error_kind, arg_delay };
+
+	const char* url = MF_GetAmxString(amx, params[arg_url], 0, &dummy);
+
+	return grip_mock_add_failure(amx, params[arg_type], url, params[arg_error_kind], params[arg_delay]);
+}
+
+cell AMX_NATIVE_CALL grip_mock_get_request_count_amxx(AMX *amx, cell *params) {
+	enum { arg_count, arg_type, arg_url };
+
+	const char* url = MF_GetAmxString(amx, params[arg_url], 0, &dummy);
+
+	return grip_mock_get_request_count(amx, params[arg_type], url);
+}
+
+cell AMX_NATIVE_CALL grip_mock_reset_amxx(AMX *amx, cell *) {
+	return grip_mock_reset(amx);
+}
+
 AMX_NATIVE_INFO grip_exports[] = {
 	{"grip_request", grip_request_amxx},
+	{"grip_create_queue", grip_create_queue_amxx},
+	{"grip_destroy_queue", grip_destroy_queue_amxx},
+	{"grip_create_request_group", grip_create_request_group_amxx},
+	{"grip_request_group_add", grip_request_group_add_amxx},
+	{"grip_request_group_seal", grip_request_group_seal_amxx},
+	{"grip_request_group_cancel", grip_request_group_cancel_amxx},
+	{"grip_destroy_request_group", grip_destroy_request_group_amxx},
+	{"grip_create_request_chain", grip_create_request_chain_amxx},
+	{"grip_request_chain_add", grip_request_chain_add_amxx},
+	{"grip_request_chain_send", grip_request_chain_send_amxx},
+	{"grip_destroy_request_chain", grip_destroy_request_chain_amxx},
+	{"grip_get_response_chain_step", grip_get_response_chain_step_amxx},
+	{"grip_schedule_request", grip_schedule_request_amxx},
+	{"grip_pause_schedule", grip_pause_schedule_amxx},
+	{"grip_resume_schedule", grip_resume_schedule_amxx},
+	{"grip_cancel_schedule", grip_cancel_schedule_amxx},
 	{"grip_destroy_body", grip_destroy_body_amxx},
 	{"grip_body_from_string", grip_body_from_string_amxx},
+	{"grip_body_from_response", grip_body_from_response_amxx},
+	{"grip_body_get_length", grip_body_get_length_amxx},
+	{"grip_body_get_string", grip_body_get_string_amxx},
+	{"grip_body_read_line", grip_body_read_line_amxx},
+	{"grip_response_detach", grip_response_detach_amxx},
+	{"grip_destroy_response", grip_destroy_response_amxx},
+	{"grip_get_response_header", grip_get_response_header_amxx},
+	{"grip_get_response_url", grip_get_response_url_amxx},
+	{"grip_websocket_connect", grip_websocket_connect_amxx},
+	{"grip_websocket_send_string", grip_websocket_send_string_amxx},
+	{"grip_websocket_send_binary", grip_websocket_send_binary_amxx},
+	{"grip_websocket_send_json", grip_websocket_send_json_amxx},
+	{"grip_websocket_close", grip_websocket_close_amxx},
+	{"grip_websocket_is_connected", grip_websocket_is_connected_amxx},
+	{"grip_websocket_destroy", grip_websocket_destroy_amxx},
+	{"grip_websocket_get_message_type", grip_websocket_get_message_type_amxx},
+	{"grip_websocket_get_message_length", grip_websocket_get_message_length_amxx},
+	{"grip_websocket_get_message_string", grip_websocket_get_message_string_amxx},
+	{"grip_websocket_get_message_bytes", grip_websocket_get_message_bytes_amxx},
+	{"grip_websocket_parse_message_json", grip_websocket_parse_message_json_amxx},
+	{"grip_websocket_get_close_code", grip_websocket_get_close_code_amxx},
+	{"grip_websocket_get_close_reason", grip_websocket_get_close_reason_amxx},
+	{"grip_websocket_get_error_description", grip_websocket_get_error_description_amxx},
+	{"grip_websocket_get_error_kind", grip_websocket_get_error_kind_amxx},
+	{"grip_sse_subscribe", grip_sse_subscribe_amxx},
+	{"grip_sse_unsubscribe", grip_sse_unsubscribe_amxx},
+	{"grip_sse_get_event_name", grip_sse_get_event_name_amxx},
+	{"grip_sse_get_event_id", grip_sse_get_event_id_amxx},
+	{"grip_sse_get_event_data", grip_sse_get_event_data_amxx},
+	{"grip_sse_parse_event_json", grip_sse_parse_event_json_amxx},
+	{"grip_sse_get_error_description", grip_sse_get_error_description_amxx},
+	{"grip_sse_get_error_kind", grip_sse_get_error_kind_amxx},
+	{"grip_server_register_route", grip_server_register_route_amxx},
+	{"grip_server_is_running", grip_server_is_running_amxx},
+	{"grip_server_get_method", grip_server_get_method_amxx},
+	{"grip_server_get_path", grip_server_get_path_amxx},
+	{"grip_server_get_query", grip_server_get_query_amxx},
+	{"grip_server_get_query_param", grip_server_get_query_param_amxx},
+	{"grip_server_get_header", grip_server_get_header_amxx},
+	{"grip_server_get_remote_address", grip_server_get_remote_address_amxx},
+	{"grip_server_get_body_length", grip_server_get_body_length_amxx},
+	{"grip_server_get_body_string", grip_server_get_body_string_amxx},
+	{"grip_server_body_from_request", grip_server_body_from_request_amxx},
+	{"grip_server_parse_body_json", grip_server_parse_body_json_amxx},
+	{"grip_server_set_reply_header", grip_server_set_reply_header_amxx},
+	{"grip_server_reply", grip_server_reply_amxx},
+	{"grip_server_reply_json", grip_server_reply_json_amxx},
+	{"grip_get_stats", grip_get_stats_amxx},
+	{"grip_get_handle_count", grip_get_handle_count_amxx},
+	{"grip_get_handles", grip_get_handles_amxx},
+	{"grip_release_handles", grip_release_handles_amxx},
+	{"grip_dump_handles", grip_dump_handles_amxx},
+	{"grip_reload_config", grip_reload_config_amxx},
+	{"grip_mock_add_response", grip_mock_add_response_amxx},
+	{"grip_mock_add_failure", grip_mock_add_failure_amxx},
+	{"grip_mock_get_request_count", grip_mock_get_request_count_amxx},
+	{"grip_mock_reset", grip_mock_reset_amxx},
 	{"grip_cancel_request", grip_cancel_request_amxx},
 	{"grip_get_response_state", grip_get_response_state_amxx},
 	{"grip_is_request_active", grip_is_request_active_amxx},
 	{"grip_get_error_description", grip_get_error_description_amxx},
+	{"grip_get_error_kind", grip_get_error_kind_amxx},
 	{"grip_get_response_body_string", grip_get_response_body_string_amxx},
+	{"grip_get_response_body_length", grip_get_response_body_length_amxx},
+	{"grip_get_response_body_bytes", grip_get_response_body_bytes_amxx},
+	{"grip_get_response_body_hash", grip_get_response_body_hash_amxx},
+	{"grip_get_response_body_base64", grip_get_response_body_base64_amxx},
+	{"grip_get_response_body_hex", grip_get_response_body_hex_amxx},
 	{"grip_json_parse_response_body", grip_json_parse_response_body_amxx},
 	{"grip_destroy_json_value", grip_destroy_json_value_amxx},
 	{"grip_create_default_options", grip_create_default_options_amxx},
 	{"grip_destroy_options", grip_destroy_options_amxx},
 	{"grip_options_add_header", grip_options_add_header_amxx},
+	{"grip_options_set_max_response_body_size", grip_options_set_max_response_body_size_amxx},
+	{"grip_options_set_must_complete", grip_options_set_must_complete_amxx},
 	{"grip_get_response_status_code", grip_get_response_status_code_amxx},
 	{"grip_json_parse_string", grip_json_parse_string_amxx},
 	{"grip_json_parse_file", grip_json_parse_file_amxx},
@@ -664,14 +1636,29 @@ AMX_NATIVE_INFO grip_exports[] = {
 void OnAmxxAttach()
 {
 	MF_AddNatives(grip_exports);
+	REG_SVR_COMMAND("grip", grip_command);
 }
 
 void OnPluginsLoaded() {
-    grip_init(log_error, MF_BuildPathname("%s/grip.ini", MF_GetLocalInfo("amxx_configsdir", "addons/amxmodx/configs")));
+	grip_init(log_error, plugin_name, MF_BuildPathname("%s/grip.ini", MF_GetLocalInfo("amxx_configsdir", "addons/amxmodx/configs")));
+
+	// Plugins are loaded again on every map, so their pause native is overridden every time.
+	if (!core_pause) {
+		core_pause = find_bound_native("pause");
+	}
+	if (core_pause) {
+		MF_OverrideNatives(pause_natives, MODULE_NAME);
+	}
+}
+
+void OnPluginsUnloading() {
+	grip_drain();
+	grip_report_leaks();
+	grip_release_plugin_handles();
 }
 
 void OnPluginsUnloaded() {
-    grip_deinit();
+	grip_deinit();
 }
 
 void StartFrame() {
@@ -29,55 +29,548 @@
  *
  */
 
+use crate::errors::*;
 use fnv::FnvHashMap;
+use std::collections::VecDeque;
 
-/// Just a simple, general and limited abstraction for storing Pawn handle id's
-pub struct CellMap<T> {
-    inner: FnvHashMap<isize, T>,
-    counter: isize,
+/// Kind of the items stored in the `CellMap`. Encoded into every handle,
+/// so that handle of one type is never accepted by natives of another type.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HandleType {
+    Body = 1,
+    Response = 2,
+    Cancellation = 3,
+    JSONValue = 4,
+    Options = 5,
+    Subscription = 6,
+    WebSocket = 7,
+    ServerRequest = 8,
+    Queue = 9,
+    RequestGroup = 10,
+    RequestChain = 11,
+    Schedule = 12,
 }
 
-impl<T> Default for CellMap<T> {
-    fn default() -> Self {
-        CellMap::new()
+impl HandleType {
+    /// Same values as `GripHandleType` in Pawn.
+    pub fn from_id(id: isize) -> Option<HandleType> {
+        match id {
+            1 => Some(HandleType::Body),
+            2 => Some(HandleType::Response),
+            3 => Some(HandleType::Cancellation),
+            4 => Some(HandleType::JSONValue),
+            5 => Some(HandleType::Options),
+            6 => Some(HandleType::Subscription),
+            7 => Some(HandleType::WebSocket),
+            8 => Some(HandleType::ServerRequest),
+            9 => Some(HandleType::Queue),
+            10 => Some(HandleType::RequestGroup),
+            11 => Some(HandleType::RequestChain),
+            12 => Some(HandleType::Schedule),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            HandleType::Body => "body",
+            HandleType::Response => "response",
+            HandleType::Cancellation => "cancellation",
+            HandleType::JSONValue => "JSON value",
+            HandleType::Options => "options",
+            HandleType::Subscription => "SSE subscription",
+            HandleType::WebSocket => "WebSocket",
+            HandleType::ServerRequest => "server request",
+            HandleType::Queue => "queue",
+            HandleType::RequestGroup => "request group",
+            HandleType::RequestChain => "request chain",
+            HandleType::Schedule => "schedule",
+        }
     }
 }
+
+// Handle is a positive 31-bit cell: 4 bits of type tag, 10 bits of generation and 17 bits of index.
+const INDEX_BITS: u32 = 17;
+const GENERATION_BITS: u32 = 10;
+const INDEX_MASK: isize = (1 << INDEX_BITS) - 1;
+const GENERATION_MASK: isize = (1 << GENERATION_BITS) - 1;
+const TAG_SHIFT: u32 = INDEX_BITS + GENERATION_BITS;
+
+/// Maximum number of items, which can be stored at once.
+pub const MAX_ITEMS: usize = 1 << INDEX_BITS;
+
+// Freed slots are reused in FIFO order, only when there are more of them than this.
+// So stale handle matches the new item only after MIN_FREE_SLOTS << GENERATION_BITS removals.
+const MIN_FREE_SLOTS: usize = 256;
+
+struct Slot<T> {
+    generation: isize,
+    owner: usize,
+    // Native, which created the item.
+    site: &'static str,
+    item: Option<T>,
+}
+
+/// Just a simple, general and limited abstraction for storing Pawn handle id's.
+/// Slots of removed items are reused with the next generation after a while, so stale handles are detected.
+/// Every item has an owner, e.g. plugin which created it. Owner `0` is the module itself.
+pub struct CellMap<T> {
+    handle_type: HandleType,
+    slots: Vec<Slot<T>>,
+    free_indices: VecDeque<usize>,
+    len: usize,
+    owner_counts: FnvHashMap<usize, usize>,
+    quota: Option<usize>,
+}
+
 impl<T> CellMap<T> {
-    pub fn new() -> CellMap<T> {
+    pub fn new(handle_type: HandleType) -> CellMap<T> {
         CellMap {
-            inner: FnvHashMap::default(),
-            counter: 1,
+            handle_type,
+            slots: vec![],
+            free_indices: VecDeque::new(),
+            len: 0,
+            owner_counts: FnvHashMap::default(),
+            quota: None,
+        }
+    }
+
+    pub fn handle_type(&self) -> HandleType {
+        self.handle_type
+    }
+
+    /// Limits number of items of every owner, except the module.
+    pub fn set_quota(&mut self, quota: Option<usize>) {
+        self.quota = quota;
+    }
+
+    /// Fails, if `owner` can't insert one more item, because of its quota or the limit.
+    pub fn check_capacity(&self, owner: usize) -> Result<()> {
+        match self.quota {
+            Some(quota) if owner != 0 && self.count_owned_by(owner) >= quota => {
+                Err(ErrorKind::HandleQuotaExceeded(self.handle_type, quota).into())
+            }
+            _ if self.len >= MAX_ITEMS => {
+                Err(ErrorKind::HandleLimitExceeded(self.handle_type, MAX_ITEMS).into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn make_id(&self, index: usize, generation: isize) -> isize {
+        (self.handle_type as isize) << TAG_SHIFT | generation << INDEX_BITS | index as isize
+    }
+
+    /// Returns index of the slot, if `id` is a handle of this map.
+    fn index_of(&self, id: isize) -> Option<usize> {
+        let slot = self.slots.get((id & INDEX_MASK) as usize)?;
+        if id >> TAG_SHIFT == self.handle_type as isize
+            && (id >> INDEX_BITS) & GENERATION_MASK == slot.generation
+            && slot.item.is_some()
+        {
+            Some((id & INDEX_MASK) as usize)
+        } else {
+            None
         }
     }
 
-    /// Returns ID which will be used next time.
-    pub fn peek_id(&self) -> isize {
-        self.counter
+    /// Explains, why `id` doesn't refer to any item.
+    fn lookup_error(&self, id: isize) -> Error {
+        if id <= 0 {
+            return ErrorKind::InvalidHandle(self.handle_type, id).into();
+        }
+
+        match HandleType::from_id(id >> TAG_SHIFT) {
+            Some(handle_type) if handle_type != self.handle_type => {
+                ErrorKind::WrongHandleType(self.handle_type, handle_type, id).into()
+            }
+            Some(_) if ((id & INDEX_MASK) as usize) < self.slots.len() => {
+                ErrorKind::DestroyedHandle(self.handle_type, id).into()
+            }
+            _ => ErrorKind::InvalidHandle(self.handle_type, id).into(),
+        }
     }
 
-    /// Inserts desired item and returns generated id which is always greater than 1
-    pub fn insert_with_unique_id(&mut self, item: T) -> isize {
-        assert!(self.counter >= 1);
+    /// Whether the next item reuses the oldest free slot instead of the new one.
+    fn reuses_slot(&self) -> bool {
+        self.free_indices.len() > MIN_FREE_SLOTS
+            || (self.slots.len() >= MAX_ITEMS && !self.free_indices.is_empty())
+    }
 
-        assert!(self.inner.insert(self.counter as isize, item).is_none());
-        self.counter += 1;
+    /// Returns ID, which the next item of `owner` gets, unless it can't insert one more item.
+    /// It's used by callbacks, which are created before the item, so nothing else should be inserted in between.
+    pub fn next_id(&self, owner: usize) -> Result<isize> {
+        self.check_capacity(owner)?;
+        Ok(self.peek_id())
+    }
 
-        self.counter - 1
+    /// Returns ID which will be used next time, there must be room for one more item.
+    fn peek_id(&self) -> isize {
+        if self.reuses_slot() {
+            let index = self.free_indices[0];
+            self.make_id(index, self.slots[index].generation)
+        } else {
+            self.make_id(self.slots.len(), 0)
+        }
+    }
+
+    /// Inserts desired item owned by the module and returns generated id which is always greater than 1
+    pub fn insert_with_unique_id(&mut self, item: T) -> Result<isize> {
+        self.insert_unchecked(0, "", item)
+    }
+
+    /// Inserts item created by the `site` native on behalf of the `owner`, unless owner's quota is reached.
+    pub fn insert_owned(&mut self, owner: usize, site: &'static str, item: T) -> Result<isize> {
+        self.check_capacity(owner)?;
+        self.insert_unchecked(owner, site, item)
+    }
+
+    fn insert_unchecked(&mut self, owner: usize, site: &'static str, item: T) -> Result<isize> {
+        if self.len >= MAX_ITEMS {
+            return Err(ErrorKind::HandleLimitExceeded(self.handle_type, MAX_ITEMS).into());
+        }
+
+        let id = self.peek_id();
+        if self.reuses_slot() {
+            let index = self.free_indices.pop_front().unwrap();
+            self.slots[index].owner = owner;
+            self.slots[index].site = site;
+            self.slots[index].item = Some(item);
+        } else {
+            self.slots.push(Slot {
+                generation: 0,
+                owner,
+                site,
+                item: Some(item),
+            })
+        }
+        self.len += 1;
+        *self.owner_counts.entry(owner).or_insert(0) += 1;
+
+        Ok(id)
+    }
+
+    fn remove_at(&mut self, index: usize) -> T {
+        let slot = &mut self.slots[index];
+        slot.generation = (slot.generation + 1) & GENERATION_MASK;
+        self.free_indices.push_back(index);
+        self.len -= 1;
+
+        let count = self.owner_counts.get_mut(&slot.owner).unwrap();
+        *count -= 1;
+        if *count == 0 {
+            self.owner_counts.remove(&slot.owner);
+        }
+
+        slot.item.take().unwrap()
     }
 
     pub fn remove_with_id(&mut self, id: isize) -> Option<T> {
-        self.inner.remove(&id)
+        self.index_of(id).map(|index| self.remove_at(index))
     }
 
     pub fn get_with_id(&self, id: isize) -> Option<&T> {
-        self.inner.get(&id)
+        self.index_of(id)
+            .and_then(move |index| self.slots[index].item.as_ref())
     }
 
     pub fn get_mut_with_id(&mut self, id: isize) -> Option<&mut T> {
-        self.inner.get_mut(&id)
+        match self.index_of(id) {
+            Some(index) => self.slots[index].item.as_mut(),
+            None => None,
+        }
+    }
+
+    /// Same as `get_with_id`, but explains why handle is invalid.
+    pub fn get(&self, id: isize) -> Result<&T> {
+        self.get_with_id(id).ok_or_else(|| self.lookup_error(id))
+    }
+
+    /// Same as `get_mut_with_id`, but explains why handle is invalid.
+    pub fn get_mut(&mut self, id: isize) -> Result<&mut T> {
+        match self.index_of(id) {
+            Some(index) => Ok(self.slots[index].item.as_mut().unwrap()),
+            None => Err(self.lookup_error(id)),
+        }
+    }
+
+    /// Same as `remove_with_id`, but explains why handle is invalid.
+    pub fn remove(&mut self, id: isize) -> Result<T> {
+        match self.index_of(id) {
+            Some(index) => Ok(self.remove_at(index)),
+            None => Err(self.lookup_error(id)),
+        }
+    }
+
+    pub fn owner_of(&self, id: isize) -> Option<usize> {
+        self.index_of(id).map(|index| self.slots[index].owner)
+    }
+
+    /// Returns ids of all items of the `owner`.
+    pub fn ids_owned_by(&self, owner: usize) -> Vec<isize> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter(|(_, slot)| slot.item.is_some() && slot.owner == owner)
+            .map(|(index, slot)| self.make_id(index, slot.generation))
+            .collect()
+    }
+
+    pub fn count_owned_by(&self, owner: usize) -> usize {
+        self.owner_counts.get(&owner).cloned().unwrap_or(0)
+    }
+
+    /// Returns number of items of every owner.
+    pub fn count_by_owner(&self) -> FnvHashMap<usize, usize> {
+        self.owner_counts.clone()
+    }
+
+    /// Returns number of items of every owner, created by every native.
+    pub fn count_by_site(&self) -> FnvHashMap<(usize, &'static str), usize> {
+        let mut counts = FnvHashMap::default();
+        for slot in self.slots.iter().filter(|slot| slot.item.is_some()) {
+            *counts.entry((slot.owner, slot.site)).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    /// Removes and returns all items of the `owner`.
+    pub fn remove_owned_by(&mut self, owner: usize) -> Vec<T> {
+        let mut items = vec![];
+        for index in 0..self.slots.len() {
+            if self.slots[index].item.is_some() && self.slots[index].owner == owner {
+                items.push(self.remove_at(index));
+            }
+        }
+        items
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns ids of all items. Useful, when items can be removed during iteration.
+    pub fn ids(&self) -> Vec<isize> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter(|(_, slot)| slot.item.is_some())
+            .map(|(index, slot)| self.make_id(index, slot.generation))
+            .collect()
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.slots.iter().filter_map(|slot| slot.item.as_ref())
+    }
+
+    /// Removes all items, for which predicate returns false.
+    pub fn retain<F: FnMut(isize, &mut T) -> bool>(&mut self, mut predicate: F) {
+        for index in 0..self.slots.len() {
+            let id = self.make_id(index, self.slots[index].generation);
+            let keep = match self.slots[index].item.as_mut() {
+                Some(item) => predicate(id, item),
+                None => true,
+            };
+
+            if !keep {
+                self.remove_at(index);
+            }
+        }
     }
 
     pub fn clear(&mut self) {
-        self.inner.clear();
+        self.retain(|_, _| false);
+    }
+}
+
+/// Type erased view of the `CellMap`, used to inspect handles of all types at once.
+pub trait Handles {
+    fn handle_type(&self) -> HandleType;
+    fn ids_owned_by(&self, owner: usize) -> Vec<isize>;
+    fn count_by_owner(&self) -> FnvHashMap<usize, usize>;
+    fn count_by_site(&self) -> FnvHashMap<(usize, &'static str), usize>;
+}
+
+impl<T> Handles for CellMap<T> {
+    fn handle_type(&self) -> HandleType {
+        self.handle_type
+    }
+
+    fn ids_owned_by(&self, owner: usize) -> Vec<isize> {
+        CellMap::ids_owned_by(self, owner)
+    }
+
+    fn count_by_owner(&self) -> FnvHashMap<usize, usize> {
+        CellMap::count_by_owner(self)
+    }
+
+    fn count_by_site(&self) -> FnvHashMap<(usize, &'static str), usize> {
+        CellMap::count_by_site(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generations() {
+        let mut map = CellMap::new(HandleType::JSONValue);
+
+        let first = map.insert_with_unique_id("first").unwrap();
+        assert!(first > 1);
+        assert_eq!(map.get(first).unwrap(), &"first");
+        assert_eq!(map.remove(first).unwrap(), "first");
+
+        // Slot isn't reused right away, and the old handle stays invalid.
+        let next = map.peek_id();
+        let second = map.insert_with_unique_id("second").unwrap();
+        assert_eq!(second, next);
+        assert_ne!(second, first);
+        assert_ne!(second & INDEX_MASK, first & INDEX_MASK);
+        assert!(map.get_with_id(first).is_none());
+        assert_eq!(map.ids(), [second]);
+        assert_eq!(map.len(), 1);
+
+        let error = map.get(first).err().unwrap().to_string();
+        assert_eq!(
+            error,
+            format!(
+                "Invalid JSON value handle {}, which was already destroyed",
+                first
+            )
+        );
+
+        map.clear();
+        assert!(map.is_empty());
+        assert!(map.get_with_id(second).is_none());
+    }
+
+    #[test]
+    fn test_slot_reuse() {
+        let mut map = CellMap::new(HandleType::Body);
+        let first = map.insert_with_unique_id(()).unwrap();
+        map.remove(first).unwrap();
+
+        // Hot slot doesn't come back after every removal, so generations wrap much later.
+        let mut seen = std::collections::HashSet::new();
+        for _ in 0..(MIN_FREE_SLOTS << GENERATION_BITS) - 1 {
+            let id = map.insert_with_unique_id(()).unwrap();
+            assert!(seen.insert(id));
+            assert_ne!(id, first);
+            map.remove(id).unwrap();
+        }
+        assert!(map.len() == 0 && map.slots.len() == MIN_FREE_SLOTS + 1);
+    }
+
+    #[test]
+    fn test_limit() {
+        let mut map = CellMap::new(HandleType::Body);
+        for _ in 0..MAX_ITEMS {
+            map.insert_with_unique_id(()).unwrap();
+        }
+
+        assert_eq!(
+            map.insert_owned(1, "grip_body_from_string", ())
+                .err()
+                .unwrap()
+                .to_string(),
+            format!(
+                "There are already {} body handles, which is the maximum",
+                MAX_ITEMS
+            )
+        );
+        assert!(map.next_id(1).is_err());
+
+        // Freed slots are reused, even if there are few of them.
+        let id = map.ids()[0];
+        map.remove(id).unwrap();
+        assert_ne!(map.insert_with_unique_id(()).unwrap(), id);
+    }
+
+    #[test]
+    fn test_wrong_type() {
+        let mut options = CellMap::new(HandleType::Options);
+        let mut bodies = CellMap::new(HandleType::Body);
+
+        let option = options.insert_with_unique_id(()).unwrap();
+        let body = bodies.insert_with_unique_id(()).unwrap();
+        assert_ne!(option, body);
+        assert!(option > 0 && option <= 0x7FFF_FFFF);
+
+        assert_eq!(
+            bodies.get(option).err().unwrap().to_string(),
+            format!("Invalid body handle {}, which is options handle", option)
+        );
+        assert_eq!(
+            bodies.get(-1).err().unwrap().to_string(),
+            "Invalid body handle -1"
+        );
+        assert_eq!(
+            bodies.get(body + 1).err().unwrap().to_string(),
+            format!("Invalid body handle {}", body + 1)
+        );
+    }
+
+    #[test]
+    fn test_owners() {
+        let mut map = CellMap::new(HandleType::Body);
+
+        let first = map.insert_owned(1, "native", "first").unwrap();
+        let second = map.insert_owned(2, "native", "second").unwrap();
+        let module = map.insert_with_unique_id("module").unwrap();
+
+        assert_eq!(map.owner_of(second), Some(2));
+        assert_eq!(map.owner_of(module), Some(0));
+        assert_eq!(map.ids_owned_by(1), [first]);
+        assert_eq!(map.count_by_owner()[&2], 1);
+
+        assert_eq!(map.remove_owned_by(2), ["second"]);
+        assert!(map.get_with_id(second).is_none());
+        assert_eq!(map.len(), 2);
+
+        // Reused slot belongs to the new owner.
+        let third = map.insert_owned(3, "native", "third").unwrap();
+        assert_eq!(map.owner_of(third), Some(3));
+        assert!(map.ids_owned_by(2).is_empty());
+    }
+
+    #[test]
+    fn test_quota() {
+        let mut map = CellMap::new(HandleType::JSONValue);
+        map.set_quota(Some(2));
+
+        let first = map.insert_owned(1, "grip_json_init_object", ()).unwrap();
+        map.insert_owned(1, "grip_json_parse_string", ()).unwrap();
+        map.insert_owned(1, "grip_json_parse_string", ())
+            .unwrap_err();
+        assert_eq!(
+            map.insert_owned(1, "grip_json_parse_string", ())
+                .err()
+                .unwrap()
+                .to_string(),
+            "Plugin already has 2 JSON value handles, \
+             which is the quota set by \"handles.max-handles-per-plugin\" in the grip.ini"
+        );
+
+        // Other plugins and the module have their own counts.
+        map.insert_owned(2, "grip_json_init_object", ()).unwrap();
+        map.insert_with_unique_id(()).unwrap();
+        map.insert_with_unique_id(()).unwrap();
+        map.insert_with_unique_id(()).unwrap();
+
+        map.remove(first).unwrap();
+        map.insert_owned(1, "grip_json_init_object", ()).unwrap();
+
+        let counts = map.count_by_site();
+        assert_eq!(counts[&(1, "grip_json_init_object")], 1);
+        assert_eq!(counts[&(1, "grip_json_parse_string")], 1);
+        assert_eq!(counts[&(2, "grip_json_init_object")], 1);
+        assert_eq!(counts[&(0, "")], 3);
+        assert_eq!(map.count_owned_by(1), 2);
     }
 }
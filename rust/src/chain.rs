@@ -0,0 +1,340 @@
+/*
+ * gRIP
+ * Copyright (c) 2018 Alik Aslanyan <cplusplus256@gmail.com>
+ *
+ *
+ *    This program is free software; you can redistribute it and/or modify it
+ *    under the terms of the GNU General Public License as published by the
+ *    Free Software Foundation; either version 3 of the License, or (at
+ *    your option) any later version.
+ *
+ *    This program is distributed in the hope that it will be useful, but
+ *    WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ *    General Public License for more details.
+ *
+ *    You should have received a copy of the GNU General Public License
+ *    along with this program; if not, write to the Free Software Foundation,
+ *    Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ *    In addition, as a special exception, the author gives permission to
+ *    link the code of this program with the Half-Life Game Engine ("HL
+ *    Engine") and Modified Game Libraries ("MODs") developed by Valve,
+ *    L.L.C ("Valve").  You must obey the GNU General Public License in all
+ *    respects for all of the code used other than the HL Engine and MODs
+ *    from Valve.  If you modify this file, you may extend this exception
+ *    to your version of the file, but you are not obligated to do so.  If
+ *    you do not wish to do so, delete this exception statement from your
+ *    version.
+ *
+ */
+
+//! Steps of the request chain, which take values from the earlier responses.
+//!
+//! URL, header values and body of the step can contain `{{N/json/pointer}}` references, which are
+//! replaced by the value at the JSON pointer in the body of the `N`th response (steps are counted from 0).
+//! `{{N}}` is replaced by the whole JSON value of the body, so referenced responses should be JSON.
+//! Strings are inserted without quotes, other values as JSON. Strings inserted into body are escaped
+//! as JSON strings, so they should be referenced inside quotes. Values inserted into the path of URL
+//! are percent-encoded as path segments, values inserted into the query are form-urlencoded.
+
+use crate::errors::*;
+use crate::networking_queue::{Request, RequestOptions, RequestType};
+use reqwest::header::HeaderValue;
+use serde_json::Value;
+use url::percent_encoding::{utf8_percent_encode, PATH_SEGMENT_ENCODE_SET};
+
+#[derive(Clone)]
+pub struct ChainStep {
+    pub http_type: RequestType,
+    pub uri: String,
+    pub body: Vec<u8>,
+    pub options: RequestOptions,
+}
+
+#[derive(Debug, PartialEq)]
+enum Part<'a> {
+    Text(&'a str),
+    Reference { step: usize, pointer: &'a str },
+}
+
+fn template_error<T: Into<String>>(t: T) -> Error {
+    ErrorKind::ChainTemplateError(t.into()).into()
+}
+
+fn parse_reference(reference: &str) -> Result<Part<'_>> {
+    let (step, pointer) = match reference.find('/') {
+        Some(position) => reference.split_at(position),
+        None => (reference, ""),
+    };
+
+    let step = step
+        .trim()
+        .parse()
+        .map_err(|_| template_error(format!("Invalid step in {{{{{}}}}}", reference)))?;
+
+    Ok(Part::Reference { step, pointer })
+}
+
+fn parse(template: &str) -> Result<Vec<Part<'_>>> {
+    let mut parts = vec![];
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        let end = rest[start..]
+            .find("}}")
+            .ok_or_else(|| template_error(format!("Unclosed {{{{ in \"{}\"", template)))?;
+
+        if start > 0 {
+            parts.push(Part::Text(&rest[..start]));
+        }
+        parts.push(parse_reference(&rest[start + 2..start + end])?);
+        rest = &rest[start + end + 2..];
+    }
+
+    if !rest.is_empty() {
+        parts.push(Part::Text(rest));
+    }
+
+    Ok(parts)
+}
+
+/// Steps referenced by the template.
+fn references(template: &str) -> Result<Vec<usize>> {
+    Ok(parse(template)?
+        .into_iter()
+        .filter_map(|part| match part {
+            Part::Reference { step, .. } => Some(step),
+            Part::Text(_) => None,
+        })
+        .collect())
+}
+
+fn templates(step: &ChainStep) -> impl Iterator<Item = &str> {
+    std::iter::once(step.uri.as_str())
+        .chain(
+            step.options
+                .headers
+                .values()
+                .filter_map(|value| value.to_str().ok()),
+        )
+        .chain(std::str::from_utf8(&step.body).ok())
+}
+
+/// Part of the request, which template belongs to.
+#[derive(Clone, Copy)]
+enum Target {
+    Url,
+    Header,
+    Body,
+}
+
+/// Responses of the earlier steps, parsed to JSON on the first use.
+struct Context<'a> {
+    bodies: &'a [Vec<u8>],
+    parsed: Vec<Option<Value>>,
+}
+
+impl<'a> Context<'a> {
+    fn new(bodies: &'a [Vec<u8>]) -> Self {
+        Context {
+            bodies,
+            parsed: vec![None; bodies.len()],
+        }
+    }
+
+    fn value(&mut self, step: usize, pointer: &str) -> Result<&Value> {
+        let body = self.bodies.get(step).ok_or_else(|| {
+            template_error(format!("Step {} has no response at this point", step))
+        })?;
+
+        if self.parsed[step].is_none() {
+            self.parsed[step] = Some(serde_json::from_slice(body).chain_err(|| {
+                template_error(format!("Response of the step {} is not JSON", step))
+            })?);
+        }
+
+        self.parsed[step]
+            .as_ref()
+            .unwrap()
+            .pointer(pointer)
+            .ok_or_else(|| {
+                template_error(format!(
+                    "Response of the step {} has no value at \"{}\"",
+                    step, pointer
+                ))
+            })
+    }
+
+    fn render(&mut self, template: &str, target: Target) -> Result<String> {
+        let mut rendered = String::with_capacity(template.len());
+        for part in parse(template)? {
+            let (step, pointer) = match part {
+                Part::Text(text) => {
+                    rendered.push_str(text);
+                    continue;
+                }
+                Part::Reference { step, pointer } => (step, pointer),
+            };
+
+            let value = match self.value(step, pointer)? {
+                Value::String(s) => match target {
+                    // Quotes of the JSON string are already in the template.
+                    Target::Body => {
+                        let escaped = Value::String(s.clone()).to_string();
+                        escaped[1..escaped.len() - 1].to_owned()
+                    }
+                    Target::Url | Target::Header => s.clone(),
+                },
+                value => value.to_string(),
+            };
+
+            match target {
+                // Encoded values never contain '?', so it comes from the template.
+                Target::Url if rendered.contains('?') => {
+                    rendered.extend(url::form_urlencoded::byte_serialize(value.as_bytes()))
+                }
+                Target::Url => {
+                    rendered.extend(utf8_percent_encode(&value, PATH_SEGMENT_ENCODE_SET))
+                }
+                Target::Header | Target::Body => rendered.push_str(&value),
+            }
+        }
+
+        Ok(rendered)
+    }
+}
+
+/// Makes request of the step, using bodies of the earlier responses.
+pub fn prepare(step: &ChainStep, bodies: &[Vec<u8>]) -> Result<Request> {
+    let mut context = Context::new(bodies);
+
+    let uri = context.render(&step.uri, Target::Url)?;
+    let uri = reqwest::Url::parse(&uri)
+        .chain_err(|| template_error(format!("Invalid URL \"{}\"", uri)))?;
+
+    let mut options = step.options.clone();
+    for value in options.headers.values_mut() {
+        if let Ok(template) = value.to_str() {
+            let rendered = context.render(template, Target::Header)?;
+            *value = HeaderValue::from_str(&rendered)
+                .chain_err(|| template_error(format!("Invalid header value \"{}\"", rendered)))?;
+        }
+    }
+
+    let body = match std::str::from_utf8(&step.body) {
+        Ok(template) => context.render(template, Target::Body)?.into_bytes(),
+        Err(_) => step.body.clone(),
+    };
+
+    Ok(Request {
+        http_type: step.http_type.clone(),
+        uri,
+        body,
+        options,
+    })
+}
+
+/// Checks, that steps reference only earlier steps. Returns request of the first step.
+pub fn validate(steps: &[ChainStep]) -> Result<Request> {
+    let first = steps
+        .first()
+        .ok_or_else(|| template_error("Chain has no steps"))?;
+
+    for (index, step) in steps.iter().enumerate() {
+        for template in templates(step) {
+            if let Some(reference) = references(template)?.into_iter().find(|r| *r >= index) {
+                return Err(template_error(format!(
+                    "Step {} references step {}, which is not sent before it",
+                    index, reference
+                )));
+            }
+        }
+    }
+
+    prepare(first, &[])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(uri: &str, body: &str) -> ChainStep {
+        ChainStep {
+            http_type: RequestType::Post,
+            uri: uri.to_owned(),
+            body: body.as_bytes().to_vec(),
+            options: RequestOptions::default(),
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse("a{{0/token}}b{{1}}").unwrap(),
+            vec![
+                Part::Text("a"),
+                Part::Reference {
+                    step: 0,
+                    pointer: "/token"
+                },
+                Part::Text("b"),
+                Part::Reference {
+                    step: 1,
+                    pointer: ""
+                },
+            ]
+        );
+        assert!(parse("{{0/token").is_err());
+        assert!(parse("{{token}}").is_err());
+    }
+
+    #[test]
+    fn test_prepare() {
+        let responses =
+            vec![br#"{"user": {"id": 5, "name": "a b&c"}, "token": "se\"cr\\et"}"#.to_vec()];
+        let mut templated = step(
+            "http://localhost/users/{{0/user/name}}/{{0/user/id}}?name={{0/user/name}}",
+            r#"{"token": "{{0/token}}", "user": {{0/user}}}"#,
+        );
+        templated.options.headers.insert(
+            reqwest::header::AUTHORIZATION,
+            HeaderValue::from_static("Bearer {{0/token}}"),
+        );
+
+        let request = prepare(&templated, &responses).unwrap();
+        assert_eq!(
+            request.uri.as_str(),
+            "http://localhost/users/a%20b&c/5?name=a+b%26c"
+        );
+        assert_eq!(
+            request.options.headers[reqwest::header::AUTHORIZATION],
+            r#"Bearer se"cr\et"#
+        );
+
+        // Strings can't break out of the JSON string in the body.
+        let body: Value = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(body["token"], r#"se"cr\et"#);
+        assert_eq!(body["user"]["name"], "a b&c");
+        assert_eq!(body.as_object().unwrap().len(), 2);
+
+        assert!(prepare(&step("http://localhost/{{0/missing}}", ""), &responses).is_err());
+        assert!(prepare(&step("http://localhost/{{0}}", ""), &[b"not json".to_vec()]).is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(&[]).is_err());
+        assert!(validate(&[step("http://localhost/{{0/id}}", "")]).is_err());
+        assert!(validate(&[
+            step("http://localhost/", ""),
+            step("http://localhost/", "{{1/id}}")
+        ])
+        .is_err());
+        assert!(validate(&[
+            step("http://localhost/", ""),
+            step("http://localhost/{{0/id}}", "{{0}}")
+        ])
+        .is_ok());
+    }
+}
@@ -0,0 +1,299 @@
+/*
+ * gRIP
+ * Copyright (c) 2018 Alik Aslanyan <cplusplus256@gmail.com>
+ *
+ *
+ *    This program is free software; you can redistribute it and/or modify it
+ *    under the terms of the GNU General Public License as published by the
+ *    Free Software Foundation; either version 3 of the License, or (at
+ *    your option) any later version.
+ *
+ *    This program is distributed in the hope that it will be useful, but
+ *    WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ *    General Public License for more details.
+ *
+ *    You should have received a copy of the GNU General Public License
+ *    along with this program; if not, write to the Free Software Foundation,
+ *    Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ *    In addition, as a special exception, the author gives permission to
+ *    link the code of this program with the Half-Life Game Engine ("HL
+ *    Engine") and Modified Game Libraries ("MODs") developed by Valve,
+ *    L.L.C ("Valve").  You must obey the GNU General Public License in all
+ *    respects for all of the code used other than the HL Engine and MODs
+ *    from Valve.  If you modify this file, you may extend this exception
+ *    to your version of the file, but you are not obligated to do so.  If
+ *    you do not wish to do so, delete this exception statement from your
+ *    version.
+ *
+ */
+
+use crate::errors::*;
+use std::error::Error as StdError;
+use std::io;
+
+/// Coarse classification of the request failure, exposed to Pawn as `GripErrorKind`.
+/// Discriminants are part of the public API and should never be reordered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorCategory {
+    Unknown = 1,
+    Cancelled = 2,
+    Timeout = 3,
+    InvalidURL = 4,
+    DNS = 5,
+    ConnectionRefused = 6,
+    ConnectionReset = 7,
+    Connect = 8,
+    TLS = 9,
+    Redirect = 10,
+    Protocol = 11,
+    Decode = 12,
+    IO = 13,
+    BodyTooLarge = 14,
+}
+
+impl ErrorCategory {
+    pub fn from_id(id: isize) -> Option<ErrorCategory> {
+        match id {
+            1 => Some(ErrorCategory::Unknown),
+            2 => Some(ErrorCategory::Cancelled),
+            3 => Some(ErrorCategory::Timeout),
+            4 => Some(ErrorCategory::InvalidURL),
+            5 => Some(ErrorCategory::DNS),
+            6 => Some(ErrorCategory::ConnectionRefused),
+            7 => Some(ErrorCategory::ConnectionReset),
+            8 => Some(ErrorCategory::Connect),
+            9 => Some(ErrorCategory::TLS),
+            10 => Some(ErrorCategory::Redirect),
+            11 => Some(ErrorCategory::Protocol),
+            12 => Some(ErrorCategory::Decode),
+            13 => Some(ErrorCategory::IO),
+            14 => Some(ErrorCategory::BodyTooLarge),
+            _ => None,
+        }
+    }
+
+    /// Stable name, which is used as the label of the metrics.
+    pub fn name(self) -> &'static str {
+        match self {
+            ErrorCategory::Unknown => "unknown",
+            ErrorCategory::Cancelled => "cancelled",
+            ErrorCategory::Timeout => "timeout",
+            ErrorCategory::InvalidURL => "invalid_url",
+            ErrorCategory::DNS => "dns",
+            ErrorCategory::ConnectionRefused => "connection_refused",
+            ErrorCategory::ConnectionReset => "connection_reset",
+            ErrorCategory::Connect => "connect",
+            ErrorCategory::TLS => "tls",
+            ErrorCategory::Redirect => "redirect",
+            ErrorCategory::Protocol => "protocol",
+            ErrorCategory::Decode => "decode",
+            ErrorCategory::IO => "io",
+            ErrorCategory::BodyTooLarge => "body_too_large",
+        }
+    }
+}
+
+impl<'a> From<&'a Error> for ErrorCategory {
+    fn from(error: &'a Error) -> Self {
+        match error.kind() {
+            ErrorKind::RequestCancelled => ErrorCategory::Cancelled,
+            ErrorKind::RequestTimeout => ErrorCategory::Timeout,
+            ErrorKind::ResponseBodyTooLarge(_) => ErrorCategory::BodyTooLarge,
+            ErrorKind::DecodingError(_) => ErrorCategory::Decode,
+            ErrorKind::HTTPError(e) => ErrorCategory::from(e),
+            ErrorKind::IOError(e) => classify_io(e, true),
+            ErrorKind::WebSocketError(e) => classify_websocket(e),
+            ErrorKind::HostResolutionError(_) => ErrorCategory::DNS,
+            ErrorKind::WebSocketURLError(_) => ErrorCategory::InvalidURL,
+            // Rejected upgrades and bad accept keys have no narrower category than the protocol.
+            ErrorKind::WebSocketHandshakeError(e) => match classify_websocket(e) {
+                ErrorCategory::Unknown => ErrorCategory::Protocol,
+                category => category,
+            },
+            ErrorKind::MockFailure(kind) => *kind,
+            _ => ErrorCategory::Unknown,
+        }
+    }
+}
+
+impl<'a> From<&'a reqwest::Error> for ErrorCategory {
+    fn from(error: &'a reqwest::Error) -> Self {
+        if error.is_redirect() {
+            ErrorCategory::Redirect
+        } else if error.is_timeout() {
+            ErrorCategory::Timeout
+        } else if let Some(inner) = error.get_ref() {
+            classify_source(inner, false)
+        } else {
+            ErrorCategory::Unknown
+        }
+    }
+}
+
+fn classify_source(
+    error: &(dyn StdError + Send + Sync + 'static),
+    connecting: bool,
+) -> ErrorCategory {
+    if error.downcast_ref::<reqwest::UrlError>().is_some() {
+        ErrorCategory::InvalidURL
+    } else if error.downcast_ref::<native_tls::Error>().is_some() {
+        ErrorCategory::TLS
+    } else if let Some(e) = error.downcast_ref::<hyper::Error>() {
+        if e.is_parse() {
+            ErrorCategory::Protocol
+        } else if e.is_canceled() || e.is_closed() {
+            ErrorCategory::ConnectionReset
+        } else if let Some(cause) = e.cause2() {
+            classify_source(cause, e.is_connect())
+        } else if e.is_connect() {
+            ErrorCategory::Connect
+        } else {
+            ErrorCategory::Unknown
+        }
+    } else if let Some(e) = error.downcast_ref::<io::Error>() {
+        classify_io(e, connecting)
+    } else if connecting {
+        ErrorCategory::Connect
+    } else {
+        ErrorCategory::Unknown
+    }
+}
+
+fn classify_io(error: &io::Error, connecting: bool) -> ErrorCategory {
+    match error.kind() {
+        io::ErrorKind::ConnectionRefused => ErrorCategory::ConnectionRefused,
+        io::ErrorKind::ConnectionReset
+        | io::ErrorKind::ConnectionAborted
+        | io::ErrorKind::BrokenPipe
+        | io::ErrorKind::UnexpectedEof => ErrorCategory::ConnectionReset,
+        io::ErrorKind::TimedOut => ErrorCategory::Timeout,
+        io::ErrorKind::InvalidData => ErrorCategory::Decode,
+        _ => match error.get_ref() {
+            // Connectors wrap TLS failures into `io::Error`.
+            Some(inner) if inner.downcast_ref::<native_tls::Error>().is_some() => {
+                ErrorCategory::TLS
+            }
+            _ if is_resolver_error(error) => ErrorCategory::DNS,
+            // Resolvers on Unix and trust-dns through reqwest leave only the message, so match it
+            // as a last resort.
+            _ if connecting && looks_like_dns_failure(&error.to_string()) => ErrorCategory::DNS,
+            _ if connecting => ErrorCategory::Connect,
+            _ => ErrorCategory::IO,
+        },
+    }
+}
+
+fn classify_websocket(error: &tungstenite::Error) -> ErrorCategory {
+    match error {
+        tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed => {
+            ErrorCategory::ConnectionReset
+        }
+        tungstenite::Error::Io(e) => classify_io(e, false),
+        tungstenite::Error::Tls(_) => ErrorCategory::TLS,
+        tungstenite::Error::Url(_) => ErrorCategory::InvalidURL,
+        tungstenite::Error::Utf8 => ErrorCategory::Decode,
+        tungstenite::Error::Capacity(_) => ErrorCategory::BodyTooLarge,
+        tungstenite::Error::Protocol(_) | tungstenite::Error::Http(_) => ErrorCategory::Protocol,
+        _ => ErrorCategory::Unknown,
+    }
+}
+
+/// Windows reports resolver failures with the `WSAHOST_NOT_FOUND`..`WSANO_DATA` error codes.
+fn is_resolver_error(error: &io::Error) -> bool {
+    match error.raw_os_error() {
+        Some(11001..=11004) => cfg!(windows),
+        _ => false,
+    }
+}
+
+fn looks_like_dns_failure(message: &str) -> bool {
+    let message = message.to_lowercase();
+    [
+        "failed to lookup address",
+        "no record found",
+        "name or service not known",
+        "nodename nor servname",
+        "temporary failure in name resolution",
+    ]
+    .iter()
+    .any(|pattern| message.contains(pattern))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify_io() {
+        let refused = io::Error::new(io::ErrorKind::ConnectionRefused, "refused");
+        assert_eq!(
+            classify_io(&refused, true),
+            ErrorCategory::ConnectionRefused
+        );
+
+        let dns = io::Error::new(
+            io::ErrorKind::Other,
+            "failed to lookup address information: Name or service not known",
+        );
+        assert_eq!(classify_io(&dns, true), ErrorCategory::DNS);
+        assert_eq!(classify_io(&dns, false), ErrorCategory::IO);
+
+        let no_record = io::Error::new(io::ErrorKind::Other, "no record found for name: example.");
+        assert_eq!(classify_io(&no_record, true), ErrorCategory::DNS);
+
+        let host_not_found = io::Error::from_raw_os_error(11001);
+        assert_eq!(
+            classify_io(&host_not_found, false) == ErrorCategory::DNS,
+            cfg!(windows)
+        );
+
+        let other = io::Error::new(io::ErrorKind::Other, "proxy authentication required");
+        assert_eq!(classify_io(&other, true), ErrorCategory::Connect);
+
+        let decode = io::Error::new(io::ErrorKind::InvalidData, "corrupt deflate stream");
+        assert_eq!(classify_io(&decode, false), ErrorCategory::Decode);
+    }
+
+    #[test]
+    fn test_classify_chain() {
+        let cancelled: Error = ErrorKind::RequestCancelled.into();
+        assert_eq!(ErrorCategory::from(&cancelled), ErrorCategory::Cancelled);
+
+        let timeout: Error = ErrorKind::RequestTimeout.into();
+        assert_eq!(ErrorCategory::from(&timeout), ErrorCategory::Timeout);
+
+        let too_large: Error = ErrorKind::ResponseBodyTooLarge(1).into();
+        assert_eq!(ErrorCategory::from(&too_large), ErrorCategory::BodyTooLarge);
+
+        let unresolved: Error = ErrorKind::HostResolutionError("example.invalid".into()).into();
+        assert_eq!(ErrorCategory::from(&unresolved), ErrorCategory::DNS);
+
+        let rejected: Error = ErrorKind::WebSocketHandshakeError(tungstenite::Error::Http(
+            tungstenite::http::StatusCode::FORBIDDEN,
+        ))
+        .into();
+        assert_eq!(ErrorCategory::from(&rejected), ErrorCategory::Protocol);
+
+        let refused: Error = ErrorKind::WebSocketHandshakeError(tungstenite::Error::Io(
+            io::Error::new(io::ErrorKind::ConnectionRefused, "refused"),
+        ))
+        .into();
+        assert_eq!(
+            ErrorCategory::from(&refused),
+            ErrorCategory::ConnectionRefused
+        );
+
+        let bad_accept: Error = ErrorKind::WebSocketHandshakeError(tungstenite::Error::Protocol(
+            "Key mismatch in Sec-WebSocket-Accept".into(),
+        ))
+        .into();
+        assert_eq!(ErrorCategory::from(&bad_accept), ErrorCategory::Protocol);
+
+        assert_eq!(
+            ErrorCategory::from(&ffi_error("Invalid handle")),
+            ErrorCategory::Unknown
+        );
+    }
+}
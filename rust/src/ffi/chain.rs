@@ -0,0 +1,162 @@
+/*
+ * gRIP
+ * Copyright (c) 2018 Alik Aslanyan <cplusplus256@gmail.com>
+ *
+ *
+ *    This program is free software; you can redistribute it and/or modify it
+ *    under the terms of the GNU General Public License as published by the
+ *    Free Software Foundation; either version 3 of the License, or (at
+ *    your option) any later version.
+ *
+ *    This program is distributed in the hope that it will be useful, but
+ *    WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ *    General Public License for more details.
+ *
+ *    You should have received a copy of the GNU General Public License
+ *    along with this program; if not, write to the Free Software Foundation,
+ *    Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ *    In addition, as a special exception, the author gives permission to
+ *    link the code of this program with the Half-Life Game Engine ("HL
+ *    Engine") and Modified Game Libraries ("MODs") developed by Valve,
+ *    L.L.C ("Valve").  You must obey the GNU General Public License in all
+ *    respects for all of the code used other than the HL Engine and MODs
+ *    from Valve.  If you modify this file, you may extend this exception
+ *    to your version of the file, but you are not obligated to do so.  If
+ *    you do not wish to do so, delete this exception statement from your
+ *    version.
+ *
+ */
+
+//! Chains of requests, where later requests take values from the earlier responses.
+
+use super::*;
+
+use crate::chain::ChainStep;
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_create_request_chain(amx: *const c_void) -> Cell {
+    try_and_log_ffi!(
+        amx,
+        get_module_mut().request_chains_handles.insert_owned(
+            owner(amx),
+            "grip_create_request_chain",
+            vec![]
+        )
+    )
+}
+
+/// Appends step to the chain. URI, header values and body may contain `{{N/json/pointer}}` references.
+#[no_mangle]
+pub unsafe extern "C" fn grip_request_chain_add(
+    amx: *const c_void,
+    chain: Cell,
+    uri: *const c_char,
+    body_handle: Cell,
+    request_type: Cell,
+    options_handle: Cell,
+) -> Cell {
+    try_and_log_ffi!(amx, get_module().request_chains_handles.get(chain));
+    let http_type = try_and_log_ffi!(amx, parse_request_type(request_type));
+    let uri = try_and_log_ffi!(
+        amx,
+        str_from_ptr(uri).map_err(|_| ffi_error("URI is not UTF-8"))
+    );
+
+    let body = if body_handle == -1 {
+        vec![]
+    } else {
+        try_and_log_ffi!(amx, get_module().bodies_handles.get(body_handle)).clone()
+    };
+
+    let options = if options_handle == -1 {
+        RequestOptions::default()
+    } else {
+        try_and_log_ffi!(amx, get_module().options_handles.get(options_handle)).clone()
+    };
+
+    let steps = get_module_mut()
+        .request_chains_handles
+        .get_mut_with_id(chain)
+        .unwrap();
+    steps.push(ChainStep {
+        http_type,
+        uri: uri.to_owned(),
+        body,
+        options,
+    });
+
+    steps.len() as Cell
+}
+
+/// Sends steps of the chain in the worker of the queue. Handler is called once, like the handler of `grip_request`.
+#[no_mangle]
+pub unsafe extern "C" fn grip_request_chain_send(
+    amx: *const c_void,
+    chain: Cell,
+    forward_id: Cell,
+    handler: Option<extern "C" fn(forward_handle: Cell, user_data: Cell) -> c_void>,
+    user_data: Cell,
+    queue: Cell,
+) -> Cell {
+    let steps = try_and_log_ffi!(amx, get_module().request_chains_handles.get(chain)).clone();
+    let queue = try_and_log_ffi!(amx, self::queue::get_queue_mut(queue));
+    // Chain can't be taken back, once it was sent.
+    let next_cancellation_id =
+        try_and_log_ffi!(amx, get_module().cancellations_handles.next_id(owner(amx)));
+
+    let cancellation = try_and_log_ffi!(
+        amx,
+        queue.send_chain(steps, move |step, response| {
+            let succeeded = response.is_ok();
+            if get_module_mut()
+                .silenced_requests
+                .remove(&next_cancellation_id)
+            {
+                self::group::request_finished(next_cancellation_id, succeeded);
+                return;
+            }
+            get_module_mut().current_response = Some(response);
+            get_module_mut().current_chain_step = Some(step);
+
+            handler.unwrap()(forward_id, user_data);
+
+            get_module_mut()
+                .cancellations_handles
+                .remove_with_id(next_cancellation_id);
+
+            get_module_mut().current_response = None;
+            get_module_mut().current_chain_step = None;
+
+            self::group::request_finished(next_cancellation_id, succeeded);
+        })
+    );
+
+    try_and_log_ffi!(
+        amx,
+        get_module_mut().cancellations_handles.insert_owned(
+            owner(amx),
+            "grip_request_chain_send",
+            cancellation
+        )
+    )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_destroy_request_chain(amx: *const c_void, chain: Cell) -> Cell {
+    try_and_log_ffi!(amx, get_module_mut().request_chains_handles.remove(chain));
+
+    1
+}
+
+/// Index of the step, whose response or error is passed to the chain handler.
+#[no_mangle]
+pub unsafe extern "C" fn grip_get_response_chain_step(amx: *const c_void) -> Cell {
+    try_and_log_ffi!(
+        amx,
+        get_module().current_chain_step.chain_err(|| ffi_error(
+            "Chain step can only be received in the request chain callback"
+        ))
+    ) as Cell
+}
@@ -0,0 +1,617 @@
+/*
+ * gRIP
+ * Copyright (c) 2018 Alik Aslanyan <cplusplus256@gmail.com>
+ *
+ *
+ *    This program is free software; you can redistribute it and/or modify it
+ *    under the terms of the GNU General Public License as published by the
+ *    Free Software Foundation; either version 3 of the License, or (at
+ *    your option) any later version.
+ *
+ *    This program is distributed in the hope that it will be useful, but
+ *    WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ *    General Public License for more details.
+ *
+ *    You should have received a copy of the GNU General Public License
+ *    along with this program; if not, write to the Free Software Foundation,
+ *    Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ *    In addition, as a special exception, the author gives permission to
+ *    link the code of this program with the Half-Life Game Engine ("HL
+ *    Engine") and Modified Game Libraries ("MODs") developed by Valve,
+ *    L.L.C ("Valve").  You must obey the GNU General Public License in all
+ *    respects for all of the code used other than the HL Engine and MODs
+ *    from Valve.  If you modify this file, you may extend this exception
+ *    to your version of the file, but you are not obligated to do so.  If
+ *    you do not wish to do so, delete this exception statement from your
+ *    version.
+ *
+ */
+
+//! Typed model of the grip.ini. Every setting has a default, so missing config or key isn't an error.
+//!
+//! Values may reference environment variables as `${NAME}`. Variables named `GRIP_<SECTION>_<KEY>`,
+//! e.g. `GRIP_QUEUE_CALLBACKS_PER_FRAME`, override keys of the config.
+
+use super::handles::HandleSettings;
+use super::schedule::SchedulerSettings;
+use super::ini::Ini;
+
+use crate::http_server::ServerConfig;
+use crate::logger::LoggerConfig;
+use crate::networking_queue::{DrainPolicy, ResponseLimits};
+use crate::websocket::WebSocketOptions;
+
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Settings, which can be applied again without the map change.
+pub struct RuntimeSettings {
+    pub callbacks_per_frame: usize,
+    pub microseconds_delay_between_attempts: usize,
+    pub response_limits: ResponseLimits,
+    pub websocket_options: WebSocketOptions,
+    pub drain_policy: DrainPolicy,
+    pub handle_settings: HandleSettings,
+    pub scheduler_settings: SchedulerSettings,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TransportType {
+    Network,
+    Mock,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HarMode {
+    Off,
+    Record,
+    Replay,
+}
+
+pub struct Config {
+    pub runtime: RuntimeSettings,
+    /// `None`, when the embedded HTTP server is disabled.
+    pub server: Option<ServerConfig>,
+    /// `None`, when the metrics endpoint is disabled.
+    pub metrics_bind_address: Option<SocketAddr>,
+    /// `None`, when there is no [log] section.
+    pub log: Option<LoggerConfig>,
+    pub transport: TransportType,
+    pub har_mode: HarMode,
+    pub har_path: Option<PathBuf>,
+}
+
+impl Config {
+    /// Loads config from the file. Invalid settings are reported and replaced by their defaults.
+    pub fn load(path: &str) -> (Config, Vec<String>) {
+        Config::load_with_env(path, &|name| std::env::var(name).ok())
+    }
+
+    pub fn load_with_env(
+        path: &str,
+        env: &dyn Fn(&str) -> Option<String>,
+    ) -> (Config, Vec<String>) {
+        let (ini, error) = match Ini::load_from_file(path) {
+            Ok(ini) => (ini, None),
+            Err(e) => (
+                Ini::new(),
+                Some(format!("Can't parse/open grip config {}: {}", path, e)),
+            ),
+        };
+
+        // Relative paths are resolved against the directory of the grip.ini.
+        let directory = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+        let (config, mut errors) = Config::parse(&ini, directory, env);
+        errors.splice(0..0, error);
+
+        (config, errors)
+    }
+
+    pub fn parse(
+        ini: &Ini,
+        directory: &Path,
+        env: &dyn Fn(&str) -> Option<String>,
+    ) -> (Config, Vec<String>) {
+        let mut reader = ConfigReader::new(ini, env);
+
+        let config = Config {
+            runtime: parse_runtime_settings(&mut reader),
+            server: parse_server(&mut reader),
+            metrics_bind_address: parse_metrics(&mut reader),
+            log: parse_log(&mut reader, directory),
+            transport: match reader
+                .get("transport", "type")
+                .as_ref()
+                .map(|value| value.as_str())
+            {
+                None | Some("network") => TransportType::Network,
+                Some("mock") => TransportType::Mock,
+                Some(value) => {
+                    reader.invalid("transport", "type", value, "expected network or mock");
+                    TransportType::Network
+                }
+            },
+            har_mode: match reader
+                .get("har", "mode")
+                .as_ref()
+                .map(|value| value.as_str())
+            {
+                None | Some("off") => HarMode::Off,
+                Some("record") => HarMode::Record,
+                Some("replay") => HarMode::Replay,
+                Some(value) => {
+                    reader.invalid("har", "mode", value, "expected off, record or replay");
+                    // Don't let QA accidentally hit real backends, every request fails instead.
+                    HarMode::Replay
+                }
+            },
+            har_path: reader.get("har", "path").map(|value| directory.join(value)),
+        };
+
+        if config.har_mode != HarMode::Off && config.har_path.is_none() {
+            reader.error("Missing \"har.path\" key in the grip.ini config".to_owned());
+        }
+
+        (config, reader.finish())
+    }
+}
+
+fn parse_runtime_settings(reader: &mut ConfigReader) -> RuntimeSettings {
+    let default_limits = ResponseLimits::default();
+    let default_websocket_options = WebSocketOptions::default();
+    let default_drain_policy = DrainPolicy::default();
+    let default_handle_settings = HandleSettings::default();
+    let default_scheduler_settings = SchedulerSettings::default();
+
+    let websocket_options = WebSocketOptions {
+        min_reconnect_delay: reader.milliseconds(
+            "websocket",
+            "min-reconnect-delay-milliseconds",
+            default_websocket_options.min_reconnect_delay,
+        ),
+        max_reconnect_delay: reader.milliseconds(
+            "websocket",
+            "max-reconnect-delay-milliseconds",
+            default_websocket_options.max_reconnect_delay,
+        ),
+        ..default_websocket_options
+    };
+    if websocket_options.min_reconnect_delay > websocket_options.max_reconnect_delay {
+        reader.error(
+            "Invalid [websocket] section in the grip.ini config: \
+             \"min-reconnect-delay-milliseconds\" is greater than \"max-reconnect-delay-milliseconds\""
+                .to_owned(),
+        );
+    }
+
+    RuntimeSettings {
+        callbacks_per_frame: reader.parse_at_least("queue", "callbacks-per-frame", 1, 1),
+        microseconds_delay_between_attempts: reader.parse(
+            "queue",
+            "microseconds-delay-between-attempts",
+            33000,
+        ),
+        response_limits: ResponseLimits {
+            max_body_size: reader.parse_at_least(
+                "queue",
+                "max-response-body-size",
+                1,
+                default_limits.max_body_size,
+            ),
+            max_decompressed_body_size: reader.parse_at_least(
+                "queue",
+                "max-decompressed-body-size",
+                1,
+                default_limits.max_decompressed_body_size,
+            ),
+        },
+        websocket_options,
+        drain_policy: DrainPolicy {
+            timeout: reader.milliseconds(
+                "shutdown",
+                "drain-timeout-milliseconds",
+                default_drain_policy.timeout,
+            ),
+            only_must_complete: reader.bool(
+                "shutdown",
+                "drain-only-must-complete",
+                default_drain_policy.only_must_complete,
+            ),
+            run_callbacks: reader.bool(
+                "shutdown",
+                "run-callbacks",
+                default_drain_policy.run_callbacks,
+            ),
+        },
+        handle_settings: HandleSettings {
+            warn_leaks_on_map_end: reader.bool(
+                "handles",
+                "warn-leaks-on-map-end",
+                default_handle_settings.warn_leaks_on_map_end,
+            ),
+            // Zero disables the limit.
+            max_handles_per_plugin: Some(reader.parse("handles", "max-handles-per-plugin", 0))
+                .filter(|&max| max != 0),
+            warn_threshold: Some(reader.parse("handles", "warn-threshold", 0))
+                .filter(|&threshold| threshold != 0),
+            warn_interval: reader.seconds(
+                "handles",
+                "warn-interval-seconds",
+                default_handle_settings.warn_interval,
+            ),
+        },
+        scheduler_settings: SchedulerSettings {
+            min_interval: reader.milliseconds(
+                "scheduler",
+                "min-interval-milliseconds",
+                default_scheduler_settings.min_interval,
+            ),
+        },
+    }
+}
+
+fn parse_server(reader: &mut ConfigReader) -> Option<ServerConfig> {
+    // Keys are validated, even if the server is disabled.
+    let enabled = reader.bool("server", "enabled", false);
+
+    let default_config = ServerConfig::default();
+    let config = ServerConfig {
+        bind_address: reader.parse("server", "bind-address", default_config.bind_address),
+        auth_token: reader.get("server", "auth-token"),
+        allowed_routes: reader.list("server", "allowed-routes", default_config.allowed_routes),
+        max_request_body_size: reader.parse(
+            "server",
+            "max-request-body-size",
+            default_config.max_request_body_size,
+        ),
+        reply_timeout: reader.milliseconds(
+            "server",
+            "reply-timeout-milliseconds",
+            default_config.reply_timeout,
+        ),
+    };
+
+    if enabled {
+        Some(config)
+    } else {
+        None
+    }
+}
+
+fn parse_metrics(reader: &mut ConfigReader) -> Option<SocketAddr> {
+    let enabled = reader.bool("metrics", "enabled", false);
+    let bind_address = reader.parse("metrics", "bind-address", ([127, 0, 0, 1], 9100).into());
+
+    if enabled {
+        Some(bind_address)
+    } else {
+        None
+    }
+}
+
+fn parse_log(reader: &mut ConfigReader, directory: &Path) -> Option<LoggerConfig> {
+    let default_config = LoggerConfig::default();
+    let config = LoggerConfig {
+        level: reader.parse("log", "level", default_config.level),
+        file: reader.get("log", "file").map(|value| directory.join(value)),
+        max_file_size: reader.parse("log", "max-file-size", default_config.max_file_size),
+        max_files: reader.parse("log", "max-files", default_config.max_files),
+        hosts: reader.list("log", "hosts", default_config.hosts),
+        exclude_hosts: reader.list("log", "exclude-hosts", default_config.exclude_hosts),
+        log_requests: reader.bool("log", "log-requests", default_config.log_requests),
+        log_bodies: reader.bool("log", "log-bodies", default_config.log_bodies),
+        max_logged_body_size: reader.parse(
+            "log",
+            "max-logged-body-size",
+            default_config.max_logged_body_size,
+        ),
+        redact_headers: reader.list("log", "redact-headers", default_config.redact_headers),
+        redact_fields: reader.list("log", "redact-fields", default_config.redact_fields),
+    };
+
+    // Checked after the keys were read, so that overridden keys are known.
+    if reader.has_section("log") {
+        Some(config)
+    } else {
+        None
+    }
+}
+
+/// Name of the environment variable, which overrides the key.
+pub fn override_variable(section: &str, key: &str) -> String {
+    format!("GRIP_{}_{}", section, key)
+        .to_uppercase()
+        .replace('-', "_")
+}
+
+/// Replaces `${NAME}` with the value of the environment variable.
+fn interpolate(value: &str, env: &dyn Fn(&str) -> Option<String>) -> Result<String, String> {
+    let mut result = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        result.push_str(&rest[..start]);
+
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| format!("unterminated \"${{\" in {}", value))?;
+        let name = &rest[start + 2..start + end];
+        result.push_str(
+            &env(name).ok_or_else(|| format!("environment variable {} is not set", name))?,
+        );
+
+        rest = &rest[start + end + 1..];
+    }
+    result.push_str(rest);
+
+    Ok(result)
+}
+
+/// Reads keys of the config and reports every invalid one, instead of failing on the first.
+pub struct ConfigReader<'a> {
+    ini: &'a Ini,
+    env: &'a dyn Fn(&str) -> Option<String>,
+    known_keys: HashSet<(String, String)>,
+    errors: Vec<String>,
+}
+
+impl<'a> ConfigReader<'a> {
+    pub fn new(ini: &'a Ini, env: &'a dyn Fn(&str) -> Option<String>) -> Self {
+        ConfigReader {
+            ini,
+            env,
+            known_keys: HashSet::new(),
+            errors: vec![],
+        }
+    }
+
+    /// Trimmed value of the key after the overrides and interpolation.
+    /// Empty value is the same as missing key.
+    pub fn get(&mut self, section: &str, key: &str) -> Option<String> {
+        self.known_keys.insert((section.to_owned(), key.to_owned()));
+
+        let value = (self.env)(&override_variable(section, key)).or_else(|| {
+            self.ini
+                .section(Some(section.to_owned()))?
+                .get(key)
+                .cloned()
+        })?;
+
+        match interpolate(value.trim(), self.env) {
+            Ok(value) => Some(value).filter(|value| !value.is_empty()),
+            Err(e) => {
+                self.invalid(section, key, &value, &e);
+                None
+            }
+        }
+    }
+
+    /// Section is present in the config, or some of its keys are overridden by the environment.
+    pub fn has_section(&self, section: &str) -> bool {
+        self.ini.section(Some(section.to_owned())).is_some()
+            || self.known_keys.iter().any(|(known_section, key)| {
+                known_section == section && (self.env)(&override_variable(section, key)).is_some()
+            })
+    }
+
+    /// Records error, which isn't related to the single key, e.g. conflict between two keys.
+    pub fn error(&mut self, message: String) {
+        self.errors.push(message);
+    }
+
+    pub fn invalid(&mut self, section: &str, key: &str, value: &str, reason: &str) {
+        self.errors.push(format!(
+            "Invalid \"{}.{}\" key in the grip.ini config: {}: {}",
+            section, key, value, reason
+        ));
+    }
+
+    fn parse_value<T: FromStr>(&mut self, section: &str, key: &str, value: &str) -> Option<T>
+    where
+        T::Err: std::fmt::Display,
+    {
+        match value.parse() {
+            Ok(value) => Some(value),
+            Err(e) => {
+                self.invalid(section, key, value, &e.to_string());
+                None
+            }
+        }
+    }
+
+    pub fn parse<T: FromStr>(&mut self, section: &str, key: &str, default: T) -> T
+    where
+        T::Err: std::fmt::Display,
+    {
+        match self.get(section, key) {
+            Some(value) => self.parse_value(section, key, &value).unwrap_or(default),
+            None => default,
+        }
+    }
+
+    /// Same as `parse`, but rejects values lower than `min`.
+    pub fn parse_at_least(
+        &mut self,
+        section: &str,
+        key: &str,
+        min: usize,
+        default: usize,
+    ) -> usize {
+        let value = self.parse(section, key, default);
+        if value < min {
+            self.invalid(
+                section,
+                key,
+                &value.to_string(),
+                &format!("should be at least {}", min),
+            );
+            default
+        } else {
+            value
+        }
+    }
+
+    pub fn bool(&mut self, section: &str, key: &str, default: bool) -> bool {
+        match self.get(section, key).as_ref().map(|value| value.as_str()) {
+            Some("true") | Some("1") => true,
+            Some("false") | Some("0") => false,
+            Some(value) => {
+                self.invalid(section, key, value, "expected true or false");
+                default
+            }
+            None => default,
+        }
+    }
+
+    /// Comma separated list.
+    pub fn list(&mut self, section: &str, key: &str, default: Vec<String>) -> Vec<String> {
+        match self.get(section, key) {
+            Some(value) => value
+                .split(',')
+                .map(|item| item.trim().to_owned())
+                .filter(|item| !item.is_empty())
+                .collect(),
+            None => default,
+        }
+    }
+
+    pub fn milliseconds(&mut self, section: &str, key: &str, default: Duration) -> Duration {
+        self.get(section, key)
+            .and_then(|value| self.parse_value(section, key, &value))
+            .map_or(default, Duration::from_millis)
+    }
+
+    pub fn seconds(&mut self, section: &str, key: &str, default: Duration) -> Duration {
+        self.get(section, key)
+            .and_then(|value| self.parse_value(section, key, &value))
+            .map_or(default, Duration::from_secs)
+    }
+
+    /// Returns errors, including keys of the config, which were never read.
+    pub fn finish(mut self) -> Vec<String> {
+        let mut unknown_keys: Vec<String> = self
+            .ini
+            .iter()
+            .flat_map(|(section, properties)| {
+                let section = section.clone().unwrap_or_default();
+                properties
+                    .keys()
+                    .map(move |key| (section.clone(), key.clone()))
+            })
+            .filter(|known_key| !self.known_keys.contains(known_key))
+            .map(|(section, key)| {
+                if section.is_empty() {
+                    format!("Unknown \"{}\" key in the grip.ini config", key)
+                } else {
+                    format!("Unknown \"{}.{}\" key in the grip.ini config", section, key)
+                }
+            })
+            .collect();
+        unknown_keys.sort();
+
+        self.errors.append(&mut unknown_keys);
+        self.errors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn no_env(_: &str) -> Option<String> {
+        None
+    }
+
+    #[test]
+    fn test_defaults() {
+        let (config, errors) = Config::load_with_env("/nonexistent/grip.ini", &no_env);
+
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("Can't parse/open grip config /nonexistent/grip.ini"));
+        assert_eq!(config.runtime.callbacks_per_frame, 1);
+        assert_eq!(config.runtime.microseconds_delay_between_attempts, 33000);
+        assert_eq!(
+            config.runtime.scheduler_settings.min_interval,
+            Duration::from_secs(1)
+        );
+        assert!(config.server.is_none());
+        assert!(config.log.is_none());
+        assert_eq!(config.transport, TransportType::Network);
+        assert_eq!(config.har_mode, HarMode::Off);
+    }
+
+    #[test]
+    fn test_errors() {
+        let ini = Ini::load_from_str(
+            "[queue]\n\
+             callbacks-per-frame = 0\n\
+             microseconds-delay-between-attempts = 5ms\n\
+             max-response-body-size =\n\
+             callback-per-frame = 4\n\
+             [shutdown]\n\
+             run-callbacks = yes\n\
+             [transport]\n\
+             type = carrier-pigeon\n",
+        )
+        .unwrap();
+
+        let (config, errors) = Config::parse(&ini, Path::new(""), &no_env);
+        assert_eq!(config.runtime.callbacks_per_frame, 1);
+        assert_eq!(config.runtime.microseconds_delay_between_attempts, 33000);
+        assert!(config.runtime.drain_policy.run_callbacks);
+        assert_eq!(config.transport, TransportType::Network);
+
+        assert_eq!(
+            errors,
+            [
+                "Invalid \"queue.callbacks-per-frame\" key in the grip.ini config: 0: should be at least 1",
+                "Invalid \"queue.microseconds-delay-between-attempts\" key in the grip.ini config: 5ms: \
+                 invalid digit found in string",
+                "Invalid \"shutdown.run-callbacks\" key in the grip.ini config: yes: expected true or false",
+                "Invalid \"transport.type\" key in the grip.ini config: carrier-pigeon: expected network or mock",
+                "Unknown \"queue.callback-per-frame\" key in the grip.ini config",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_environment() {
+        let ini = Ini::load_from_str(
+            "[queue]\n\
+             callbacks-per-frame = 4\n\
+             [server]\n\
+             enabled = true\n\
+             auth-token = ${GRIP_TEST_TOKEN}\n\
+             [har]\n\
+             mode = record\n\
+             path = ${MISSING}/grip.har\n",
+        )
+        .unwrap();
+
+        let env = |name: &str| match name {
+            "GRIP_QUEUE_CALLBACKS_PER_FRAME" => Some("8".to_owned()),
+            "GRIP_LOG_LEVEL" => Some("debug".to_owned()),
+            "GRIP_TEST_TOKEN" => Some("secret".to_owned()),
+            _ => None,
+        };
+
+        let (config, errors) = Config::parse(&ini, Path::new("configs"), &env);
+        assert_eq!(config.runtime.callbacks_per_frame, 8);
+        assert_eq!(config.server.unwrap().auth_token, Some("secret".to_owned()));
+        // Section is enabled by the override alone.
+        assert_eq!(config.log.unwrap().level, log::LevelFilter::Debug);
+
+        assert_eq!(
+            errors,
+            [
+                "Invalid \"har.path\" key in the grip.ini config: ${MISSING}/grip.har: \
+                 environment variable MISSING is not set",
+                "Missing \"har.path\" key in the grip.ini config",
+            ]
+        );
+    }
+}
@@ -0,0 +1,269 @@
+/*
+ * gRIP
+ * Copyright (c) 2018 Alik Aslanyan <cplusplus256@gmail.com>
+ *
+ *
+ *    This program is free software; you can redistribute it and/or modify it
+ *    under the terms of the GNU General Public License as published by the
+ *    Free Software Foundation; either version 3 of the License, or (at
+ *    your option) any later version.
+ *
+ *    This program is distributed in the hope that it will be useful, but
+ *    WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ *    General Public License for more details.
+ *
+ *    You should have received a copy of the GNU General Public License
+ *    along with this program; if not, write to the Free Software Foundation,
+ *    Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ *    In addition, as a special exception, the author gives permission to
+ *    link the code of this program with the Half-Life Game Engine ("HL
+ *    Engine") and Modified Game Libraries ("MODs") developed by Valve,
+ *    L.L.C ("Valve").  You must obey the GNU General Public License in all
+ *    respects for all of the code used other than the HL Engine and MODs
+ *    from Valve.  If you modify this file, you may extend this exception
+ *    to your version of the file, but you are not obligated to do so.  If
+ *    you do not wish to do so, delete this exception statement from your
+ *    version.
+ *
+ */
+
+//! `grip` server command, which lets admins look into the module at runtime.
+
+use super::*;
+
+const USAGE: &[&str] = &[
+    "Usage: grip <command>",
+    "    status         - queue, handles and server summary",
+    "    pending        - pending requests with their age",
+    "    cancel <id>    - cancels pending request",
+    "    handles        - live handles by plugin, type and creation site",
+    "    reload         - applies changes of the grip.ini, which don't need the map change",
+    "    gc             - JSON garbage collector statistics",
+];
+
+/// Executes the command and returns lines to print.
+pub(super) unsafe fn run_command(args: &[&str]) -> Vec<String> {
+    if MODULE.is_none() {
+        return vec!["gRIP is not initialized".to_owned()];
+    }
+
+    match args {
+        ["status"] => status(),
+        ["pending"] => pending(),
+        ["cancel", id] => match id.parse() {
+            Ok(id) => cancel(id),
+            Err(_) => vec![format!("Invalid request id: {}", id)],
+        },
+        ["handles"] => {
+            let report = self::handles::handle_report();
+            if report.is_empty() {
+                vec!["No live handles".to_owned()]
+            } else {
+                report
+            }
+        }
+        ["reload"] => reload(),
+        ["gc"] => gc(),
+        _ => USAGE.iter().map(|&line| line.to_owned()).collect(),
+    }
+}
+
+unsafe fn status() -> Vec<String> {
+    let module = get_module();
+
+    let pending: usize = module
+        .queues()
+        .map(|queue| queue.number_of_pending_requests())
+        .sum();
+    let must_complete = module
+        .queues()
+        .flat_map(|queue| queue.pending_requests())
+        .filter(|request| request.must_complete)
+        .count();
+    let handles: Vec<String> = module
+        .handle_maps()
+        .into_iter()
+        .map(|map| {
+            (
+                map.handle_type(),
+                map.count_by_owner().values().sum::<usize>(),
+            )
+        })
+        .filter(|&(_, count)| count != 0)
+        .map(|(handle_type, count)| format!("{} {}", count, handle_type.name()))
+        .collect();
+
+    vec![
+        format!("gRIP {}", env!("CARGO_PKG_VERSION")),
+        format!(
+            "Transport: {}",
+            if module.mock_transport.is_some() {
+                "mock"
+            } else {
+                "network"
+            }
+        ),
+        format!(
+            "Pending requests: {} ({} must complete)",
+            pending, must_complete
+        ),
+        format!(
+            "Callbacks per frame: {}, delay between attempts: {} us",
+            module.callbacks_per_frame, module.microseconds_delay_between_attempts
+        ),
+        format!("Queues created by plugins: {}", module.queues_handles.len()),
+        format!(
+            "Scheduled requests: {} ({} paused)",
+            module.schedules_handles.len(),
+            module
+                .schedules_handles
+                .values()
+                .filter(|schedule| schedule.is_paused())
+                .count()
+        ),
+        format!(
+            "Handles: {}",
+            if handles.is_empty() {
+                "none".to_owned()
+            } else {
+                handles.join(", ")
+            }
+        ),
+        format!(
+            "HTTP server: {}",
+            module
+                .http_server
+                .as_ref()
+                .map_or("disabled".to_owned(), |server| format!(
+                    "listening on {}",
+                    server.local_address()
+                ))
+        ),
+        format!(
+            "Metrics: {}",
+            module
+                ._metrics_server
+                .as_ref()
+                .map_or("disabled".to_owned(), |server| format!(
+                    "listening on {}",
+                    server.local_address()
+                ))
+        ),
+    ]
+}
+
+unsafe fn pending() -> Vec<String> {
+    let now = std::time::Instant::now();
+    let mut requests: Vec<_> = get_module()
+        .queues()
+        .flat_map(|queue| queue.pending_requests())
+        .collect();
+    requests.sort_by_key(|request| request.id);
+
+    let mut lines: Vec<String> = requests
+        .into_iter()
+        .map(|request| {
+            format!(
+                "{:>6} {:<6} {:>8} ms {}{}",
+                request.id,
+                request.http_type.as_str(),
+                now.duration_since(request.started).as_millis(),
+                request.url,
+                if request.must_complete {
+                    " (must complete)"
+                } else {
+                    ""
+                }
+            )
+        })
+        .collect();
+
+    if lines.is_empty() {
+        vec!["No pending requests".to_owned()]
+    } else {
+        lines.insert(0, format!("{:>6} {:<6} {:>11} URL", "ID", "METHOD", "AGE"));
+        lines
+    }
+}
+
+/// Cancels request the same way as `grip_cancel_request`, so its callback is called with the cancelled state.
+unsafe fn cancel(request_id: u64) -> Vec<String> {
+    let cancellations = &mut get_module_mut().cancellations_handles;
+    let handle = cancellations.ids().into_iter().find(|&handle| {
+        cancellations
+            .get_with_id(handle)
+            .map_or(false, |cancellation| {
+                cancellation.request_id() == request_id
+            })
+    });
+
+    match handle {
+        Some(handle) => {
+            cancellations.remove_with_id(handle);
+            vec![format!("Request {} was cancelled", request_id)]
+        }
+        None => vec![format!("No pending request with id {}", request_id)],
+    }
+}
+
+unsafe fn reload() -> Vec<String> {
+    match reload_config() {
+        Ok(()) => vec![
+            format!("Reloaded {}", get_module().config_file_path),
+            "Sections [transport], [har], [server], [metrics] and [log] are applied on the map change"
+                .to_owned(),
+        ],
+        Err(mut errors) => {
+            errors.push("Config is invalid, settings were not changed".to_owned());
+            errors
+        }
+    }
+}
+
+unsafe fn gc() -> Vec<String> {
+    let stats = crate::gc_json::gc_stats();
+
+    vec![
+        format!("Collections: {}", stats.collections),
+        format!("Total collection time: {} us", stats.total_time.as_micros()),
+        format!(
+            "Last collection: {}",
+            match (stats.last_time, stats.last_collected_at) {
+                (Some(time), Some(at)) => format!(
+                    "took {} us, {} s ago",
+                    time.as_micros(),
+                    at.elapsed().as_secs()
+                ),
+                _ => "never".to_owned(),
+            }
+        ),
+        format!(
+            "Possible cycle roots: {}",
+            crate::gc_json::gc_roots_buffered()
+        ),
+        format!("Live JSON values: {}", get_module().json_handles.len()),
+    ]
+}
+
+/// Executes `grip` server command. Every line of the output is passed to the `printer`.
+#[no_mangle]
+pub unsafe extern "C" fn grip_console_command(
+    argc: Cell,
+    argv: *const *const c_char,
+    printer: extern "C" fn(*const c_char),
+) {
+    let args: Vec<String> = (0..std::cmp::max(argc, 0) as usize)
+        .map(|i| CStr::from_ptr(*argv.add(i)).to_string_lossy().into_owned())
+        .collect();
+    let args: Vec<&str> = args.iter().map(|arg| arg.as_str()).collect();
+
+    print_lines(printer, run_command(&args));
+}
+
+pub(super) fn print_lines(printer: extern "C" fn(*const c_char), lines: Vec<String>) {
+    for line in lines {
+        printer(format!("{}\0", line).as_ptr() as *const c_char);
+    }
+}
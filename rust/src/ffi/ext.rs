@@ -146,8 +146,7 @@ macro_rules! try_to_get_json_value_gc {
             $amx,
             get_module_mut()
                 .json_handles
-                .get_with_id($value)
-                .chain_err(|| ffi_error(format!("Invalid JSON value handle {}", $value)))
+                .get($value)
         );
 
         value
@@ -165,8 +164,7 @@ macro_rules! try_to_get_json_value_gc_mut {
             $amx,
             get_module_mut()
                 .json_handles
-                .get_mut_with_id($value)
-                .chain_err(|| ffi_error(format!("Invalid JSON value handle {}", $value)))
+                .get_mut($value)
         );
 
         value
@@ -0,0 +1,239 @@
+/*
+ * gRIP
+ * Copyright (c) 2018 Alik Aslanyan <cplusplus256@gmail.com>
+ *
+ *
+ *    This program is free software; you can redistribute it and/or modify it
+ *    under the terms of the GNU General Public License as published by the
+ *    Free Software Foundation; either version 3 of the License, or (at
+ *    your option) any later version.
+ *
+ *    This program is distributed in the hope that it will be useful, but
+ *    WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ *    General Public License for more details.
+ *
+ *    You should have received a copy of the GNU General Public License
+ *    along with this program; if not, write to the Free Software Foundation,
+ *    Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ *    In addition, as a special exception, the author gives permission to
+ *    link the code of this program with the Half-Life Game Engine ("HL
+ *    Engine") and Modified Game Libraries ("MODs") developed by Valve,
+ *    L.L.C ("Valve").  You must obey the GNU General Public License in all
+ *    respects for all of the code used other than the HL Engine and MODs
+ *    from Valve.  If you modify this file, you may extend this exception
+ *    to your version of the file, but you are not obligated to do so.  If
+ *    you do not wish to do so, delete this exception statement from your
+ *    version.
+ *
+ */
+
+//! Groups of requests, which call one more handler, when all of their requests are finished.
+
+use super::*;
+
+use std::collections::HashSet;
+
+pub struct RequestGroupHandle {
+    // Cancellation handles of the requests, whose callbacks weren't called yet.
+    pending: HashSet<Cell>,
+    // Group completes only after it was sealed, so it can be filled over several frames.
+    sealed: bool,
+    succeeded: Cell,
+    failed: Cell,
+    forward_id: Cell,
+    handler: extern "C" fn(
+        forward_handle: Cell,
+        group: Cell,
+        succeeded: Cell,
+        failed: Cell,
+        user_data: Cell,
+    ) -> c_void,
+    forward_releaser: extern "C" fn(forward_handle: Cell) -> c_void,
+    user_data: Cell,
+}
+
+impl RequestGroupHandle {
+    pub fn release_forward(&self) {
+        (self.forward_releaser)(self.forward_id);
+    }
+}
+
+fn group_of(cancellation: Cell) -> Option<Cell> {
+    unsafe {
+        let group = *get_module().grouped_requests.get(&cancellation)?;
+        get_module()
+            .request_groups_handles
+            .get_with_id(group)
+            .filter(|handle| handle.pending.contains(&cancellation))
+            .map(|_| group)
+    }
+}
+
+/// Called, when the group is destroyed before all of its requests are finished.
+pub(super) unsafe fn forget_requests(handle: &RequestGroupHandle) {
+    for cancellation in &handle.pending {
+        get_module_mut().grouped_requests.remove(cancellation);
+    }
+}
+
+/// Calls the handler of the sealed group, once all of its requests are finished, and destroys the group.
+unsafe fn complete_if_finished(group: Cell) {
+    let groups = &mut get_module_mut().request_groups_handles;
+    match groups.get_with_id(group) {
+        Some(handle) if handle.sealed && handle.pending.is_empty() => {}
+        _ => return,
+    }
+
+    let handle = groups.remove_with_id(group).unwrap();
+    (handle.handler)(
+        handle.forward_id,
+        group,
+        handle.succeeded,
+        handle.failed,
+        handle.user_data,
+    );
+    handle.release_forward();
+}
+
+/// Called after the callback of every request.
+pub(super) unsafe fn request_finished(cancellation: Cell, succeeded: bool) {
+    let group = match get_module_mut().grouped_requests.remove(&cancellation) {
+        Some(group) => group,
+        None => return,
+    };
+
+    let handle = match get_module_mut()
+        .request_groups_handles
+        .get_mut_with_id(group)
+    {
+        Some(handle) => handle,
+        None => return,
+    };
+    if !handle.pending.remove(&cancellation) {
+        return;
+    }
+    if succeeded {
+        handle.succeeded += 1;
+    } else {
+        handle.failed += 1;
+    }
+
+    complete_if_finished(group);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_create_request_group(
+    amx: *const c_void,
+    forward_id: Cell,
+    handler: Option<
+        extern "C" fn(
+            forward_handle: Cell,
+            group: Cell,
+            succeeded: Cell,
+            failed: Cell,
+            user_data: Cell,
+        ) -> c_void,
+    >,
+    forward_releaser: Option<extern "C" fn(forward_handle: Cell) -> c_void>,
+    user_data: Cell,
+) -> Cell {
+    try_and_log_ffi!(
+        amx,
+        get_module_mut().request_groups_handles.insert_owned(
+            owner(amx),
+            "grip_create_request_group",
+            RequestGroupHandle {
+                pending: HashSet::new(),
+                sealed: false,
+                succeeded: 0,
+                failed: 0,
+                forward_id,
+                handler: handler.unwrap(),
+                forward_releaser: forward_releaser.unwrap(),
+                user_data,
+            }
+        )
+    )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_request_group_add(
+    amx: *const c_void,
+    group: Cell,
+    cancellation: Cell,
+) -> Cell {
+    let handle = try_and_log_ffi!(amx, get_module().request_groups_handles.get(group));
+    try_and_log_ffi!(
+        amx,
+        if handle.sealed {
+            Err(ffi_error(format!(
+                "Request group {} is sealed, requests can't be added to it",
+                group
+            )))
+        } else {
+            Ok(())
+        }
+    );
+    try_and_log_ffi!(amx, get_module().cancellations_handles.get(cancellation));
+    try_and_log_ffi!(
+        amx,
+        match group_of(cancellation) {
+            Some(other_group) if other_group != group => Err(ffi_error(format!(
+                "Request {} is already added to another group",
+                cancellation
+            ))),
+            _ => Ok(()),
+        }
+    );
+
+    get_module_mut()
+        .request_groups_handles
+        .get_mut_with_id(group)
+        .unwrap()
+        .pending
+        .insert(cancellation);
+    get_module_mut()
+        .grouped_requests
+        .insert(cancellation, group);
+
+    1
+}
+
+/// Group completes after the last request, which was added before this.
+/// Group, whose requests are already finished, completes right away.
+#[no_mangle]
+pub unsafe extern "C" fn grip_request_group_seal(amx: *const c_void, group: Cell) -> Cell {
+    try_and_log_ffi!(amx, get_module_mut().request_groups_handles.get_mut(group)).sealed = true;
+    complete_if_finished(group);
+
+    1
+}
+
+/// Cancels pending requests of the group the same way as `grip_cancel_request`.
+#[no_mangle]
+pub unsafe extern "C" fn grip_request_group_cancel(amx: *const c_void, group: Cell) -> Cell {
+    let pending: Vec<Cell> = try_and_log_ffi!(amx, get_module().request_groups_handles.get(group))
+        .pending
+        .iter()
+        .cloned()
+        .collect();
+
+    for cancellation in pending {
+        get_module_mut()
+            .cancellations_handles
+            .remove_with_id(cancellation);
+    }
+
+    1
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_destroy_request_group(amx: *const c_void, group: Cell) -> Cell {
+    let handle = try_and_log_ffi!(amx, get_module_mut().request_groups_handles.remove(group));
+    forget_requests(&handle);
+    handle.release_forward();
+
+    1
+}
@@ -0,0 +1,391 @@
+/*
+ * gRIP
+ * Copyright (c) 2018 Alik Aslanyan <cplusplus256@gmail.com>
+ *
+ *
+ *    This program is free software; you can redistribute it and/or modify it
+ *    under the terms of the GNU General Public License as published by the
+ *    Free Software Foundation; either version 3 of the License, or (at
+ *    your option) any later version.
+ *
+ *    This program is distributed in the hope that it will be useful, but
+ *    WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ *    General Public License for more details.
+ *
+ *    You should have received a copy of the GNU General Public License
+ *    along with this program; if not, write to the Free Software Foundation,
+ *    Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ *    In addition, as a special exception, the author gives permission to
+ *    link the code of this program with the Half-Life Game Engine ("HL
+ *    Engine") and Modified Game Libraries ("MODs") developed by Valve,
+ *    L.L.C ("Valve").  You must obey the GNU General Public License in all
+ *    respects for all of the code used other than the HL Engine and MODs
+ *    from Valve.  If you modify this file, you may extend this exception
+ *    to your version of the file, but you are not obligated to do so.  If
+ *    you do not wish to do so, delete this exception statement from your
+ *    version.
+ *
+ */
+
+use super::*;
+
+use crate::cell_map::Handles;
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+/// Settings of the `[handles]` section of the config.
+pub struct HandleSettings {
+    pub warn_leaks_on_map_end: bool,
+    pub max_handles_per_plugin: Option<usize>,
+    pub warn_threshold: Option<usize>,
+    pub warn_interval: Duration,
+}
+
+impl Default for HandleSettings {
+    fn default() -> Self {
+        HandleSettings {
+            warn_leaks_on_map_end: false,
+            max_handles_per_plugin: None,
+            warn_threshold: None,
+            warn_interval: Duration::from_secs(60),
+        }
+    }
+}
+
+impl ModuleStorage {
+    pub(super) fn handle_maps(&self) -> Vec<&dyn Handles> {
+        vec![
+            &self.bodies_handles,
+            &self.responses_handles,
+            &self.cancellations_handles,
+            &self.request_groups_handles,
+            &self.request_chains_handles,
+            &self.schedules_handles,
+            &self.json_handles,
+            &self.options_handles,
+            &self.subscriptions_handles,
+            &self.websockets_handles,
+            &self.server_requests_handles,
+            &self.queues_handles,
+        ]
+    }
+}
+
+/// Applies "handles.max-handles-per-plugin" to the handles of every type.
+pub(super) unsafe fn apply_quotas() {
+    let module = get_module_mut();
+    let quota = module.handle_settings.max_handles_per_plugin;
+
+    module.bodies_handles.set_quota(quota);
+    module.responses_handles.set_quota(quota);
+    module.cancellations_handles.set_quota(quota);
+    module.request_groups_handles.set_quota(quota);
+    module.request_chains_handles.set_quota(quota);
+    module.schedules_handles.set_quota(quota);
+    module.json_handles.set_quota(quota);
+    module.options_handles.set_quota(quota);
+    module.subscriptions_handles.set_quota(quota);
+    module.websockets_handles.set_quota(quota);
+    module.queues_handles.set_quota(quota);
+    // Server request handles are exempt. They are owned by the module, not by the plugins,
+    // and the ones, which weren't replied in time, are dropped every frame.
+}
+
+/// Name of the plugin, which owns handles. Owner `0` is the module itself.
+unsafe fn plugin_name(owner: usize) -> String {
+    if owner == 0 {
+        return "gRIP".to_owned();
+    }
+
+    // Resolver returns null for plugins, which aren't loaded.
+    let name = (get_module().plugin_name_resolver)(owner as *const c_void);
+    if name.is_null() {
+        return format!("unloaded plugin {:#x}", owner);
+    }
+
+    match str_from_ptr(name) {
+        Ok(name) => name.to_owned(),
+        Err(_) => format!("plugin {:#x}", owner),
+    }
+}
+
+/// Describes live handles by plugin, type and native, which created them.
+pub(super) unsafe fn handle_report() -> Vec<String> {
+    let mut counts: BTreeMap<usize, Vec<(HandleType, &'static str, usize)>> = BTreeMap::new();
+    for map in get_module().handle_maps() {
+        for ((owner, site), count) in map.count_by_site() {
+            counts
+                .entry(owner)
+                .or_insert_with(Vec::new)
+                .push((map.handle_type(), site, count));
+        }
+    }
+
+    let mut counts: Vec<_> = counts
+        .into_iter()
+        .map(|(owner, sites)| (plugin_name(owner), sites))
+        .collect();
+    counts.sort_by(|(name, _), (other_name, _)| name.cmp(other_name));
+
+    let mut lines = vec![];
+    for (name, mut sites) in counts {
+        sites.sort_by_key(|&(handle_type, site, count)| {
+            (handle_type as usize, std::cmp::Reverse(count), site)
+        });
+
+        let total: usize = sites.iter().map(|&(_, _, count)| count).sum();
+        lines.push(format!("{}: {} handles", name, total));
+        for (handle_type, site, count) in sites {
+            if site.is_empty() {
+                lines.push(format!("    {} {}", count, handle_type.name()));
+            } else {
+                lines.push(format!(
+                    "    {} {} created by {}",
+                    count,
+                    handle_type.name(),
+                    site
+                ));
+            }
+        }
+    }
+
+    lines
+}
+
+/// Warns about plugins, which hold more handles of some type than "handles.warn-threshold".
+/// Checked once in "handles.warn-interval-seconds".
+pub(super) unsafe fn check_thresholds(now: Instant) -> Vec<String> {
+    let threshold = match get_module().handle_settings.warn_threshold {
+        Some(threshold) => threshold,
+        None => return vec![],
+    };
+
+    if now.duration_since(get_module().last_handles_check)
+        < get_module().handle_settings.warn_interval
+    {
+        return vec![];
+    }
+    get_module_mut().last_handles_check = now;
+
+    let mut warnings = vec![];
+    for map in get_module().handle_maps() {
+        let mut counts: Vec<_> = map
+            .count_by_owner()
+            .into_iter()
+            .filter(|&(owner, count)| owner != 0 && count > threshold)
+            .collect();
+        if counts.is_empty() {
+            continue;
+        }
+        counts.sort();
+
+        let sites = map.count_by_site();
+        for (owner, count) in counts {
+            let (site, site_count) = sites
+                .iter()
+                .filter(|&(&(site_owner, _), _)| site_owner == owner)
+                .map(|(&(_, site), &site_count)| (site, site_count))
+                .max_by_key(|&(site, site_count)| (site_count, std::cmp::Reverse(site)))
+                .unwrap();
+
+            warnings.push(format!(
+                "[gRIP] Warning: {} holds {} {} handles, which is more than {}. {} of them were created by {}",
+                plugin_name(owner),
+                count,
+                map.handle_type().name(),
+                threshold,
+                site_count,
+                site
+            ));
+        }
+    }
+
+    warnings
+}
+
+/// `0` selects handles of all types.
+fn parse_handle_type(handle_type: Cell) -> Result<Option<HandleType>> {
+    if handle_type == 0 {
+        Ok(None)
+    } else {
+        HandleType::from_id(handle_type)
+            .map(Some)
+            .chain_err(|| ffi_error(format!("Invalid handle type {}", handle_type)))
+    }
+}
+
+unsafe fn owned_handles(owner: usize, handle_type: Option<HandleType>) -> Vec<Cell> {
+    get_module()
+        .handle_maps()
+        .into_iter()
+        .filter(|map| handle_type.map_or(true, |handle_type| map.handle_type() == handle_type))
+        .flat_map(|map| map.ids_owned_by(owner))
+        .collect()
+}
+
+/// Destroys all handles of the `owner`. Pending requests and subscriptions are cancelled.
+/// Handlers of the cancelled requests are only called, unless `silently`,
+/// because paused and unloading plugins can't receive them.
+pub(super) unsafe fn release_handles(owner: usize, silently: bool) -> usize {
+    let module = get_module_mut();
+
+    if silently {
+        module
+            .silenced_requests
+            .extend(module.cancellations_handles.ids_owned_by(owner));
+    }
+
+    let mut count = module.bodies_handles.remove_owned_by(owner).len()
+        + module.responses_handles.remove_owned_by(owner).len()
+        + module.cancellations_handles.remove_owned_by(owner).len()
+        + module.json_handles.remove_owned_by(owner).len()
+        + module.options_handles.remove_owned_by(owner).len()
+        + module.request_chains_handles.remove_owned_by(owner).len();
+
+    for handle in module.subscriptions_handles.remove_owned_by(owner) {
+        handle.release_forward();
+        count += 1;
+    }
+    for handle in module.websockets_handles.remove_owned_by(owner) {
+        handle.release_forward();
+        count += 1;
+    }
+    for handle in module.request_groups_handles.remove_owned_by(owner) {
+        super::group::forget_requests(&handle);
+        handle.release_forward();
+        count += 1;
+    }
+    for handle in module.schedules_handles.remove_owned_by(owner) {
+        handle.release_forward();
+        count += 1;
+    }
+    for handle in module.queues_handles.remove_owned_by(owner) {
+        super::queue::destroy_queue(handle);
+        count += 1;
+    }
+
+    count
+}
+
+/// Handles, which plugins are expected to destroy themselves.
+/// Pending requests, subscriptions and sockets may legitimately live until the map end.
+const LEAKABLE_HANDLE_TYPES: [HandleType; 5] = [
+    HandleType::Body,
+    HandleType::Response,
+    HandleType::JSONValue,
+    HandleType::Options,
+    HandleType::RequestChain,
+];
+
+/// Logs handles, which plugins didn't destroy, on behalf of every plugin.
+/// Called before plugins are unloaded, when enabled by "handles.warn-leaks-on-map-end".
+#[no_mangle]
+pub unsafe extern "C" fn grip_report_leaks() {
+    if MODULE.is_none() || !get_module().handle_settings.warn_leaks_on_map_end {
+        return;
+    }
+
+    let mut leaks: std::collections::BTreeMap<usize, Vec<String>> = Default::default();
+    for map in get_module().handle_maps() {
+        if !LEAKABLE_HANDLE_TYPES.contains(&map.handle_type()) {
+            continue;
+        }
+
+        let mut counts: Vec<_> = map.count_by_owner().into_iter().collect();
+        counts.sort();
+        for (owner, count) in counts.into_iter().filter(|&(owner, _)| owner != 0) {
+            leaks.entry(owner).or_insert_with(Vec::new).push(format!(
+                "{} {}",
+                count,
+                map.handle_type().name()
+            ));
+        }
+    }
+
+    for (owner, counts) in leaks {
+        log_error(
+            owner as *const c_void,
+            format!(
+                "Handles were not destroyed until the map end: {}",
+                counts.join(", ")
+            ),
+        );
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_get_handle_count(amx: *const c_void, handle_type: Cell) -> Cell {
+    let handle_type = try_and_log_ffi!(amx, parse_handle_type(handle_type));
+
+    owned_handles(owner(amx), handle_type).len() as Cell
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_get_handles(
+    amx: *const c_void,
+    handle_type: Cell,
+    handles: *mut Cell,
+    size: Cell,
+) -> Cell {
+    let handle_type = try_and_log_ffi!(amx, parse_handle_type(handle_type));
+    let size = try_as_usize!(amx, size);
+
+    let owned = owned_handles(owner(amx), handle_type);
+    let copied = std::cmp::min(size, owned.len());
+    std::ptr::copy_nonoverlapping(owned.as_ptr(), handles, copied);
+
+    owned.len() as Cell
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_release_handles(amx: *const c_void) -> Cell {
+    release_handles(owner(amx), false) as Cell
+}
+
+/// Called by the wrapper of the `pause` native, after the plugin was paused.
+#[no_mangle]
+pub unsafe extern "C" fn grip_plugin_paused(amx: *const c_void) -> Cell {
+    if MODULE.is_none() {
+        return 0;
+    }
+
+    release_handles(owner(amx), true) as Cell
+}
+
+/// Destroys handles of every plugin before plugins are unloaded, so that they are never called again.
+#[no_mangle]
+pub unsafe extern "C" fn grip_release_plugin_handles() -> Cell {
+    if MODULE.is_none() {
+        return 0;
+    }
+
+    let mut owners: Vec<usize> = get_module()
+        .handle_maps()
+        .iter()
+        .flat_map(|map| map.count_by_owner().into_iter().map(|(owner, _)| owner))
+        .filter(|&owner| owner != 0)
+        .collect();
+    owners.sort();
+    owners.dedup();
+
+    owners
+        .into_iter()
+        .map(|owner| release_handles(owner, true))
+        .sum::<usize>() as Cell
+}
+
+/// Prints live handles of every plugin like the `grip handles` server command.
+/// Every line of the report is passed to the `printer`.
+#[no_mangle]
+pub unsafe extern "C" fn grip_dump_handles(printer: extern "C" fn(*const c_char)) -> Cell {
+    super::console::print_lines(printer, super::console::run_command(&["handles"]));
+
+    get_module()
+        .handle_maps()
+        .iter()
+        .map(|map| map.count_by_owner().values().sum::<usize>())
+        .sum::<usize>() as Cell
+}
@@ -0,0 +1,1366 @@
+/*
+ * gRIP
+ * Copyright (c) 2018 Alik Aslanyan <cplusplus256@gmail.com>
+ *
+ *
+ *    This program is free software; you can redistribute it and/or modify it
+ *    under the terms of the GNU General Public License as published by the
+ *    Free Software Foundation; either version 3 of the License, or (at
+ *    your option) any later version.
+ *
+ *    This program is distributed in the hope that it will be useful, but
+ *    WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ *    General Public License for more details.
+ *
+ *    You should have received a copy of the GNU General Public License
+ *    along with this program; if not, write to the Free Software Foundation,
+ *    Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ *    In addition, as a special exception, the author gives permission to
+ *    link the code of this program with the Half-Life Game Engine ("HL
+ *    Engine") and Modified Game Libraries ("MODs") developed by Valve,
+ *    L.L.C ("Valve").  You must obey the GNU General Public License in all
+ *    respects for all of the code used other than the HL Engine and MODs
+ *    from Valve.  If you modify this file, you may extend this exception
+ *    to your version of the file, but you are not obligated to do so.  If
+ *    you do not wish to do so, delete this exception statement from your
+ *    version.
+ *
+ */
+
+//! Exercises natives through the C ABI the same way AMXX does, but without HLDS.
+
+use super::*;
+
+use super::chain::{
+    grip_create_request_chain, grip_get_response_chain_step, grip_request_chain_add,
+    grip_request_chain_send,
+};
+use super::console::run_command;
+use super::group::{
+    grip_create_request_group, grip_request_group_add, grip_request_group_cancel,
+    grip_request_group_seal,
+};
+use super::handles::{
+    check_thresholds, grip_get_handle_count, grip_get_handles, grip_plugin_paused,
+    grip_release_handles, grip_release_plugin_handles, grip_report_leaks, handle_report,
+};
+use super::mock::{grip_mock_add_failure, grip_mock_add_response, grip_mock_get_request_count};
+use super::queue::{grip_create_queue, grip_destroy_queue, DEFAULT_QUEUE};
+use super::schedule::{
+    grip_cancel_schedule, grip_pause_schedule, grip_resume_schedule, grip_schedule_request,
+};
+use super::sse::grip_sse_subscribe;
+use super::websocket::{grip_websocket_close, grip_websocket_connect};
+
+use std::ffi::CString;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+lazy_static! {
+    // Module is a global, so only one harness may exist at a time.
+    static ref HARNESS_LOCK: Mutex<()> = Mutex::new(());
+    static ref LOGGED_ERRORS: Mutex<Vec<(usize, String)>> = Mutex::new(vec![]);
+    static ref CALLBACKS: Mutex<Vec<Callback>> = Mutex::new(vec![]);
+    static ref GROUP_CALLBACKS: Mutex<Vec<[Cell; 4]>> = Mutex::new(vec![]);
+    static ref RELEASED_FORWARDS: Mutex<Vec<Cell>> = Mutex::new(vec![]);
+    static ref CHAIN_STEPS: Mutex<Vec<Cell>> = Mutex::new(vec![]);
+}
+
+static FAKE_AMX: u8 = 0;
+static OTHER_FAKE_AMX: u8 = 0;
+
+fn amx() -> *const c_void {
+    &FAKE_AMX as *const u8 as *const c_void
+}
+
+/// Another plugin, which owns its own handles.
+fn other_amx() -> *const c_void {
+    &OTHER_FAKE_AMX as *const u8 as *const c_void
+}
+
+fn c_string(value: &str) -> CString {
+    CString::new(value).unwrap()
+}
+
+extern "C" fn capture_error(amx: *const c_void, message: *const c_char) {
+    LOGGED_ERRORS.lock().unwrap().push((
+        amx as usize,
+        unsafe { CStr::from_ptr(message) }
+            .to_string_lossy()
+            .into_owned(),
+    ));
+}
+
+extern "C" fn resolve_plugin_name(amx: *const c_void) -> *const c_char {
+    if amx == self::amx() {
+        "harness.amxx\0".as_ptr() as *const c_char
+    } else if amx == other_amx() {
+        "other.amxx\0".as_ptr() as *const c_char
+    } else {
+        std::ptr::null()
+    }
+}
+
+/// State of the response observed by the request handler.
+#[derive(Debug)]
+struct Callback {
+    user_data: Cell,
+    state: Cell,
+    status: Cell,
+    body: String,
+}
+
+extern "C" fn capture_response(_forward_handle: Cell, user_data: Cell) -> c_void {
+    unsafe {
+        let state = grip_get_response_state(amx());
+        let mut body = [0 as c_char; 64];
+        let (status, body) = if state == 3 {
+            grip_get_response_body_string(amx(), body.as_mut_ptr(), 63, CURRENT_RESPONSE);
+            (
+                grip_get_response_status_code(amx(), CURRENT_RESPONSE),
+                CStr::from_ptr(body.as_ptr()).to_string_lossy().into_owned(),
+            )
+        } else {
+            (0, String::new())
+        };
+
+        CALLBACKS.lock().unwrap().push(Callback {
+            user_data,
+            state,
+            status,
+            body,
+        });
+
+        std::mem::zeroed()
+    }
+}
+
+/// Status code is read whatever the state of the response is.
+extern "C" fn capture_status(_forward_handle: Cell, user_data: Cell) -> c_void {
+    unsafe {
+        CALLBACKS.lock().unwrap().push(Callback {
+            user_data,
+            state: grip_get_response_state(amx()),
+            status: grip_get_response_status_code(amx(), CURRENT_RESPONSE),
+            body: String::new(),
+        });
+
+        std::mem::zeroed()
+    }
+}
+
+/// Destroys the queue passed as user data and creates other queues, which may move the queue slots.
+extern "C" fn destroy_own_queue(forward_handle: Cell, user_data: Cell) -> c_void {
+    unsafe {
+        assert_eq!(grip_destroy_queue(amx(), user_data), 1);
+        for _ in 0..8 {
+            assert!(grip_create_queue(amx(), 1, 0, 1) > 0);
+        }
+    }
+
+    capture_response(forward_handle, user_data)
+}
+
+extern "C" fn capture_chain_response(forward_handle: Cell, user_data: Cell) -> c_void {
+    CHAIN_STEPS
+        .lock()
+        .unwrap()
+        .push(unsafe { grip_get_response_chain_step(amx()) });
+
+    capture_response(forward_handle, user_data)
+}
+
+extern "C" fn capture_scheduled_response(
+    forward_handle: Cell,
+    _schedule: Cell,
+    user_data: Cell,
+) -> c_void {
+    capture_response(forward_handle, user_data)
+}
+
+extern "C" fn capture_group(
+    _forward_handle: Cell,
+    group: Cell,
+    succeeded: Cell,
+    failed: Cell,
+    user_data: Cell,
+) -> c_void {
+    GROUP_CALLBACKS
+        .lock()
+        .unwrap()
+        .push([group, succeeded, failed, user_data]);
+
+    unsafe { std::mem::zeroed() }
+}
+
+extern "C" fn release_forward(forward_handle: Cell) -> c_void {
+    RELEASED_FORWARDS.lock().unwrap().push(forward_handle);
+
+    unsafe { std::mem::zeroed() }
+}
+
+/// Initializes the module with the mock transport and deinitializes it, when dropped.
+struct Harness {
+    config_path: std::path::PathBuf,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl Harness {
+    fn start() -> Self {
+        Harness::start_with_config("")
+    }
+
+    /// Sections of the `config` are appended to the minimal config.
+    fn start_with_config(config: &str) -> Self {
+        // Lock is poisoned by the failed test, but module is already deinitialized by then.
+        let lock = HARNESS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        LOGGED_ERRORS.lock().unwrap().clear();
+        CALLBACKS.lock().unwrap().clear();
+        GROUP_CALLBACKS.lock().unwrap().clear();
+        RELEASED_FORWARDS.lock().unwrap().clear();
+        CHAIN_STEPS.lock().unwrap().clear();
+
+        let config_path =
+            std::env::temp_dir().join(format!("grip-harness-{}.ini", std::process::id()));
+        std::fs::write(
+            &config_path,
+            format!(
+                "[queue]\n\
+                 callbacks-per-frame = 100\n\
+                 microseconds-delay-between-attempts = 0\n\
+                 [transport]\n\
+                 type = mock\n\
+                 {}",
+                config
+            ),
+        )
+        .unwrap();
+
+        unsafe {
+            grip_init(
+                capture_error,
+                resolve_plugin_name,
+                c_string(config_path.to_str().unwrap()).as_ptr(),
+            );
+        }
+
+        Harness {
+            config_path,
+            _lock: lock,
+        }
+    }
+
+    /// Errors, which were logged on behalf of the `amx()`.
+    fn take_errors(&self) -> Vec<String> {
+        self.take_errors_with_amx()
+            .into_iter()
+            .map(|(amx, message)| {
+                assert_eq!(amx, self::amx());
+                message
+            })
+            .collect()
+    }
+
+    fn take_errors_with_amx(&self) -> Vec<(*const c_void, String)> {
+        std::mem::replace(&mut *LOGGED_ERRORS.lock().unwrap(), vec![])
+            .into_iter()
+            .map(|(amx, message)| (amx as *const c_void, message))
+            .collect()
+    }
+
+    /// Calls `grip_process_request` every frame, until `count` callbacks were called.
+    fn wait_for_callbacks(&self, count: usize) -> Vec<Callback> {
+        let start = Instant::now();
+        while CALLBACKS.lock().unwrap().len() < count {
+            assert!(start.elapsed() < Duration::from_secs(5), "No callback");
+            unsafe { grip_process_request() };
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        std::mem::replace(&mut *CALLBACKS.lock().unwrap(), vec![])
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        unsafe { grip_deinit() };
+        std::fs::remove_file(&self.config_path).ok();
+    }
+}
+
+const GET: Cell = 0;
+const POST: Cell = 1;
+
+#[test]
+fn test_request_and_response() {
+    let harness = Harness::start();
+    let url = c_string("http://example.com/stats");
+
+    unsafe {
+        assert_eq!(
+            grip_mock_add_response(
+                amx(),
+                GET,
+                url.as_ptr(),
+                200,
+                c_string("ok").as_ptr(),
+                0,
+                -1
+            ),
+            1
+        );
+        let cancellation = grip_request(
+            amx(),
+            7,
+            url.as_ptr(),
+            -1,
+            GET,
+            Some(capture_response),
+            -1,
+            42,
+            DEFAULT_QUEUE,
+        );
+        assert!(cancellation > 0);
+        assert_eq!(grip_is_request_active(cancellation), 1);
+
+        let callbacks = harness.wait_for_callbacks(1);
+        assert_eq!(callbacks[0].user_data, 42);
+        assert_eq!(callbacks[0].state, 3);
+        assert_eq!(callbacks[0].status, 200);
+        assert_eq!(callbacks[0].body, "ok");
+
+        assert_eq!(grip_is_request_active(cancellation), 0);
+        assert_eq!(grip_mock_get_request_count(amx(), GET, url.as_ptr()), 1);
+    }
+
+    assert!(harness.take_errors().is_empty());
+}
+
+#[test]
+fn test_cancel_request() {
+    let harness = Harness::start();
+    let url = c_string("http://example.com/slow");
+
+    unsafe {
+        grip_mock_add_response(
+            amx(),
+            GET,
+            url.as_ptr(),
+            200,
+            c_string("").as_ptr(),
+            10_000,
+            -1,
+        );
+        let cancellation = grip_request(
+            amx(),
+            7,
+            url.as_ptr(),
+            -1,
+            GET,
+            Some(capture_response),
+            -1,
+            1,
+            DEFAULT_QUEUE,
+        );
+
+        assert_eq!(grip_cancel_request(amx(), cancellation), 1);
+        assert_eq!(harness.wait_for_callbacks(1)[0].state, 1);
+
+        // Handle is released after the cancellation.
+        assert_eq!(grip_cancel_request(amx(), cancellation), 0);
+
+        let errors = harness.take_errors();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains(&format!(
+            "Invalid cancellation handle {}, which was already destroyed",
+            cancellation
+        )));
+    }
+}
+
+#[test]
+fn test_failed_response_status() {
+    let harness = Harness::start();
+    let url = c_string("http://example.com/refused");
+
+    unsafe {
+        assert_eq!(grip_mock_add_failure(amx(), GET, url.as_ptr(), 6, 0), 1);
+        grip_request(
+            amx(),
+            7,
+            url.as_ptr(),
+            -1,
+            GET,
+            Some(capture_status),
+            -1,
+            1,
+            DEFAULT_QUEUE,
+        );
+
+        let callbacks = harness.wait_for_callbacks(1);
+        assert_eq!(callbacks[0].state, 2);
+        assert_eq!(callbacks[0].status, -1);
+    }
+
+    let errors = harness.take_errors();
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].contains("Error/Cancellation/Timeout occurred for this response."));
+}
+
+#[test]
+fn test_invalid_arguments() {
+    let harness = Harness::start();
+
+    unsafe {
+        // Response natives are only valid inside of the request callback.
+        assert_eq!(grip_get_response_state(amx()), 0);
+        assert_eq!(
+            grip_request(
+                amx(),
+                7,
+                c_string("http://example.com/").as_ptr(),
+                -1,
+                100,
+                Some(capture_response),
+                -1,
+                0,
+                DEFAULT_QUEUE,
+            ),
+            0
+        );
+        assert_eq!(
+            grip_request(
+                amx(),
+                7,
+                c_string("not a url").as_ptr(),
+                -1,
+                GET,
+                Some(capture_response),
+                -1,
+                0,
+                DEFAULT_QUEUE,
+            ),
+            0
+        );
+        assert_eq!(grip_destroy_options(amx(), 12345), 0);
+        assert_eq!(
+            grip_websocket_close(amx(), 12345, 1005, c_string("").as_ptr()),
+            0
+        );
+        assert_eq!(
+            grip_websocket_close(amx(), 12345, 66536, c_string("").as_ptr()),
+            0
+        );
+    }
+
+    let errors = harness.take_errors();
+    assert_eq!(errors.len(), 6);
+    assert!(errors[0].contains("Response state can only be received in the request callback"));
+    assert!(errors[1].contains("Invalid request type 100"));
+    assert!(errors[2].contains("URI parsing error: not a url"));
+    assert!(errors[3].contains("Invalid options handle 12345"));
+    assert!(errors[4].contains("Invalid close code 1005"));
+    assert!(errors[5].contains("Invalid close code 66536"));
+}
+
+#[test]
+fn test_streams_without_network() {
+    let subscribe = || unsafe {
+        grip_sse_subscribe(
+            amx(),
+            7,
+            c_string("http://example.com/events").as_ptr(),
+            None,
+            Some(release_forward),
+            -1,
+            0,
+        )
+    };
+    let connect = || unsafe {
+        grip_websocket_connect(
+            amx(),
+            7,
+            c_string("ws://example.com/socket").as_ptr(),
+            None,
+            Some(release_forward),
+            -1,
+            false,
+            0,
+        )
+    };
+
+    let harness = Harness::start();
+    assert_eq!(subscribe(), 0);
+    assert_eq!(connect(), 0);
+    let errors = harness.take_errors();
+    assert_eq!(errors.len(), 2);
+    assert!(errors
+        .iter()
+        .all(|error| error.contains("with the mock transport")));
+    drop(harness);
+
+    let harness = Harness::start_with_config(
+        "[har]\n\
+         mode = replay\n\
+         path = missing.har\n",
+    );
+    assert_eq!(subscribe(), 0);
+    assert_eq!(connect(), 0);
+    let errors = harness.take_errors();
+    assert_eq!(errors.len(), 2);
+    assert!(errors
+        .iter()
+        .all(|error| error.contains("in the HAR replay mode")));
+}
+
+#[test]
+fn test_wrong_handle_type() {
+    let harness = Harness::start();
+
+    unsafe {
+        let options = grip_create_default_options(amx(), -1.0);
+        let body = grip_body_from_string(amx(), c_string("body").as_ptr());
+
+        // Handles of different types never collide.
+        assert_ne!(options, body);
+        assert_eq!(grip_destroy_body(amx(), options), 0);
+        assert_eq!(grip_options_set_must_complete(amx(), body, true), 0);
+        assert_eq!(grip_body_get_length(amx(), body), 4);
+
+        let errors = harness.take_errors();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].contains(&format!(
+            "Invalid body handle {}, which is options handle",
+            options
+        )));
+        assert!(errors[1].contains(&format!(
+            "Invalid options handle {}, which is body handle",
+            body
+        )));
+    }
+}
+
+#[test]
+fn test_handle_lifetimes() {
+    let harness = Harness::start();
+
+    unsafe {
+        let mut error = [0 as c_char; 64];
+        let value = grip_json_parse_string(
+            amx(),
+            c_string(r#"{"a": {"b": 5}}"#).as_ptr() as *mut c_char,
+            error.as_mut_ptr(),
+            63,
+        );
+        assert!(value > 0);
+        assert_eq!(
+            grip_json_object_get_number(amx(), value, c_string("a.b").as_ptr(), true),
+            5
+        );
+
+        let body = grip_body_from_json(amx(), value, false, 10);
+        assert!(body > 0);
+
+        assert_eq!(grip_destroy_json_value(amx(), value), 1);
+        assert_eq!(grip_destroy_json_value(amx(), value), 0);
+        assert_eq!(
+            grip_json_object_get_number(amx(), value, c_string("a.b").as_ptr(), true),
+            0
+        );
+
+        // Body outlives the value, from which it was created.
+        assert_eq!(grip_body_get_length(amx(), body), 13);
+        assert_eq!(grip_destroy_body(amx(), body), 1);
+        assert_eq!(grip_body_get_length(amx(), body), 0);
+
+        // Parse errors are copied to the buffer and are not logged.
+        assert_eq!(
+            grip_json_parse_string(
+                amx(),
+                c_string("{").as_ptr() as *mut c_char,
+                error.as_mut_ptr(),
+                63
+            ),
+            0
+        );
+        assert!(CStr::from_ptr(error.as_ptr())
+            .to_str()
+            .unwrap()
+            .contains("EOF while parsing"));
+
+        let destroyed_value = format!(
+            "Invalid JSON value handle {}, which was already destroyed",
+            value
+        );
+        let errors = harness.take_errors();
+        assert_eq!(errors.len(), 3);
+        assert!(errors[0].contains(&destroyed_value));
+        assert!(errors[1].contains(&destroyed_value));
+        assert!(errors[2].contains(&format!(
+            "Invalid body handle {}, which was already destroyed",
+            body
+        )));
+    }
+}
+
+#[test]
+fn test_buffer_truncation() {
+    let harness = Harness::start();
+
+    unsafe {
+        let mut buffer = [0x7F as c_char; 16];
+
+        let value = grip_json_parse_string(
+            amx(),
+            c_string(r#"{"key":"value"}"#).as_ptr() as *mut c_char,
+            buffer.as_mut_ptr(),
+            15,
+        );
+
+        // Length of the whole string is returned, while only `maxlen` characters are copied.
+        assert_eq!(
+            grip_json_serial_to_string(amx(), value, false, buffer.as_mut_ptr(), 4, 10),
+            15
+        );
+        assert_eq!(CStr::from_ptr(buffer.as_ptr()).to_str().unwrap(), r#"{"ke"#);
+        assert_eq!(buffer[5], 0x7F);
+
+        // Multibyte characters are never split.
+        let body = grip_body_from_string(amx(), c_string("héllo").as_ptr());
+        assert_eq!(
+            grip_body_get_string(amx(), body, buffer.as_mut_ptr(), 2, 0),
+            1
+        );
+        assert_eq!(CStr::from_ptr(buffer.as_ptr()).to_str().unwrap(), "h");
+        assert_eq!(
+            grip_body_get_string(amx(), body, buffer.as_mut_ptr(), 3, 0),
+            3
+        );
+        assert_eq!(CStr::from_ptr(buffer.as_ptr()).to_str().unwrap(), "hé");
+
+        assert_eq!(
+            grip_body_get_string(amx(), body, buffer.as_mut_ptr(), -1, 0),
+            0
+        );
+    }
+
+    let errors = harness.take_errors();
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].contains("Index/Size -1 should be greater or equal to zero."));
+}
+
+#[test]
+fn test_read_line_small_buffer() {
+    let harness = Harness::start();
+
+    unsafe {
+        let mut buffer = [0 as c_char; 16];
+        let body = grip_body_from_string(amx(), c_string("😀\nline").as_ptr());
+
+        // Offset can't advance, so the loop over the lines should stop.
+        for &size in &[0, 2] {
+            let mut offset = 0;
+            assert_eq!(
+                grip_body_read_line(amx(), body, &mut offset, buffer.as_mut_ptr(), size),
+                -1
+            );
+            assert_eq!(offset, 0);
+        }
+
+        let errors = harness.take_errors();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[1].contains("Buffer of 2 bytes is too small for the character at offset 0"));
+    }
+}
+
+#[test]
+fn test_drain() {
+    let harness = Harness::start_with_config(
+        "[shutdown]\n\
+         drain-timeout-milliseconds = 2000\n\
+         drain-only-must-complete = true\n",
+    );
+    let url = c_string("http://example.com/upload");
+
+    unsafe {
+        grip_mock_add_response(
+            amx(),
+            GET,
+            url.as_ptr(),
+            200,
+            c_string("ok").as_ptr(),
+            50,
+            -1,
+        );
+
+        let options = grip_create_default_options(amx(), -1.0);
+        assert_eq!(grip_options_set_must_complete(amx(), options, true), 1);
+
+        for &(options, user_data) in &[(-1, 1), (options, 2)] {
+            grip_request(
+                amx(),
+                7,
+                url.as_ptr(),
+                -1,
+                GET,
+                Some(capture_response),
+                options,
+                user_data,
+                DEFAULT_QUEUE,
+            );
+        }
+
+        grip_drain();
+    }
+
+    // Request without `must_complete` is cancelled without calling its callback.
+    let callbacks = std::mem::replace(&mut *CALLBACKS.lock().unwrap(), vec![]);
+    assert_eq!(callbacks.len(), 1);
+    assert_eq!(callbacks[0].user_data, 2);
+    assert_eq!(callbacks[0].state, 3);
+    assert_eq!(callbacks[0].body, "ok");
+    assert!(harness.take_errors().is_empty());
+}
+
+#[test]
+fn test_plugin_ownership() {
+    let harness = Harness::start_with_config(
+        "[handles]\n\
+         warn-leaks-on-map-end = true\n",
+    );
+
+    unsafe {
+        let object = grip_json_init_object(amx());
+        let options = grip_create_default_options(amx(), -1.0);
+        let other_object = grip_json_init_object(other_amx());
+        let other_body = grip_body_from_string(other_amx(), c_string("body").as_ptr());
+
+        assert_eq!(grip_get_handle_count(amx(), 0), 2);
+        assert_eq!(
+            grip_get_handle_count(amx(), HandleType::JSONValue as Cell),
+            1
+        );
+        assert_eq!(grip_get_handle_count(other_amx(), 0), 2);
+
+        let mut handles = [0 as Cell; 1];
+        assert_eq!(grip_get_handles(amx(), 0, handles.as_mut_ptr(), 1), 2);
+        assert!(handles[0] == object || handles[0] == options);
+
+        // Handles of the other plugin stay valid.
+        assert_eq!(grip_release_handles(amx()), 2);
+        assert_eq!(grip_get_handle_count(amx(), 0), 0);
+        assert_eq!(grip_destroy_options(amx(), options), 0);
+        assert!(harness.take_errors()[0].contains("already destroyed"));
+        assert_eq!(grip_json_get_type(other_amx(), other_object), 4);
+        assert_eq!(grip_body_get_length(other_amx(), other_body), 4);
+
+        grip_report_leaks();
+        let errors = harness.take_errors_with_amx();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, other_amx());
+        assert_eq!(
+            errors[0].1,
+            "Handles were not destroyed until the map end: 1 body, 1 JSON value"
+        );
+    }
+}
+
+#[test]
+fn test_paused_plugin_handles() {
+    let harness = Harness::start();
+    let url = c_string("http://example.com/slow");
+
+    unsafe {
+        grip_mock_add_response(
+            amx(),
+            GET,
+            url.as_ptr(),
+            200,
+            c_string("ok").as_ptr(),
+            100,
+            -1,
+        );
+        let request = |amx: *const c_void| {
+            grip_request(
+                amx,
+                7,
+                url.as_ptr(),
+                -1,
+                GET,
+                Some(capture_response),
+                -1,
+                1,
+                DEFAULT_QUEUE,
+            )
+        };
+
+        grip_json_init_object(amx());
+        let cancellation = request(amx());
+        grip_json_init_object(other_amx());
+        request(other_amx());
+
+        // Cancelled requests of the paused plugin don't call its handlers.
+        assert_eq!(grip_plugin_paused(amx()), 2);
+        assert_eq!(grip_is_request_active(cancellation), 0);
+        assert_eq!(grip_get_handle_count(amx(), 0), 0);
+
+        let callbacks = harness.wait_for_callbacks(1);
+        assert_eq!(callbacks.len(), 1);
+        assert_eq!(callbacks[0].status, 200);
+        assert!(get_module().silenced_requests.is_empty());
+
+        // Handles of every plugin are released before plugins are unloaded.
+        request(other_amx());
+        assert_eq!(grip_release_plugin_handles(), 2);
+        assert_eq!(grip_get_handle_count(other_amx(), 0), 0);
+        for _ in 0..10 {
+            grip_process_request();
+        }
+        std::thread::sleep(Duration::from_millis(150));
+        grip_process_request();
+        assert!(CALLBACKS.lock().unwrap().is_empty());
+    }
+
+    assert!(harness.take_errors().is_empty());
+}
+
+#[test]
+fn test_plugin_queues() {
+    let harness = Harness::start();
+    let slow_url = c_string("http://example.com/slow");
+    let fast_url = c_string("http://example.com/fast");
+
+    unsafe {
+        for (url, delay) in &[(&slow_url, 10_000), (&fast_url, 0)] {
+            grip_mock_add_response(
+                amx(),
+                GET,
+                url.as_ptr(),
+                200,
+                c_string("ok").as_ptr(),
+                *delay,
+                -1,
+            );
+        }
+
+        assert_eq!(grip_create_queue(amx(), 0, 0, 1), 0);
+        assert!(harness.take_errors()[0].contains("Queue should have at least 1 worker thread"));
+        assert_eq!(grip_create_queue(amx(), 65, 0, 1), 0);
+        assert!(harness.take_errors()[0].contains("Queue should have at most 64 worker threads"));
+
+        let queue = grip_create_queue(amx(), 1, 1, 10);
+        assert!(queue > 0);
+        assert_eq!(grip_get_handle_count(amx(), HandleType::Queue as Cell), 1);
+
+        let request = |url: &CString, queue: Cell, user_data: Cell| {
+            grip_request(
+                amx(),
+                7,
+                url.as_ptr(),
+                -1,
+                GET,
+                Some(capture_response),
+                -1,
+                user_data,
+                queue,
+            )
+        };
+
+        // Request waits for the slot behind the slow one, but requests of other queues don't.
+        let slow = request(&slow_url, queue, 1);
+        let waiting = request(&fast_url, queue, 2);
+        request(&fast_url, DEFAULT_QUEUE, 3);
+
+        let callbacks = harness.wait_for_callbacks(1);
+        assert_eq!(callbacks[0].user_data, 3);
+        assert_eq!(
+            grip_mock_get_request_count(amx(), GET, fast_url.as_ptr()),
+            1
+        );
+        assert!(
+            run_command(&["status"]).contains(&"Pending requests: 2 (0 must complete)".to_owned())
+        );
+
+        // Pending requests of the destroyed queue are cancelled without callbacks.
+        assert_eq!(grip_destroy_queue(amx(), queue), 1);
+        assert_eq!(grip_is_request_active(slow), 0);
+        assert_eq!(grip_is_request_active(waiting), 0);
+        grip_process_request();
+        assert!(
+            run_command(&["status"]).contains(&"Pending requests: 0 (0 must complete)".to_owned())
+        );
+        assert!(CALLBACKS.lock().unwrap().is_empty());
+
+        assert_eq!(request(&fast_url, queue, 4), 0);
+        assert!(harness.take_errors()[0].contains("already destroyed"));
+
+        // Queue can be destroyed by its own callback.
+        let queue = grip_create_queue(amx(), 1, 0, 10);
+        grip_request(
+            amx(),
+            7,
+            fast_url.as_ptr(),
+            -1,
+            GET,
+            Some(destroy_own_queue),
+            -1,
+            queue,
+            queue,
+        );
+        assert_eq!(harness.wait_for_callbacks(1)[0].status, 200);
+        assert_eq!(grip_get_handle_count(amx(), HandleType::Queue as Cell), 8);
+    }
+
+    assert!(harness.take_errors().is_empty());
+}
+
+#[test]
+fn test_request_groups() {
+    let harness = Harness::start();
+    let url = c_string("http://example.com/stats");
+    let slow_url = c_string("http://example.com/slow");
+
+    unsafe {
+        grip_mock_add_response(
+            amx(),
+            GET,
+            slow_url.as_ptr(),
+            200,
+            c_string("").as_ptr(),
+            10_000,
+            -1,
+        );
+
+        let request = |url: &CString| {
+            grip_request(
+                amx(),
+                7,
+                url.as_ptr(),
+                -1,
+                GET,
+                Some(capture_response),
+                -1,
+                0,
+                DEFAULT_QUEUE,
+            )
+        };
+
+        let group =
+            grip_create_request_group(amx(), 5, Some(capture_group), Some(release_forward), 77);
+        let other_group =
+            grip_create_request_group(amx(), 6, Some(capture_group), Some(release_forward), 0);
+        assert!(group > 0);
+
+        let requests = [request(&url), request(&url), request(&slow_url)];
+        for &cancellation in &requests {
+            assert_eq!(grip_request_group_add(amx(), group, cancellation), 1);
+        }
+        assert_eq!(grip_request_group_add(amx(), other_group, requests[0]), 0);
+        assert!(harness.take_errors()[0].contains("is already added to another group"));
+
+        // Group completes after the last request, even if it was cancelled.
+        assert_eq!(harness.wait_for_callbacks(2).len(), 2);
+        assert!(GROUP_CALLBACKS.lock().unwrap().is_empty());
+        assert_eq!(grip_request_group_seal(amx(), group), 1);
+        assert!(GROUP_CALLBACKS.lock().unwrap().is_empty());
+        assert_eq!(grip_request_group_add(amx(), group, request(&url)), 0);
+        assert!(harness.take_errors()[0].contains("is sealed"));
+        assert_eq!(harness.wait_for_callbacks(1).len(), 1);
+
+        assert_eq!(grip_request_group_cancel(amx(), group), 1);
+        assert_eq!(harness.wait_for_callbacks(1)[0].state, 1);
+        assert_eq!(*GROUP_CALLBACKS.lock().unwrap(), [[group, 2, 1, 77]]);
+        assert_eq!(*RELEASED_FORWARDS.lock().unwrap(), [5]);
+
+        // Completed group is destroyed.
+        assert_eq!(grip_request_group_add(amx(), group, request(&url)), 0);
+        assert!(harness.take_errors()[0].contains("already destroyed"));
+        assert_eq!(harness.wait_for_callbacks(1).len(), 1);
+
+        // Group doesn't complete before it's sealed, even if its requests are finished.
+        assert_eq!(grip_request_group_add(amx(), other_group, request(&url)), 1);
+        assert_eq!(harness.wait_for_callbacks(1).len(), 1);
+        assert_eq!(GROUP_CALLBACKS.lock().unwrap().len(), 1);
+        assert_eq!(grip_request_group_seal(amx(), other_group), 1);
+        assert_eq!(GROUP_CALLBACKS.lock().unwrap()[1], [other_group, 1, 0, 0]);
+
+        // Empty group completes right away.
+        let empty_group =
+            grip_create_request_group(amx(), 8, Some(capture_group), Some(release_forward), 0);
+        assert_eq!(grip_request_group_seal(amx(), empty_group), 1);
+        assert_eq!(GROUP_CALLBACKS.lock().unwrap()[2], [empty_group, 0, 0, 0]);
+        assert_eq!(*RELEASED_FORWARDS.lock().unwrap(), [5, 6, 8]);
+        assert!(harness.take_errors().is_empty());
+    }
+}
+
+#[test]
+fn test_request_chain() {
+    let harness = Harness::start();
+    let login_url = c_string("http://example.com/login");
+    let denied_url = c_string("http://example.com/denied");
+    let profile_url = c_string("http://example.com/users/5?token=a+b");
+
+    unsafe {
+        grip_mock_add_response(
+            amx(),
+            POST,
+            login_url.as_ptr(),
+            200,
+            c_string(r#"{"token": "a b", "user": {"id": 5}}"#).as_ptr(),
+            0,
+            -1,
+        );
+        grip_mock_add_response(
+            amx(),
+            POST,
+            denied_url.as_ptr(),
+            401,
+            c_string("denied").as_ptr(),
+            0,
+            -1,
+        );
+        grip_mock_add_response(
+            amx(),
+            GET,
+            profile_url.as_ptr(),
+            200,
+            c_string("profile").as_ptr(),
+            0,
+            -1,
+        );
+
+        let chain = |first_url: &CString, second_url: &str| {
+            let chain = grip_create_request_chain(amx());
+            assert_eq!(
+                grip_request_chain_add(amx(), chain, first_url.as_ptr(), -1, POST, -1),
+                1
+            );
+            assert_eq!(
+                grip_request_chain_add(amx(), chain, c_string(second_url).as_ptr(), -1, GET, -1),
+                2
+            );
+            chain
+        };
+
+        let profile = chain(
+            &login_url,
+            "http://example.com/users/{{0/user/id}}?token={{0/token}}",
+        );
+        let cancellation = grip_request_chain_send(
+            amx(),
+            profile,
+            7,
+            Some(capture_chain_response),
+            1,
+            DEFAULT_QUEUE,
+        );
+        assert!(cancellation > 0);
+
+        let callbacks = harness.wait_for_callbacks(1);
+        assert_eq!(callbacks[0].status, 200);
+        assert_eq!(callbacks[0].body, "profile");
+        assert_eq!(grip_is_request_active(cancellation), 0);
+
+        // Chain stops at the first non-2xx response.
+        let denied = chain(&denied_url, "http://example.com/users/{{0/user/id}}");
+        grip_request_chain_send(
+            amx(),
+            denied,
+            7,
+            Some(capture_chain_response),
+            2,
+            DEFAULT_QUEUE,
+        );
+        let callbacks = harness.wait_for_callbacks(1);
+        assert_eq!(callbacks[0].status, 401);
+        assert_eq!(*CHAIN_STEPS.lock().unwrap(), [1, 0]);
+        assert_eq!(
+            grip_mock_get_request_count(amx(), POST, login_url.as_ptr())
+                + grip_mock_get_request_count(amx(), POST, denied_url.as_ptr())
+                + grip_mock_get_request_count(amx(), GET, profile_url.as_ptr()),
+            3
+        );
+
+        // Steps can only reference earlier steps.
+        let invalid = chain(&login_url, "http://example.com/users/{{1/id}}");
+        assert_eq!(
+            grip_request_chain_send(
+                amx(),
+                invalid,
+                7,
+                Some(capture_chain_response),
+                3,
+                DEFAULT_QUEUE
+            ),
+            0
+        );
+        assert!(harness.take_errors()[0].contains(
+            "Chain template error: Step 1 references step 1, which is not sent before it"
+        ));
+
+        assert_eq!(grip_get_response_chain_step(amx()), 0);
+        assert!(harness.take_errors()[0].contains("only be received in the request chain callback"));
+    }
+}
+
+#[test]
+fn test_scheduled_requests() {
+    let harness = Harness::start_with_config(
+        "[scheduler]\n\
+         min-interval-milliseconds = 20\n",
+    );
+    let url = c_string("http://example.com/poll");
+    let slow_url = c_string("http://example.com/slow");
+
+    unsafe {
+        grip_mock_add_response(
+            amx(),
+            GET,
+            url.as_ptr(),
+            200,
+            c_string("ok").as_ptr(),
+            0,
+            -1,
+        );
+        grip_mock_add_response(
+            amx(),
+            GET,
+            slow_url.as_ptr(),
+            200,
+            c_string("").as_ptr(),
+            10_000,
+            -1,
+        );
+
+        let schedule = |url: &CString, delay: f64, interval: f64, user_data: Cell| {
+            grip_schedule_request(
+                amx(),
+                7,
+                url.as_ptr(),
+                -1,
+                GET,
+                Some(capture_scheduled_response),
+                Some(release_forward),
+                -1,
+                user_data,
+                delay,
+                interval,
+                0.0,
+                true,
+                DEFAULT_QUEUE,
+            )
+        };
+
+        assert_eq!(schedule(&url, 0.0, 0.01, 0), 0);
+        assert!(harness.take_errors()[0].contains("Interval should be at least 20 milliseconds"));
+
+        // Request, which is sent once, destroys its schedule after the handler.
+        let once = schedule(&url, 0.01, 0.0, 1);
+        assert_eq!(harness.wait_for_callbacks(1)[0].user_data, 1);
+        assert_eq!(*RELEASED_FORWARDS.lock().unwrap(), [7]);
+        assert_eq!(grip_pause_schedule(amx(), once), 0);
+        assert!(harness.take_errors()[0].contains("already destroyed"));
+
+        let recurring = schedule(&url, 0.0, 0.02, 2);
+        let callbacks = harness.wait_for_callbacks(3);
+        assert!(callbacks.iter().all(|callback| callback.user_data == 2));
+
+        assert_eq!(grip_pause_schedule(amx(), recurring), 1);
+        assert!(run_command(&["status"]).contains(&"Scheduled requests: 1 (1 paused)".to_owned()));
+        let requests = grip_mock_get_request_count(amx(), GET, url.as_ptr());
+        for _ in 0..50 {
+            grip_process_request();
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert!(grip_mock_get_request_count(amx(), GET, url.as_ptr()) <= requests + 1);
+
+        assert_eq!(grip_resume_schedule(amx(), recurring), 1);
+        harness.wait_for_callbacks(1);
+        assert_eq!(grip_cancel_schedule(amx(), recurring), 1);
+        assert_eq!(*RELEASED_FORWARDS.lock().unwrap(), [7, 7]);
+
+        // Next request waits for the previous one, which is cancelled with the schedule.
+        let slow = schedule(&slow_url, 0.0, 0.02, 3);
+        for _ in 0..100 {
+            grip_process_request();
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(
+            grip_mock_get_request_count(amx(), GET, slow_url.as_ptr()),
+            1
+        );
+        assert_eq!(grip_cancel_schedule(amx(), slow), 1);
+        for _ in 0..10 {
+            grip_process_request();
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert!(CALLBACKS.lock().unwrap().is_empty());
+        assert_eq!(
+            grip_get_handle_count(amx(), HandleType::Schedule as Cell),
+            0
+        );
+    }
+}
+
+#[test]
+fn test_handle_quota() {
+    let harness = Harness::start_with_config(
+        "[handles]\n\
+         max-handles-per-plugin = 2\n",
+    );
+
+    unsafe {
+        let first = grip_json_init_object(amx());
+        assert_ne!(grip_json_init_array(amx()), 0);
+        assert_eq!(grip_json_init_null(amx()), 0);
+        let errors = harness.take_errors();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains(
+            "Plugin already has 2 JSON value handles, \
+             which is the quota set by \"handles.max-handles-per-plugin\" in the grip.ini"
+        ));
+
+        // Quota is per plugin and per type.
+        assert_ne!(grip_json_init_null(other_amx()), 0);
+        assert_ne!(grip_create_default_options(amx(), -1.0), 0);
+
+        assert_eq!(grip_destroy_json_value(amx(), first), 1);
+        assert_ne!(grip_json_init_null(amx()), 0);
+        assert!(harness.take_errors().is_empty());
+    }
+}
+
+#[test]
+fn test_handle_report() {
+    let _harness = Harness::start_with_config(
+        "[handles]\n\
+         warn-threshold = 2\n\
+         warn-interval-seconds = 0\n",
+    );
+
+    unsafe {
+        let string = c_string("{}");
+        for _ in 0..2 {
+            grip_json_parse_string(
+                amx(),
+                string.as_ptr() as *mut c_char,
+                std::ptr::null_mut(),
+                0,
+            );
+        }
+        assert!(check_thresholds(Instant::now()).is_empty());
+
+        grip_json_init_object(amx());
+        grip_create_default_options(other_amx(), -1.0);
+
+        assert_eq!(
+            handle_report(),
+            [
+                "harness.amxx: 3 handles",
+                "    2 JSON value created by grip_json_parse_string",
+                "    1 JSON value created by grip_json_init_object",
+                "other.amxx: 1 handles",
+                "    1 options created by grip_create_default_options",
+            ]
+        );
+        assert_eq!(
+            check_thresholds(Instant::now()),
+            [
+                "[gRIP] Warning: harness.amxx holds 3 JSON value handles, which is more than 2. \
+              2 of them were created by grip_json_parse_string"
+            ]
+        );
+    }
+}
+
+#[test]
+fn test_console_commands() {
+    let harness = Harness::start();
+    let url = c_string("http://example.com/slow");
+
+    unsafe {
+        assert_eq!(run_command(&[])[0], "Usage: grip <command>");
+        assert_eq!(run_command(&["pending"]), ["No pending requests"]);
+
+        grip_mock_add_response(
+            amx(),
+            GET,
+            url.as_ptr(),
+            200,
+            c_string("").as_ptr(),
+            10_000,
+            -1,
+        );
+        grip_request(
+            amx(),
+            7,
+            url.as_ptr(),
+            -1,
+            GET,
+            Some(capture_response),
+            -1,
+            1,
+            DEFAULT_QUEUE,
+        );
+
+        let pending = run_command(&["pending"]);
+        assert_eq!(pending.len(), 2);
+        assert!(pending[1].contains("GET"));
+        assert!(pending[1].ends_with(" ms http://example.com/slow"));
+
+        let id = pending[1].split_whitespace().next().unwrap();
+        assert_eq!(
+            run_command(&["cancel", id]),
+            [format!("Request {} was cancelled", id)]
+        );
+        assert_eq!(harness.wait_for_callbacks(1)[0].state, 1);
+        assert_eq!(
+            run_command(&["cancel", id]),
+            [format!("No pending request with id {}", id)]
+        );
+
+        let status = run_command(&["status"]);
+        assert!(status.contains(&"Transport: mock".to_owned()));
+        assert!(status.contains(&"Pending requests: 0 (0 must complete)".to_owned()));
+
+        std::fs::write(
+            &harness.config_path,
+            "[queue]\n\
+             callbacks-per-frame = 5\n\
+             microseconds-delay-between-attempts = 0\n",
+        )
+        .unwrap();
+        assert!(run_command(&["reload"])[0].starts_with("Reloaded "));
+        assert_eq!(get_module().callbacks_per_frame, 5);
+
+        // Invalid config doesn't change anything.
+        std::fs::write(
+            &harness.config_path,
+            "[queue]\n\
+             callbacks-per-frame = many\n\
+             microseconds-delay-between-attempts = 0\n",
+        )
+        .unwrap();
+        assert_eq!(
+            run_command(&["reload"]),
+            [
+                "Invalid \"queue.callbacks-per-frame\" key in the grip.ini config: many: \
+                 invalid digit found in string",
+                "Config is invalid, settings were not changed",
+            ]
+        );
+
+        let mut error = [0 as c_char; 256];
+        assert_eq!(grip_reload_config(amx(), error.as_mut_ptr(), 255), 0);
+        assert!(CStr::from_ptr(error.as_ptr())
+            .to_string_lossy()
+            .starts_with("Invalid \"queue.callbacks-per-frame\" key"));
+        assert_eq!(get_module().callbacks_per_frame, 5);
+
+        assert!(run_command(&["gc"])[0].starts_with("Collections: "));
+        assert!(harness.take_errors().is_empty());
+    }
+}
@@ -0,0 +1,155 @@
+/*
+ * gRIP
+ * Copyright (c) 2018 Alik Aslanyan <cplusplus256@gmail.com>
+ *
+ *
+ *    This program is free software; you can redistribute it and/or modify it
+ *    under the terms of the GNU General Public License as published by the
+ *    Free Software Foundation; either version 3 of the License, or (at
+ *    your option) any later version.
+ *
+ *    This program is distributed in the hope that it will be useful, but
+ *    WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ *    General Public License for more details.
+ *
+ *    You should have received a copy of the GNU General Public License
+ *    along with this program; if not, write to the Free Software Foundation,
+ *    Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ *    In addition, as a special exception, the author gives permission to
+ *    link the code of this program with the Half-Life Game Engine ("HL
+ *    Engine") and Modified Game Libraries ("MODs") developed by Valve,
+ *    L.L.C ("Valve").  You must obey the GNU General Public License in all
+ *    respects for all of the code used other than the HL Engine and MODs
+ *    from Valve.  If you modify this file, you may extend this exception
+ *    to your version of the file, but you are not obligated to do so.  If
+ *    you do not wish to do so, delete this exception statement from your
+ *    version.
+ *
+ */
+
+use super::*;
+
+use crate::transport::MockReply;
+
+unsafe fn get_mock_transport() -> Result<&'static MockTransport> {
+    get_module()
+        .mock_transport
+        .as_ref()
+        .map(|transport| &**transport)
+        .chain_err(|| {
+            ffi_error(
+                "Mock transport is disabled. Set \"transport.type = mock\" in the grip.ini config",
+            )
+        })
+}
+
+unsafe fn parse_url(url: *const c_char) -> Result<reqwest::Url> {
+    let url = str_from_ptr(url)?;
+    url.parse()
+        .chain_err(|| ffi_error(format!("URL parsing error: {}", url)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_mock_add_response(
+    amx: *const c_void,
+    request_type: Cell,
+    url: *const c_char,
+    status: Cell,
+    body: *const c_char,
+    delay_milliseconds: Cell,
+    options_handle: Cell,
+) -> Cell {
+    let transport = try_and_log_ffi!(amx, get_mock_transport());
+    let request_type = try_and_log_ffi!(amx, parse_request_type(request_type));
+    let url = try_and_log_ffi!(amx, parse_url(url));
+
+    let status = try_and_log_ffi!(
+        amx,
+        reqwest::StatusCode::from_u16(status as u16)
+            .ok()
+            .filter(|_| status > 0 && status <= 0xFFFF)
+            .chain_err(|| ffi_error(format!("Invalid status code {}", status)))
+    );
+
+    let body = CStr::from_ptr(try_and_log_ffi!(
+        amx,
+        ptr_to_option(body).chain_err(|| ffi_error("Invalid body."))
+    ))
+    .to_bytes()
+    .to_vec();
+
+    let mut reply =
+        MockReply::new(status, body)
+            .with_delay(std::time::Duration::from_millis(
+                try_as_usize!(amx, delay_milliseconds) as u64,
+            ));
+
+    if options_handle != -1 {
+        reply = reply.with_headers(
+            &try_and_log_ffi!(
+                amx,
+                get_module()
+                    .options_handles
+                    .get(options_handle)
+            )
+            .headers,
+        );
+    }
+
+    transport.add_reply(request_type, &url, reply);
+
+    1
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_mock_add_failure(
+    amx: *const c_void,
+    request_type: Cell,
+    url: *const c_char,
+    error_kind: Cell,
+    delay_milliseconds: Cell,
+) -> Cell {
+    let transport = try_and_log_ffi!(amx, get_mock_transport());
+    let request_type = try_and_log_ffi!(amx, parse_request_type(request_type));
+    let url = try_and_log_ffi!(amx, parse_url(url));
+
+    let error_kind = try_and_log_ffi!(
+        amx,
+        ErrorCategory::from_id(error_kind)
+            .chain_err(|| ffi_error(format!("Invalid error kind {}", error_kind)))
+    );
+
+    transport.add_reply(
+        request_type,
+        &url,
+        MockReply::failure(error_kind).with_delay(std::time::Duration::from_millis(try_as_usize!(
+            amx,
+            delay_milliseconds
+        )
+            as u64)),
+    );
+
+    1
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_mock_get_request_count(
+    amx: *const c_void,
+    request_type: Cell,
+    url: *const c_char,
+) -> Cell {
+    let transport = try_and_log_ffi!(amx, get_mock_transport());
+    let request_type = try_and_log_ffi!(amx, parse_request_type(request_type));
+    let url = try_and_log_ffi!(amx, parse_url(url));
+
+    transport.received_count(request_type, &url) as Cell
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_mock_reset(amx: *const c_void) -> Cell {
+    try_and_log_ffi!(amx, get_mock_transport()).reset();
+
+    1
+}
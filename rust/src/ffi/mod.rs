@@ -32,17 +32,32 @@
 extern crate ini;
 extern crate libc;
 
-use self::ini::Ini;
-
 #[macro_use]
 mod ext;
 
 mod strlcpy;
 
+mod chain;
+mod config;
+mod console;
+mod group;
+mod handles;
+mod mock;
+mod queue;
+mod schedule;
+mod server;
+mod sse;
+mod websocket;
+
+#[cfg(test)]
+mod harness;
+
 use serde_json::json;
 
 use crate::ffi::ext::*;
 
+use self::config::{Config, HarMode, TransportType};
+
 use self::libc::{c_char, c_void};
 
 use std::ffi::CStr;
@@ -55,23 +70,68 @@ use lazy_static::*;
 type Cell = isize;
 
 use crate::networking_queue::{
-    Queue, RequestBuilder, RequestCancellation, RequestOptions, RequestType, Response,
+    DrainPolicy, Queue, QueueConfigBuilder, RequestBuilder, RequestCancellation, RequestOptions,
+    RequestType, Response, QueueConfig,
 };
 use std::prelude::v1::Vec;
 
-use crate::cell_map::CellMap;
+use crate::cell_map::{CellMap, HandleType};
+use crate::chain::ChainStep;
+use crate::error_category::ErrorCategory;
+use crate::har::{HarRecorder, HarReplay};
+use crate::http_server::{BackgroundServer, HttpServer};
+use crate::logger;
+use crate::metrics::Gauges;
+use crate::response_body;
+use crate::transport::{MockTransport, Transport};
+use crate::websocket::WebSocketOptions;
 use crate::gc_json::*;
 use std::cell::RefCell;
+use std::sync::Arc;
 use std::panic::catch_unwind;
 
 struct ModuleStorage {
     pub global_queue: Queue,
+    pub queues_handles: CellMap<self::queue::QueueHandle>,
+    // Dropped after the callbacks, because destroyed queue may be executing one.
+    pub destroyed_queues: Vec<self::queue::QueueHandle>,
+    // Queues created by plugins are configured the same way as the global queue.
+    pub queue_config: QueueConfig,
     pub current_response: Option<Result<Response>>,
+    // Cancelled requests of the paused and unloading plugins, which handlers are not called.
+    pub silenced_requests: std::collections::HashSet<Cell>,
+    // Group of every pending request, which was added to one.
+    pub grouped_requests: std::collections::HashMap<Cell, Cell>,
+    // Index of the last sent step, when callback of the request chain is executed.
+    pub current_chain_step: Option<usize>,
     pub bodies_handles: CellMap<Vec<u8>>,
+    pub responses_handles: CellMap<Response>,
     pub cancellations_handles: CellMap<RequestCancellation>,
+    pub request_groups_handles: CellMap<self::group::RequestGroupHandle>,
+    pub request_chains_handles: CellMap<Vec<ChainStep>>,
+    pub schedules_handles: CellMap<self::schedule::ScheduleHandle>,
     pub json_handles: CellMap<GCValue>,
     pub options_handles: CellMap<RequestOptions>,
+    pub subscriptions_handles: CellMap<self::sse::SubscriptionHandle>,
+    pub current_sse_event: Option<crate::sse::SubscriptionEvent>,
+    pub websockets_handles: CellMap<self::websocket::WebSocketHandle>,
+    pub current_websocket_event: Option<crate::websocket::Event>,
+    pub websocket_options: WebSocketOptions,
+    pub http_server: Option<HttpServer>,
+    pub server_routes: Vec<self::server::Route>,
+    pub server_requests_handles: CellMap<self::server::ServerRequestHandle>,
+    // Serves metrics endpoint, until module is deinitialized.
+    pub _metrics_server: Option<BackgroundServer>,
+    pub mock_transport: Option<Arc<MockTransport>>,
+    pub har_mode: HarMode,
+    pub drain_policy: DrainPolicy,
+    pub handle_settings: self::handles::HandleSettings,
+    pub scheduler_settings: self::schedule::SchedulerSettings,
+    pub last_handles_check: std::time::Instant,
+    pub config_file_path: String,
     pub error_logger: extern "C" fn(*const c_void, *const c_char),
+    // Returns file name of the plugin, or null if plugin isn't loaded.
+    pub plugin_name_resolver: extern "C" fn(*const c_void) -> *const c_char,
     pub callbacks_per_frame: usize,
     pub microseconds_delay_between_attempts: usize,
 }
@@ -81,62 +141,208 @@ static mut MODULE: Option<ModuleStorage> = None;
 #[no_mangle]
 pub unsafe extern "C" fn grip_init(
     error_logger: extern "C" fn(*const c_void, *const c_char),
+    plugin_name_resolver: extern "C" fn(*const c_void) -> *const c_char,
     config_file_path: *const c_char,
 ) {
     if MODULE.is_some() {
         return;
     }
 
-    let ini = Ini::load_from_file(str_from_ptr(config_file_path).unwrap())
-        .map_err(|e| {
-            println!(
-                "Error: Can't parse/open grip config. Examine carefully ini parser log message\n{}",
-                e
-            );
-            e
-        })
-        .unwrap();
+    let config_file_path = str_from_ptr(config_file_path).unwrap();
 
-    let queue_section = ini
-        .section(Some("queue".to_owned()))
-        .or_else(|| {
-            println!("Error: Missing [queue] section in the grip.ini config");
-            None
-        })
+    // Invalid settings shouldn't take down the server, defaults are used instead.
+    let (config, errors) = Config::load(config_file_path);
+    for error in &errors {
+        println!("[gRIP] Error: {}", error);
+    }
+    if !errors.is_empty() {
+        println!("[gRIP] Warning: Defaults are used instead of the invalid settings");
+    }
+    let runtime_settings = config.runtime;
+
+    let http_server = match config.server {
+        Some(server_config) => {
+            if server_config.auth_token.is_none() && !server_config.bind_address.ip().is_loopback()
+            {
+                println!(
+                    "[gRIP] Warning: Embedded HTTP server listens on {} without \"server.auth-token\"",
+                    server_config.bind_address
+                );
+            }
+
+            // Server is optional, so failure to start it shouldn't take down the whole module.
+            match HttpServer::start(server_config) {
+                Ok(server) => Some(server),
+                Err(e) => {
+                    use error_chain::ChainedError;
+                    println!("[gRIP] Error: {}", e.display_chain());
+                    None
+                }
+            }
+        }
+        None => None,
+    };
+
+    // Recorded archive shouldn't keep the credentials, which aren't logged either.
+    let redaction = config.log.clone().unwrap_or_default();
+
+    if let Some(logger_config) = config.log {
+        // Logging is optional, so failure to open the file shouldn't take down the whole module.
+        if let Err(e) = logger::init(logger_config) {
+            use error_chain::ChainedError;
+            println!("[gRIP] Error: {}", e.display_chain());
+        }
+    }
+
+    let mock_transport = match config.transport {
+        TransportType::Network => None,
+        TransportType::Mock => {
+            println!("[gRIP] Warning: Mock transport is enabled. Requests are served from the replies added by plugins");
+            Some(Arc::new(MockTransport::new()))
+        }
+    };
+
+    let mut queue_config = QueueConfigBuilder::default();
+    if let Some(ref mock_transport) = mock_transport {
+        queue_config.transport(mock_transport.clone() as Arc<dyn Transport>);
+    }
+
+    let har_result: Result<()> = match (config.har_mode, config.har_path.clone()) {
+        (HarMode::Off, _) => Ok(()),
+        (HarMode::Record, Some(path)) => HarRecorder::new(path, redaction.clone()).map(|recorder| {
+            queue_config.recorder(Some(Arc::new(recorder)));
+        }),
+        (HarMode::Replay, Some(path)) => HarReplay::load(&path, redaction.clone()).map(|replay| {
+            queue_config.transport(Arc::new(replay));
+        }),
+        (_, None) => Err("Missing \"har.path\" key in the grip.ini config".into()),
+    };
+
+    if let Err(e) = har_result {
+        // Missing path was already reported with the rest of the config.
+        if config.har_path.is_some() {
+            use error_chain::ChainedError;
+            println!("[gRIP] Error: {}", e.display_chain());
+        }
+
+        // Don't let QA accidentally hit real backends, every request fails instead.
+        if config.har_mode == HarMode::Replay {
+            queue_config.transport(Arc::new(HarReplay::default()));
+        }
+    }
+
+    if config.har_mode == HarMode::Replay {
+        println!("[gRIP] Warning: HAR replay mode is enabled. Requests are served from the recorded responses");
+    }
+
+    let queue_config = queue_config
+        .limits(runtime_settings.response_limits)
+        .build()
         .unwrap();
+    let global_queue = Queue::with_config(queue_config.clone());
+
+    let metrics_server = config.metrics_bind_address.and_then(|bind_address| {
+        match crate::metrics::serve(&bind_address, global_queue.metrics()) {
+            Ok(server) => Some(server),
+            Err(e) => {
+                use error_chain::ChainedError;
+                println!("[gRIP] Error: {}", e.display_chain());
+                None
+            }
+        }
+    });
 
     MODULE = Some(ModuleStorage {
-        global_queue: Queue::new(),
-        cancellations_handles: CellMap::new(),
+        global_queue,
+        queues_handles: CellMap::new(HandleType::Queue),
+        destroyed_queues: vec![],
+        queue_config,
+        cancellations_handles: CellMap::new(HandleType::Cancellation),
+        request_groups_handles: CellMap::new(HandleType::RequestGroup),
+        request_chains_handles: CellMap::new(HandleType::RequestChain),
+        schedules_handles: CellMap::new(HandleType::Schedule),
         current_response: None,
-        bodies_handles: CellMap::new(),
-        json_handles: CellMap::new(),
-        options_handles: CellMap::new(),
+        silenced_requests: Default::default(),
+        grouped_requests: Default::default(),
+        current_chain_step: None,
+        bodies_handles: CellMap::new(HandleType::Body),
+        responses_handles: CellMap::new(HandleType::Response),
+        json_handles: CellMap::new(HandleType::JSONValue),
+        options_handles: CellMap::new(HandleType::Options),
+        subscriptions_handles: CellMap::new(HandleType::Subscription),
+        current_sse_event: None,
+        websockets_handles: CellMap::new(HandleType::WebSocket),
+        current_websocket_event: None,
+        websocket_options: runtime_settings.websocket_options,
+        http_server,
+        server_routes: vec![],
+        server_requests_handles: CellMap::new(HandleType::ServerRequest),
+        _metrics_server: metrics_server,
+        mock_transport,
+        har_mode: config.har_mode,
+        drain_policy: runtime_settings.drain_policy,
+        handle_settings: runtime_settings.handle_settings,
+        scheduler_settings: runtime_settings.scheduler_settings,
+        last_handles_check: std::time::Instant::now(),
+        config_file_path: config_file_path.to_owned(),
         error_logger,
-        callbacks_per_frame: {
-            queue_section
-                .get("callbacks-per-frame")
-                .or_else(|| {
-                    println!(
-                        "Error: Missing \"queue.callbacks-per-frame\" key in the grip.ini config"
-                    );
-                    None
-                })
-                .unwrap()
-                .parse()
-                .unwrap()
-        },
-        microseconds_delay_between_attempts: {
-            queue_section
-                .get("microseconds-delay-between-attempts")
-                .or_else(|| {
-                    println!("Error: Missing \"queue.microseconds-delay-between-attempts\" key in the grip.ini config");
-                    None
-                }).unwrap()
-                .parse()
-                .unwrap()
-        },
+        plugin_name_resolver,
+        callbacks_per_frame: runtime_settings.callbacks_per_frame,
+        microseconds_delay_between_attempts: runtime_settings.microseconds_delay_between_attempts,
     });
+
+    self::handles::apply_quotas();
+}
+
+/// Parses the grip.ini again and applies settings, which don't need the module restart.
+/// Nothing is changed, if the config is invalid.
+unsafe fn reload_config() -> std::result::Result<(), Vec<String>> {
+    let (config, errors) = Config::load(&get_module().config_file_path);
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    let settings = config.runtime;
+
+    let module = get_module_mut();
+    module.callbacks_per_frame = settings.callbacks_per_frame;
+    module.microseconds_delay_between_attempts = settings.microseconds_delay_between_attempts;
+    module.queue_config.limits = settings.response_limits;
+    module.global_queue.set_limits(settings.response_limits);
+    for handle in module.queues_handles.ids() {
+        if let Some(handle) = module.queues_handles.get_mut_with_id(handle) {
+            handle.queue.set_limits(settings.response_limits);
+        }
+    }
+    // Connected sockets keep their reconnect delays.
+    module.websocket_options.min_reconnect_delay = settings.websocket_options.min_reconnect_delay;
+    module.websocket_options.max_reconnect_delay = settings.websocket_options.max_reconnect_delay;
+    module.drain_policy = settings.drain_policy;
+    module.handle_settings = settings.handle_settings;
+    self::handles::apply_quotas();
+    // Existing schedules keep their intervals.
+    module.scheduler_settings = settings.scheduler_settings;
+
+    Ok(())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_reload_config(
+    amx: *const c_void,
+    error_buffer: *mut c_char,
+    error_buffer_size: Cell,
+) -> Cell {
+    match reload_config() {
+        Ok(()) => 1,
+        Err(errors) => {
+            try_to_copy_unsafe_string!(amx, error_buffer, errors.join("\n"), error_buffer_size);
+            0
+        }
+    }
+}
+
+/// Handles are owned by the plugin, which created them.
+fn owner(amx: *const c_void) -> usize {
+    amx as usize
 }
 
 unsafe fn get_module() -> &'static ModuleStorage {
@@ -147,12 +353,77 @@ unsafe fn get_module_mut() -> &'static mut ModuleStorage {
     MODULE.as_mut().unwrap()
 }
 
+/// Waits for the pending requests according to the `[shutdown]` section of the config.
+/// Called before plugins are unloaded, so that callbacks can still be executed.
+#[no_mangle]
+pub unsafe extern "C" fn grip_drain() {
+    if MODULE.is_none() || get_module().drain_policy.timeout == std::time::Duration::from_secs(0) {
+        return;
+    }
+
+    let policy = get_module().drain_policy.clone();
+    if policy.only_must_complete {
+        let must_complete: std::collections::HashSet<u64> = get_module()
+            .queues()
+            .flat_map(|queue| queue.pending_requests())
+            .filter(|request| request.must_complete)
+            .map(|request| request.id)
+            .collect();
+
+        get_module_mut()
+            .cancellations_handles
+            .retain(|_, cancellation| must_complete.contains(&cancellation.request_id()));
+    }
+
+    // Queues are drained one by one, but all of them share the same timeout.
+    let deadline = std::time::Instant::now() + policy.timeout;
+    let mut queues = vec![self::queue::DEFAULT_QUEUE];
+    queues.extend(get_module().queues_handles.ids());
+
+    let mut report = crate::networking_queue::DrainReport::default();
+    for queue in queues {
+        let queue = match self::queue::get_queue_mut(queue) {
+            Ok(queue) => queue,
+            Err(_) => continue,
+        };
+
+        let now = std::time::Instant::now();
+        let queue_report = queue.drain(&DrainPolicy {
+            timeout: if now < deadline {
+                deadline - now
+            } else {
+                std::time::Duration::from_secs(0)
+            },
+            ..policy.clone()
+        });
+        report.completed += queue_report.completed;
+        report.abandoned.extend(queue_report.abandoned);
+    }
+    if report.completed > 0 {
+        println!(
+            "[gRIP] Completed {} pending requests on shutdown",
+            report.completed
+        );
+    }
+    for request in &report.abandoned {
+        println!(
+            "[gRIP] Warning: Abandoned {} {} on shutdown, pending for {} ms",
+            request.http_type.as_str(),
+            request.url,
+            request.started.elapsed().as_millis()
+        );
+    }
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn grip_deinit() {
     if MODULE.is_some() {
         get_module_mut().cancellations_handles.clear(); // Cancel all operations, before queue stopped.
+        get_module_mut().subscriptions_handles.clear();
+        get_module_mut().server_requests_handles.clear(); // Unblock clients, before server stopped.
     }
     MODULE = None;
+    logger::shutdown();
 }
 
 #[no_mangle]
@@ -161,8 +432,7 @@ pub unsafe extern "C" fn grip_destroy_body(amx: *const c_void, body: Cell) -> Ce
         amx,
         get_module_mut()
             .bodies_handles
-            .remove_with_id(body)
-            .chain_err(|| ffi_error(format!("Invalid body handle {}", body)))
+            .remove(body)
     );
 
     1
@@ -170,16 +440,115 @@ pub unsafe extern "C" fn grip_destroy_body(amx: *const c_void, body: Cell) -> Ce
 
 #[no_mangle]
 pub unsafe extern "C" fn grip_body_from_string(amx: *const c_void, str: *const c_char) -> Cell {
-    get_module_mut().bodies_handles.insert_with_unique_id(
-        CStr::from_ptr(try_and_log_ffi!(
-            amx,
-            ptr_to_option(str).chain_err(|| ffi_error("Invalid URI."))
-        ))
-        .to_bytes()
-        .to_vec(),
+    try_and_log_ffi!(
+        amx,
+        get_module_mut().bodies_handles.insert_owned(
+            owner(amx),
+            "grip_body_from_string",
+            CStr::from_ptr(try_and_log_ffi!(
+                amx,
+                ptr_to_option(str).chain_err(|| ffi_error("Invalid URI."))
+            ))
+            .to_bytes()
+            .to_vec(),
+        )
     )
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn grip_body_from_response(amx: *const c_void, response: Cell) -> Cell {
+    let body = try_and_log_ffi!(amx, get_response(response))
+        .body
+        .clone();
+
+    try_and_log_ffi!(
+        amx,
+        get_module_mut()
+            .bodies_handles
+            .insert_owned(owner(amx), "grip_body_from_response", body)
+    )
+}
+
+unsafe fn get_body(body: Cell) -> Result<&'static Vec<u8>> {
+    get_module().bodies_handles.get(body)
+}
+
+unsafe fn copy_bytes_as_string(dest: *mut c_char, bytes: &[u8]) {
+    std::ptr::copy_nonoverlapping(bytes.as_ptr() as *const c_char, dest, bytes.len());
+    *dest.add(bytes.len()) = 0;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_body_get_length(amx: *const c_void, body: Cell) -> Cell {
+    try_and_log_ffi!(amx, get_body(body)).len() as Cell
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_body_get_string(
+    amx: *const c_void,
+    body: Cell,
+    buffer: *mut c_char,
+    size: Cell,
+    offset: Cell,
+) -> Cell {
+    let body = try_and_log_ffi!(amx, get_body(body));
+    let size = try_as_usize!(amx, size);
+    let bytes = try_and_log_ffi!(
+        amx,
+        response_body::slice(body, try_as_usize!(amx, offset), None)
+    );
+    let bytes = &bytes[..response_body::utf8_prefix_len(bytes, size)];
+
+    copy_bytes_as_string(buffer, bytes);
+
+    bytes.len() as Cell
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_body_read_line(
+    amx: *const c_void,
+    body: Cell,
+    offset: *mut Cell,
+    buffer: *mut c_char,
+    size: Cell,
+) -> Cell {
+    let body = try_and_log_ffi!(amx, get_body(body));
+    let size = try_as_usize!(amx, size);
+
+    match response_body::next_line(body, try_as_usize!(amx, *offset), size) {
+        Ok(Some((line, next_offset))) => {
+            copy_bytes_as_string(buffer, line);
+            *offset = next_offset as Cell;
+
+            line.len() as Cell
+        }
+        Ok(None) => {
+            copy_bytes_as_string(buffer, &[]);
+
+            -1
+        }
+        // Loops over the lines stop on -1, otherwise they would never end.
+        Err(e) => {
+            use error_chain::ChainedError;
+            log_error(amx, format!("{}", e.display_chain()));
+            copy_bytes_as_string(buffer, &[]);
+
+            -1
+        }
+    }
+}
+
+/// Same values as `GripRequestType` in Pawn.
+fn parse_request_type(request_type: Cell) -> Result<RequestType> {
+    match request_type {
+        0 => Ok(RequestType::Get),
+        1 => Ok(RequestType::Post),
+        2 => Ok(RequestType::Put),
+        3 => Ok(RequestType::Delete),
+        _ => Err(ErrorKind::FFIError(format!("Invalid request type {}", request_type)).into()),
+    }
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn grip_request(
     amx: *const c_void,
@@ -190,16 +559,14 @@ pub unsafe extern "C" fn grip_request(
     handler: Option<extern "C" fn(forward_handle: Cell, user_data: Cell) -> c_void>,
     options_handle: Cell,
     user_data: Cell,
+    queue: Cell,
 ) -> Cell {
-    let request_type = try_and_log_ffi!(
+    let request_type = try_and_log_ffi!(amx, parse_request_type(request_type));
+    let queue = try_and_log_ffi!(amx, self::queue::get_queue_mut(queue));
+    // Request can't be taken back, once it was sent.
+    let next_cancellation_id = try_and_log_ffi!(
         amx,
-        match request_type {
-            0 => Ok(RequestType::Get),
-            1 => Ok(RequestType::Post),
-            2 => Ok(RequestType::Put),
-            3 => Ok(RequestType::Delete),
-            _ => Err(ErrorKind::FFIError(format!("Invalid request type {}", request_type)).into()),
-        }
+        get_module().cancellations_handles.next_id(owner(amx))
     );
 
     let uri = try_and_log_ffi!(
@@ -214,38 +581,29 @@ pub unsafe extern "C" fn grip_request(
 
     let body = try_and_log_ffi!(
         amx,
-        get_module()
-            .bodies_handles
-            .get_with_id(body_handle)
-            .or_else(|| if body_handle == -1 {
-                lazy_static! {
-                    static ref EMPTY_VEC: Vec<u8> = vec![];
-                }
-                Some(&EMPTY_VEC)
-            } else {
-                None
-            })
-            .chain_err(|| ffi_error(format!("Invalid body handle: {}", body_handle)))
+        if body_handle == -1 {
+            lazy_static! {
+                static ref EMPTY_VEC: Vec<u8> = vec![];
+            }
+            Ok(&*EMPTY_VEC)
+        } else {
+            get_module().bodies_handles.get(body_handle)
+        }
     );
 
     let options = try_and_log_ffi!(
         amx,
-        get_module()
-            .options_handles
-            .get_with_id(options_handle)
-            .or_else(|| if options_handle == -1 {
-                lazy_static! {
-                    static ref EMPTY_OPTIONS: RequestOptions = RequestOptions::default();
-                }
-                Some(&EMPTY_OPTIONS)
-            } else {
-                None
-            })
-            .chain_err(|| ffi_error(format!("Invalid options handle: {}", options_handle)))
+        if options_handle == -1 {
+            lazy_static! {
+                static ref EMPTY_OPTIONS: RequestOptions = RequestOptions::default();
+            }
+            Ok(&*EMPTY_OPTIONS)
+        } else {
+            get_module().options_handles.get(options_handle)
+        }
     );
 
-    let next_cancellation_id = get_module().cancellations_handles.peek_id();
-    let cancellation = get_module_mut().global_queue.send_request(
+    let cancellation = queue.send_request(
         RequestBuilder::default()
             .http_type(request_type)
             .body(body.clone())
@@ -258,6 +616,14 @@ pub unsafe extern "C" fn grip_request(
             .build()
             .unwrap(),
         move |response| {
+            let succeeded = response.is_ok();
+            if get_module_mut()
+                .silenced_requests
+                .remove(&next_cancellation_id)
+            {
+                self::group::request_finished(next_cancellation_id, succeeded);
+                return;
+            }
             get_module_mut().current_response = Some(response);
 
             handler.unwrap()(forward_id, user_data);
@@ -267,12 +633,17 @@ pub unsafe extern "C" fn grip_request(
                 .remove_with_id(next_cancellation_id);
 
             get_module_mut().current_response = None;
+
+            self::group::request_finished(next_cancellation_id, succeeded);
         },
     );
 
-    get_module_mut()
-        .cancellations_handles
-        .insert_with_unique_id(cancellation)
+    try_and_log_ffi!(
+        amx,
+        get_module_mut()
+            .cancellations_handles
+            .insert_owned(owner(amx), "grip_request", cancellation)
+    )
 }
 
 //cell grip_cancel_request(const void* amx, cell cancellation);
@@ -282,11 +653,7 @@ pub unsafe extern "C" fn grip_cancel_request(amx: *const c_void, cancellation: C
         amx,
         get_module_mut()
             .cancellations_handles
-            .remove_with_id(cancellation)
-            .chain_err(|| ffi_error(format!(
-                "Cancellation with the id {} doesn't exist.",
-                cancellation
-            )))
+            .remove(cancellation)
     );
 
     1
@@ -351,54 +718,264 @@ pub unsafe extern "C" fn grip_get_error_description(
     }
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn grip_get_error_kind(amx: *const c_void) -> Cell {
+    if let Err(e) = try_and_log_ffi!(
+        amx,
+        get_module()
+            .current_response
+            .as_ref()
+            .chain_err(|| ffi_error("No active response at this time"))
+    ) {
+        ErrorCategory::from(e) as Cell
+    } else {
+        try_and_log_ffi!(amx, Err(ffi_error("No error for this response.")))
+    }
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn grip_get_response_body_string(
     amx: *const c_void,
     buffer: *mut c_char,
     size: Cell,
+    response: Cell,
 ) -> Cell {
-    if let Ok(response) = try_and_log_ffi!(
+    let response = try_and_log_ffi!(amx, get_response(response));
+
+    try_to_copy_unsafe_string!(
         amx,
-        get_module()
+        buffer,
+        try_and_log_ffi!(amx, response.text()),
+        size
+    )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_get_response_status_code(
+    amx: *const c_void,
+    response: Cell,
+) -> Cell {
+    // Failed response has no status code, which is told apart from the invalid handle by -1.
+    if response == CURRENT_RESPONSE {
+        if let Some(Err(_)) = get_module().current_response {
+            log_error(
+                amx,
+                ffi_error("Error/Cancellation/Timeout occurred for this response.").to_string(),
+            );
+            return -1;
+        }
+    }
+
+    try_and_log_ffi!(amx, get_response(response))
+        .status_code
+        .as_u16() as Cell
+}
+
+/// SSE subscriptions and WebSockets always connect to the network, they aren't served by the transport.
+/// So they are refused, when plugins expect the network to be never touched.
+unsafe fn check_streams_allowed() -> Result<()> {
+    if get_module().har_mode == HarMode::Replay {
+        Err(ffi_error(
+            "Streams can't be opened in the HAR replay mode, because they aren't recorded",
+        ))
+    } else if get_module().mock_transport.is_some() {
+        Err(ffi_error(
+            "Streams can't be opened with the mock transport, because it can't script them",
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Same value as `Current_GripResponse` in Pawn.
+const CURRENT_RESPONSE: Cell = -1;
+
+unsafe fn get_response(response: Cell) -> Result<&'static Response> {
+    if response == CURRENT_RESPONSE {
+        match get_module()
             .current_response
             .as_ref()
-            .chain_err(|| ffi_error("No active response at this time"))
-    ) {
-        try_to_copy_unsafe_string!(
+            .chain_err(|| ffi_error("No active response at this time"))?
+        {
+            Ok(response) => Ok(response),
+            Err(_) => Err(ffi_error(
+                "Error/Cancellation/Timeout occurred for this response.",
+            )),
+        }
+    } else {
+        get_module().responses_handles.get(response)
+    }
+}
+
+unsafe fn get_response_body_slice(
+    response: Cell,
+    offset: Cell,
+    length: Cell,
+) -> Result<&'static [u8]> {
+    if offset < 0 {
+        bail!(ffi_error(format!(
+            "Offset {} should be greater or equal to zero.",
+            offset
+        )));
+    }
+
+    response_body::slice(
+        &get_response(response)?.body[..],
+        offset as usize,
+        if length < 0 { None } else { Some(length as usize) },
+    )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_response_detach(amx: *const c_void) -> Cell {
+    let response = try_and_log_ffi!(amx, get_response(CURRENT_RESPONSE)).clone();
+
+    try_and_log_ffi!(
+        amx,
+        get_module_mut()
+            .responses_handles
+            .insert_owned(owner(amx), "grip_response_detach", response)
+    )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_destroy_response(amx: *const c_void, response: Cell) -> Cell {
+    try_and_log_ffi!(
+        amx,
+        get_module_mut()
+            .responses_handles
+            .remove(response)
+    );
+
+    1
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_get_response_header(
+    amx: *const c_void,
+    name: *const c_char,
+    buffer: *mut c_char,
+    size: Cell,
+    response: Cell,
+) -> Cell {
+    let response = try_and_log_ffi!(amx, get_response(response));
+    let name = try_and_log_ffi!(amx, str_from_ptr(name));
+
+    match response.headers.get(name) {
+        Some(value) => try_to_copy_unsafe_string!(
             amx,
             buffer,
             try_and_log_ffi!(
                 amx,
-                std::str::from_utf8(&response.body[..])
-                    .chain_err(|| ffi_error("Unable to parse UTF-8"))
+                value
+                    .to_str()
+                    .chain_err(|| ffi_error(format!("Header {} is not valid string", name)))
             ),
             size
-        )
-    } else {
-        unconditionally_log_error!(
-            amx,
-            ffi_error("Error/Cancellation/Timeout occurred for this response.")
-        )
+        ),
+        None => {
+            try_to_copy_unsafe_string!(amx, buffer, "", size);
+            -1
+        }
     }
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn grip_get_response_status_code(amx: *const c_void) -> Cell {
-    if let Ok(response) = try_and_log_ffi!(
+pub unsafe extern "C" fn grip_get_response_url(
+    amx: *const c_void,
+    buffer: *mut c_char,
+    size: Cell,
+    response: Cell,
+) -> Cell {
+    try_to_copy_unsafe_string!(
         amx,
-        get_module()
-            .current_response
-            .as_ref()
-            .chain_err(|| ffi_error("No active response at this time"))
-    ) {
-        response.status_code.as_u16() as Cell
-    } else {
-        unconditionally_log_error!(
-            amx,
-            ffi_error("Error/Cancellation/Timeout occurred for this response.")
-        );
-        -1
-    }
+        buffer,
+        try_and_log_ffi!(amx, get_response(response)).base_request.uri,
+        size
+    )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_get_response_body_length(
+    amx: *const c_void,
+    response: Cell,
+) -> Cell {
+    try_and_log_ffi!(amx, get_response(response))
+        .body
+        .len() as Cell
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_get_response_body_bytes(
+    amx: *const c_void,
+    buffer: *mut u8,
+    size: Cell,
+    offset: Cell,
+    response: Cell,
+) -> Cell {
+    let size = try_as_usize!(amx, size);
+    let bytes = try_and_log_ffi!(
+        amx,
+        get_response_body_slice(response, offset, size as Cell)
+    );
+
+    std::ptr::copy_nonoverlapping(bytes.as_ptr(), buffer, bytes.len());
+
+    bytes.len() as Cell
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_get_response_body_hash(
+    amx: *const c_void,
+    hash_type: Cell,
+    buffer: *mut c_char,
+    size: Cell,
+    offset: Cell,
+    length: Cell,
+    response: Cell,
+) -> Cell {
+    let hash_type = try_and_log_ffi!(
+        amx,
+        response_body::HashType::from_id(hash_type)
+            .chain_err(|| ffi_error(format!("Invalid hash type {}", hash_type)))
+    );
+
+    let bytes = try_and_log_ffi!(amx, get_response_body_slice(response, offset, length));
+
+    try_to_copy_unsafe_string!(
+        amx,
+        buffer,
+        response_body::hash_to_hex(hash_type, bytes),
+        size
+    )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_get_response_body_base64(
+    amx: *const c_void,
+    buffer: *mut c_char,
+    size: Cell,
+    offset: Cell,
+    length: Cell,
+    response: Cell,
+) -> Cell {
+    let bytes = try_and_log_ffi!(amx, get_response_body_slice(response, offset, length));
+
+    try_to_copy_unsafe_string!(amx, buffer, base64::encode(bytes), size)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_get_response_body_hex(
+    amx: *const c_void,
+    buffer: *mut c_char,
+    size: Cell,
+    offset: Cell,
+    length: Cell,
+    response: Cell,
+) -> Cell {
+    let bytes = try_and_log_ffi!(amx, get_response_body_slice(response, offset, length));
+
+    try_to_copy_unsafe_string!(amx, buffer, response_body::to_hex(bytes), size)
 }
 
 #[no_mangle]
@@ -407,8 +984,7 @@ pub unsafe extern "C" fn grip_destroy_json_value(amx: *const c_void, json_value:
         amx,
         get_module_mut()
             .json_handles
-            .remove_with_id(json_value)
-            .chain_err(|| ffi_error(format!("Invalid json value handle {}", json_value)))
+            .remove(json_value)
     );
 
     1
@@ -418,23 +994,27 @@ pub unsafe extern "C" fn grip_destroy_json_value(amx: *const c_void, json_value:
 pub unsafe extern "C" fn grip_create_default_options(amx: *const c_void, timeout: f64) -> Cell {
     use float_cmp::ApproxEq;
 
-    get_module_mut()
-        .options_handles
-        .insert_with_unique_id(RequestOptions::new(
-            reqwest::header::HeaderMap::default(),
-            try_and_log_ffi!(
-                amx,
-                if timeout.approx_eq(&-1.0, std::f64::EPSILON, 2) {
-                    Ok(None)
-                } else if timeout >= 0.0 {
-                    Ok(Some(std::time::Duration::from_millis(
-                        (timeout * 1000.0) as u64,
-                    )))
-                } else {
-                    Err(ffi_error(format!("Invalid timeout: {}", timeout)))
-                }
-            ),
-        ))
+    let timeout = try_and_log_ffi!(
+        amx,
+        if timeout.approx_eq(&-1.0, std::f64::EPSILON, 2) {
+            Ok(None)
+        } else if timeout >= 0.0 {
+            Ok(Some(std::time::Duration::from_millis(
+                (timeout * 1000.0) as u64,
+            )))
+        } else {
+            Err(ffi_error(format!("Invalid timeout: {}", timeout)))
+        }
+    );
+
+    try_and_log_ffi!(
+        amx,
+        get_module_mut().options_handles.insert_owned(
+            owner(amx),
+            "grip_create_default_options",
+            RequestOptions::new(reqwest::header::HeaderMap::default(), timeout, None, false),
+        )
+    )
 }
 
 #[no_mangle]
@@ -443,8 +1023,7 @@ pub unsafe extern "C" fn grip_destroy_options(amx: *const c_void, options_handle
         amx,
         get_module_mut()
             .options_handles
-            .remove_with_id(options_handle)
-            .chain_err(|| ffi_error(format!("Invalid options handle {}", options_handle)))
+            .remove(options_handle)
     );
 
     1
@@ -461,8 +1040,7 @@ pub unsafe extern "C" fn grip_options_add_header(
         amx,
         get_module_mut()
             .options_handles
-            .get_mut_with_id(options_handle)
-            .chain_err(|| ffi_error(format!("Invalid options handle: {}", options_handle)))
+            .get_mut(options_handle)
     );
 
     let header_name = try_and_log_ffi!(
@@ -489,6 +1067,80 @@ pub unsafe extern "C" fn grip_options_add_header(
     1
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn grip_options_set_max_response_body_size(
+    amx: *const c_void,
+    options_handle: Cell,
+    max_size: Cell,
+) -> Cell {
+    let max_size = try_as_usize!(amx, max_size);
+
+    try_and_log_ffi!(
+        amx,
+        get_module_mut()
+            .options_handles
+            .get_mut(options_handle)
+    )
+    .max_response_body_size = Some(max_size);
+
+    1
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_options_set_must_complete(
+    amx: *const c_void,
+    options_handle: Cell,
+    must_complete: bool,
+) -> Cell {
+    try_and_log_ffi!(
+        amx,
+        get_module_mut()
+            .options_handles
+            .get_mut(options_handle)
+    )
+    .must_complete = must_complete;
+
+    1
+}
+
+unsafe fn collect_gauges() -> Gauges {
+    let module = get_module();
+
+    Gauges {
+        pending_requests: module
+            .queues()
+            .map(|queue| queue.number_of_pending_requests())
+            .sum(),
+        handles: vec![
+            ("json", module.json_handles.len()),
+            ("body", module.bodies_handles.len()),
+            ("options", module.options_handles.len()),
+            ("response", module.responses_handles.len()),
+            ("cancellation", module.cancellations_handles.len()),
+            ("websocket", module.websockets_handles.len()),
+            ("sse_subscription", module.subscriptions_handles.len()),
+            ("server_request", module.server_requests_handles.len()),
+            ("queue", module.queues_handles.len()),
+            ("request_group", module.request_groups_handles.len()),
+            ("request_chain", module.request_chains_handles.len()),
+            ("schedule", module.schedules_handles.len()),
+        ],
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_get_stats(amx: *const c_void) -> Cell {
+    let metrics = get_module().global_queue.metrics();
+    metrics.set_gauges(collect_gauges());
+
+    try_and_log_ffi!(
+        amx,
+        get_module_mut()
+            .json_handles
+            .insert_owned(owner(amx), "grip_get_stats", metrics.to_json().into())
+    )
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn grip_process_request() {
     let multiplier = std::cmp::min(
@@ -503,6 +1155,20 @@ pub unsafe extern "C" fn grip_process_request() {
         get_module().callbacks_per_frame * multiplier,
         std::time::Duration::from_micros(get_module().microseconds_delay_between_attempts as u64),
     );
+    self::queue::process_queues();
+    self::schedule::process_schedules();
+
+    self::websocket::process_websocket_events(get_module().callbacks_per_frame);
+    self::server::process_server_requests(get_module().callbacks_per_frame);
+
+    for warning in self::handles::check_thresholds(std::time::Instant::now()) {
+        println!("{}", warning);
+    }
+
+    get_module()
+        .global_queue
+        .metrics()
+        .set_gauges(collect_gauges());
 
     collect_cycles_if_needed();
 }
@@ -512,37 +1178,30 @@ pub unsafe extern "C" fn grip_json_parse_response_body(
     amx: *const c_void,
     error_buffer: *mut c_char,
     error_buffer_size: Cell,
+    response: Cell,
 ) -> Cell {
-    if let Ok(response) = try_and_log_ffi!(
-        amx,
-        get_module()
-            .current_response
-            .as_ref()
-            .chain_err(|| ffi_error("No active response at this time"))
-    ) {
-        let value: Result<serde_json::Value> =
-            serde_json::from_slice(&response.body[..]).map_err(|e| ErrorKind::JSONError(e).into());
+    let response = try_and_log_ffi!(amx, get_response(response));
 
-        match value {
-            Ok(value) => get_module_mut()
+    let value: Result<serde_json::Value> =
+        serde_json::from_slice(&response.body[..]).map_err(|e| ErrorKind::JSONError(e).into());
+
+    match value {
+        Ok(value) => try_and_log_ffi!(
+            amx,
+            get_module_mut()
                 .json_handles
-                .insert_with_unique_id(value.into()),
-            Err(error) => {
-                use error_chain::ChainedError;
-                try_to_copy_unsafe_string!(
-                    amx,
-                    error_buffer,
-                    error.display_chain(),
-                    error_buffer_size
-                );
-                0
-            }
+                .insert_owned(owner(amx), "grip_json_parse_response_body", value.into())
+        ),
+        Err(error) => {
+            use error_chain::ChainedError;
+            try_to_copy_unsafe_string!(
+                amx,
+                error_buffer,
+                error.display_chain(),
+                error_buffer_size
+            );
+            0
         }
-    } else {
-        unconditionally_log_error!(
-            amx,
-            ffi_error("Error/Cancellation/Timeout occurred for this response.")
-        )
     }
 }
 
@@ -563,9 +1222,12 @@ pub unsafe extern "C" fn grip_json_parse_string(
     .map_err(|e| ErrorKind::JSONError(e).into());
 
     match value {
-        Ok(value) => get_module_mut()
-            .json_handles
-            .insert_with_unique_id(value.into()),
+        Ok(value) => try_and_log_ffi!(
+            amx,
+            get_module_mut()
+                .json_handles
+                .insert_owned(owner(amx), "grip_json_parse_string", value.into())
+        ),
         Err(error) => {
             use error_chain::ChainedError;
             try_to_copy_unsafe_string!(amx, error_buffer, error.display_chain(), error_buffer_size);
@@ -595,9 +1257,12 @@ pub unsafe extern "C" fn grip_json_parse_file(
         .map_err(|e| ErrorKind::JSONError(e).into());
 
     match value {
-        Ok(value) => get_module_mut()
-            .json_handles
-            .insert_with_unique_id(value.into()),
+        Ok(value) => try_and_log_ffi!(
+            amx,
+            get_module_mut()
+                .json_handles
+                .insert_owned(owner(amx), "grip_json_parse_file", value.into())
+        ),
         Err(error) => {
             use error_chain::ChainedError;
             try_to_copy_unsafe_string!(amx, error_buffer, error.display_chain(), error_buffer_size);
@@ -612,16 +1277,14 @@ pub unsafe extern "C" fn grip_json_equals(amx: *const c_void, value1: Cell, valu
         amx,
         get_module()
             .json_handles
-            .get_with_id(value1)
-            .chain_err(|| ffi_error(format!("value1 handle {} is invalid", value1)))
+            .get(value1)
     );
 
     let value2 = try_and_log_ffi!(
         amx,
         get_module()
             .json_handles
-            .get_with_id(value2)
-            .chain_err(|| ffi_error(format!("value2 {} handle is invalid", value2)))
+            .get(value2)
     );
 
     if value2 == value1 {
@@ -644,57 +1307,82 @@ pub unsafe extern "C" fn grip_json_get_type(amx: *const c_void, value: Cell) ->
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn grip_json_init_object() -> Cell {
-    get_module_mut()
-        .json_handles
-        .insert_with_unique_id(gc_json!({}))
+pub unsafe extern "C" fn grip_json_init_object(amx: *const c_void) -> Cell {
+    try_and_log_ffi!(
+        amx,
+        get_module_mut()
+            .json_handles
+            .insert_owned(owner(amx), "grip_json_init_object", gc_json!({}))
+    )
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn grip_json_init_array() -> Cell {
-    get_module_mut()
-        .json_handles
-        .insert_with_unique_id(gc_json!([]))
+pub unsafe extern "C" fn grip_json_init_array(amx: *const c_void) -> Cell {
+    try_and_log_ffi!(
+        amx,
+        get_module_mut()
+            .json_handles
+            .insert_owned(owner(amx), "grip_json_init_array", gc_json!([]))
+    )
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn grip_json_init_string(amx: *const c_void, string: *mut c_char) -> Cell {
-    get_module_mut()
-        .json_handles
-        .insert_with_unique_id(gc_json!(try_and_log_ffi!(
-            amx,
-            str_from_ptr(string)
-                .chain_err(|| ffi_error("Invalid string. Can't create UTF-8 string"))
-        )
-        .to_owned()))
+    let string = try_and_log_ffi!(
+        amx,
+        str_from_ptr(string).chain_err(|| ffi_error("Invalid string. Can't create UTF-8 string"))
+    );
+
+    try_and_log_ffi!(
+        amx,
+        get_module_mut()
+            .json_handles
+            .insert_owned(
+                owner(amx),
+                "grip_json_init_string",
+                gc_json!(string.to_owned())
+            )
+    )
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn grip_json_init_number(value: Cell) -> Cell {
-    get_module_mut()
-        .json_handles
-        .insert_with_unique_id(gc_json!(value))
+pub unsafe extern "C" fn grip_json_init_number(amx: *const c_void, value: Cell) -> Cell {
+    try_and_log_ffi!(
+        amx,
+        get_module_mut()
+            .json_handles
+            .insert_owned(owner(amx), "grip_json_init_number", gc_json!(value))
+    )
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn grip_json_init_float(value: f64) -> Cell {
-    get_module_mut()
-        .json_handles
-        .insert_with_unique_id(gc_json!(value))
+pub unsafe extern "C" fn grip_json_init_float(amx: *const c_void, value: f64) -> Cell {
+    try_and_log_ffi!(
+        amx,
+        get_module_mut()
+            .json_handles
+            .insert_owned(owner(amx), "grip_json_init_float", gc_json!(value))
+    )
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn grip_json_init_bool(value: bool) -> Cell {
-    get_module_mut()
-        .json_handles
-        .insert_with_unique_id(gc_json!(value))
+pub unsafe extern "C" fn grip_json_init_bool(amx: *const c_void, value: bool) -> Cell {
+    try_and_log_ffi!(
+        amx,
+        get_module_mut()
+            .json_handles
+            .insert_owned(owner(amx), "grip_json_init_bool", gc_json!(value))
+    )
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn grip_json_init_null() -> Cell {
-    get_module_mut()
-        .json_handles
-        .insert_with_unique_id(gc_json!(null))
+pub unsafe extern "C" fn grip_json_init_null(amx: *const c_void) -> Cell {
+    try_and_log_ffi!(
+        amx,
+        get_module_mut()
+            .json_handles
+            .insert_owned(owner(amx), "grip_json_init_null", gc_json!(null))
+    )
 }
 
 #[no_mangle]
@@ -767,9 +1455,16 @@ pub unsafe extern "C" fn grip_json_array_get_value(
     array: Cell,
     index: Cell,
 ) -> Cell {
-    get_module_mut()
-        .json_handles
-        .insert_with_unique_id(try_to_get_json_array_value!(amx, array, index).clone())
+    try_and_log_ffi!(
+        amx,
+        get_module_mut()
+            .json_handles
+            .insert_owned(
+                owner(amx),
+                "grip_json_array_get_value",
+                try_to_get_json_array_value!(amx, array, index).clone()
+            )
+    )
 }
 
 #[no_mangle]
@@ -1023,8 +1718,13 @@ pub unsafe extern "C" fn grip_json_object_get_value(
     name: *const c_char,
     dot_notation: bool,
 ) -> Cell {
-    get_module_mut().json_handles.insert_with_unique_id(
-        try_to_get_json_object_value_gc!(amx, object, name, dot_notation).clone(),
+    try_and_log_ffi!(
+        amx,
+        get_module_mut().json_handles.insert_owned(
+            owner(amx),
+            "grip_json_object_get_value",
+            try_to_get_json_object_value_gc!(amx, object, name, dot_notation).clone(),
+        )
     )
 }
 #[no_mangle]
@@ -1149,15 +1849,20 @@ pub unsafe extern "C" fn grip_json_object_get_value_at(
     index: Cell,
 ) -> Cell {
     match try_to_get_json_value!(amx, object) {
-        InnerValue::Object(m) => get_module_mut().json_handles.insert_with_unique_id(
-            try_and_log_ffi!(
-                amx,
-                m.get_index(try_as_usize!(amx, index))
-                    .chain_err(|| "Index wasn't found")
+        InnerValue::Object(m) => try_and_log_ffi!(
+            amx,
+            get_module_mut().json_handles.insert_owned(
+                owner(amx),
+                "grip_json_object_get_value_at",
+                try_and_log_ffi!(
+                    amx,
+                    m.get_index(try_as_usize!(amx, index))
+                        .chain_err(|| "Index wasn't found")
+                )
+                .1
+                .borrow()
+                .clone(),
             )
-            .1
-            .borrow()
-            .clone(),
         ),
         v => unconditionally_log_error!(
             amx,
@@ -1324,9 +2029,14 @@ pub unsafe extern "C" fn grip_json_deep_copy(
     object: Cell,
     recursion_limit: Cell,
 ) -> Cell {
-    get_module_mut().json_handles.insert_with_unique_id(
-        try_to_get_json_value_gc!(amx, object)
-            .deep_clone_with_recursion_limit(try_as_usize!(amx, recursion_limit)),
+    try_and_log_ffi!(
+        amx,
+        get_module_mut().json_handles.insert_owned(
+            owner(amx),
+            "grip_json_deep_copy",
+            try_to_get_json_value_gc!(amx, object)
+                .deep_clone_with_recursion_limit(try_as_usize!(amx, recursion_limit)),
+        )
     )
 }
 
@@ -1462,17 +2172,22 @@ pub unsafe extern "C" fn grip_body_from_json(
     pretty: bool,
     recursion_limit: Cell,
 ) -> Cell {
-    get_module_mut().bodies_handles.insert_with_unique_id(
-        try_and_log_ffi!(
-            amx,
-            serialize_to_string(
-                &try_to_get_json_value!(amx, value)
-                    .clone()
-                    .into_with_recursion_limit(try_as_usize!(amx, recursion_limit)),
-                pretty,
-                false
+    try_and_log_ffi!(
+        amx,
+        get_module_mut().bodies_handles.insert_owned(
+            owner(amx),
+            "grip_body_from_json",
+            try_and_log_ffi!(
+                amx,
+                serialize_to_string(
+                    &try_to_get_json_value!(amx, value)
+                        .clone()
+                        .into_with_recursion_limit(try_as_usize!(amx, recursion_limit)),
+                    pretty,
+                    false
+                )
             )
+            .into_bytes(),
         )
-        .into_bytes(),
     )
 }
@@ -0,0 +1,187 @@
+/*
+ * gRIP
+ * Copyright (c) 2018 Alik Aslanyan <cplusplus256@gmail.com>
+ *
+ *
+ *    This program is free software; you can redistribute it and/or modify it
+ *    under the terms of the GNU General Public License as published by the
+ *    Free Software Foundation; either version 3 of the License, or (at
+ *    your option) any later version.
+ *
+ *    This program is distributed in the hope that it will be useful, but
+ *    WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ *    General Public License for more details.
+ *
+ *    You should have received a copy of the GNU General Public License
+ *    along with this program; if not, write to the Free Software Foundation,
+ *    Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ *    In addition, as a special exception, the author gives permission to
+ *    link the code of this program with the Half-Life Game Engine ("HL
+ *    Engine") and Modified Game Libraries ("MODs") developed by Valve,
+ *    L.L.C ("Valve").  You must obey the GNU General Public License in all
+ *    respects for all of the code used other than the HL Engine and MODs
+ *    from Valve.  If you modify this file, you may extend this exception
+ *    to your version of the file, but you are not obligated to do so.  If
+ *    you do not wish to do so, delete this exception statement from your
+ *    version.
+ *
+ */
+
+//! Queues created by plugins. Each of them has its own workers and callbacks budget,
+//! so slow requests of one queue don't delay callbacks of another.
+
+use super::*;
+
+use crate::networking_queue::QueueConfig;
+
+/// Same value as `Default_GripQueue` in Pawn.
+pub(super) const DEFAULT_QUEUE: Cell = -1;
+
+/// Every worker is the OS thread, so plugin can't exhaust the server with the single call.
+const MAX_WORKER_THREADS: usize = 64;
+
+pub struct QueueHandle {
+    // Boxed, so the queue stays in place, while its callbacks create and destroy queues.
+    pub queue: Box<Queue>,
+    pub callbacks_per_frame: usize,
+}
+
+impl ModuleStorage {
+    /// Global queue, followed by the queues created by plugins.
+    pub(super) fn queues(&self) -> impl Iterator<Item = &Queue> {
+        std::iter::once(&self.global_queue)
+            .chain(self.queues_handles.values().map(|handle| &*handle.queue))
+    }
+}
+
+pub(super) unsafe fn get_queue_mut(queue: Cell) -> Result<&'static mut Queue> {
+    if queue == DEFAULT_QUEUE {
+        Ok(&mut get_module_mut().global_queue)
+    } else {
+        Ok(&mut get_module_mut().queues_handles.get_mut(queue)?.queue)
+    }
+}
+
+/// Cancels pending requests of the queue without calling their callbacks.
+/// Queue itself is dropped by `drop_destroyed_queues` after all queues are processed,
+/// because it may be executing a callback right now.
+pub(super) unsafe fn destroy_queue(handle: QueueHandle) {
+    let pending: std::collections::HashSet<u64> = handle
+        .queue
+        .pending_requests()
+        .map(|request| request.id)
+        .collect();
+
+    let mut cancelled = vec![];
+    get_module_mut()
+        .cancellations_handles
+        .retain(|cancellation_id, cancellation| {
+            if pending.contains(&cancellation.request_id()) {
+                cancelled.push(cancellation_id);
+                false
+            } else {
+                true
+            }
+        });
+    get_module_mut().destroyed_queues.push(handle);
+
+    // Groups still complete, even though callbacks of these requests are never called.
+    for cancellation in cancelled {
+        super::group::request_finished(cancellation, false);
+    }
+}
+
+/// Waits for the workers of the destroyed queues to stop.
+pub(super) unsafe fn drop_destroyed_queues() {
+    get_module_mut().destroyed_queues.clear();
+}
+
+/// Executes callbacks of the queues created by plugins, up to their own limit per frame.
+pub(super) unsafe fn process_queues() {
+    let delay =
+        std::time::Duration::from_micros(get_module().microseconds_delay_between_attempts as u64);
+
+    // Handles are looked up on every step, because callbacks may create and destroy queues.
+    // Callbacks run on the pointer to the boxed queue, not on the borrowed slot, which they may move.
+    for queue in get_module().queues_handles.ids() {
+        let (queue, limit): (*mut Queue, usize) =
+            match get_module_mut().queues_handles.get_mut_with_id(queue) {
+                Some(handle) => (&mut *handle.queue, handle.callbacks_per_frame),
+                None => continue,
+            };
+        (*queue).execute_queue_with_limit(limit, delay);
+    }
+
+    drop_destroyed_queues();
+}
+
+/// Queues created by plugins share transport, limits and metrics of the global queue.
+pub(super) unsafe fn plugin_queue_config(
+    worker_threads: usize,
+    max_concurrent_requests: Option<usize>,
+) -> QueueConfig {
+    let mut config = get_module().queue_config.clone();
+    config.worker_threads = Some(worker_threads);
+    config.max_concurrent_requests = max_concurrent_requests;
+    config
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_create_queue(
+    amx: *const c_void,
+    worker_threads: Cell,
+    max_concurrent_requests: Cell,
+    callbacks_per_frame: Cell,
+) -> Cell {
+    let worker_threads = try_as_usize!(amx, worker_threads);
+    let max_concurrent_requests = try_as_usize!(amx, max_concurrent_requests);
+    let callbacks_per_frame = try_as_usize!(amx, callbacks_per_frame);
+
+    try_and_log_ffi!(
+        amx,
+        if worker_threads == 0 {
+            Err(ffi_error("Queue should have at least 1 worker thread"))
+        } else if worker_threads > MAX_WORKER_THREADS {
+            Err(ffi_error(format!(
+                "Queue should have at most {} worker threads",
+                MAX_WORKER_THREADS
+            )))
+        } else if callbacks_per_frame == 0 {
+            Err(ffi_error(
+                "Queue should execute at least 1 callback per frame",
+            ))
+        } else {
+            Ok(())
+        }
+    );
+    try_and_log_ffi!(amx, get_module().queues_handles.check_capacity(owner(amx)));
+
+    let queue = Queue::with_config(plugin_queue_config(
+        worker_threads,
+        Some(max_concurrent_requests).filter(|&max| max != 0),
+    ));
+
+    try_and_log_ffi!(
+        amx,
+        get_module_mut().queues_handles.insert_owned(
+            owner(amx),
+            "grip_create_queue",
+            QueueHandle {
+                queue: Box::new(queue),
+                callbacks_per_frame,
+            }
+        )
+    )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_destroy_queue(amx: *const c_void, queue: Cell) -> Cell {
+    destroy_queue(try_and_log_ffi!(
+        amx,
+        get_module_mut().queues_handles.remove(queue)
+    ));
+
+    1
+}
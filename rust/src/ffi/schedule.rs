@@ -0,0 +1,282 @@
+/*
+ * gRIP
+ * Copyright (c) 2018 Alik Aslanyan <cplusplus256@gmail.com>
+ *
+ *
+ *    This program is free software; you can redistribute it and/or modify it
+ *    under the terms of the GNU General Public License as published by the
+ *    Free Software Foundation; either version 3 of the License, or (at
+ *    your option) any later version.
+ *
+ *    This program is distributed in the hope that it will be useful, but
+ *    WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ *    General Public License for more details.
+ *
+ *    You should have received a copy of the GNU General Public License
+ *    along with this program; if not, write to the Free Software Foundation,
+ *    Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ *    In addition, as a special exception, the author gives permission to
+ *    link the code of this program with the Half-Life Game Engine ("HL
+ *    Engine") and Modified Game Libraries ("MODs") developed by Valve,
+ *    L.L.C ("Valve").  You must obey the GNU General Public License in all
+ *    respects for all of the code used other than the HL Engine and MODs
+ *    from Valve.  If you modify this file, you may extend this exception
+ *    to your version of the file, but you are not obligated to do so.  If
+ *    you do not wish to do so, delete this exception statement from your
+ *    version.
+ *
+ */
+
+//! Requests, which grip sends after a delay or repeatedly, so that plugins don't need timers for polling.
+
+use super::*;
+
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, Instant};
+
+/// Settings of the `[scheduler]` section of the config.
+pub struct SchedulerSettings {
+    pub min_interval: Duration,
+}
+
+impl Default for SchedulerSettings {
+    fn default() -> Self {
+        SchedulerSettings {
+            min_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+pub struct ScheduleHandle {
+    request: crate::networking_queue::Request,
+    queue: Cell,
+    // `None` for the request, which is sent once.
+    interval: Option<Duration>,
+    jitter: Duration,
+    wait_for_previous: bool,
+    paused: bool,
+    // `None`, when request is not going to be sent anymore.
+    next_run: Option<Instant>,
+    runs: u64,
+    // Sent requests by the run. They are cancelled, when schedule is destroyed.
+    pending: HashMap<u64, RequestCancellation>,
+    forward_id: Cell,
+    handler: extern "C" fn(forward_handle: Cell, schedule: Cell, user_data: Cell) -> c_void,
+    forward_releaser: extern "C" fn(forward_handle: Cell) -> c_void,
+    user_data: Cell,
+}
+
+impl ScheduleHandle {
+    pub fn release_forward(&self) {
+        (self.forward_releaser)(self.forward_id);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+}
+
+/// Random duration from 0 to `max`.
+fn random_jitter(max: Duration) -> Duration {
+    if max == Duration::from_secs(0) {
+        return max;
+    }
+
+    // Every `RandomState` is seeded differently, which is random enough to spread requests.
+    let random = std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish();
+    let fraction = (random >> 11) as f64 / (1u64 << 53) as f64;
+
+    Duration::from_micros((max.as_micros() as f64 * fraction) as u64)
+}
+
+fn parse_seconds(name: &str, seconds: f64) -> Result<Duration> {
+    if seconds >= 0.0 && seconds.is_finite() {
+        Ok(Duration::from_micros((seconds * 1_000_000.0) as u64))
+    } else {
+        Err(ffi_error(format!("Invalid {}: {}", name, seconds)))
+    }
+}
+
+unsafe fn destroy_schedule(schedule: Cell) {
+    if let Some(handle) = get_module_mut().schedules_handles.remove_with_id(schedule) {
+        handle.release_forward();
+    }
+}
+
+/// Sends requests, whose time has come. Called every frame.
+pub(super) unsafe fn process_schedules() {
+    let now = Instant::now();
+
+    for schedule in get_module().schedules_handles.ids() {
+        let handle = match get_module_mut().schedules_handles.get_mut_with_id(schedule) {
+            Some(handle) => handle,
+            None => continue,
+        };
+        if handle.paused || handle.next_run.map_or(true, |next_run| next_run > now) {
+            continue;
+        }
+
+        // Callbacks of the destroyed queue are never called, so schedule can't continue.
+        let queue = match self::queue::get_queue_mut(handle.queue) {
+            Ok(queue) => queue,
+            Err(_) => {
+                destroy_schedule(schedule);
+                continue;
+            }
+        };
+
+        // Request stays overdue, until the previous one finishes.
+        if handle.wait_for_previous && !handle.pending.is_empty() {
+            continue;
+        }
+
+        // Missed runs are not repeated, e.g. after the pause.
+        handle.next_run = handle
+            .interval
+            .map(|interval| now + interval + random_jitter(handle.jitter));
+        handle.runs += 1;
+
+        let run = handle.runs;
+        let cancellation = queue.send_request(handle.request.clone(), move |response| {
+            let handle = match get_module_mut().schedules_handles.get_mut_with_id(schedule) {
+                Some(handle) => handle,
+                // Cancelled together with the schedule.
+                None => return,
+            };
+            handle.pending.remove(&run);
+            let finished = handle.next_run.is_none() && handle.pending.is_empty();
+            let (handler, forward_id, user_data) =
+                (handle.handler, handle.forward_id, handle.user_data);
+
+            get_module_mut().current_response = Some(response);
+            handler(forward_id, schedule, user_data);
+            get_module_mut().current_response = None;
+
+            // Request, which is sent once, destroys its schedule.
+            if finished {
+                destroy_schedule(schedule);
+            }
+        });
+        handle.pending.insert(run, cancellation);
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_schedule_request(
+    amx: *const c_void,
+    forward_id: Cell,
+    uri: *const c_char,
+    body_handle: Cell,
+    request_type: Cell,
+    handler: Option<extern "C" fn(forward_handle: Cell, schedule: Cell, user_data: Cell) -> c_void>,
+    forward_releaser: Option<extern "C" fn(forward_handle: Cell) -> c_void>,
+    options_handle: Cell,
+    user_data: Cell,
+    delay: f64,
+    interval: f64,
+    jitter: f64,
+    wait_for_previous: bool,
+    queue: Cell,
+) -> Cell {
+    let request_type = try_and_log_ffi!(amx, parse_request_type(request_type));
+    try_and_log_ffi!(amx, self::queue::get_queue_mut(queue));
+    let delay = try_and_log_ffi!(amx, parse_seconds("delay", delay));
+    let jitter = try_and_log_ffi!(amx, parse_seconds("jitter", jitter));
+    let interval = try_and_log_ffi!(amx, parse_seconds("interval", interval));
+    // Zero interval means that request is sent once.
+    let interval = Some(interval).filter(|&interval| interval != Duration::from_secs(0));
+
+    let min_interval = get_module().scheduler_settings.min_interval;
+    try_and_log_ffi!(
+        amx,
+        match interval {
+            Some(interval) if interval < min_interval => Err(ffi_error(format!(
+                "Interval should be at least {} milliseconds, \
+                 which is set by \"scheduler.min-interval-milliseconds\" in the grip.ini",
+                min_interval.as_millis()
+            ))),
+            _ => Ok(()),
+        }
+    );
+
+    let uri = try_and_log_ffi!(
+        amx,
+        str_from_ptr(uri).map_err(|_| ffi_error("URI is not UTF-8"))
+    );
+
+    let body = if body_handle == -1 {
+        vec![]
+    } else {
+        try_and_log_ffi!(amx, get_module().bodies_handles.get(body_handle)).clone()
+    };
+
+    let options = if options_handle == -1 {
+        RequestOptions::default()
+    } else {
+        try_and_log_ffi!(amx, get_module().options_handles.get(options_handle)).clone()
+    };
+
+    let request = RequestBuilder::default()
+        .http_type(request_type)
+        .body(body)
+        .uri(try_and_log_ffi!(
+            amx,
+            uri.parse()
+                .chain_err(|| ffi_error(format!("URI parsing error: {}", uri)))
+        ))
+        .options(options)
+        .build()
+        .unwrap();
+
+    try_and_log_ffi!(
+        amx,
+        get_module_mut().schedules_handles.insert_owned(
+            owner(amx),
+            "grip_schedule_request",
+            ScheduleHandle {
+                request,
+                queue,
+                interval,
+                jitter,
+                wait_for_previous,
+                paused: false,
+                next_run: Some(Instant::now() + delay + random_jitter(jitter)),
+                runs: 0,
+                pending: HashMap::new(),
+                forward_id,
+                handler: handler.unwrap(),
+                forward_releaser: forward_releaser.unwrap(),
+                user_data,
+            }
+        )
+    )
+}
+
+/// Requests aren't sent, until schedule is resumed. Already sent request isn't cancelled.
+#[no_mangle]
+pub unsafe extern "C" fn grip_pause_schedule(amx: *const c_void, schedule: Cell) -> Cell {
+    try_and_log_ffi!(amx, get_module_mut().schedules_handles.get_mut(schedule)).paused = true;
+
+    1
+}
+
+/// Request, which became due during the pause, is sent on the next frame.
+#[no_mangle]
+pub unsafe extern "C" fn grip_resume_schedule(amx: *const c_void, schedule: Cell) -> Cell {
+    try_and_log_ffi!(amx, get_module_mut().schedules_handles.get_mut(schedule)).paused = false;
+
+    1
+}
+
+/// Destroys the schedule and cancels its pending requests without calling the handler.
+#[no_mangle]
+pub unsafe extern "C" fn grip_cancel_schedule(amx: *const c_void, schedule: Cell) -> Cell {
+    try_and_log_ffi!(amx, get_module_mut().schedules_handles.remove(schedule)).release_forward();
+
+    1
+}
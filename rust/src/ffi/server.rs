@@ -0,0 +1,456 @@
+/*
+ * gRIP
+ * Copyright (c) 2018 Alik Aslanyan <cplusplus256@gmail.com>
+ *
+ *
+ *    This program is free software; you can redistribute it and/or modify it
+ *    under the terms of the GNU General Public License as published by the
+ *    Free Software Foundation; either version 3 of the License, or (at
+ *    your option) any later version.
+ *
+ *    This program is distributed in the hope that it will be useful, but
+ *    WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ *    General Public License for more details.
+ *
+ *    You should have received a copy of the GNU General Public License
+ *    along with this program; if not, write to the Free Software Foundation,
+ *    Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ *    In addition, as a special exception, the author gives permission to
+ *    link the code of this program with the Half-Life Game Engine ("HL
+ *    Engine") and Modified Game Libraries ("MODs") developed by Valve,
+ *    L.L.C ("Valve").  You must obey the GNU General Public License in all
+ *    respects for all of the code used other than the HL Engine and MODs
+ *    from Valve.  If you modify this file, you may extend this exception
+ *    to your version of the file, but you are not obligated to do so.  If
+ *    you do not wish to do so, delete this exception statement from your
+ *    version.
+ *
+ */
+
+use super::*;
+
+use crate::http_server::{IncomingRequest, Reply};
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+use hyper::StatusCode;
+
+pub struct Route {
+    pattern: String,
+    forward_id: Cell,
+    user_data: Cell,
+    handler: extern "C" fn(forward_handle: Cell, request: Cell, user_data: Cell) -> c_void,
+}
+
+pub struct ServerRequestHandle {
+    request: IncomingRequest,
+    reply_headers: HeaderMap,
+}
+
+/// Dispatches received requests to the first matching route. Returns number of executed handlers.
+pub unsafe fn process_server_requests(limit: usize) -> usize {
+    // Requests, which were not replied in time, are useless.
+    get_module_mut()
+        .server_requests_handles
+        .retain(|_, handle| handle.request.is_waiting_for_reply());
+
+    let mut counter = 0;
+
+    while counter < limit {
+        let mut request = match get_module().http_server.as_ref() {
+            Some(server) => match server.try_recv_request() {
+                Some(request) => request,
+                None => break,
+            },
+            None => break,
+        };
+
+        let route = get_module()
+            .server_routes
+            .iter()
+            .find(|route| crate::http_server::route_matches(&route.pattern, &request.path))
+            .map(|route| (route.handler, route.forward_id, route.user_data));
+
+        match route {
+            Some((handler, forward_id, user_data)) => {
+                // Request dropped without reply, when handles are exhausted, is answered with 500.
+                let request_id = match get_module_mut()
+                    .server_requests_handles
+                    .insert_with_unique_id(ServerRequestHandle {
+                        request,
+                        reply_headers: HeaderMap::new(),
+                    }) {
+                    Ok(request_id) => request_id,
+                    Err(_) => continue,
+                };
+
+                handler(forward_id, request_id, user_data);
+                counter += 1;
+            }
+            None => {
+                request
+                    .reply(Reply::new(
+                        StatusCode::NOT_FOUND,
+                        "text/plain; charset=utf-8",
+                        b"No handler registered for this route".to_vec(),
+                    ))
+                    .ok();
+            }
+        }
+    }
+
+    counter
+}
+
+/// Handles are destroyed, when request is replied or reply timeout expires.
+fn explain_destroyed_request(error: Error) -> Error {
+    match error.kind() {
+        ErrorKind::DestroyedHandle(..) => ffi_error(format!(
+            "{}. Request was already replied or reply timeout has expired",
+            error
+        )),
+        _ => error,
+    }
+}
+
+unsafe fn get_server_request(request: Cell) -> Result<&'static mut ServerRequestHandle> {
+    get_module_mut()
+        .server_requests_handles
+        .get_mut(request)
+        .map_err(explain_destroyed_request)
+}
+
+unsafe fn reply(
+    request: Cell,
+    status: Cell,
+    content_type: &'static str,
+    body: Vec<u8>,
+) -> Result<()> {
+    let status = StatusCode::from_u16(status as u16)
+        .ok()
+        .filter(|_| status > 0 && status <= 0xFFFF)
+        .chain_err(|| ffi_error(format!("Invalid status code {}", status)))?;
+
+    let mut handle = get_module_mut()
+        .server_requests_handles
+        .remove(request)
+        .map_err(explain_destroyed_request)?;
+
+    let mut reply = Reply::new(status, content_type, body);
+    for (name, value) in handle.reply_headers.iter() {
+        reply.headers.insert(name.clone(), value.clone());
+    }
+
+    handle.request.reply(reply)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_server_register_route(
+    amx: *const c_void,
+    forward_id: Cell,
+    pattern: *const c_char,
+    handler: Option<extern "C" fn(forward_handle: Cell, request: Cell, user_data: Cell) -> c_void>,
+    user_data: Cell,
+) -> Cell {
+    let pattern = try_and_log_ffi!(amx, str_from_ptr(pattern));
+    if !pattern.starts_with('/') {
+        unconditionally_log_error!(
+            amx,
+            ffi_error(format!("Route should start with \"/\": {}", pattern))
+        );
+        return 0;
+    }
+
+    get_module_mut().server_routes.push(Route {
+        pattern: pattern.to_owned(),
+        forward_id,
+        user_data,
+        handler: handler.unwrap(),
+    });
+
+    1
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_server_is_running() -> bool {
+    get_module().http_server.is_some()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_server_get_method(
+    amx: *const c_void,
+    request: Cell,
+    buffer: *mut c_char,
+    size: Cell,
+) -> Cell {
+    try_to_copy_unsafe_string!(
+        amx,
+        buffer,
+        try_and_log_ffi!(amx, get_server_request(request))
+            .request
+            .method
+            .as_str(),
+        size
+    )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_server_get_path(
+    amx: *const c_void,
+    request: Cell,
+    buffer: *mut c_char,
+    size: Cell,
+) -> Cell {
+    try_to_copy_unsafe_string!(
+        amx,
+        buffer,
+        try_and_log_ffi!(amx, get_server_request(request))
+            .request
+            .path
+            .as_str(),
+        size
+    )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_server_get_query(
+    amx: *const c_void,
+    request: Cell,
+    buffer: *mut c_char,
+    size: Cell,
+) -> Cell {
+    try_to_copy_unsafe_string!(
+        amx,
+        buffer,
+        try_and_log_ffi!(amx, get_server_request(request))
+            .request
+            .query
+            .as_str(),
+        size
+    )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_server_get_query_param(
+    amx: *const c_void,
+    request: Cell,
+    name: *const c_char,
+    buffer: *mut c_char,
+    size: Cell,
+) -> Cell {
+    let name = try_and_log_ffi!(amx, str_from_ptr(name));
+
+    match try_and_log_ffi!(amx, get_server_request(request))
+        .request
+        .query_param(name)
+    {
+        Some(value) => try_to_copy_unsafe_string!(amx, buffer, value, size),
+        None => {
+            try_to_copy_unsafe_string!(amx, buffer, "", size);
+            -1
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_server_get_header(
+    amx: *const c_void,
+    request: Cell,
+    name: *const c_char,
+    buffer: *mut c_char,
+    size: Cell,
+) -> Cell {
+    let name = try_and_log_ffi!(amx, str_from_ptr(name));
+
+    match try_and_log_ffi!(amx, get_server_request(request))
+        .request
+        .headers
+        .get(name)
+    {
+        Some(value) => try_to_copy_unsafe_string!(
+            amx,
+            buffer,
+            try_and_log_ffi!(
+                amx,
+                value
+                    .to_str()
+                    .chain_err(|| ffi_error(format!("Header {} is not valid string", name)))
+            ),
+            size
+        ),
+        None => {
+            try_to_copy_unsafe_string!(amx, buffer, "", size);
+            -1
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_server_get_remote_address(
+    amx: *const c_void,
+    request: Cell,
+    buffer: *mut c_char,
+    size: Cell,
+) -> Cell {
+    try_to_copy_unsafe_string!(
+        amx,
+        buffer,
+        try_and_log_ffi!(amx, get_server_request(request))
+            .request
+            .remote_address
+            .ip()
+            .to_string(),
+        size
+    )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_server_get_body_length(amx: *const c_void, request: Cell) -> Cell {
+    try_and_log_ffi!(amx, get_server_request(request))
+        .request
+        .body
+        .len() as Cell
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_server_get_body_string(
+    amx: *const c_void,
+    request: Cell,
+    buffer: *mut c_char,
+    size: Cell,
+    offset: Cell,
+) -> Cell {
+    let body = &try_and_log_ffi!(amx, get_server_request(request))
+        .request
+        .body;
+    let size = try_as_usize!(amx, size);
+    let bytes = try_and_log_ffi!(
+        amx,
+        response_body::slice(body, try_as_usize!(amx, offset), None)
+    );
+    let bytes = &bytes[..response_body::utf8_prefix_len(bytes, size)];
+
+    copy_bytes_as_string(buffer, bytes);
+
+    bytes.len() as Cell
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_server_body_from_request(amx: *const c_void, request: Cell) -> Cell {
+    let body = try_and_log_ffi!(amx, get_server_request(request))
+        .request
+        .body
+        .clone();
+
+    try_and_log_ffi!(
+        amx,
+        get_module_mut()
+            .bodies_handles
+            .insert_owned(owner(amx), "grip_server_body_from_request", body)
+    )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_server_parse_body_json(
+    amx: *const c_void,
+    request: Cell,
+    error_buffer: *mut c_char,
+    error_buffer_size: Cell,
+) -> Cell {
+    let handle = try_and_log_ffi!(amx, get_server_request(request));
+
+    let value: Result<serde_json::Value> = serde_json::from_slice(&handle.request.body[..])
+        .map_err(|e| ErrorKind::JSONError(e).into());
+
+    match value {
+        Ok(value) => try_and_log_ffi!(
+            amx,
+            get_module_mut()
+                .json_handles
+                .insert_owned(owner(amx), "grip_server_parse_body_json", value.into())
+        ),
+        Err(error) => {
+            use error_chain::ChainedError;
+            try_to_copy_unsafe_string!(amx, error_buffer, error.display_chain(), error_buffer_size);
+            0
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_server_set_reply_header(
+    amx: *const c_void,
+    request: Cell,
+    name: *const c_char,
+    value: *const c_char,
+) -> Cell {
+    let handle = try_and_log_ffi!(amx, get_server_request(request));
+
+    let name = try_and_log_ffi!(amx, str_from_ptr(name));
+    let name = try_and_log_ffi!(
+        amx,
+        HeaderName::from_bytes(name.as_bytes())
+            .chain_err(|| ffi_error(format!("Invalid header name: {}", name)))
+    );
+
+    let value = try_and_log_ffi!(amx, str_from_ptr(value));
+    let value = try_and_log_ffi!(
+        amx,
+        HeaderValue::from_str(value)
+            .chain_err(|| ffi_error(format!("Invalid header value: {}", value)))
+    );
+
+    handle.reply_headers.insert(name, value);
+
+    1
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_server_reply(
+    amx: *const c_void,
+    request: Cell,
+    status: Cell,
+    body: *const c_char,
+) -> Cell {
+    let body = CStr::from_ptr(try_and_log_ffi!(
+        amx,
+        ptr_to_option(body).chain_err(|| ffi_error("Invalid body."))
+    ))
+    .to_bytes()
+    .to_vec();
+
+    try_and_log_ffi!(
+        amx,
+        reply(request, status, "text/plain; charset=utf-8", body)
+    );
+
+    1
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_server_reply_json(
+    amx: *const c_void,
+    request: Cell,
+    status: Cell,
+    value: Cell,
+    pretty: bool,
+    recursion_limit: Cell,
+) -> Cell {
+    let body = try_and_log_ffi!(
+        amx,
+        serialize_to_string(
+            &try_to_get_json_value!(amx, value)
+                .clone()
+                .into_with_recursion_limit(try_as_usize!(amx, recursion_limit)),
+            pretty,
+            false
+        )
+    );
+
+    try_and_log_ffi!(
+        amx,
+        reply(request, status, "application/json", body.into_bytes())
+    );
+
+    1
+}
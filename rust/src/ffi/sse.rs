@@ -0,0 +1,254 @@
+/*
+ * gRIP
+ * Copyright (c) 2018 Alik Aslanyan <cplusplus256@gmail.com>
+ *
+ *
+ *    This program is free software; you can redistribute it and/or modify it
+ *    under the terms of the GNU General Public License as published by the
+ *    Free Software Foundation; either version 3 of the License, or (at
+ *    your option) any later version.
+ *
+ *    This program is distributed in the hope that it will be useful, but
+ *    WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ *    General Public License for more details.
+ *
+ *    You should have received a copy of the GNU General Public License
+ *    along with this program; if not, write to the Free Software Foundation,
+ *    Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ *    In addition, as a special exception, the author gives permission to
+ *    link the code of this program with the Half-Life Game Engine ("HL
+ *    Engine") and Modified Game Libraries ("MODs") developed by Valve,
+ *    L.L.C ("Valve").  You must obey the GNU General Public License in all
+ *    respects for all of the code used other than the HL Engine and MODs
+ *    from Valve.  If you modify this file, you may extend this exception
+ *    to your version of the file, but you are not obligated to do so.  If
+ *    you do not wish to do so, delete this exception statement from your
+ *    version.
+ *
+ */
+
+use super::*;
+
+use crate::sse::SubscriptionEvent;
+
+pub struct SubscriptionHandle {
+    // Subscription is cancelled, when dropped.
+    _cancellation: RequestCancellation,
+    forward_id: Cell,
+    forward_releaser: extern "C" fn(forward_handle: Cell) -> c_void,
+}
+
+impl SubscriptionHandle {
+    pub fn release_forward(&self) {
+        (self.forward_releaser)(self.forward_id);
+    }
+}
+
+/// Same values as `GripSSEEvent` in Pawn.
+fn event_id(event: &SubscriptionEvent) -> Cell {
+    match event {
+        SubscriptionEvent::Open => 1,
+        SubscriptionEvent::Event(_) => 2,
+        SubscriptionEvent::Error(_) => 3,
+    }
+}
+
+unsafe fn get_sse_event() -> Result<&'static crate::sse::Event> {
+    match get_module()
+        .current_sse_event
+        .as_ref()
+        .chain_err(|| ffi_error("No active SSE event at this time"))?
+    {
+        SubscriptionEvent::Event(event) => Ok(event),
+        _ => Err(ffi_error("Current SSE event is not a message")),
+    }
+}
+
+unsafe fn get_sse_error() -> Result<&'static Error> {
+    match get_module()
+        .current_sse_event
+        .as_ref()
+        .chain_err(|| ffi_error("No active SSE event at this time"))?
+    {
+        SubscriptionEvent::Error(error) => Ok(error),
+        _ => Err(ffi_error("Current SSE event is not an error")),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_sse_subscribe(
+    amx: *const c_void,
+    forward_id: Cell,
+    url: *const c_char,
+    handler: Option<
+        extern "C" fn(
+            forward_handle: Cell,
+            subscription: Cell,
+            event: Cell,
+            user_data: Cell,
+        ) -> c_void,
+    >,
+    forward_releaser: Option<extern "C" fn(forward_handle: Cell) -> c_void>,
+    options_handle: Cell,
+    user_data: Cell,
+) -> Cell {
+    let url = try_and_log_ffi!(amx, str_from_ptr(url));
+    try_and_log_ffi!(amx, check_streams_allowed());
+    let subscription_id = try_and_log_ffi!(
+        amx,
+        get_module().subscriptions_handles.next_id(owner(amx))
+    );
+
+    let options = if options_handle == -1 {
+        RequestOptions::default()
+    } else {
+        try_and_log_ffi!(
+            amx,
+            get_module()
+                .options_handles
+                .get(options_handle)
+        )
+        .clone()
+    };
+
+    let handler = handler.unwrap();
+    let cancellation = get_module_mut().global_queue.subscribe(
+        RequestBuilder::default()
+            .http_type(RequestType::Get)
+            .uri(try_and_log_ffi!(
+                amx,
+                url.parse()
+                    .chain_err(|| ffi_error(format!("URL parsing error: {}", url)))
+            ))
+            .options(options)
+            .build()
+            .unwrap(),
+        move |event| {
+            // Events, which were received before unsubscribe, are dropped.
+            if get_module()
+                .subscriptions_handles
+                .get_with_id(subscription_id)
+                .is_none()
+            {
+                return;
+            }
+
+            let id = event_id(&event);
+            get_module_mut().current_sse_event = Some(event);
+            handler(forward_id, subscription_id, id, user_data);
+            get_module_mut().current_sse_event = None;
+        },
+    );
+
+    try_and_log_ffi!(
+        amx,
+        get_module_mut().subscriptions_handles.insert_owned(
+            owner(amx),
+            "grip_sse_subscribe",
+            SubscriptionHandle {
+                _cancellation: cancellation,
+                forward_id,
+                forward_releaser: forward_releaser.unwrap(),
+            },
+        )
+    )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_sse_unsubscribe(amx: *const c_void, subscription: Cell) -> Cell {
+    let handle = try_and_log_ffi!(
+        amx,
+        get_module_mut()
+            .subscriptions_handles
+            .remove(subscription)
+    );
+
+    handle.release_forward();
+
+    1
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_sse_get_event_name(
+    amx: *const c_void,
+    buffer: *mut c_char,
+    size: Cell,
+) -> Cell {
+    try_to_copy_unsafe_string!(
+        amx,
+        buffer,
+        try_and_log_ffi!(amx, get_sse_event()).name,
+        size
+    )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_sse_get_event_id(
+    amx: *const c_void,
+    buffer: *mut c_char,
+    size: Cell,
+) -> Cell {
+    try_to_copy_unsafe_string!(amx, buffer, try_and_log_ffi!(amx, get_sse_event()).id, size)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_sse_get_event_data(
+    amx: *const c_void,
+    buffer: *mut c_char,
+    size: Cell,
+) -> Cell {
+    try_to_copy_unsafe_string!(
+        amx,
+        buffer,
+        try_and_log_ffi!(amx, get_sse_event()).data,
+        size
+    )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_sse_parse_event_json(
+    amx: *const c_void,
+    error_buffer: *mut c_char,
+    error_buffer_size: Cell,
+) -> Cell {
+    let event = try_and_log_ffi!(amx, get_sse_event());
+
+    let value: Result<serde_json::Value> =
+        serde_json::from_str(&event.data).map_err(|e| ErrorKind::JSONError(e).into());
+
+    match value {
+        Ok(value) => try_and_log_ffi!(
+            amx,
+            get_module_mut()
+                .json_handles
+                .insert_owned(owner(amx), "grip_sse_parse_event_json", value.into())
+        ),
+        Err(error) => {
+            use error_chain::ChainedError;
+            try_to_copy_unsafe_string!(amx, error_buffer, error.display_chain(), error_buffer_size);
+            0
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_sse_get_error_description(
+    amx: *const c_void,
+    buffer: *mut c_char,
+    size: Cell,
+) -> Cell {
+    use error_chain::ChainedError;
+    try_to_copy_unsafe_string!(
+        amx,
+        buffer,
+        try_and_log_ffi!(amx, get_sse_error()).display_chain(),
+        size
+    )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_sse_get_error_kind(amx: *const c_void) -> Cell {
+    ErrorCategory::from(try_and_log_ffi!(amx, get_sse_error())) as Cell
+}
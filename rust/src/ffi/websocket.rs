@@ -0,0 +1,413 @@
+/*
+ * gRIP
+ * Copyright (c) 2018 Alik Aslanyan <cplusplus256@gmail.com>
+ *
+ *
+ *    This program is free software; you can redistribute it and/or modify it
+ *    under the terms of the GNU General Public License as published by the
+ *    Free Software Foundation; either version 3 of the License, or (at
+ *    your option) any later version.
+ *
+ *    This program is distributed in the hope that it will be useful, but
+ *    WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ *    General Public License for more details.
+ *
+ *    You should have received a copy of the GNU General Public License
+ *    along with this program; if not, write to the Free Software Foundation,
+ *    Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ *    In addition, as a special exception, the author gives permission to
+ *    link the code of this program with the Half-Life Game Engine ("HL
+ *    Engine") and Modified Game Libraries ("MODs") developed by Valve,
+ *    L.L.C ("Valve").  You must obey the GNU General Public License in all
+ *    respects for all of the code used other than the HL Engine and MODs
+ *    from Valve.  If you modify this file, you may extend this exception
+ *    to your version of the file, but you are not obligated to do so.  If
+ *    you do not wish to do so, delete this exception statement from your
+ *    version.
+ *
+ */
+
+use super::*;
+
+use crate::websocket::{Event, Message, WebSocket, WebSocketOptions};
+
+pub struct WebSocketHandle {
+    socket: WebSocket,
+    forward_id: Cell,
+    user_data: Cell,
+    handler:
+        extern "C" fn(forward_handle: Cell, socket: Cell, event: Cell, user_data: Cell) -> c_void,
+    forward_releaser: extern "C" fn(forward_handle: Cell) -> c_void,
+}
+
+impl WebSocketHandle {
+    pub fn release_forward(&self) {
+        (self.forward_releaser)(self.forward_id);
+    }
+}
+
+/// Same values as `GripWebSocketEvent` in Pawn.
+fn event_id(event: &Event) -> Cell {
+    match event {
+        Event::Connected => 1,
+        Event::Message(_) => 2,
+        Event::Ping(_) => 3,
+        Event::Disconnected { .. } => 4,
+        Event::Error(_) => 5,
+    }
+}
+
+/// Executes handlers of the received WebSocket events. Returns number of executed handlers.
+pub unsafe fn process_websocket_events(limit: usize) -> usize {
+    let mut counter = 0;
+
+    for socket_id in get_module().websockets_handles.ids() {
+        while counter < limit {
+            let (event, handler, forward_id, user_data) =
+                match get_module().websockets_handles.get_with_id(socket_id) {
+                    Some(handle) => match handle.socket.try_recv_event() {
+                        Some(event) => (event, handle.handler, handle.forward_id, handle.user_data),
+                        None => break,
+                    },
+                    // Destroyed in the handler.
+                    None => break,
+                };
+
+            let id = event_id(&event);
+            get_module_mut().current_websocket_event = Some(event);
+            handler(forward_id, socket_id, id, user_data);
+            get_module_mut().current_websocket_event = None;
+
+            counter += 1;
+        }
+    }
+
+    counter
+}
+
+unsafe fn get_websocket(socket: Cell) -> Result<&'static WebSocketHandle> {
+    get_module().websockets_handles.get(socket)
+}
+
+unsafe fn get_websocket_event() -> Result<&'static Event> {
+    get_module()
+        .current_websocket_event
+        .as_ref()
+        .chain_err(|| ffi_error("No active WebSocket event at this time"))
+}
+
+unsafe fn get_websocket_payload() -> Result<&'static [u8]> {
+    match get_websocket_event()? {
+        Event::Message(Message::Text(text)) => Ok(text.as_bytes()),
+        Event::Message(Message::Binary(data)) | Event::Ping(data) => Ok(&data[..]),
+        _ => Err(ffi_error("Current WebSocket event has no message")),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_websocket_connect(
+    amx: *const c_void,
+    forward_id: Cell,
+    url: *const c_char,
+    handler: Option<
+        extern "C" fn(forward_handle: Cell, socket: Cell, event: Cell, user_data: Cell) -> c_void,
+    >,
+    forward_releaser: Option<extern "C" fn(forward_handle: Cell) -> c_void>,
+    options_handle: Cell,
+    reconnect: bool,
+    user_data: Cell,
+) -> Cell {
+    let url = try_and_log_ffi!(amx, str_from_ptr(url));
+    try_and_log_ffi!(amx, check_streams_allowed());
+    try_and_log_ffi!(
+        amx,
+        get_module().websockets_handles.check_capacity(owner(amx))
+    );
+
+    let mut options = WebSocketOptions {
+        reconnect,
+        ..get_module().websocket_options.clone()
+    };
+
+    if options_handle != -1 {
+        let request_options = try_and_log_ffi!(
+            amx,
+            get_module()
+                .options_handles
+                .get(options_handle)
+        );
+
+        options.headers = request_options.headers.clone();
+        if let Some(timeout) = request_options.timeout {
+            options.connect_timeout = timeout;
+        }
+    }
+
+    let socket = try_and_log_ffi!(
+        amx,
+        WebSocket::connect(
+            try_and_log_ffi!(
+                amx,
+                url.parse()
+                    .chain_err(|| ffi_error(format!("URL parsing error: {}", url)))
+            ),
+            options
+        )
+    );
+
+    try_and_log_ffi!(
+        amx,
+        get_module_mut().websockets_handles.insert_owned(
+            owner(amx),
+            "grip_websocket_connect",
+            WebSocketHandle {
+                socket,
+                forward_id,
+                user_data,
+                handler: handler.unwrap(),
+                forward_releaser: forward_releaser.unwrap(),
+            },
+        )
+    )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_websocket_send_string(
+    amx: *const c_void,
+    socket: Cell,
+    message: *const c_char,
+) -> Cell {
+    let message = try_and_log_ffi!(amx, str_from_ptr(message));
+
+    try_and_log_ffi!(amx, get_websocket(socket))
+        .socket
+        .send(Message::Text(message.to_owned()));
+
+    1
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_websocket_send_binary(
+    amx: *const c_void,
+    socket: Cell,
+    data: *const u8,
+    length: Cell,
+) -> Cell {
+    let length = try_as_usize!(amx, length);
+
+    try_and_log_ffi!(amx, get_websocket(socket))
+        .socket
+        .send(Message::Binary(
+            std::slice::from_raw_parts(data, length).to_vec(),
+        ));
+
+    1
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_websocket_send_json(
+    amx: *const c_void,
+    socket: Cell,
+    value: Cell,
+    pretty: bool,
+    recursion_limit: Cell,
+) -> Cell {
+    let message = try_and_log_ffi!(
+        amx,
+        serialize_to_string(
+            &try_to_get_json_value!(amx, value)
+                .clone()
+                .into_with_recursion_limit(try_as_usize!(amx, recursion_limit)),
+            pretty,
+            false
+        )
+    );
+
+    try_and_log_ffi!(amx, get_websocket(socket))
+        .socket
+        .send(Message::Text(message));
+
+    1
+}
+
+/// Codes, which endpoint may send in the close frame. Others are reserved by RFC 6455.
+fn is_valid_close_code(code: Cell) -> bool {
+    match code {
+        1000..=1003 | 1007..=1014 | 3000..=4999 => true,
+        _ => false,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_websocket_close(
+    amx: *const c_void,
+    socket: Cell,
+    code: Cell,
+    reason: *const c_char,
+) -> Cell {
+    let reason = try_and_log_ffi!(amx, str_from_ptr(reason));
+    try_and_log_ffi!(
+        amx,
+        match code {
+            code if !is_valid_close_code(code) => {
+                Err(ffi_error(format!("Invalid close code {}", code)))
+            }
+            _ => Ok(()),
+        }
+    );
+
+    try_and_log_ffi!(amx, get_websocket(socket))
+        .socket
+        .close(code as u16, reason.to_owned());
+
+    1
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_websocket_is_connected(amx: *const c_void, socket: Cell) -> Cell {
+    try_and_log_ffi!(amx, get_websocket(socket))
+        .socket
+        .is_connected() as Cell
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_websocket_destroy(amx: *const c_void, socket: Cell) -> Cell {
+    let handle = try_and_log_ffi!(
+        amx,
+        get_module_mut()
+            .websockets_handles
+            .remove(socket)
+    );
+
+    handle.release_forward();
+
+    1
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_websocket_get_message_type(amx: *const c_void) -> Cell {
+    match try_and_log_ffi!(amx, get_websocket_event()) {
+        Event::Message(Message::Text(_)) => 1,
+        Event::Message(Message::Binary(_)) | Event::Ping(_) => 2,
+        _ => unconditionally_log_error!(amx, ffi_error("Current WebSocket event has no message")),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_websocket_get_message_length(amx: *const c_void) -> Cell {
+    try_and_log_ffi!(amx, get_websocket_payload()).len() as Cell
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_websocket_get_message_string(
+    amx: *const c_void,
+    buffer: *mut c_char,
+    size: Cell,
+) -> Cell {
+    let payload = try_and_log_ffi!(amx, get_websocket_payload());
+
+    try_to_copy_unsafe_string!(amx, buffer, String::from_utf8_lossy(payload), size)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_websocket_get_message_bytes(
+    amx: *const c_void,
+    buffer: *mut u8,
+    size: Cell,
+    offset: Cell,
+) -> Cell {
+    let payload = try_and_log_ffi!(amx, get_websocket_payload());
+    let bytes = try_and_log_ffi!(
+        amx,
+        response_body::slice(
+            payload,
+            try_as_usize!(amx, offset),
+            Some(try_as_usize!(amx, size))
+        )
+    );
+
+    std::ptr::copy_nonoverlapping(bytes.as_ptr(), buffer, bytes.len());
+
+    bytes.len() as Cell
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_websocket_parse_message_json(
+    amx: *const c_void,
+    error_buffer: *mut c_char,
+    error_buffer_size: Cell,
+) -> Cell {
+    let payload = try_and_log_ffi!(amx, get_websocket_payload());
+
+    let value: Result<serde_json::Value> =
+        serde_json::from_slice(payload).map_err(|e| ErrorKind::JSONError(e).into());
+
+    match value {
+        Ok(value) => try_and_log_ffi!(
+            amx,
+            get_module_mut()
+                .json_handles
+                .insert_owned(
+                    owner(amx),
+                    "grip_websocket_parse_message_json",
+                    value.into()
+                )
+        ),
+        Err(error) => {
+            use error_chain::ChainedError;
+            try_to_copy_unsafe_string!(amx, error_buffer, error.display_chain(), error_buffer_size);
+            0
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_websocket_get_close_code(amx: *const c_void) -> Cell {
+    match try_and_log_ffi!(amx, get_websocket_event()) {
+        Event::Disconnected { code, .. } => *code as Cell,
+        _ => unconditionally_log_error!(
+            amx,
+            ffi_error("Current WebSocket event is not a disconnect")
+        ),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_websocket_get_close_reason(
+    amx: *const c_void,
+    buffer: *mut c_char,
+    size: Cell,
+) -> Cell {
+    match try_and_log_ffi!(amx, get_websocket_event()) {
+        Event::Disconnected { reason, .. } => try_to_copy_unsafe_string!(amx, buffer, reason, size),
+        _ => unconditionally_log_error!(
+            amx,
+            ffi_error("Current WebSocket event is not a disconnect")
+        ),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_websocket_get_error_description(
+    amx: *const c_void,
+    buffer: *mut c_char,
+    size: Cell,
+) -> Cell {
+    match try_and_log_ffi!(amx, get_websocket_event()) {
+        Event::Error(e) => {
+            use error_chain::ChainedError;
+            try_to_copy_unsafe_string!(amx, buffer, e.display_chain(), size)
+        }
+        _ => unconditionally_log_error!(amx, ffi_error("Current WebSocket event is not an error")),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn grip_websocket_get_error_kind(amx: *const c_void) -> Cell {
+    match try_and_log_ffi!(amx, get_websocket_event()) {
+        Event::Error(e) => ErrorCategory::from(e) as Cell,
+        _ => unconditionally_log_error!(amx, ffi_error("Current WebSocket event is not an error")),
+    }
+}
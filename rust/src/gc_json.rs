@@ -163,6 +163,26 @@ impl Trace for InnerValue {
     }
 }
 
+/// Statistics of the cycle collector, since the module was loaded.
+#[derive(Clone, Debug, Default)]
+pub struct GCStats {
+    pub collections: usize,
+    pub total_time: Duration,
+    pub last_time: Option<Duration>,
+    pub last_collected_at: Option<Instant>,
+}
+
+static mut GC_STATS: Option<GCStats> = None;
+
+pub unsafe fn gc_stats() -> GCStats {
+    GC_STATS.clone().unwrap_or_default()
+}
+
+/// Number of values, which may be part of the garbage cycles.
+pub fn gc_roots_buffered() -> usize {
+    number_of_roots_buffered()
+}
+
 pub unsafe fn collect_cycles_if_needed() {
     let current_time = Instant::now();
 
@@ -176,6 +196,13 @@ pub unsafe fn collect_cycles_if_needed() {
     {
         LAST_TIME = Some(current_time);
 
-        collect_cycles()
+        collect_cycles();
+
+        let stats = GC_STATS.get_or_insert_with(GCStats::default);
+        let elapsed = current_time.elapsed();
+        stats.collections += 1;
+        stats.total_time += elapsed;
+        stats.last_time = Some(elapsed);
+        stats.last_collected_at = Some(current_time);
     }
 }
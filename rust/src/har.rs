@@ -0,0 +1,568 @@
+/*
+ * gRIP
+ * Copyright (c) 2018 Alik Aslanyan <cplusplus256@gmail.com>
+ *
+ *
+ *    This program is free software; you can redistribute it and/or modify it
+ *    under the terms of the GNU General Public License as published by the
+ *    Free Software Foundation; either version 3 of the License, or (at
+ *    your option) any later version.
+ *
+ *    This program is distributed in the hope that it will be useful, but
+ *    WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ *    General Public License for more details.
+ *
+ *    You should have received a copy of the GNU General Public License
+ *    along with this program; if not, write to the Free Software Foundation,
+ *    Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ *    In addition, as a special exception, the author gives permission to
+ *    link the code of this program with the Half-Life Game Engine ("HL
+ *    Engine") and Modified Game Libraries ("MODs") developed by Valve,
+ *    L.L.C ("Valve").  You must obey the GNU General Public License in all
+ *    respects for all of the code used other than the HL Engine and MODs
+ *    from Valve.  If you modify this file, you may extend this exception
+ *    to your version of the file, but you are not obligated to do so.  If
+ *    you do not wish to do so, delete this exception statement from your
+ *    version.
+ *
+ */
+
+//! Recording of the exchanges to the HAR (HTTP Archive 1.2) file and replaying them without network.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+
+use crate::errors::*;
+use crate::logger::{self, LoggerConfig};
+use crate::networking_queue::{Request, ResponseLimits};
+use crate::transport::{Exchange, ExchangeFuture, Transport};
+
+/// Values of the headers, which are redacted in the log, are replaced.
+fn headers_to_json(headers: &HeaderMap, redaction: &LoggerConfig) -> Value {
+    Value::Array(
+        headers
+            .iter()
+            .map(|(name, value)| {
+                let value = if redaction.is_header_redacted(name.as_str()) {
+                    logger::REDACTED.into()
+                } else {
+                    String::from_utf8_lossy(value.as_bytes())
+                };
+
+                json!({ "name": name.as_str(), "value": value })
+            })
+            .collect(),
+    )
+}
+
+fn headers_from_json(value: &Value) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+
+    for header in value.as_array().into_iter().flatten() {
+        let name = header["name"]
+            .as_str()
+            .and_then(|name| HeaderName::from_bytes(name.as_bytes()).ok());
+        let value = header["value"]
+            .as_str()
+            .and_then(|value| HeaderValue::from_str(value).ok());
+
+        if let (Some(name), Some(value)) = (name, value) {
+            headers.append(name, value);
+        }
+    }
+
+    headers
+}
+
+/// Text bodies are stored with the fields, which are redacted in the log, replaced.
+fn content_to_json(headers: &HeaderMap, body: &[u8], redaction: &LoggerConfig) -> Value {
+    let mime_type = headers
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+
+    match std::str::from_utf8(body) {
+        Ok(_) => json!({
+            "size": body.len(),
+            "mimeType": mime_type,
+            "text": logger::redact_text(redaction, body, logger::is_form(headers)),
+        }),
+        Err(_) => json!({
+            "size": body.len(),
+            "mimeType": mime_type,
+            "text": base64::encode(body),
+            "encoding": "base64",
+        }),
+    }
+}
+
+fn content_from_json(content: &Value) -> Result<Vec<u8>> {
+    let text = content["text"].as_str().unwrap_or_default();
+
+    if content["encoding"].as_str() == Some("base64") {
+        base64::decode(text).chain_err(|| "Invalid base64 content in HAR entry")
+    } else {
+        Ok(text.as_bytes().to_vec())
+    }
+}
+
+fn format_time(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let tm = time::at_utc(time::Timespec::new(since_epoch.as_secs() as i64, 0));
+
+    format!(
+        "{}.{:03}Z",
+        time::strftime("%Y-%m-%dT%H:%M:%S", &tm).unwrap_or_default(),
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Closes the archive written by `HarRecorder`.
+const HAR_END: &[u8] = b"\n]}}\n";
+
+fn load_entries(path: &Path) -> Result<Vec<Value>> {
+    let mut content = fs::read(path)?;
+    let mut har: Value = match serde_json::from_slice(&content) {
+        Ok(har) => har,
+        // Recording wasn't finished, e.g. server crashed.
+        Err(e) => {
+            content.extend_from_slice(HAR_END);
+            serde_json::from_slice(&content).map_err(|_| e)?
+        }
+    };
+
+    match har["log"]["entries"].take() {
+        Value::Array(entries) => Ok(entries),
+        _ => bail!("Missing log.entries array"),
+    }
+}
+
+/// Beginning of the archive, after which entries are appended.
+fn har_start() -> String {
+    format!(
+        "{{\"log\": {{\"version\": \"1.2\", \"creator\": {{\"name\": \"gRIP\", \"version\": \"{}\"}}, \"entries\": [",
+        env!("CARGO_PKG_VERSION")
+    )
+}
+
+/// Opens the archive for appending. Returns whether it already has entries.
+fn open_archive(path: &Path) -> Result<(fs::File, bool)> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let start = har_start();
+    let content = if path.exists() {
+        fs::read(path)?
+    } else {
+        vec![]
+    };
+
+    // Archive written by the recorder is reopened by cutting its end off.
+    if content.starts_with(start.as_bytes()) && content.ends_with(HAR_END) {
+        let mut file = fs::OpenOptions::new().write(true).open(path)?;
+        let length = (content.len() - HAR_END.len()) as u64;
+        file.set_len(length)?;
+        file.seek(SeekFrom::End(0))?;
+
+        return Ok((file, length > start.len() as u64));
+    }
+
+    // Other archives are converted once, their entries are kept.
+    let entries = if content.is_empty() {
+        vec![]
+    } else {
+        load_entries(path)?
+    };
+
+    let mut file = fs::File::create(path)?;
+    file.write_all(start.as_bytes())?;
+    for (index, entry) in entries.iter().enumerate() {
+        write_entry(&mut file, entry, index == 0)?;
+    }
+    file.flush()?;
+
+    Ok((file, !entries.is_empty()))
+}
+
+fn write_entry(file: &mut fs::File, entry: &Value, first: bool) -> Result<()> {
+    let mut bytes = if first {
+        b"\n".to_vec()
+    } else {
+        b",\n".to_vec()
+    };
+    bytes.extend(serde_json::to_vec(entry)?);
+    file.write_all(&bytes)?;
+
+    Ok(())
+}
+
+/// Appends exchanges to the HAR file from the dedicated thread, so request processing isn't blocked.
+/// Archive is closed, when recorder is dropped. Archive, which wasn't closed, is still loaded.
+pub struct HarRecorder {
+    redaction: LoggerConfig,
+    entries: Mutex<Option<crossbeam_channel::Sender<Value>>>,
+    writing_thread: Option<thread::JoinHandle<()>>,
+}
+
+impl HarRecorder {
+    /// Entries of the existing file are kept, so recording continues across map changes.
+    /// Headers, fields and query parameters redacted by the `redaction` are not written to the file.
+    pub fn new(path: PathBuf, redaction: LoggerConfig) -> Result<Self> {
+        let (mut file, mut has_entries) =
+            open_archive(&path).chain_err(|| format!("Can't open HAR file {}", path.display()))?;
+        let (sender, receiver) = crossbeam_channel::unbounded::<Value>();
+
+        let writing_thread = thread::spawn(move || {
+            for entry in receiver {
+                if let Err(e) = write_entry(&mut file, &entry, !has_entries) {
+                    error!("Can't write HAR file {}: {}", path.display(), e);
+                }
+                has_entries = true;
+            }
+
+            if let Err(e) = file.write_all(HAR_END) {
+                error!("Can't write HAR file {}: {}", path.display(), e);
+            }
+        });
+
+        Ok(HarRecorder {
+            redaction,
+            entries: Mutex::new(Some(sender)),
+            writing_thread: Some(writing_thread),
+        })
+    }
+
+    pub fn record(
+        &self,
+        request: &Request,
+        response: std::result::Result<(StatusCode, &HeaderMap, &[u8]), &Error>,
+        started: SystemTime,
+        elapsed: Duration,
+    ) {
+        let elapsed = elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_millis());
+
+        let mut request_json = json!({
+            "method": request.http_type.as_str(),
+            "url": logger::redact_url(&self.redaction, &request.uri),
+            "httpVersion": "HTTP/1.1",
+            "headers": headers_to_json(&request.options.headers, &self.redaction),
+            "queryString": request.uri.query_pairs()
+                .map(|(name, value)| {
+                    if self.redaction.is_field_redacted(&name) {
+                        json!({ "name": name, "value": logger::REDACTED })
+                    } else {
+                        json!({ "name": name, "value": value })
+                    }
+                })
+                .collect::<Vec<_>>(),
+            "cookies": [],
+            "headersSize": -1,
+            "bodySize": request.body.len(),
+        });
+
+        if !request.body.is_empty() {
+            let content = content_to_json(&request.options.headers, &request.body, &self.redaction);
+            request_json["postData"] = json!({
+                "mimeType": content["mimeType"],
+                "text": content["text"],
+            });
+            if let Some(encoding) = content.get("encoding") {
+                request_json["postData"]["encoding"] = encoding.clone();
+            }
+        }
+
+        let mut entry = json!({
+            "startedDateTime": format_time(started),
+            "time": elapsed,
+            "request": request_json,
+            "cache": {},
+            "timings": { "send": 0, "wait": elapsed, "receive": 0 },
+        });
+
+        entry["response"] = match response {
+            Ok((status, headers, body)) => {
+                // Body is stored decompressed.
+                let mut headers = headers.clone();
+                headers.remove(reqwest::header::CONTENT_ENCODING);
+                headers.remove(reqwest::header::CONTENT_LENGTH);
+
+                json!({
+                    "status": status.as_u16(),
+                    "statusText": status.canonical_reason().unwrap_or_default(),
+                    "httpVersion": "HTTP/1.1",
+                    "headers": headers_to_json(&headers, &self.redaction),
+                    "cookies": [],
+                    "content": content_to_json(&headers, body, &self.redaction),
+                    "redirectURL": "",
+                    "headersSize": -1,
+                    "bodySize": body.len(),
+                })
+            }
+            Err(error) => {
+                use error_chain::ChainedError;
+                json!({
+                    "status": 0,
+                    "statusText": "",
+                    "httpVersion": "HTTP/1.1",
+                    "headers": [],
+                    "cookies": [],
+                    "content": { "size": 0, "mimeType": "" },
+                    "redirectURL": "",
+                    "headersSize": -1,
+                    "bodySize": -1,
+                    "_error": error.display_chain().to_string(),
+                })
+            }
+        };
+
+        if let Some(ref entries) = *self.entries.lock().unwrap() {
+            entries.send(entry).ok();
+        }
+    }
+}
+
+impl Drop for HarRecorder {
+    fn drop(&mut self) {
+        // Writing thread closes the archive, once sender is dropped.
+        self.entries.lock().unwrap().take();
+        if let Some(thread) = self.writing_thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+struct ReplayEntry {
+    response: std::result::Result<(StatusCode, HeaderMap, Vec<u8>), String>,
+}
+
+/// Serves recorded responses by method and URL. Repeated requests receive recorded responses in order,
+/// the last one is repeated, when they are exhausted.
+#[derive(Default)]
+pub struct HarReplay {
+    entries: HashMap<(String, String), Vec<ReplayEntry>>,
+    cursors: Mutex<HashMap<(String, String), usize>>,
+    /// URLs are recorded with the query parameters redacted the same way.
+    redaction: LoggerConfig,
+}
+
+impl HarReplay {
+    /// Loads HAR file, or all `*.har` files of the directory in alphabetical order.
+    pub fn load(path: &Path, redaction: LoggerConfig) -> Result<Self> {
+        let files = if path.is_dir() {
+            let mut files: Vec<PathBuf> = fs::read_dir(path)?
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.extension().map_or(false, |ext| ext == "har"))
+                .collect();
+            files.sort();
+            files
+        } else {
+            vec![path.to_owned()]
+        };
+
+        let mut entries: HashMap<(String, String), Vec<ReplayEntry>> = HashMap::new();
+
+        for file in files {
+            for entry in load_entries(&file)
+                .chain_err(|| format!("Can't parse HAR file {}", file.display()))?
+            {
+                let key = (
+                    entry["request"]["method"]
+                        .as_str()
+                        .unwrap_or_default()
+                        .to_uppercase(),
+                    entry["request"]["url"]
+                        .as_str()
+                        .unwrap_or_default()
+                        .to_owned(),
+                );
+
+                let response = &entry["response"];
+                let status = response["status"].as_u64().unwrap_or(0);
+
+                let response = if status == 0 {
+                    Err(response["_error"]
+                        .as_str()
+                        .unwrap_or("Recorded request failed")
+                        .to_owned())
+                } else {
+                    Ok((
+                        StatusCode::from_u16(status as u16)
+                            .chain_err(|| format!("Invalid status {} in HAR entry", status))?,
+                        headers_from_json(&response["headers"]),
+                        content_from_json(&response["content"])?,
+                    ))
+                };
+
+                entries
+                    .entry(key)
+                    .or_insert_with(Vec::new)
+                    .push(ReplayEntry { response });
+            }
+        }
+
+        Ok(HarReplay {
+            entries,
+            cursors: Mutex::new(HashMap::new()),
+            redaction,
+        })
+    }
+
+    pub fn find(&self, request: &Request) -> Result<Exchange> {
+        let method = request.http_type.as_str();
+        let mut key = (method.to_owned(), request.uri.as_str().to_owned());
+        if !self.entries.contains_key(&key) {
+            // Archive was recorded with the query parameters redacted.
+            let redacted_key = (
+                method.to_owned(),
+                logger::redact_url(&self.redaction, &request.uri),
+            );
+            if self.entries.contains_key(&redacted_key) {
+                key = redacted_key;
+            }
+        }
+
+        let entries = self
+            .entries
+            .get(&key)
+            .chain_err(|| ErrorKind::ReplayEntryNotFound(format!("{} {}", key.0, key.1)))?;
+
+        let mut cursors = self.cursors.lock().unwrap();
+        let cursor = cursors.entry(key).or_insert(0);
+        let entry = &entries[std::cmp::min(*cursor, entries.len() - 1)];
+        *cursor += 1;
+
+        match entry.response {
+            Ok(ref response) => Ok(response.clone()),
+            Err(ref error) => Err(ErrorKind::ReplayedError(error.clone()).into()),
+        }
+    }
+}
+
+impl Transport for HarReplay {
+    fn send(&self, request: &Request, _limits: ResponseLimits) -> ExchangeFuture {
+        Box::new(futures::future::result(self.find(request)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::networking_queue::{RequestBuilder, RequestType};
+
+    fn request(http_type: RequestType, url: &str) -> Request {
+        RequestBuilder::default()
+            .http_type(http_type)
+            .uri(url.parse().unwrap())
+            .body(b"{\"a\":1,\"password\":\"p\"}".to_vec())
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_record_and_replay() {
+        let directory = std::env::temp_dir().join(format!("grip-har-test-{}", std::process::id()));
+        fs::remove_dir_all(&directory).ok();
+        let path = directory.join("session.har");
+
+        let mut headers = HeaderMap::new();
+        headers.insert("content-type", "application/json".parse().unwrap());
+        headers.insert("content-encoding", "gzip".parse().unwrap());
+
+        let recorder = HarRecorder::new(path.clone(), LoggerConfig::default()).unwrap();
+        let mut get = request(RequestType::Get, "https://a.com/x?y=1&token=t");
+        get.options
+            .headers
+            .insert("authorization", "Bearer secret".parse().unwrap());
+        recorder.record(
+            &get,
+            Ok((StatusCode::OK, &headers, b"first")),
+            SystemTime::now(),
+            Duration::from_millis(5),
+        );
+        recorder.record(
+            &get,
+            Ok((StatusCode::NOT_FOUND, &headers, &[0xff, 0x00])),
+            SystemTime::now(),
+            Duration::from_millis(5),
+        );
+        let error: Error = ErrorKind::RequestTimeout.into();
+        recorder.record(
+            &request(RequestType::Post, "https://a.com/x"),
+            Err(&error),
+            SystemTime::now(),
+            Duration::from_millis(5),
+        );
+
+        drop(recorder);
+        let entries = load_entries(&path).unwrap();
+        assert_eq!(entries.len(), 3);
+        let recorded = &entries[0]["request"];
+        assert_eq!(recorded["headers"][0]["value"], logger::REDACTED);
+        assert_eq!(recorded["url"], "https://a.com/x?y=1&token=%5BREDACTED%5D");
+        assert_eq!(recorded["queryString"][1]["value"], logger::REDACTED);
+        assert_eq!(
+            recorded["postData"]["text"],
+            r#"{"a":1,"password":"[REDACTED]"}"#
+        );
+
+        // Recording continues in the existing file, even if it wasn't closed.
+        let content = fs::read(&path).unwrap();
+        fs::write(&path, &content[..content.len() - HAR_END.len()]).unwrap();
+        let recorder = HarRecorder::new(path.clone(), LoggerConfig::default()).unwrap();
+        recorder.record(
+            &request(RequestType::Put, "https://a.com/x"),
+            Ok((StatusCode::OK, &headers, b"")),
+            SystemTime::now(),
+            Duration::from_millis(5),
+        );
+        drop(recorder);
+        assert_eq!(load_entries(&path).unwrap().len(), 4);
+
+        let replay = HarReplay::load(&directory, LoggerConfig::default()).unwrap();
+
+        // Request is found by the redacted URL.
+        let (status, headers, body) = replay.find(&get).unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers.get("content-type").unwrap(), "application/json");
+        assert!(headers.get("content-encoding").is_none());
+        assert_eq!(body, b"first");
+
+        for _ in 0..2 {
+            let (status, _, body) = replay.find(&get).unwrap();
+            assert_eq!(status, StatusCode::NOT_FOUND);
+            assert_eq!(body, vec![0xff, 0x00]);
+        }
+
+        match replay
+            .find(&request(RequestType::Post, "https://a.com/x"))
+            .unwrap_err()
+            .kind()
+        {
+            ErrorKind::ReplayedError(message) => assert!(message.contains("Request timeout")),
+            _ => unreachable!(),
+        }
+
+        match replay
+            .find(&request(RequestType::Delete, "https://a.com/x"))
+            .unwrap_err()
+            .kind()
+        {
+            ErrorKind::ReplayEntryNotFound(key) => assert_eq!(key, "DELETE https://a.com/x"),
+            _ => unreachable!(),
+        }
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
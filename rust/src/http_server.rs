@@ -0,0 +1,562 @@
+/*
+ * gRIP
+ * Copyright (c) 2018 Alik Aslanyan <cplusplus256@gmail.com>
+ *
+ *
+ *    This program is free software; you can redistribute it and/or modify it
+ *    under the terms of the GNU General Public License as published by the
+ *    Free Software Foundation; either version 3 of the License, or (at
+ *    your option) any later version.
+ *
+ *    This program is distributed in the hope that it will be useful, but
+ *    WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ *    General Public License for more details.
+ *
+ *    You should have received a copy of the GNU General Public License
+ *    along with this program; if not, write to the Free Software Foundation,
+ *    Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ *    In addition, as a special exception, the author gives permission to
+ *    link the code of this program with the Half-Life Game Engine ("HL
+ *    Engine") and Modified Game Libraries ("MODs") developed by Valve,
+ *    L.L.C ("Valve").  You must obey the GNU General Public License in all
+ *    respects for all of the code used other than the HL Engine and MODs
+ *    from Valve.  If you modify this file, you may extend this exception
+ *    to your version of the file, but you are not obligated to do so.  If
+ *    you do not wish to do so, delete this exception statement from your
+ *    version.
+ *
+ */
+
+//! Embedded HTTP server. Requests are accepted in the background thread and handed
+//! over to the main thread, which replies to them through `IncomingRequest::reply`.
+
+use std::net::{SocketAddr, TcpListener};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use futures::future;
+use futures::prelude::*;
+use futures::sync::oneshot;
+
+use hyper::header::{self, HeaderMap, HeaderValue};
+use hyper::server::conn::AddrStream;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, StatusCode};
+
+use tokio::prelude::FutureExt;
+
+use crate::errors::*;
+
+#[derive(Clone, Debug)]
+pub struct ServerConfig {
+    pub bind_address: SocketAddr,
+    /// Token, which should be passed in the `Authorization: Bearer <token>` header.
+    pub auth_token: Option<String>,
+    /// Patterns of the paths accepted by the server. See `route_matches`.
+    pub allowed_routes: Vec<String>,
+    pub max_request_body_size: usize,
+    /// Time given to the main thread to reply, before client receives `504 Gateway Timeout`.
+    pub reply_timeout: Duration,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind_address: ([127, 0, 0, 1], 8080).into(),
+            auth_token: None,
+            allowed_routes: vec!["/*".to_owned()],
+            max_request_body_size: 1024 * 1024,
+            reply_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Reply {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+impl Reply {
+    pub fn new(status: StatusCode, content_type: &'static str, body: Vec<u8>) -> Self {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+
+        Reply {
+            status,
+            headers,
+            body,
+        }
+    }
+
+    fn text(status: StatusCode) -> Self {
+        Reply::new(
+            status,
+            "text/plain; charset=utf-8",
+            status
+                .canonical_reason()
+                .unwrap_or_default()
+                .as_bytes()
+                .to_vec(),
+        )
+    }
+
+    fn into_response(self) -> hyper::Response<Body> {
+        let mut response = hyper::Response::new(Body::from(self.body));
+        *response.status_mut() = self.status;
+        *response.headers_mut() = self.headers;
+        response
+    }
+}
+
+#[derive(Debug)]
+pub struct IncomingRequest {
+    pub method: String,
+    pub path: String,
+    pub query: String,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+    pub remote_address: SocketAddr,
+    reply_sender: Option<oneshot::Sender<Reply>>,
+}
+
+impl IncomingRequest {
+    /// First value of the decoded query parameter.
+    pub fn query_param(&self, name: &str) -> Option<String> {
+        url::form_urlencoded::parse(self.query.as_bytes())
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    }
+
+    /// Sends the reply to the client. Every request can be replied only once.
+    pub fn reply(&mut self, reply: Reply) -> Result<()> {
+        let sender = self
+            .reply_sender
+            .take()
+            .chain_err(|| ffi_error("Request was already replied"))?;
+
+        sender
+            .send(reply)
+            .map_err(|_| ffi_error("Client has gone away or reply timeout has expired"))
+    }
+
+    /// Whether the client still waits for the reply.
+    pub fn is_waiting_for_reply(&self) -> bool {
+        self.reply_sender
+            .as_ref()
+            .map_or(false, |sender| !sender.is_canceled())
+    }
+}
+
+/// Collapses empty and `.` segments, resolves `..` segments and drops the trailing slash,
+/// so the same route can't be reached by the different spellings.
+pub fn normalize_path(path: &str) -> String {
+    let mut segments = vec![];
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+
+    format!("/{}", segments.join("/"))
+}
+
+/// Matches exact path, or any nested path, when pattern ends with `/*`.
+pub fn route_matches(pattern: &str, path: &str) -> bool {
+    if pattern.ends_with("/*") {
+        let prefix = &pattern[..pattern.len() - 1];
+        path.starts_with(prefix) || path == &prefix[..prefix.len() - 1]
+    } else {
+        pattern == path
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+fn is_authorized(config: &ServerConfig, headers: &HeaderMap) -> bool {
+    match config.auth_token {
+        Some(ref token) => headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| value.starts_with("Bearer "))
+            .map_or(false, |value| {
+                constant_time_eq(value["Bearer ".len()..].as_bytes(), token.as_bytes())
+            }),
+        None => true,
+    }
+}
+
+fn handle_request(
+    config: &ServerConfig,
+    request_sender: crossbeam_channel::Sender<IncomingRequest>,
+    remote_address: SocketAddr,
+    request: hyper::Request<Body>,
+) -> ResponseFuture {
+    // Unauthorized clients can't tell, which routes exist.
+    if !is_authorized(config, request.headers()) {
+        let mut reply = Reply::text(StatusCode::UNAUTHORIZED);
+        reply
+            .headers
+            .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+        return Box::new(future::ok(reply.into_response()));
+    }
+
+    let path = normalize_path(request.uri().path());
+    if !config
+        .allowed_routes
+        .iter()
+        .any(|pattern| route_matches(pattern, &path))
+    {
+        return Box::new(future::ok(
+            Reply::text(StatusCode::NOT_FOUND).into_response(),
+        ));
+    }
+
+    let (parts, body) = request.into_parts();
+    let max_body_size = config.max_request_body_size;
+    let reply_timeout = config.reply_timeout;
+
+    Box::new(
+        body.fold(Some(vec![]), move |body, chunk| {
+            future::ok::<_, hyper::Error>(body.and_then(|mut body| {
+                if body.len() + chunk.len() > max_body_size {
+                    None
+                } else {
+                    body.extend_from_slice(&chunk);
+                    Some(body)
+                }
+            }))
+        })
+        .and_then(move |body| {
+            let body = match body {
+                Some(body) => body,
+                None => {
+                    return future::Either::A(future::ok(
+                        Reply::text(StatusCode::PAYLOAD_TOO_LARGE).into_response(),
+                    ))
+                }
+            };
+
+            let (reply_sender, reply_receiver) = oneshot::channel();
+
+            let incoming_request = IncomingRequest {
+                method: parts.method.as_str().to_owned(),
+                path,
+                query: parts.uri.query().unwrap_or_default().to_owned(),
+                headers: parts.headers,
+                body,
+                remote_address,
+                reply_sender: Some(reply_sender),
+            };
+
+            if request_sender.send(incoming_request).is_err() {
+                return future::Either::A(future::ok(
+                    Reply::text(StatusCode::SERVICE_UNAVAILABLE).into_response(),
+                ));
+            }
+
+            future::Either::B(reply_receiver.timeout(reply_timeout).then(|result| {
+                Ok(match result {
+                    Ok(reply) => reply,
+                    Err(ref e) if e.is_elapsed() => Reply::text(StatusCode::GATEWAY_TIMEOUT),
+                    // Dropped without reply.
+                    Err(_) => Reply::text(StatusCode::INTERNAL_SERVER_ERROR),
+                }
+                .into_response())
+            }))
+        }),
+    )
+}
+
+fn bind(address: &SocketAddr) -> Result<TcpListener> {
+    let builder = match address {
+        SocketAddr::V4(_) => net2::TcpBuilder::new_v4()?,
+        SocketAddr::V6(_) => net2::TcpBuilder::new_v6()?,
+    };
+
+    // Server is restarted on every map change, so port shouldn't be blocked by connections in TIME_WAIT.
+    builder.reuse_address(true)?;
+    builder.bind(address)?;
+
+    Ok(builder.listen(128)?)
+}
+
+pub type ResponseFuture =
+    Box<dyn Future<Item = hyper::Response<Body>, Error = hyper::Error> + Send>;
+
+/// Hyper server running in the own thread, until stopped or dropped.
+pub struct BackgroundServer {
+    working_thread: Option<thread::JoinHandle<()>>,
+    shutdown_sender: Option<oneshot::Sender<()>>,
+    local_address: SocketAddr,
+}
+
+impl Drop for BackgroundServer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl BackgroundServer {
+    /// Handler receives address of the client and the request.
+    pub fn start<H>(bind_address: &SocketAddr, handler: H) -> Result<Self>
+    where
+        H: Fn(SocketAddr, hyper::Request<Body>) -> ResponseFuture + Send + Sync + 'static,
+    {
+        let listener = bind(bind_address)
+            .chain_err(|| format!("Can't bind HTTP server to {}", bind_address))?;
+        let local_address = listener.local_addr()?;
+
+        let (shutdown_sender, shutdown_receiver) = oneshot::channel::<()>();
+        let handler = Arc::new(handler);
+
+        let working_thread = thread::spawn(move || {
+            let mut runtime = tokio::runtime::Runtime::new().unwrap();
+
+            let result = runtime.block_on(future::lazy(move || {
+                future::result(hyper::Server::from_tcp(listener)).and_then(move |server| {
+                    server
+                        .serve(make_service_fn(move |socket: &AddrStream| {
+                            let remote_address = socket.remote_addr();
+                            let handler = handler.clone();
+
+                            future::ok::<_, hyper::Error>(service_fn(move |request| {
+                                handler(remote_address, request)
+                            }))
+                        }))
+                        .with_graceful_shutdown(shutdown_receiver.then(|_| Ok::<_, ()>(())))
+                })
+            }));
+
+            if let Err(e) = result {
+                error!("HTTP server stopped with error: {}", e);
+            }
+
+            runtime.shutdown_now().wait().ok();
+        });
+
+        Ok(BackgroundServer {
+            working_thread: Some(working_thread),
+            shutdown_sender: Some(shutdown_sender),
+            local_address,
+        })
+    }
+
+    pub fn local_address(&self) -> SocketAddr {
+        self.local_address
+    }
+
+    /// Stops accepting connections and waits for already accepted requests.
+    pub fn stop(&mut self) {
+        if let Some(sender) = self.shutdown_sender.take() {
+            sender.send(()).ok();
+        }
+
+        if let Some(thread) = self.working_thread.take() {
+            thread.join().unwrap();
+        }
+    }
+}
+
+pub struct HttpServer {
+    server: BackgroundServer,
+    request_receiver: Option<crossbeam_channel::Receiver<IncomingRequest>>,
+}
+
+impl Drop for HttpServer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl HttpServer {
+    pub fn start(config: ServerConfig) -> Result<Self> {
+        let (request_sender, request_receiver) = crossbeam_channel::unbounded();
+
+        let bind_address = config.bind_address;
+        let server = BackgroundServer::start(&bind_address, move |remote_address, request| {
+            handle_request(&config, request_sender.clone(), remote_address, request)
+        })?;
+
+        Ok(HttpServer {
+            server,
+            request_receiver: Some(request_receiver),
+        })
+    }
+
+    pub fn local_address(&self) -> SocketAddr {
+        self.server.local_address()
+    }
+
+    pub fn try_recv_request(&self) -> Option<IncomingRequest> {
+        self.request_receiver
+            .as_ref()
+            .and_then(|receiver| receiver.try_recv().ok())
+    }
+
+    /// Stops accepting connections. Requests, which were not handed over yet, are rejected.
+    pub fn stop(&mut self) {
+        // Dropping receiver unblocks requests, that are still in the channel.
+        self.request_receiver = None;
+        self.server.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::time::Instant;
+
+    fn start(auth_token: Option<&str>) -> HttpServer {
+        HttpServer::start(ServerConfig {
+            bind_address: ([127, 0, 0, 1], 0).into(),
+            auth_token: auth_token.map(|s| s.to_owned()),
+            allowed_routes: vec!["/api/*".to_owned(), "/kick".to_owned()],
+            max_request_body_size: 16,
+            reply_timeout: Duration::from_millis(300),
+        })
+        .unwrap()
+    }
+
+    fn send_raw(server: &HttpServer, raw: &str) -> thread::JoinHandle<String> {
+        let mut stream = TcpStream::connect(server.local_address()).unwrap();
+        stream.write_all(raw.as_bytes()).unwrap();
+
+        thread::spawn(move || {
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        })
+    }
+
+    fn wait_for_request(server: &HttpServer) -> IncomingRequest {
+        let instant = Instant::now();
+        loop {
+            if let Some(request) = server.try_recv_request() {
+                return request;
+            }
+            assert!(instant.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn test_route_matches() {
+        assert!(route_matches("/kick", "/kick"));
+        assert!(!route_matches("/kick", "/kick/1"));
+        assert!(route_matches("/api/*", "/api"));
+        assert!(route_matches("/api/*", "/api/map/de_dust2"));
+        assert!(!route_matches("/api/*", "/apis"));
+        assert!(route_matches("/*", "/"));
+        assert!(route_matches("/*", "/anything"));
+    }
+
+    #[test]
+    fn test_normalize_path() {
+        assert_eq!(normalize_path("/kick"), "/kick");
+        assert_eq!(normalize_path("/kick/"), "/kick");
+        assert_eq!(normalize_path("//api//./map/"), "/api/map");
+        assert_eq!(normalize_path("/api/../kick"), "/kick");
+        assert_eq!(normalize_path("/../.."), "/");
+        assert_eq!(normalize_path("/"), "/");
+    }
+
+    #[test]
+    fn test_request_and_reply() {
+        let server = start(Some("secret"));
+
+        let client = send_raw(
+            &server,
+            "POST /api/map?name=de%20dust2&force=1 HTTP/1.1\r\nHost: localhost\r\n\
+             Authorization: Bearer secret\r\nX-Test: 1\r\nContent-Length: 4\r\nConnection: close\r\n\r\nbody",
+        );
+
+        let mut request = wait_for_request(&server);
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/api/map");
+        assert_eq!(request.query_param("name").unwrap(), "de dust2");
+        assert!(request.query_param("missing").is_none());
+        assert_eq!(request.headers.get("x-test").unwrap(), "1");
+        assert_eq!(request.body, b"body");
+        assert!(request.is_waiting_for_reply());
+
+        request
+            .reply(Reply::new(
+                StatusCode::CREATED,
+                "application/json",
+                b"{}".to_vec(),
+            ))
+            .unwrap();
+        assert!(request.reply(Reply::text(StatusCode::OK)).is_err());
+
+        let response = client.join().unwrap();
+        assert!(response.starts_with("HTTP/1.1 201 Created\r\n"));
+        assert!(response.contains("content-type: application/json\r\n"));
+        assert!(response.ends_with("\r\n\r\n{}"));
+    }
+
+    #[test]
+    fn test_rejections() {
+        let server = start(Some("secret"));
+
+        let response = send_raw(
+            &server,
+            "GET /kick HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        )
+        .join()
+        .unwrap();
+        assert!(response.starts_with("HTTP/1.1 401 Unauthorized\r\n"));
+
+        // Missing routes aren't revealed before the authorization.
+        let response = send_raw(
+            &server,
+            "GET /admin HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        )
+        .join()
+        .unwrap();
+        assert!(response.starts_with("HTTP/1.1 401 Unauthorized\r\n"));
+
+        let response = send_raw(
+            &server,
+            "GET /admin HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer secret\r\nConnection: close\r\n\r\n",
+        )
+        .join()
+        .unwrap();
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        let response = send_raw(
+            &server,
+            "POST /kick HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer secret\r\n\
+             Content-Length: 17\r\nConnection: close\r\n\r\n01234567890123456",
+        )
+        .join()
+        .unwrap();
+        assert!(response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+
+        // Nothing should reach the main thread.
+        assert!(server.try_recv_request().is_none());
+
+        let client = send_raw(
+            &server,
+            "GET //./kick/ HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer secret\r\nConnection: close\r\n\r\n",
+        );
+        let request = wait_for_request(&server);
+        assert_eq!(request.path, "/kick");
+
+        let response = client.join().unwrap();
+        assert!(response.starts_with("HTTP/1.1 504 Gateway Timeout\r\n"));
+        assert!(!request.is_waiting_for_reply());
+    }
+}
@@ -63,12 +63,59 @@ mod errors {
             RequestTimeout {
                 display("Request timeout")
             }
+            ResponseBodyTooLarge(limit: usize) {
+                display("Response body is larger than {} bytes", limit)
+            }
+            DecodingError(t: String) {
+                display("Decoding Error: {}", t)
+            }
+            HostResolutionError(host: String) {
+                display("Unable to resolve {}", host)
+            }
+            WebSocketURLError(t: String) {
+                display("WebSocket URL Error: {}", t)
+            }
+            WebSocketHandshakeError(e: ::tungstenite::Error) {
+                display("WebSocket Handshake Error: {}", e)
+            }
+            WebSocketNotConnected {
+                display("WebSocket is not connected")
+            }
+            ReplayEntryNotFound(t: String) {
+                display("No recorded response for {}", t)
+            }
+            ReplayedError(t: String) {
+                display("Replayed error: {}", t)
+            }
+            MockFailure(kind: crate::error_category::ErrorCategory) {
+                display("Mock transport failure: {}", kind.name())
+            }
+            InvalidHandle(handle_type: crate::cell_map::HandleType, handle: isize) {
+                display("Invalid {} handle {}", handle_type.name(), handle)
+            }
+            WrongHandleType(expected: crate::cell_map::HandleType, actual: crate::cell_map::HandleType, handle: isize) {
+                display("Invalid {} handle {}, which is {} handle", expected.name(), handle, actual.name())
+            }
+            DestroyedHandle(handle_type: crate::cell_map::HandleType, handle: isize) {
+                display("Invalid {} handle {}, which was already destroyed", handle_type.name(), handle)
+            }
+            ChainTemplateError(t: String) {
+                display("Chain template error: {}", t)
+            }
+            HandleLimitExceeded(handle_type: crate::cell_map::HandleType, limit: usize) {
+                display("There are already {} {} handles, which is the maximum", limit, handle_type.name())
+            }
+            HandleQuotaExceeded(handle_type: crate::cell_map::HandleType, quota: usize) {
+                display("Plugin already has {} {} handles, which is the quota set by \"handles.max-handles-per-plugin\" in the grip.ini", quota, handle_type.name())
+            }
         }
 
         foreign_links {
             CrossBeamError(::crossbeam_channel::TryRecvError);
             HTTPError(::reqwest::Error);
             JSONError(::serde_json::Error);
+            IOError(::std::io::Error);
+            WebSocketError(::tungstenite::Error);
         }
     }
 
@@ -81,6 +128,19 @@ mod errors {
 pub mod gc_json;
 
 pub mod cell_map;
+pub mod chain;
+pub mod error_category;
 pub mod ffi;
+pub mod har;
 
+pub mod http_server;
+pub mod logger;
+pub mod metrics;
 pub mod networking_queue;
+pub mod response_body;
+pub mod sse;
+pub mod transport;
+pub mod websocket;
+
+#[cfg(test)]
+mod test_server;
@@ -0,0 +1,525 @@
+/*
+ * gRIP
+ * Copyright (c) 2018 Alik Aslanyan <cplusplus256@gmail.com>
+ *
+ *
+ *    This program is free software; you can redistribute it and/or modify it
+ *    under the terms of the GNU General Public License as published by the
+ *    Free Software Foundation; either version 3 of the License, or (at
+ *    your option) any later version.
+ *
+ *    This program is distributed in the hope that it will be useful, but
+ *    WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ *    General Public License for more details.
+ *
+ *    You should have received a copy of the GNU General Public License
+ *    along with this program; if not, write to the Free Software Foundation,
+ *    Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ *    In addition, as a special exception, the author gives permission to
+ *    link the code of this program with the Half-Life Game Engine ("HL
+ *    Engine") and Modified Game Libraries ("MODs") developed by Valve,
+ *    L.L.C ("Valve").  You must obey the GNU General Public License in all
+ *    respects for all of the code used other than the HL Engine and MODs
+ *    from Valve.  If you modify this file, you may extend this exception
+ *    to your version of the file, but you are not obligated to do so.  If
+ *    you do not wish to do so, delete this exception statement from your
+ *    version.
+ *
+ */
+
+//! Implementation of the `log` facade, which writes to the rotating file.
+//! Requests of the queue are logged through `log_exchange` with `grip::http` target.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use reqwest::header::HeaderMap;
+use reqwest::{StatusCode, Url};
+
+use crate::errors::*;
+
+pub const HTTP_TARGET: &str = "grip::http";
+
+pub const REDACTED: &str = "[REDACTED]";
+
+#[derive(Clone, Debug)]
+pub struct LoggerConfig {
+    pub level: LevelFilter,
+    /// Messages are discarded, when there is no file.
+    pub file: Option<PathBuf>,
+    /// File is rotated, when it grows bigger than this size in bytes.
+    pub max_file_size: u64,
+    /// Number of rotated files kept beside the current one.
+    pub max_files: usize,
+    /// Requests only to these hosts are logged. Empty list allows all hosts.
+    pub hosts: Vec<String>,
+    pub exclude_hosts: Vec<String>,
+    pub log_requests: bool,
+    pub log_bodies: bool,
+    pub max_logged_body_size: usize,
+    /// Case insensitive names of the headers, which values are hidden.
+    pub redact_headers: Vec<String>,
+    /// Names of the JSON fields, form fields and query parameters, which values are hidden.
+    pub redact_fields: Vec<String>,
+}
+
+impl Default for LoggerConfig {
+    fn default() -> Self {
+        LoggerConfig {
+            level: LevelFilter::Info,
+            file: None,
+            max_file_size: 10 * 1024 * 1024,
+            max_files: 3,
+            hosts: vec![],
+            exclude_hosts: vec![],
+            log_requests: false,
+            log_bodies: false,
+            max_logged_body_size: 4096,
+            redact_headers: [
+                "authorization",
+                "proxy-authorization",
+                "cookie",
+                "set-cookie",
+            ]
+            .iter()
+            .map(|s| (*s).to_owned())
+            .collect(),
+            redact_fields: ["password", "token", "access_token", "secret"]
+                .iter()
+                .map(|s| (*s).to_owned())
+                .collect(),
+        }
+    }
+}
+
+impl LoggerConfig {
+    fn is_host_logged(&self, host: &str) -> bool {
+        let matches = |pattern: &String| pattern.eq_ignore_ascii_case(host);
+
+        (self.hosts.is_empty() || self.hosts.iter().any(matches))
+            && !self.exclude_hosts.iter().any(matches)
+    }
+
+    pub(crate) fn is_header_redacted(&self, name: &str) -> bool {
+        self.redact_headers
+            .iter()
+            .any(|header| header.eq_ignore_ascii_case(name))
+    }
+
+    pub(crate) fn is_field_redacted(&self, name: &str) -> bool {
+        self.redact_fields.iter().any(|field| field == name)
+    }
+}
+
+struct State {
+    config: LoggerConfig,
+    file: Option<File>,
+    file_size: u64,
+}
+
+impl State {
+    fn write_line(&mut self, line: &str) {
+        if self.file.is_none() {
+            return;
+        }
+
+        if self.file_size + line.len() as u64 > self.config.max_file_size {
+            if let Err(e) = self.rotate() {
+                println!("[gRIP] Error: Can't rotate log file: {}", e);
+            }
+        }
+
+        if let Some(file) = self.file.as_mut() {
+            if file.write_all(line.as_bytes()).is_ok() {
+                self.file_size += line.len() as u64;
+            }
+        }
+    }
+
+    fn rotate(&mut self) -> Result<()> {
+        let path = match self.config.file {
+            Some(ref path) => path.clone(),
+            None => return Ok(()),
+        };
+
+        self.file = None;
+
+        let rotated_path = |index: usize| {
+            let mut name = path.clone().into_os_string();
+            name.push(format!(".{}", index));
+            PathBuf::from(name)
+        };
+
+        if self.config.max_files == 0 {
+            fs::remove_file(&path)?;
+        } else {
+            fs::remove_file(rotated_path(self.config.max_files)).ok();
+            for index in (1..self.config.max_files).rev() {
+                fs::rename(rotated_path(index), rotated_path(index + 1)).ok();
+            }
+            fs::rename(&path, rotated_path(1))?;
+        }
+
+        self.file = Some(open_file(&path)?);
+        self.file_size = 0;
+
+        Ok(())
+    }
+}
+
+fn open_file(path: &Path) -> Result<File> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    Ok(OpenOptions::new().create(true).append(true).open(path)?)
+}
+
+struct FileLogger {
+    state: Mutex<Option<State>>,
+}
+
+lazy_static! {
+    static ref LOGGER: FileLogger = FileLogger {
+        state: Mutex::new(None),
+    };
+}
+
+impl Log for FileLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        // Dependencies are too chatty on the lower levels.
+        metadata.target().starts_with("grip") || metadata.level() <= Level::Warn
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let line = format!(
+            "{} [{}] {}: {}\n",
+            time::strftime("%Y-%m-%d %H:%M:%S", &time::now()).unwrap_or_default(),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+
+        if let Some(state) = self.state.lock().unwrap().as_mut() {
+            if record.level() <= state.config.level {
+                state.write_line(&line);
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(file) = self
+            .state
+            .lock()
+            .unwrap()
+            .as_mut()
+            .and_then(|state| state.file.as_mut())
+        {
+            file.flush().ok();
+        }
+    }
+}
+
+/// Installs the logger or replaces configuration of already installed one.
+pub fn init(config: LoggerConfig) -> Result<()> {
+    let (file, file_size) = match config.file {
+        Some(ref path) => {
+            let file =
+                open_file(path).chain_err(|| format!("Can't open log file {}", path.display()))?;
+            let file_size = file.metadata().map(|m| m.len()).unwrap_or(0);
+            (Some(file), file_size)
+        }
+        None => (None, 0),
+    };
+
+    let level = config.level;
+    *LOGGER.state.lock().unwrap() = Some(State {
+        config,
+        file,
+        file_size,
+    });
+
+    // Logger can be set only once per process, so later calls just replace the state.
+    log::set_logger(&*LOGGER).ok();
+    log::set_max_level(level);
+
+    Ok(())
+}
+
+/// Closes the log file. Messages are discarded until next `init`.
+pub fn shutdown() {
+    *LOGGER.state.lock().unwrap() = None;
+}
+
+/// Request and its outcome, as seen by the queue.
+pub struct Exchange<'a> {
+    pub method: &'a str,
+    pub url: &'a Url,
+    pub request_headers: &'a HeaderMap,
+    pub request_body: &'a [u8],
+    pub response: std::result::Result<(StatusCode, &'a HeaderMap, &'a [u8]), &'a Error>,
+    pub elapsed: Duration,
+}
+
+fn config_for_host(host: &str) -> Option<LoggerConfig> {
+    LOGGER
+        .state
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(|state| &state.config)
+        .filter(|config| config.log_requests && config.is_host_logged(host))
+        .cloned()
+}
+
+pub fn log_exchange(exchange: &Exchange) {
+    let level = match exchange.response {
+        Ok((status, _, _)) if !status.is_client_error() && !status.is_server_error() => Level::Info,
+        _ => Level::Warn,
+    };
+
+    if !log_enabled!(target: HTTP_TARGET, level) {
+        return;
+    }
+
+    let config = match config_for_host(exchange.url.host_str().unwrap_or_default()) {
+        Some(config) => config,
+        None => return,
+    };
+
+    let mut message = format!(
+        "{} {} -> {} in {} ms",
+        exchange.method,
+        redact_url(&config, exchange.url),
+        match exchange.response {
+            Ok((status, _, _)) => status.to_string(),
+            Err(error) => format!("error: {}", error),
+        },
+        exchange.elapsed.as_secs() * 1000 + u64::from(exchange.elapsed.subsec_millis())
+    );
+
+    if config.log_bodies {
+        append_headers(&mut message, "> ", &config, exchange.request_headers);
+        append_body(
+            &mut message,
+            "> ",
+            &config,
+            exchange.request_headers,
+            exchange.request_body,
+        );
+
+        if let Ok((_, headers, body)) = exchange.response {
+            append_headers(&mut message, "< ", &config, headers);
+            append_body(&mut message, "< ", &config, headers, body);
+        }
+    }
+
+    log!(target: HTTP_TARGET, level, "{}", message);
+}
+
+fn append_headers(message: &mut String, prefix: &str, config: &LoggerConfig, headers: &HeaderMap) {
+    for (name, value) in headers {
+        let value = if config.is_header_redacted(name.as_str()) {
+            REDACTED
+        } else {
+            value.to_str().unwrap_or("[binary]")
+        };
+
+        message.push_str(&format!("\n{}{}: {}", prefix, name, value));
+    }
+}
+
+fn append_body(
+    message: &mut String,
+    prefix: &str,
+    config: &LoggerConfig,
+    headers: &HeaderMap,
+    body: &[u8],
+) {
+    if body.is_empty() {
+        return;
+    }
+
+    let body = redact_body(config, body, is_form(headers));
+    message.push_str(&format!("\n{}", prefix));
+    message.push_str(&body);
+}
+
+pub(crate) fn is_form(headers: &HeaderMap) -> bool {
+    headers
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map_or(false, |value| {
+            value.starts_with("application/x-www-form-urlencoded")
+        })
+}
+
+fn redact_json(config: &LoggerConfig, value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if config.is_field_redacted(key) {
+                    *value = serde_json::Value::String(REDACTED.to_owned());
+                } else {
+                    redact_json(config, value);
+                }
+            }
+        }
+        serde_json::Value::Array(values) => {
+            for value in values {
+                redact_json(config, value);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn redact_pairs(config: &LoggerConfig, input: &[u8]) -> String {
+    url::form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(url::form_urlencoded::parse(input).map(|(key, value)| {
+            let value = if config.is_field_redacted(&key) {
+                REDACTED.into()
+            } else {
+                value
+            };
+            (key, value)
+        }))
+        .finish()
+}
+
+pub(crate) fn redact_url(config: &LoggerConfig, url: &Url) -> String {
+    let mut url = url.clone();
+    if let Some(query) = url.query().map(|query| query.to_owned()) {
+        url.set_query(Some(&redact_pairs(config, query.as_bytes())));
+    }
+
+    url.to_string()
+}
+
+/// Replaces values of the redacted fields of JSON and form bodies. Other bodies are returned as is.
+pub(crate) fn redact_text(config: &LoggerConfig, body: &[u8], is_form: bool) -> String {
+    if let Ok(mut value) = serde_json::from_slice::<serde_json::Value>(body) {
+        redact_json(config, &mut value);
+        value.to_string()
+    } else if is_form {
+        redact_pairs(config, body)
+    } else {
+        String::from_utf8_lossy(body).into_owned()
+    }
+}
+
+fn redact_body(config: &LoggerConfig, body: &[u8], is_form: bool) -> String {
+    let text = redact_text(config, body, is_form);
+
+    if text.len() > config.max_logged_body_size {
+        let mut end = config.max_logged_body_size;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        format!("{}... ({} bytes total)", &text[..end], text.len())
+    } else {
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redaction() {
+        let config = LoggerConfig {
+            max_logged_body_size: 32,
+            ..LoggerConfig::default()
+        };
+
+        assert_eq!(
+            redact_url(
+                &config,
+                &"https://a.com/login?user=admin&token=123".parse().unwrap()
+            ),
+            "https://a.com/login?user=admin&token=%5BREDACTED%5D"
+        );
+
+        assert_eq!(
+            redact_body(
+                &LoggerConfig::default(),
+                br#"{"user":{"password":"1"},"list":[{"token":2}]}"#,
+                false
+            ),
+            r#"{"user":{"password":"[REDACTED]"},"list":[{"token":"[REDACTED]"}]}"#
+        );
+
+        assert_eq!(
+            redact_body(&config, b"user=admin&password=1", true),
+            "user=admin&password=%5BREDACTED%5D"[..32].to_owned() + "... (34 bytes total)"
+        );
+
+        assert_eq!(redact_body(&config, b"password=1", false), "password=1");
+
+        let mut headers = HeaderMap::new();
+        headers.insert("Authorization", "Bearer 1".parse().unwrap());
+        headers.insert("X-Id", "2".parse().unwrap());
+        let mut message = String::new();
+        append_headers(&mut message, "> ", &config, &headers);
+        assert_eq!(message, "\n> authorization: [REDACTED]\n> x-id: 2");
+    }
+
+    #[test]
+    fn test_host_filter() {
+        let config = LoggerConfig {
+            hosts: vec!["a.com".to_owned(), "b.com".to_owned()],
+            exclude_hosts: vec!["b.com".to_owned()],
+            ..LoggerConfig::default()
+        };
+
+        assert!(config.is_host_logged("A.com"));
+        assert!(!config.is_host_logged("b.com"));
+        assert!(!config.is_host_logged("c.com"));
+        assert!(LoggerConfig::default().is_host_logged("c.com"));
+    }
+
+    #[test]
+    fn test_rotation() {
+        let directory = std::env::temp_dir().join(format!("grip-log-test-{}", std::process::id()));
+        fs::remove_dir_all(&directory).ok();
+        let path = directory.join("grip.log");
+
+        let mut state = State {
+            config: LoggerConfig {
+                file: Some(path.clone()),
+                max_file_size: 10,
+                max_files: 2,
+                ..LoggerConfig::default()
+            },
+            file: Some(open_file(&path).unwrap()),
+            file_size: 0,
+        };
+
+        for line in &["first\n", "second\n", "third\n", "fourth\n"] {
+            state.write_line(line);
+        }
+        drop(state);
+
+        let read = |suffix: &str| {
+            fs::read_to_string(directory.join(format!("grip.log{}", suffix))).unwrap()
+        };
+        assert_eq!(read(""), "fourth\n");
+        assert_eq!(read(".1"), "third\n");
+        assert_eq!(read(".2"), "second\n");
+        assert!(!directory.join("grip.log.3").exists());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
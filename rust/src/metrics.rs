@@ -0,0 +1,436 @@
+/*
+ * gRIP
+ * Copyright (c) 2018 Alik Aslanyan <cplusplus256@gmail.com>
+ *
+ *
+ *    This program is free software; you can redistribute it and/or modify it
+ *    under the terms of the GNU General Public License as published by the
+ *    Free Software Foundation; either version 3 of the License, or (at
+ *    your option) any later version.
+ *
+ *    This program is distributed in the hope that it will be useful, but
+ *    WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ *    General Public License for more details.
+ *
+ *    You should have received a copy of the GNU General Public License
+ *    along with this program; if not, write to the Free Software Foundation,
+ *    Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ *    In addition, as a special exception, the author gives permission to
+ *    link the code of this program with the Half-Life Game Engine ("HL
+ *    Engine") and Modified Game Libraries ("MODs") developed by Valve,
+ *    L.L.C ("Valve").  You must obey the GNU General Public License in all
+ *    respects for all of the code used other than the HL Engine and MODs
+ *    from Valve.  If you modify this file, you may extend this exception
+ *    to your version of the file, but you are not obligated to do so.  If
+ *    you do not wish to do so, delete this exception statement from your
+ *    version.
+ *
+ */
+
+//! Counters and histograms of the requests, exposed in the Prometheus text format and as JSON.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::future;
+use hyper::header::{self, HeaderValue};
+use hyper::{Body, Method, StatusCode};
+use serde_json::json;
+
+use crate::error_category::ErrorCategory;
+use crate::errors::*;
+use crate::http_server::BackgroundServer;
+
+/// Upper bounds of the latency histogram buckets in seconds.
+const DURATION_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+#[derive(Clone, Debug)]
+pub struct Histogram {
+    bounds: &'static [f64],
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    pub fn new(bounds: &'static [f64]) -> Self {
+        Histogram {
+            bounds,
+            counts: vec![0; bounds.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    pub fn observe(&mut self, value: f64) {
+        if let Some(index) = self.bounds.iter().position(|bound| value <= *bound) {
+            self.counts[index] += 1;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    /// Pairs of upper bound and number of observations less or equal to it.
+    pub fn cumulative_buckets(&self) -> Vec<(f64, u64)> {
+        let mut total = 0;
+        self.bounds
+            .iter()
+            .zip(&self.counts)
+            .map(|(bound, count)| {
+                total += count;
+                (*bound, total)
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug)]
+struct HostMetrics {
+    responses_by_status_class: BTreeMap<&'static str, u64>,
+    errors_by_kind: BTreeMap<&'static str, u64>,
+    duration: Histogram,
+    response_body_bytes: u64,
+}
+
+impl Default for HostMetrics {
+    fn default() -> Self {
+        HostMetrics {
+            responses_by_status_class: BTreeMap::new(),
+            errors_by_kind: BTreeMap::new(),
+            duration: Histogram::new(&DURATION_BUCKETS),
+            response_body_bytes: 0,
+        }
+    }
+}
+
+/// Values, which are owned by the main thread and published from there.
+#[derive(Clone, Debug, Default)]
+pub struct Gauges {
+    pub pending_requests: usize,
+    /// Number of live handles by type.
+    pub handles: Vec<(&'static str, usize)>,
+}
+
+#[derive(Default)]
+struct Inner {
+    hosts: BTreeMap<String, HostMetrics>,
+    gauges: Gauges,
+}
+
+/// Thread-safe storage of the metrics. Queue records requests from its worker thread.
+#[derive(Default)]
+pub struct Metrics {
+    inner: Mutex<Inner>,
+}
+
+fn status_class(status: StatusCode) -> &'static str {
+    match status.as_u16() {
+        100..=199 => "1xx",
+        200..=299 => "2xx",
+        300..=399 => "3xx",
+        400..=499 => "4xx",
+        _ => "5xx",
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn format_bound(bound: f64) -> String {
+    if bound.is_infinite() {
+        "+Inf".to_owned()
+    } else {
+        bound.to_string()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics::default()
+    }
+
+    pub fn record_response(
+        &self,
+        host: &str,
+        status: StatusCode,
+        duration: Duration,
+        body_size: usize,
+    ) {
+        let mut inner = self.inner.lock().unwrap();
+        let host = inner.hosts.entry(host.to_owned()).or_default();
+
+        *host
+            .responses_by_status_class
+            .entry(status_class(status))
+            .or_insert(0) += 1;
+        host.duration.observe(duration_as_seconds(duration));
+        host.response_body_bytes += body_size as u64;
+    }
+
+    pub fn record_error(&self, host: &str, kind: ErrorCategory, duration: Duration) {
+        let mut inner = self.inner.lock().unwrap();
+        let host = inner.hosts.entry(host.to_owned()).or_default();
+
+        *host.errors_by_kind.entry(kind.name()).or_insert(0) += 1;
+        host.duration.observe(duration_as_seconds(duration));
+    }
+
+    pub fn set_gauges(&self, gauges: Gauges) {
+        self.inner.lock().unwrap().gauges = gauges;
+    }
+
+    /// Renders metrics in the Prometheus text exposition format.
+    pub fn render_text(&self) -> String {
+        let inner = self.inner.lock().unwrap();
+        let mut out = String::new();
+
+        writeln!(
+            out,
+            "# HELP grip_requests_total Number of received responses by host and status class."
+        )
+        .unwrap();
+        writeln!(out, "# TYPE grip_requests_total counter").unwrap();
+        for (host, metrics) in &inner.hosts {
+            for (class, count) in &metrics.responses_by_status_class {
+                writeln!(
+                    out,
+                    "grip_requests_total{{host=\"{}\",status_class=\"{}\"}} {}",
+                    escape_label(host),
+                    class,
+                    count
+                )
+                .unwrap();
+            }
+        }
+
+        writeln!(
+            out,
+            "# HELP grip_request_errors_total Number of failed requests by host and error kind."
+        )
+        .unwrap();
+        writeln!(out, "# TYPE grip_request_errors_total counter").unwrap();
+        for (host, metrics) in &inner.hosts {
+            for (kind, count) in &metrics.errors_by_kind {
+                writeln!(
+                    out,
+                    "grip_request_errors_total{{host=\"{}\",kind=\"{}\"}} {}",
+                    escape_label(host),
+                    kind,
+                    count
+                )
+                .unwrap();
+            }
+        }
+
+        writeln!(out, "# HELP grip_request_duration_seconds Time from sending the request until the response or error.").unwrap();
+        writeln!(out, "# TYPE grip_request_duration_seconds histogram").unwrap();
+        for (host, metrics) in &inner.hosts {
+            let host = escape_label(host);
+            let buckets = metrics
+                .duration
+                .cumulative_buckets()
+                .into_iter()
+                .chain(std::iter::once((
+                    std::f64::INFINITY,
+                    metrics.duration.count,
+                )));
+
+            for (bound, count) in buckets {
+                writeln!(
+                    out,
+                    "grip_request_duration_seconds_bucket{{host=\"{}\",le=\"{}\"}} {}",
+                    host,
+                    format_bound(bound),
+                    count
+                )
+                .unwrap();
+            }
+            writeln!(
+                out,
+                "grip_request_duration_seconds_sum{{host=\"{}\"}} {}",
+                host, metrics.duration.sum
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "grip_request_duration_seconds_count{{host=\"{}\"}} {}",
+                host, metrics.duration.count
+            )
+            .unwrap();
+        }
+
+        writeln!(out, "# HELP grip_response_body_bytes_total Size of the received response bodies after decompression.").unwrap();
+        writeln!(out, "# TYPE grip_response_body_bytes_total counter").unwrap();
+        for (host, metrics) in &inner.hosts {
+            writeln!(
+                out,
+                "grip_response_body_bytes_total{{host=\"{}\"}} {}",
+                escape_label(host),
+                metrics.response_body_bytes
+            )
+            .unwrap();
+        }
+
+        writeln!(
+            out,
+            "# HELP grip_pending_requests Number of requests waiting for the callback."
+        )
+        .unwrap();
+        writeln!(out, "# TYPE grip_pending_requests gauge").unwrap();
+        writeln!(
+            out,
+            "grip_pending_requests {}",
+            inner.gauges.pending_requests
+        )
+        .unwrap();
+
+        writeln!(
+            out,
+            "# HELP grip_handles Number of live Pawn handles by type."
+        )
+        .unwrap();
+        writeln!(out, "# TYPE grip_handles gauge").unwrap();
+        for (handle_type, count) in &inner.gauges.handles {
+            writeln!(out, "grip_handles{{type=\"{}\"}} {}", handle_type, count).unwrap();
+        }
+
+        out
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let inner = self.inner.lock().unwrap();
+
+        let hosts: serde_json::Map<String, serde_json::Value> = inner
+            .hosts
+            .iter()
+            .map(|(host, metrics)| {
+                let buckets: serde_json::Map<String, serde_json::Value> = metrics
+                    .duration
+                    .cumulative_buckets()
+                    .into_iter()
+                    .map(|(bound, count)| (format_bound(bound), json!(count)))
+                    .collect();
+
+                (
+                    host.clone(),
+                    json!({
+                        "responses": metrics.responses_by_status_class,
+                        "errors": metrics.errors_by_kind,
+                        "response_body_bytes": metrics.response_body_bytes,
+                        "duration_seconds": {
+                            "count": metrics.duration.count,
+                            "sum": metrics.duration.sum,
+                            "buckets": buckets,
+                        },
+                    }),
+                )
+            })
+            .collect();
+
+        let handles: serde_json::Map<String, serde_json::Value> = inner
+            .gauges
+            .handles
+            .iter()
+            .map(|(handle_type, count)| ((*handle_type).to_owned(), json!(count)))
+            .collect();
+
+        json!({
+            "pending_requests": inner.gauges.pending_requests,
+            "handles": handles,
+            "hosts": hosts,
+        })
+    }
+}
+
+fn duration_as_seconds(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1_000_000_000.0
+}
+
+/// Serves `GET /metrics` in the Prometheus text format.
+pub fn serve(bind_address: &SocketAddr, metrics: Arc<Metrics>) -> Result<BackgroundServer> {
+    BackgroundServer::start(bind_address, move |_, request| {
+        let mut response = hyper::Response::new(Body::empty());
+
+        if request.uri().path() != "/metrics" {
+            *response.status_mut() = StatusCode::NOT_FOUND;
+        } else if request.method() != Method::GET {
+            *response.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
+        } else {
+            *response.body_mut() = Body::from(metrics.render_text());
+            response.headers_mut().insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("text/plain; version=0.0.4; charset=utf-8"),
+            );
+        }
+
+        Box::new(future::ok(response))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram() {
+        let mut histogram = Histogram::new(&[0.1, 1.0]);
+        histogram.observe(0.05);
+        histogram.observe(0.5);
+        histogram.observe(0.5);
+        histogram.observe(20.0);
+
+        assert_eq!(histogram.cumulative_buckets(), vec![(0.1, 1), (1.0, 3)]);
+        assert_eq!(histogram.count, 4);
+        assert!((histogram.sum - 21.05).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::new();
+        metrics.record_response("a.com", StatusCode::OK, Duration::from_millis(20), 10);
+        metrics.record_response("a.com", StatusCode::NOT_FOUND, Duration::from_millis(3), 5);
+        metrics.record_error("b\"c", ErrorCategory::Timeout, Duration::from_secs(30));
+        metrics.set_gauges(Gauges {
+            pending_requests: 2,
+            handles: vec![("json", 7)],
+        });
+
+        let text = metrics.render_text();
+        assert!(text.contains("grip_requests_total{host=\"a.com\",status_class=\"2xx\"} 1\n"));
+        assert!(text.contains("grip_requests_total{host=\"a.com\",status_class=\"4xx\"} 1\n"));
+        assert!(text.contains("grip_request_errors_total{host=\"b\\\"c\",kind=\"timeout\"} 1\n"));
+        assert!(
+            text.contains("grip_request_duration_seconds_bucket{host=\"a.com\",le=\"0.005\"} 1\n")
+        );
+        assert!(
+            text.contains("grip_request_duration_seconds_bucket{host=\"a.com\",le=\"0.025\"} 2\n")
+        );
+        assert!(
+            text.contains("grip_request_duration_seconds_bucket{host=\"b\\\"c\",le=\"10\"} 0\n")
+        );
+        assert!(
+            text.contains("grip_request_duration_seconds_bucket{host=\"b\\\"c\",le=\"+Inf\"} 1\n")
+        );
+        assert!(text.contains("grip_response_body_bytes_total{host=\"a.com\"} 15\n"));
+        assert!(text.contains("grip_pending_requests 2\n"));
+        assert!(text.contains("grip_handles{type=\"json\"} 7\n"));
+
+        let value = metrics.to_json();
+        assert_eq!(value["pending_requests"], 2);
+        assert_eq!(value["handles"]["json"], 7);
+        assert_eq!(value["hosts"]["a.com"]["responses"]["2xx"], 1);
+        assert_eq!(value["hosts"]["b\"c"]["errors"]["timeout"], 1);
+        assert_eq!(value["hosts"]["a.com"]["duration_seconds"]["count"], 2);
+    }
+}
@@ -34,10 +34,22 @@ use std::thread;
 use futures::future;
 use futures::prelude::*;
 use futures::sync::oneshot;
+use std::collections::VecDeque;
 use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::{Duration, Instant};
 
+use crate::chain::{self, ChainStep};
+use crate::error_category::ErrorCategory;
 use crate::errors::*;
+use crate::har::HarRecorder;
+use crate::logger;
+use crate::metrics::Metrics;
+use crate::response_body;
+use crate::sse;
+use crate::transport::{ExchangeFuture, ReqwestTransport, Transport};
+
+use std::sync::{Arc, Mutex};
 
 use tokio::prelude::FutureExt;
 
@@ -53,8 +65,29 @@ pub enum RequestType {
     Delete,
 }
 
+impl RequestType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RequestType::Get => "GET",
+            RequestType::Post => "POST",
+            RequestType::Put => "PUT",
+            RequestType::Delete => "DELETE",
+        }
+    }
+}
+
 #[derive(Debug)]
-pub struct RequestCancellation(oneshot::Sender<()>);
+pub struct RequestCancellation {
+    _cancellation_signal: oneshot::Sender<()>,
+    request_id: u64,
+}
+
+impl RequestCancellation {
+    /// Id of the `PendingRequest`, which is cancelled by this handle.
+    pub fn request_id(&self) -> u64 {
+        self.request_id
+    }
+}
 
 #[derive(Constructor, Builder, Clone, Debug, Default)]
 pub struct RequestOptions {
@@ -63,6 +96,14 @@ pub struct RequestOptions {
 
     #[builder(default)]
     pub timeout: Option<Duration>,
+
+    /// Maximum size of the response body. Can only lower limits of the queue.
+    #[builder(default)]
+    pub max_response_body_size: Option<usize>,
+
+    /// Request is waited for on shutdown, when only such requests are drained.
+    #[builder(default)]
+    pub must_complete: bool,
 }
 
 #[derive(Builder, Clone, Constructor, Debug)]
@@ -77,46 +118,427 @@ pub struct Request {
     pub options: RequestOptions,
 }
 
-#[derive(Constructor, Builder)]
+#[derive(Constructor, Builder, Clone)]
 pub struct Response {
     pub base_request: Request,
     pub body: Vec<u8>,
     pub status_code: reqwest::StatusCode,
+    pub headers: reqwest::header::HeaderMap,
+}
+
+impl Response {
+    /// Body decoded to UTF-8 according to the charset of the response.
+    pub fn text(&self) -> Result<std::borrow::Cow<str>> {
+        response_body::decode_text(&self.headers, &self.body[..])
+    }
+}
+
+/// Request, which was sent, but whose callback wasn't called yet.
+#[derive(Clone, Debug)]
+pub struct PendingRequest {
+    pub id: u64,
+    pub http_type: RequestType,
+    pub url: reqwest::Url,
+    pub started: Instant,
+    pub must_complete: bool,
+}
+
+/// How pending requests are finished, before the queue is stopped.
+#[derive(Clone, Debug)]
+pub struct DrainPolicy {
+    /// Maximum time to wait. Requests, which are still pending after it, are abandoned.
+    pub timeout: Duration,
+    /// Wait only for requests with `RequestOptions::must_complete`.
+    pub only_must_complete: bool,
+    /// Callbacks of the drained requests are called, otherwise they are dropped.
+    pub run_callbacks: bool,
+}
+
+impl Default for DrainPolicy {
+    fn default() -> Self {
+        DrainPolicy {
+            timeout: Duration::from_secs(0),
+            only_must_complete: false,
+            run_callbacks: true,
+        }
+    }
+}
+
+impl DrainPolicy {
+    fn should_wait_for(&self, request: &PendingRequest) -> bool {
+        !self.only_must_complete || request.must_complete
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct DrainReport {
+    pub completed: usize,
+    /// Requests, which were not waited for or didn't complete in time.
+    pub abandoned: Vec<PendingRequest>,
+}
+
+/// Limits applied to the every response received by the queue.
+#[derive(Clone, Copy, Debug)]
+pub struct ResponseLimits {
+    /// Maximum size of the body as it was received from the network.
+    pub max_body_size: usize,
+    /// Maximum size of the body after decompression.
+    pub max_decompressed_body_size: usize,
+}
+
+impl Default for ResponseLimits {
+    fn default() -> Self {
+        ResponseLimits {
+            max_body_size: 16 * 1024 * 1024,
+            max_decompressed_body_size: 16 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Builder, Clone)]
+pub struct QueueConfig {
+    #[builder(default)]
+    pub limits: ResponseLimits,
+
+    /// Transport of the requests. Subscriptions are always sent to the network.
+    #[builder(default = "Arc::new(ReqwestTransport::new())")]
+    pub transport: Arc<dyn Transport>,
+
+    /// Every exchange is appended to the HAR file, when set.
+    #[builder(default)]
+    pub recorder: Option<Arc<HarRecorder>>,
+
+    /// Number of the threads, which send requests. Defaults to the number of CPUs.
+    #[builder(default)]
+    pub worker_threads: Option<usize>,
+
+    /// Maximum number of the requests, which are sent at once. Unlimited by default.
+    #[builder(default)]
+    pub max_concurrent_requests: Option<usize>,
+
+    /// Metrics of the requests. Can be shared by several queues.
+    #[builder(default = "Arc::new(Metrics::new())")]
+    pub metrics: Arc<Metrics>,
+}
+
+/// Limits number of the requests, which are sent at once.
+/// Other requests wait for the free slot in the order they were sent.
+#[derive(Clone)]
+struct ConcurrencyLimit {
+    state: Arc<Mutex<ConcurrencyLimitState>>,
+}
+
+struct ConcurrencyLimitState {
+    max: usize,
+    active: usize,
+    waiting: VecDeque<oneshot::Sender<Permit>>,
+}
+
+/// Slot of the `ConcurrencyLimit`, which is passed to the next waiting request, when dropped.
+struct Permit {
+    limit: Option<ConcurrencyLimit>,
+}
+
+impl ConcurrencyLimit {
+    fn new(max: usize) -> Self {
+        ConcurrencyLimit {
+            state: Arc::new(Mutex::new(ConcurrencyLimitState {
+                max,
+                active: 0,
+                waiting: VecDeque::new(),
+            })),
+        }
+    }
+
+    fn acquire(&self) -> Box<dyn Future<Item = Permit, Error = oneshot::Canceled> + Send> {
+        let mut state = self.state.lock().unwrap();
+        if state.active < state.max {
+            state.active += 1;
+            Box::new(future::ok(Permit {
+                limit: Some(self.clone()),
+            }))
+        } else {
+            let (sender, receiver) = oneshot::channel();
+            state.waiting.push_back(sender);
+            Box::new(receiver)
+        }
+    }
+
+    /// Request is sent, once there is a free slot. Slot is held, until the exchange completes.
+    fn send(
+        &self,
+        transport: &Arc<dyn Transport>,
+        request: &Request,
+        limits: ResponseLimits,
+    ) -> ExchangeFuture {
+        let transport = transport.clone();
+        let request = request.clone();
+
+        Box::new(
+            self.acquire()
+                .map_err(|_| Error::from("Queue was stopped"))
+                .and_then(move |permit| {
+                    transport.send(&request, limits).then(move |result| {
+                        drop(permit);
+                        result
+                    })
+                }),
+        )
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let limit = match self.limit.take() {
+            Some(limit) => limit,
+            None => return,
+        };
+
+        let mut state = limit.state.lock().unwrap();
+        while let Some(sender) = state.waiting.pop_front() {
+            match sender.send(Permit {
+                limit: Some(limit.clone()),
+            }) {
+                Ok(()) => return,
+                // Request was cancelled while waiting, so the slot goes to the next one.
+                Err(mut permit) => permit.limit = None,
+            }
+        }
+        state.active -= 1;
+    }
 }
 
 // TODO: Replace with trait alias, when they became stable
 // https://github.com/rust-lang/rust/issues/41517
 type ResponseCallBack = Fn(Result<Response>) + Sync + Send;
+type SubscriptionCallBack = Fn(sse::SubscriptionEvent) + Sync + Send;
+type ChainCallBack = Fn(usize, Result<Response>) + Sync + Send;
+
+/// Reconnection time of the subscription, until server specifies another one.
+const DEFAULT_SUBSCRIPTION_RETRY: Duration = Duration::from_secs(3);
 
 #[allow(clippy::large_enum_variant)]
 enum InputCommand {
     Request {
+        id: u64,
         cancellation_signal: oneshot::Receiver<()>,
         request: Request,
         callback: Box<ResponseCallBack>,
     },
+    Subscribe {
+        cancellation_signal: oneshot::Receiver<()>,
+        request: Request,
+        callback: Arc<SubscriptionCallBack>,
+    },
+    Chain {
+        id: u64,
+        cancellation_signal: oneshot::Receiver<()>,
+        steps: Vec<ChainStep>,
+        callback: Box<ChainCallBack>,
+    },
+    SetLimits(ResponseLimits),
     Quit,
 }
 
 #[allow(clippy::large_enum_variant)]
 enum OutputCommand {
     Response {
+        id: u64,
         response: Response,
         callback: Box<ResponseCallBack>,
     },
     Error {
+        id: u64,
         error: Error,
         callback: Box<ResponseCallBack>,
     },
+    SubscriptionEvent {
+        event: sse::SubscriptionEvent,
+        callback: Arc<SubscriptionCallBack>,
+    },
+    ChainFinished {
+        id: u64,
+        step: usize,
+        result: Result<Response>,
+        callback: Box<ChainCallBack>,
+    },
+}
+
+impl OutputCommand {
+    /// Id of the finished request and the call of its callback. Subscription events have no id.
+    fn into_call(self) -> (Option<u64>, Box<dyn FnOnce()>) {
+        match self {
+            OutputCommand::Response {
+                id,
+                response,
+                callback,
+            } => (Some(id), Box::new(move || (callback)(Ok(response)))),
+            OutputCommand::Error {
+                id,
+                error,
+                callback,
+            } => (Some(id), Box::new(move || (callback)(Err(error)))),
+            OutputCommand::SubscriptionEvent { event, callback } => {
+                (None, Box::new(move || (callback)(event)))
+            }
+            OutputCommand::ChainFinished {
+                id,
+                step,
+                result,
+                callback,
+            } => (Some(id), Box::new(move || (callback)(step, result))),
+        }
+    }
+}
+
+/// Outcome of the exchange.
+enum State {
+    Successful(Vec<u8>, reqwest::StatusCode, reqwest::header::HeaderMap),
+    Error(Error),
+    Canceled,
+    Timeout,
+}
+
+impl State {
+    fn into_result(self, request: Request) -> Result<Response> {
+        match self {
+            State::Successful(body, status_code, headers) => {
+                Ok(Response::new(request, body, status_code, headers))
+            }
+            State::Error(error) => Err(error),
+            State::Canceled => Err(ErrorKind::RequestCancelled.into()),
+            State::Timeout => Err(ErrorKind::RequestTimeout.into()),
+        }
+    }
+}
+
+/// Sends requests on behalf of the queue.
+#[derive(Clone)]
+struct Worker {
+    transport: Arc<dyn Transport>,
+    recorder: Option<Arc<HarRecorder>>,
+    metrics: Arc<Metrics>,
+    concurrency_limit: Option<ConcurrencyLimit>,
+}
+
+impl Worker {
+    /// Waits for the response, until request is cancelled or timed out.
+    /// Outcome is recorded to the metrics, log and HAR file.
+    fn execute<C>(
+        &self,
+        request: Request,
+        limits: ResponseLimits,
+        cancellation: C,
+    ) -> impl Future<Item = (Request, State), Error = ()>
+    where
+        C: Future<Item = (), Error = ()> + Send + 'static,
+    {
+        let max_body_size = request
+            .options
+            .max_response_body_size
+            .map_or(limits.max_body_size, |size| {
+                std::cmp::min(size, limits.max_body_size)
+            });
+        let max_decompressed_body_size = request
+            .options
+            .max_response_body_size
+            .map_or(limits.max_decompressed_body_size, |size| {
+                std::cmp::min(size, limits.max_decompressed_body_size)
+            });
+        let response_limits = ResponseLimits {
+            max_body_size,
+            max_decompressed_body_size,
+        };
+
+        let started = Instant::now();
+        let started_at = std::time::SystemTime::now();
+        let exchange = match self.concurrency_limit {
+            Some(ref concurrency_limit) => {
+                concurrency_limit.send(&self.transport, &request, response_limits)
+            }
+            None => self.transport.send(&request, response_limits),
+        };
+        let worker = self.clone();
+
+        exchange
+            // Cancelling / Error handling.
+            .map(|(status_code, headers, body)| State::Successful(body, status_code, headers))
+            .or_else(|e| future::ok(State::Error(e)))
+            .select2(cancellation.then(|_| future::ok(State::Canceled)))
+            .map_err(|_: future::Either<((), _), ((), _)>| unreachable!())
+            .map(|either| either.split().0)
+            // Timeout.
+            .timeout(
+                request
+                    .options
+                    .timeout
+                    .unwrap_or_else(|| Duration::new(u64::from(std::u16::MAX), 0)),
+            )
+            .or_else(|_| future::ok(State::Timeout))
+            .map_err(|_: tokio::timer::Error| unreachable!())
+            .map(move |state| {
+                worker.record(&request, &state, started, started_at);
+                (request, state)
+            })
+    }
+
+    fn record(
+        &self,
+        request: &Request,
+        state: &State,
+        started: Instant,
+        started_at: std::time::SystemTime,
+    ) {
+        let elapsed = started.elapsed();
+        let host = request.uri.host_str().unwrap_or_default();
+        match state {
+            State::Successful(body, status_code, _) => {
+                self.metrics
+                    .record_response(host, *status_code, elapsed, body.len())
+            }
+            State::Error(error) => {
+                self.metrics
+                    .record_error(host, ErrorCategory::from(error), elapsed)
+            }
+            State::Canceled => self
+                .metrics
+                .record_error(host, ErrorCategory::Cancelled, elapsed),
+            State::Timeout => self
+                .metrics
+                .record_error(host, ErrorCategory::Timeout, elapsed),
+        }
+
+        let cancelled_error: Error = ErrorKind::RequestCancelled.into();
+        let timeout_error: Error = ErrorKind::RequestTimeout.into();
+        let response = || match state {
+            State::Successful(body, status_code, headers) => Ok((*status_code, headers, &body[..])),
+            State::Error(error) => Err(error),
+            State::Canceled => Err(&cancelled_error),
+            State::Timeout => Err(&timeout_error),
+        };
+
+        logger::log_exchange(&logger::Exchange {
+            method: request.http_type.as_str(),
+            url: &request.uri,
+            request_headers: &request.options.headers,
+            request_body: &request.body,
+            response: response(),
+            elapsed,
+        });
+
+        if let Some(ref recorder) = self.recorder {
+            recorder.record(request, response(), started_at, elapsed);
+        }
+    }
 }
 
 pub struct Queue {
     working_thread: Option<thread::JoinHandle<()>>,
-    executor: tokio::runtime::TaskExecutor,
     input_command_sender: futures::sync::mpsc::UnboundedSender<InputCommand>,
     response_receiver: crossbeam_channel::Receiver<OutputCommand>,
     last_time_executed_with_limit: Option<Instant>,
-    number_of_pending_requests: usize,
+    pending_requests: std::collections::BTreeMap<u64, PendingRequest>,
+    metrics: Arc<Metrics>,
 }
 
 impl Drop for Queue {
@@ -133,131 +555,160 @@ impl Default for Queue {
 
 impl Queue {
     pub fn new() -> Self {
-        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        Queue::with_limits(ResponseLimits::default())
+    }
+
+    pub fn with_limits(limits: ResponseLimits) -> Self {
+        Queue::with_config(
+            QueueConfigBuilder::default()
+                .limits(limits)
+                .build()
+                .unwrap(),
+        )
+    }
+
+    pub fn with_config(config: QueueConfig) -> Self {
+        let QueueConfig {
+            mut limits,
+            transport,
+            recorder,
+            worker_threads,
+            max_concurrent_requests,
+            metrics,
+        } = config;
+
+        let mut runtime = match worker_threads {
+            Some(worker_threads) => tokio::runtime::Builder::new()
+                .core_threads(worker_threads)
+                .build()
+                .unwrap(),
+            None => tokio::runtime::Runtime::new().unwrap(),
+        };
         let executor = runtime.executor();
+        let worker = Worker {
+            transport,
+            recorder,
+            metrics: metrics.clone(),
+            concurrency_limit: max_concurrent_requests.map(ConcurrencyLimit::new),
+        };
 
         let (input_command_sender, input_command_receiver) = futures::sync::mpsc::unbounded();
         let (response_sender, response_receiver) = crossbeam_channel::unbounded();
 
-        let client = reqwest_async::Client::new();
-
-        let working_thread = {
-            let executor = executor.clone();
-            clone_all!(response_sender);
-            thread::spawn(move || {
-                clone_all!(response_sender);
-                runtime
-                    .block_on(future::lazy(move || {
-                        clone_all!(response_sender);
-                        input_command_receiver
-                            .take_while(|cmd| {
-                                Ok(match cmd {
-                                    InputCommand::Quit => {
-                                        info!("Received quit command. New commands will not be received");
-                                        false
-                                    },
-                                    _ => true,
-                                })
-                            }).for_each(move |cmd| {
-                                clone_all!(response_sender);
-                                match cmd {
-                                    InputCommand::Quit => unreachable!(),
-                                    InputCommand::Request { request, callback, cancellation_signal } => {
-
-                                        enum State {
-                                            Successful(Vec<u8>, reqwest::StatusCode),
-                                            Error(Error),
-                                            Canceled,
-                                            Timeout
-                                        }
-
-
-                                        executor.spawn(
-                                            // Request construction.
-                                            match request.http_type {
-                                                RequestType::Post => client.post(request.uri.clone()),
-                                                RequestType::Get => client.get(request.uri.clone()),
-                                                RequestType::Delete => client.delete(request.uri.clone()),
-                                                RequestType::Put => client.put(request.uri.clone()),
-                                            }
-                                                .body(reqwest_async::Body::from(request.body.clone()))
-                                                .headers(request.options.headers.clone()) // TODO: Optimize clone away
-                                                .send()
-                                                .and_then(move |res| {
-                                                    let status = res.status();
-                                                    res.into_body().concat2().map(move |body| (status, body))
-                                                })
-                                                // Cancelling / Error handling.
-                                                .map(|(status_code, body)| {
-                                                    State::Successful(body.to_vec(), status_code)
-                                                })
-                                                .or_else(|e| {
-                                                    future::ok(State::Error(ErrorKind::HTTPError(e).into()))
-                                                })
-                                                .select2(cancellation_signal
-                                                    .map(|_| State::Canceled)
-                                                    .or_else(|_| future::ok(State::Canceled))
-                                                )
-                                                .map_err(|_: future::Either<((), _), ((), _)>| unreachable!())
-                                                .map(|either| {
-                                                    either.split().0
-                                                })
-                                                // Timeout.
-                                                .timeout(request.options.timeout
-                                                    .unwrap_or_else(|| Duration::new(u64::from(std::u16::MAX), 0)))
-                                                .or_else(|_| future::ok(State::Timeout))
-                                                .map_err(|_:tokio::timer::Error| unreachable!())
-                                                // Sending output command.
-                                                .and_then(move |state| {
-                                                    match state {
-                                                        State::Successful(vec, status_code) => {
-                                                            response_sender.send(OutputCommand::Response {
-                                                                response: Response::new(
-                                                                    request,
-                                                                    vec,
-                                                                    status_code
-                                                                ),
-                                                                callback
-                                                            }).unwrap()
+        // Decompression is done manually, because reqwest doesn't limit decompressed size.
+        let subscription_client = reqwest_async::Client::builder()
+            .gzip(false)
+            .build()
+            .unwrap();
+
+        let working_thread = thread::spawn(move || {
+            runtime
+                .block_on(future::lazy(move || {
+                    input_command_receiver
+                        .take_while(|cmd| {
+                            Ok(match cmd {
+                                InputCommand::Quit => {
+                                    info!("Received quit command. New commands will not be received");
+                                    false
+                                }
+                                _ => true,
+                            })
+                        })
+                        .for_each(move |cmd| {
+                            clone_all!(response_sender);
+                            match cmd {
+                                InputCommand::Quit => unreachable!(),
+                                InputCommand::SetLimits(new_limits) => limits = new_limits,
+                                InputCommand::Subscribe {
+                                    request,
+                                    callback,
+                                    cancellation_signal,
+                                } => {
+                                    executor.spawn(
+                                        run_subscription(
+                                            subscription_client.clone(),
+                                            request,
+                                            response_sender,
+                                            callback,
+                                        )
+                                        .select2(
+                                            cancellation_signal
+                                                .then(|_| future::ok::<(), ()>(())),
+                                        )
+                                        .then(|_| future::ok(())),
+                                    );
+                                }
+                                InputCommand::Request {
+                                    id,
+                                    request,
+                                    callback,
+                                    cancellation_signal,
+                                } => {
+                                    executor.spawn(
+                                        worker
+                                            .execute(
+                                                request,
+                                                limits,
+                                                cancellation_signal.then(|_| future::ok(())),
+                                            )
+                                            .map(move |(request, state)| {
+                                                response_sender
+                                                    .send(match state.into_result(request) {
+                                                        Ok(response) => OutputCommand::Response {
+                                                            id,
+                                                            response,
+                                                            callback,
                                                         },
-                                                        State::Error(error) => {
-                                                            response_sender.send(OutputCommand::Error {
-                                                                error,
-                                                                callback,
-                                                            }).unwrap();
+                                                        Err(error) => OutputCommand::Error {
+                                                            id,
+                                                            error,
+                                                            callback,
                                                         },
-                                                        State::Canceled => {
-                                                            response_sender.send(OutputCommand::Error {
-                                                                error: ErrorKind::RequestCancelled.into(),
-                                                                callback,
-                                                            }).unwrap();
-                                                        }
-                                                        State::Timeout => {
-                                                            response_sender.send(OutputCommand::Error {
-                                                                error: ErrorKind::RequestTimeout.into(),
-                                                                callback,
-                                                            }).unwrap()
-                                                        }
-                                                    }
-                                                    future::ok(())
-                                                }).map(|_| {})
+                                                    })
+                                                    .unwrap()
+                                            }),
+                                    );
+                                }
+                                InputCommand::Chain {
+                                    id,
+                                    steps,
+                                    callback,
+                                    cancellation_signal,
+                                } => {
+                                    executor.spawn(
+                                        run_chain(
+                                            worker.clone(),
+                                            steps,
+                                            limits,
+                                            cancellation_signal,
                                         )
-                                    }
+                                        .map(move |(step, result)| {
+                                            response_sender
+                                                .send(OutputCommand::ChainFinished {
+                                                    id,
+                                                    step,
+                                                    result,
+                                                    callback,
+                                                })
+                                                .unwrap()
+                                        }),
+                                    );
                                 }
+                            }
 
-                                Ok(())
-                            })
-                    })).unwrap();
-            })
-        };
+                            Ok(())
+                        })
+                }))
+                .unwrap();
+        });
 
         Queue {
             working_thread: Some(working_thread),
-            executor,
             input_command_sender,
             response_receiver,
             last_time_executed_with_limit: None,
-            number_of_pending_requests: 0,
+            pending_requests: std::collections::BTreeMap::new(),
+            metrics,
         }
     }
 
@@ -269,6 +720,12 @@ impl Queue {
         }
     }
 
+    /// Changes limits of the requests sent after this call.
+    /// Requests, which are already received by the worker, keep the old limits.
+    pub fn set_limits(&mut self, limits: ResponseLimits) {
+        self.send_input_command(InputCommand::SetLimits(limits));
+    }
+
     #[must_use = "this `RequestCancellation` should be alive, because when it drops request cancels."]
     pub fn send_request<T: 'static + Fn(Result<Response>) + Sync + Send>(
         &mut self,
@@ -277,39 +734,149 @@ impl Queue {
     ) -> RequestCancellation {
         let (cancellation_signal_sender, cancellation_signal) = oneshot::channel();
 
+        let id = next_request_id();
+        self.pending_requests.insert(
+            id,
+            PendingRequest {
+                id,
+                http_type: request.http_type.clone(),
+                url: request.uri.clone(),
+                started: Instant::now(),
+                must_complete: request.options.must_complete,
+            },
+        );
         self.send_input_command(InputCommand::Request {
+            id,
             cancellation_signal,
             request,
             callback: Box::new(callback),
         });
 
-        RequestCancellation(cancellation_signal_sender)
+        RequestCancellation {
+            _cancellation_signal: cancellation_signal_sender,
+            request_id: id,
+        }
     }
 
+    /// Sends steps of the chain one by one in the worker. Callback receives index of the last sent step
+    /// and its outcome, which is either the response of the last step or the first failure.
+    #[must_use = "this `RequestCancellation` should be alive, because when it drops chain cancels."]
+    pub fn send_chain<T: 'static + Fn(usize, Result<Response>) + Sync + Send>(
+        &mut self,
+        steps: Vec<ChainStep>,
+        callback: T,
+    ) -> Result<RequestCancellation> {
+        let first_request = chain::validate(&steps)?;
+        let (cancellation_signal_sender, cancellation_signal) = oneshot::channel();
+
+        let id = next_request_id();
+        self.pending_requests.insert(
+            id,
+            PendingRequest {
+                id,
+                http_type: first_request.http_type,
+                url: first_request.uri,
+                started: Instant::now(),
+                must_complete: first_request.options.must_complete,
+            },
+        );
+        self.send_input_command(InputCommand::Chain {
+            id,
+            cancellation_signal,
+            steps,
+            callback: Box::new(callback),
+        });
+
+        Ok(RequestCancellation {
+            _cancellation_signal: cancellation_signal_sender,
+            request_id: id,
+        })
+    }
+
+    /// Subscribes to the `text/event-stream` and reconnects, until returned handle is dropped.
+    /// Subscriptions are not counted as pending requests.
+    #[must_use = "this `RequestCancellation` should be alive, because when it drops subscription cancels."]
+    pub fn subscribe<T: 'static + Fn(sse::SubscriptionEvent) + Sync + Send>(
+        &mut self,
+        request: Request,
+        callback: T,
+    ) -> RequestCancellation {
+        let (cancellation_signal_sender, cancellation_signal) = oneshot::channel();
+
+        let id = next_request_id();
+        self.send_input_command(InputCommand::Subscribe {
+            cancellation_signal,
+            request,
+            callback: Arc::new(callback),
+        });
+
+        RequestCancellation {
+            _cancellation_signal: cancellation_signal_sender,
+            request_id: id,
+        }
+    }
+
+    /// Commands are received by the worker in the same order, as they were sent.
     fn send_input_command(&mut self, input_command: InputCommand) {
-        let input_command_sender = self.input_command_sender.clone();
-        self.number_of_pending_requests += 1;
-        self.executor.spawn(futures::lazy(move || {
-            input_command_sender
-                .send(input_command)
-                .map(|_| {})
-                .map_err(|_| unreachable!())
-        }));
+        // Fails only after the worker has quit, then command has nothing to do anyway.
+        self.input_command_sender
+            .unbounded_send(input_command)
+            .ok();
     }
 
     fn try_recv_queue(&mut self) -> Result<()> {
-        match self.response_receiver.try_recv()? {
-            OutputCommand::Response { response, callback } => {
-                (callback)(Ok(response));
+        let (id, call) = self.response_receiver.try_recv()?.into_call();
+        if let Some(id) = id {
+            self.pending_requests.remove(&id);
+        }
+        call();
+
+        Ok(())
+    }
+
+    /// Blocks, until requests selected by the `policy` are completed or its timeout passes.
+    /// Subscription events are dropped meanwhile.
+    pub fn drain(&mut self, policy: &DrainPolicy) -> DrainReport {
+        let deadline = Instant::now() + policy.timeout;
+        let mut report = DrainReport::default();
+
+        while self
+            .pending_requests
+            .values()
+            .any(|request| policy.should_wait_for(request))
+        {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
             }
-            OutputCommand::Error { error, callback } => {
-                (callback)(Err(error));
+
+            let (id, call) = match self.response_receiver.recv_timeout(deadline - now) {
+                Ok(command) => match command.into_call() {
+                    (Some(id), call) => (id, call),
+                    (None, _) => continue,
+                },
+                Err(_) => break,
+            };
+
+            let request = match self.pending_requests.remove(&id) {
+                Some(request) => request,
+                None => continue,
+            };
+
+            if policy.should_wait_for(&request) {
+                report.completed += 1;
+                if policy.run_callbacks {
+                    call();
+                }
+            } else {
+                report.abandoned.push(request);
             }
         }
 
-        self.number_of_pending_requests -= 1;
-
-        Ok(())
+        report
+            .abandoned
+            .extend(self.pending_requests.values().cloned());
+        report
     }
 
     pub fn execute_queue_with_limit(
@@ -345,18 +912,224 @@ impl Queue {
     }
 
     pub fn number_of_pending_requests(&self) -> usize {
-        self.number_of_pending_requests
+        self.pending_requests.len()
+    }
+
+    /// Pending requests from the oldest to the newest.
+    pub fn pending_requests(&self) -> impl Iterator<Item = &PendingRequest> {
+        self.pending_requests.values()
+    }
+
+    /// Metrics of the requests, recorded by the worker thread.
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
     }
 }
 
+/// Ids are unique across all queues, so requests can be found without knowing their queue.
+fn next_request_id() -> u64 {
+    static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(1);
+
+    NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Sends steps one by one, until one of them fails or the last one completes.
+/// Step fails, when its response has non-2xx status, so later steps don't use error responses.
+fn run_chain(
+    worker: Worker,
+    steps: Vec<ChainStep>,
+    limits: ResponseLimits,
+    cancellation_signal: oneshot::Receiver<()>,
+) -> impl Future<Item = (usize, Result<Response>), Error = ()> {
+    // Every step is cancelled by the same signal.
+    let cancellation = cancellation_signal.shared();
+
+    future::loop_fn(
+        (0, Vec::new()),
+        move |(step, mut bodies): (usize, Vec<Vec<u8>>)| {
+            let request = match chain::prepare(&steps[step], &bodies) {
+                Ok(request) => request,
+                Err(error) => {
+                    return future::Either::A(future::ok(future::Loop::Break((step, Err(error)))))
+                }
+            };
+            let last = step + 1 == steps.len();
+
+            future::Either::B(
+                worker
+                    .execute(request, limits, cancellation.clone().then(|_| future::ok(())))
+                    .map(move |(request, state)| match state.into_result(request) {
+                        Ok(response) if !last && response.status_code.is_success() => {
+                            bodies.push(response.body);
+                            future::Loop::Continue((step + 1, bodies))
+                        }
+                        result => future::Loop::Break((step, result)),
+                    }),
+            )
+        },
+    )
+}
+
+fn run_subscription(
+    client: reqwest_async::Client,
+    request: Request,
+    response_sender: crossbeam_channel::Sender<OutputCommand>,
+    callback: Arc<SubscriptionCallBack>,
+) -> impl Future<Item = (), Error = ()> {
+    let parser = Arc::new(Mutex::new(sse::Parser::new()));
+
+    let send_event = move |event| {
+        response_sender
+            .send(OutputCommand::SubscriptionEvent {
+                event,
+                callback: Arc::clone(&callback),
+            })
+            .ok();
+    };
+
+    future::loop_fn((), move |_| {
+        clone_all!(parser, send_event);
+
+        let mut headers = request.options.headers.clone();
+        headers.insert(
+            reqwest::header::ACCEPT,
+            reqwest::header::HeaderValue::from_static("text/event-stream"),
+        );
+        headers.insert(
+            reqwest::header::CACHE_CONTROL,
+            reqwest::header::HeaderValue::from_static("no-cache"),
+        );
+
+        {
+            let mut parser = parser.lock().unwrap();
+            parser.reset_connection();
+            if let Ok(id) = reqwest::header::HeaderValue::from_str(parser.last_event_id()) {
+                if !id.is_empty() {
+                    headers.insert("Last-Event-ID", id);
+                }
+            }
+        }
+
+        client
+            .get(request.uri.clone())
+            .headers(headers)
+            .send()
+            .map_err(|e| -> Error { ErrorKind::HTTPError(e).into() })
+            // Timeout is applied only to the connection, because stream is endless.
+            .timeout(
+                request
+                    .options
+                    .timeout
+                    .unwrap_or_else(|| Duration::new(u64::from(std::u16::MAX), 0)),
+            )
+            .map_err(|e| {
+                e.into_inner()
+                    .unwrap_or_else(|| ErrorKind::RequestTimeout.into())
+            })
+            .and_then(|res| {
+                if res.status().is_success() {
+                    Ok(res)
+                } else {
+                    Err(format!("Subscription failed with HTTP status {}", res.status()).into())
+                }
+            })
+            .and_then({
+                clone_all!(parser, send_event);
+                move |res| {
+                    send_event(sse::SubscriptionEvent::Open);
+
+                    res.into_body()
+                        .map_err(|e| -> Error { ErrorKind::HTTPError(e).into() })
+                        .for_each(move |chunk| {
+                            for event in parser.lock().unwrap().feed(&chunk) {
+                                send_event(sse::SubscriptionEvent::Event(event));
+                            }
+                            Ok(())
+                        })
+                }
+            })
+            .then(move |result| {
+                send_event(sse::SubscriptionEvent::Error(match result {
+                    Ok(()) => "Subscription stream was closed".into(),
+                    Err(e) => e,
+                }));
+
+                let retry = parser
+                    .lock()
+                    .unwrap()
+                    .retry()
+                    .unwrap_or(DEFAULT_SUBSCRIPTION_RETRY);
+
+                tokio::timer::Delay::new(Instant::now() + retry)
+                    .then(|_| future::ok::<_, ()>(future::Loop::Continue(())))
+            })
+    })
+}
+
 #[cfg(test)]
 mod tests {
+    use crate::test_server::{Route, TestServer, CERTIFICATE_PEM};
+    use crate::transport::{MockReply, MockTransport};
+
+    /// Queue, which serves scripted replies for `GET https://docs.rs/` without network.
+    fn mock_queue(reply: MockReply) -> super::Queue {
+        use super::*;
+
+        let transport = Arc::new(MockTransport::new());
+        transport.add_reply(
+            RequestType::Get,
+            &"https://docs.rs/".parse().unwrap(),
+            reply,
+        );
+
+        Queue::with_config(
+            QueueConfigBuilder::default()
+                .transport(transport)
+                .build()
+                .unwrap(),
+        )
+    }
+
+    /// Sends the request and executes the queue until the callback is called.
+    fn send_and_wait(
+        queue: &mut super::Queue,
+        request: super::Request,
+    ) -> crate::errors::Result<super::Response> {
+        use super::*;
+
+        let result = Arc::new(Mutex::new(None));
+        let result_c = Arc::clone(&result);
+        let _handle = queue.send_request(request, move |response| {
+            *result_c.lock().unwrap() = Some(response);
+        });
+
+        let start = Instant::now();
+        while result.lock().unwrap().is_none() {
+            assert!(start.elapsed() < Duration::from_secs(10), "No response");
+            queue.execute_query_with_timeout(Duration::from_millis(10), Duration::from_millis(5));
+        }
+
+        let result = result.lock().unwrap().take().unwrap();
+        result
+    }
+
+    fn get(url: reqwest::Url) -> super::Request {
+        super::RequestBuilder::default()
+            .http_type(super::RequestType::Get)
+            .uri(url)
+            .build()
+            .unwrap()
+    }
+
     #[test]
     fn test_basic_request() {
         use super::*;
         use std::sync::{Arc, Mutex};
 
-        let mut queue = Queue::new();
+        let mut queue = mock_queue(MockReply::new(
+            reqwest::StatusCode::OK,
+            b"<title>Docs.rs</title> docs.rs".to_vec(),
+        ));
 
         use std::default::Default;
 
@@ -386,7 +1159,9 @@ mod tests {
         use super::*;
         use std::sync::{Arc, Mutex};
 
-        let mut queue = Queue::new();
+        let mut queue = mock_queue(
+            MockReply::new(reqwest::StatusCode::OK, vec![]).with_delay(Duration::from_secs(10)),
+        );
 
         use std::default::Default;
 
@@ -422,12 +1197,40 @@ mod tests {
         assert_eq!(*control_variable.lock().unwrap(), true);
     }
 
+    #[test]
+    fn test_metrics_cancelled() {
+        use super::*;
+
+        let mut queue = mock_queue(
+            MockReply::new(reqwest::StatusCode::OK, vec![]).with_delay(Duration::from_secs(10)),
+        );
+
+        let handle = queue.send_request(
+            RequestBuilder::default()
+                .http_type(RequestType::Get)
+                .uri("https://docs.rs/".parse().unwrap())
+                .build()
+                .unwrap(),
+            |_| {},
+        );
+        drop(handle);
+
+        queue.execute_query_with_timeout(Duration::from_secs(5), Duration::from_millis(100));
+
+        assert_eq!(
+            queue.metrics().to_json()["hosts"]["docs.rs"]["errors"]["cancelled"],
+            1
+        );
+    }
+
     #[test]
     fn test_timeout() {
         use super::*;
         use std::sync::{Arc, Mutex};
 
-        let mut queue = Queue::new();
+        let mut queue = mock_queue(
+            MockReply::new(reqwest::StatusCode::OK, vec![]).with_delay(Duration::from_secs(10)),
+        );
 
         use std::default::Default;
 
@@ -466,4 +1269,458 @@ mod tests {
 
         assert_eq!(*control_variable.lock().unwrap(), true);
     }
+
+    #[test]
+    fn test_subscription() {
+        use super::*;
+        use std::io::{Read, Write};
+        use std::sync::{Arc, Mutex};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let requests = Arc::new(Mutex::new(vec![]));
+        let requests_c = Arc::clone(&requests);
+        thread::spawn(move || {
+            for (id, stream) in listener.incoming().take(2).enumerate() {
+                let mut stream = stream.unwrap();
+                let mut request = vec![];
+                let mut buffer = [0; 1024];
+                while !String::from_utf8_lossy(&request).contains("\r\n\r\n") {
+                    let read = stream.read(&mut buffer).unwrap();
+                    request.extend_from_slice(&buffer[..read]);
+                }
+                requests_c
+                    .lock()
+                    .unwrap()
+                    .push(String::from_utf8_lossy(&request).to_lowercase());
+
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nConnection: close\r\n\r\n\
+                     retry: 10\nid: {}\ndata: event {}\n\n",
+                    id, id
+                )
+                .unwrap();
+            }
+        });
+
+        let mut queue = Queue::new();
+
+        let events = Arc::new(Mutex::new(vec![]));
+        let events_c = Arc::clone(&events);
+        let _handle = queue.subscribe(
+            RequestBuilder::default()
+                .http_type(RequestType::Get)
+                .uri(format!("http://127.0.0.1:{}/", port).parse().unwrap())
+                .build()
+                .unwrap(),
+            move |event| {
+                events_c.lock().unwrap().push(match event {
+                    sse::SubscriptionEvent::Open => "open".to_owned(),
+                    sse::SubscriptionEvent::Event(event) => event.data,
+                    sse::SubscriptionEvent::Error(_) => "error".to_owned(),
+                })
+            },
+        );
+
+        queue.execute_query_with_timeout(Duration::from_secs(1), Duration::from_millis(10));
+
+        assert_eq!(
+            events.lock().unwrap()[..6],
+            ["open", "event 0", "error", "open", "event 1", "error"]
+        );
+        assert!(requests.lock().unwrap()[1].contains("last-event-id: 0"));
+        assert_eq!(queue.number_of_pending_requests(), 0);
+    }
+
+    #[test]
+    fn test_replay() {
+        use super::*;
+        use crate::har::{HarRecorder, HarReplay};
+
+        let path =
+            std::env::temp_dir().join(format!("grip-replay-test-{}.har", std::process::id()));
+        std::fs::remove_file(&path).ok();
+
+        let request = RequestBuilder::default()
+            .http_type(RequestType::Get)
+            .uri("https://docs.rs/".parse().unwrap())
+            .build()
+            .unwrap();
+
+        HarRecorder::new(path.clone(), Default::default()).unwrap().record(
+            &request,
+            Ok((
+                reqwest::StatusCode::OK,
+                &reqwest::header::HeaderMap::new(),
+                b"recorded docs.rs",
+            )),
+            std::time::SystemTime::now(),
+            Duration::from_millis(1),
+        );
+
+        let mut queue = Queue::with_config(
+            QueueConfigBuilder::default()
+                .transport(Arc::new(HarReplay::load(&path, Default::default()).unwrap()))
+                .build()
+                .unwrap(),
+        );
+
+        let body = Arc::new(Mutex::new(None));
+        let body_c = Arc::clone(&body);
+        let _handle = queue.send_request(request, move |response| {
+            *body_c.lock().unwrap() = Some(response.unwrap().body);
+        });
+
+        queue.execute_query_with_timeout(Duration::from_millis(500), Duration::from_millis(10));
+
+        assert_eq!(body.lock().unwrap().as_ref().unwrap(), b"recorded docs.rs");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_local_headers_and_body() {
+        use super::*;
+        use reqwest::header::{HeaderMap, HeaderValue};
+
+        let server = TestServer::start();
+        server.route(
+            "/echo",
+            Route::new(201, "created").with_header("X-Reply", "reply value"),
+        );
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-request", HeaderValue::from_static("request value"));
+
+        let response = send_and_wait(
+            &mut Queue::new(),
+            RequestBuilder::default()
+                .http_type(RequestType::Post)
+                .uri(server.url("/echo?query=1"))
+                .body(b"request body".to_vec())
+                .options(
+                    RequestOptionsBuilder::default()
+                        .headers(headers)
+                        .build()
+                        .unwrap(),
+                )
+                .build()
+                .unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(response.status_code, reqwest::StatusCode::CREATED);
+        assert_eq!(response.headers["x-reply"], "reply value");
+        assert_eq!(response.body, b"created");
+
+        let received = server.received_requests();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].method, "POST");
+        assert_eq!(received[0].path, "/echo");
+        assert_eq!(received[0].header("X-Request"), Some("request value"));
+        assert_eq!(received[0].body, b"request body");
+    }
+
+    #[test]
+    fn test_local_status_codes() {
+        use super::*;
+
+        let server = TestServer::start();
+        server.route("/error", Route::new(503, "unavailable"));
+
+        let mut queue = Queue::new();
+
+        // Error statuses are delivered as responses.
+        let response = send_and_wait(&mut queue, get(server.url("/error"))).unwrap();
+        assert_eq!(
+            response.status_code,
+            reqwest::StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(response.body, b"unavailable");
+
+        let response = send_and_wait(&mut queue, get(server.url("/missing"))).unwrap();
+        assert_eq!(response.status_code, reqwest::StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_local_redirect() {
+        use super::*;
+
+        let server = TestServer::start();
+        server
+            .route("/old", Route::redirect(302, "/new"))
+            .route("/new", Route::new(200, "new location"));
+
+        let response = send_and_wait(&mut Queue::new(), get(server.url("/old"))).unwrap();
+
+        assert_eq!(response.status_code, reqwest::StatusCode::OK);
+        assert_eq!(response.body, b"new location");
+        let paths: Vec<_> = server
+            .received_requests()
+            .into_iter()
+            .map(|request| request.path)
+            .collect();
+        assert_eq!(paths, ["/old", "/new"]);
+    }
+
+    #[test]
+    fn test_local_chunked_body() {
+        use super::*;
+
+        let server = TestServer::start();
+        server.route(
+            "/chunked",
+            Route::chunked(200, vec![b"first ".to_vec(), b"second".to_vec()])
+                .with_chunk_delay(Duration::from_millis(20)),
+        );
+
+        let response = send_and_wait(&mut Queue::new(), get(server.url("/chunked"))).unwrap();
+
+        assert_eq!(response.body, b"first second");
+    }
+
+    #[test]
+    fn test_local_large_body() {
+        use super::*;
+
+        let server = TestServer::start();
+        server
+            .route("/large", Route::new(200, vec![b'a'; 3 * 1024 * 1024]))
+            .route(
+                "/large-chunked",
+                Route::chunked(200, vec![vec![b'a'; 1024 * 1024]; 3]),
+            );
+
+        let mut queue = Queue::new();
+        let response = send_and_wait(&mut queue, get(server.url("/large"))).unwrap();
+        assert_eq!(response.body.len(), 3 * 1024 * 1024);
+
+        let mut queue = Queue::with_limits(ResponseLimits {
+            max_body_size: 2 * 1024 * 1024,
+            ..ResponseLimits::default()
+        });
+        for path in &["/large", "/large-chunked"] {
+            let error = send_and_wait(&mut queue, get(server.url(path)))
+                .err()
+                .unwrap();
+            assert_eq!(ErrorCategory::from(&error), ErrorCategory::BodyTooLarge);
+        }
+
+        // Limits are changed without restarting the queue.
+        queue.set_limits(ResponseLimits::default());
+        let response = send_and_wait(&mut queue, get(server.url("/large"))).unwrap();
+        assert_eq!(response.body.len(), 3 * 1024 * 1024);
+    }
+
+    #[test]
+    fn test_local_timeout() {
+        use super::*;
+
+        let server = TestServer::start();
+        server.route(
+            "/slow",
+            Route::new(200, "slow").with_delay(Duration::from_secs(2)),
+        );
+
+        let start = Instant::now();
+        let error = send_and_wait(
+            &mut Queue::new(),
+            RequestBuilder::default()
+                .http_type(RequestType::Get)
+                .uri(server.url("/slow"))
+                .options(
+                    RequestOptionsBuilder::default()
+                        .timeout(Some(Duration::from_millis(200)))
+                        .build()
+                        .unwrap(),
+                )
+                .build()
+                .unwrap(),
+        )
+        .err()
+        .unwrap();
+
+        assert_eq!(ErrorCategory::from(&error), ErrorCategory::Timeout);
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_local_cancelling() {
+        use super::*;
+
+        let server = TestServer::start();
+        server.route(
+            "/slow",
+            Route::new(200, "slow").with_delay(Duration::from_secs(2)),
+        );
+
+        let mut queue = Queue::new();
+        let result = Arc::new(Mutex::new(None));
+        let result_c = Arc::clone(&result);
+        let handle = queue.send_request(get(server.url("/slow")), move |response| {
+            *result_c.lock().unwrap() = Some(response.map(|_| ()));
+        });
+
+        // Cancel, when the request has already reached the server.
+        let start = Instant::now();
+        while server.received_requests().is_empty() {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(5));
+        }
+        drop(handle);
+
+        queue.execute_query_with_timeout(Duration::from_millis(300), Duration::from_millis(10));
+
+        let error = result.lock().unwrap().take().unwrap().err().unwrap();
+        assert_eq!(ErrorCategory::from(&error), ErrorCategory::Cancelled);
+        assert_eq!(queue.number_of_pending_requests(), 0);
+    }
+
+    #[test]
+    fn test_local_tls() {
+        use super::*;
+        use crate::transport::ReqwestTransport;
+
+        let server = TestServer::start_tls();
+        server.route("/secure", Route::new(200, "secure"));
+
+        let error = send_and_wait(&mut Queue::new(), get(server.url("/secure")))
+            .err()
+            .unwrap();
+        assert_eq!(ErrorCategory::from(&error), ErrorCategory::TLS);
+
+        let mut queue = Queue::with_config(
+            QueueConfigBuilder::default()
+                .transport(Arc::new(ReqwestTransport::with_root_certificate(
+                    reqwest::Certificate::from_pem(CERTIFICATE_PEM).unwrap(),
+                )))
+                .build()
+                .unwrap(),
+        );
+        let response = send_and_wait(&mut queue, get(server.url("/secure"))).unwrap();
+        assert_eq!(response.body, b"secure");
+    }
+
+    #[test]
+    fn test_drain() {
+        use super::*;
+
+        let transport = Arc::new(MockTransport::new());
+        let fast_url: reqwest::Url = "https://fast.example.com/".parse().unwrap();
+        let slow_url: reqwest::Url = "https://slow.example.com/".parse().unwrap();
+        transport.add_reply(
+            RequestType::Get,
+            &fast_url,
+            MockReply::new(reqwest::StatusCode::OK, vec![]).with_delay(Duration::from_millis(50)),
+        );
+        transport.add_reply(
+            RequestType::Get,
+            &slow_url,
+            MockReply::new(reqwest::StatusCode::OK, vec![]).with_delay(Duration::from_secs(10)),
+        );
+
+        let mut queue = Queue::with_config(
+            QueueConfigBuilder::default()
+                .transport(transport)
+                .build()
+                .unwrap(),
+        );
+
+        let must_complete = RequestOptionsBuilder::default()
+            .must_complete(true)
+            .build()
+            .unwrap();
+
+        let completed = Arc::new(Mutex::new(vec![]));
+        let mut send = |url: &reqwest::Url, options: RequestOptions| {
+            let completed = Arc::clone(&completed);
+            let url_c = url.clone();
+            queue.send_request(
+                RequestBuilder::default()
+                    .http_type(RequestType::Get)
+                    .uri(url.clone())
+                    .options(options)
+                    .build()
+                    .unwrap(),
+                move |response| {
+                    assert!(response.is_ok());
+                    completed.lock().unwrap().push(url_c.clone());
+                },
+            )
+        };
+        let _handles = [
+            send(&fast_url, must_complete.clone()),
+            send(&slow_url, RequestOptions::default()),
+            send(&slow_url, must_complete),
+        ];
+
+        assert_eq!(queue.pending_requests().count(), 3);
+
+        let report = queue.drain(&DrainPolicy {
+            timeout: Duration::from_millis(500),
+            only_must_complete: true,
+            run_callbacks: true,
+        });
+
+        assert_eq!(report.completed, 1);
+        assert_eq!(*completed.lock().unwrap(), [fast_url]);
+        let abandoned: Vec<_> = report
+            .abandoned
+            .iter()
+            .map(|request| (request.url.clone(), request.must_complete))
+            .collect();
+        assert_eq!(
+            abandoned,
+            [(slow_url.clone(), false), (slow_url.clone(), true)]
+        );
+    }
+
+    #[test]
+    fn test_concurrency_limit() {
+        use super::*;
+
+        let transport = Arc::new(MockTransport::new());
+        let url: reqwest::Url = "https://slow.example.com/".parse().unwrap();
+        transport.add_reply(
+            RequestType::Get,
+            &url,
+            MockReply::new(reqwest::StatusCode::OK, vec![]).with_delay(Duration::from_secs(10)),
+        );
+
+        let mut queue = Queue::with_config(
+            QueueConfigBuilder::default()
+                .transport(transport.clone())
+                .worker_threads(Some(1))
+                .max_concurrent_requests(Some(1))
+                .build()
+                .unwrap(),
+        );
+
+        let wait_for_sent = |count: usize| {
+            let start = Instant::now();
+            while transport.received_count(RequestType::Get, &url) < count {
+                assert!(start.elapsed() < Duration::from_secs(5), "Request wasn't sent");
+                thread::sleep(Duration::from_millis(5));
+            }
+        };
+
+        let first = queue.send_request(get(url.clone()), |_| {});
+        let second = queue.send_request(get(url.clone()), |_| {});
+        let third = queue.send_request(get(url.clone()), |_| {});
+        assert_ne!(first.request_id(), second.request_id());
+
+        wait_for_sent(1);
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(transport.received_count(RequestType::Get, &url), 1);
+
+        // Cancelled request frees its slot, while waiting request gives it up without being sent.
+        drop(second);
+        drop(first);
+        wait_for_sent(2);
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(transport.received_count(RequestType::Get, &url), 2);
+
+        drop(third);
+    }
 }
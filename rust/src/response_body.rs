@@ -0,0 +1,370 @@
+/*
+ * gRIP
+ * Copyright (c) 2018 Alik Aslanyan <cplusplus256@gmail.com>
+ *
+ *
+ *    This program is free software; you can redistribute it and/or modify it
+ *    under the terms of the GNU General Public License as published by the
+ *    Free Software Foundation; either version 3 of the License, or (at
+ *    your option) any later version.
+ *
+ *    This program is distributed in the hope that it will be useful, but
+ *    WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ *    General Public License for more details.
+ *
+ *    You should have received a copy of the GNU General Public License
+ *    along with this program; if not, write to the Free Software Foundation,
+ *    Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ *    In addition, as a special exception, the author gives permission to
+ *    link the code of this program with the Half-Life Game Engine ("HL
+ *    Engine") and Modified Game Libraries ("MODs") developed by Valve,
+ *    L.L.C ("Valve").  You must obey the GNU General Public License in all
+ *    respects for all of the code used other than the HL Engine and MODs
+ *    from Valve.  If you modify this file, you may extend this exception
+ *    to your version of the file, but you are not obligated to do so.  If
+ *    you do not wish to do so, delete this exception statement from your
+ *    version.
+ *
+ */
+
+use crate::errors::*;
+use reqwest::header::{HeaderMap, CONTENT_ENCODING, CONTENT_TYPE};
+use std::borrow::Cow;
+use std::io::Read;
+
+/// Value of the `Accept-Encoding` header, which is sent unless request overrides it.
+pub const ACCEPT_ENCODING: &str = "gzip, deflate, br";
+
+/// Decompresses body according to the `Content-Encoding` header.
+/// Result is never larger than `limit`, otherwise error is returned.
+pub fn decompress(headers: &HeaderMap, body: Vec<u8>, limit: usize) -> Result<Vec<u8>> {
+    let encoding = match headers.get(CONTENT_ENCODING) {
+        Some(encoding) => encoding
+            .to_str()
+            .chain_err(|| decoding_error("Content-Encoding header is not valid string"))?
+            .trim()
+            .to_lowercase(),
+        None => return Ok(body),
+    };
+
+    match encoding.as_str() {
+        "" | "identity" => Ok(body),
+        "gzip" | "x-gzip" => read_with_limit(flate2::read::GzDecoder::new(&body[..]), limit),
+        "deflate" => {
+            // Servers are inconsistent, some of them send raw deflate stream without zlib header.
+            read_with_limit(flate2::read::ZlibDecoder::new(&body[..]), limit).or_else(|e| {
+                match e.kind() {
+                    ErrorKind::ResponseBodyTooLarge(_) => Err(e),
+                    _ => read_with_limit(flate2::read::DeflateDecoder::new(&body[..]), limit),
+                }
+            })
+        }
+        "br" => read_with_limit(
+            brotli_decompressor::Decompressor::new(&body[..], 4096),
+            limit,
+        ),
+        encoding => Err(decoding_error(format!(
+            "Unsupported Content-Encoding: {}",
+            encoding
+        ))),
+    }
+}
+
+fn read_with_limit<R: Read>(reader: R, limit: usize) -> Result<Vec<u8>> {
+    let mut output = vec![];
+    reader
+        .take(limit as u64 + 1)
+        .read_to_end(&mut output)
+        .chain_err(|| decoding_error("Unable to decompress response body"))?;
+
+    if output.len() > limit {
+        Err(ErrorKind::ResponseBodyTooLarge(limit).into())
+    } else {
+        Ok(output)
+    }
+}
+
+/// Decodes body to UTF-8 using charset from the `Content-Type` header.
+/// Body without charset is expected to be UTF-8.
+pub fn decode_text<'a>(headers: &HeaderMap, body: &'a [u8]) -> Result<Cow<'a, str>> {
+    let charset = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<mime::Mime>().ok())
+        .and_then(|mime| mime.get_param(mime::CHARSET).map(|c| c.as_str().to_owned()));
+
+    let encoding = match charset {
+        Some(charset) => encoding_rs::Encoding::for_label(charset.as_bytes())
+            .chain_err(|| decoding_error(format!("Unknown charset: {}", charset)))?,
+        None => encoding_rs::UTF_8,
+    };
+
+    match encoding.decode_without_bom_handling_and_without_replacement(body) {
+        Some(text) => Ok(text),
+        None => Err(decoding_error(format!(
+            "Unable to decode body as {}",
+            encoding.name()
+        ))),
+    }
+}
+
+/// Hash algorithms exposed to Pawn as `GripHashType`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HashType {
+    MD5 = 1,
+    SHA1 = 2,
+    SHA256 = 3,
+}
+
+impl HashType {
+    pub fn from_id(id: isize) -> Option<HashType> {
+        match id {
+            1 => Some(HashType::MD5),
+            2 => Some(HashType::SHA1),
+            3 => Some(HashType::SHA256),
+            _ => None,
+        }
+    }
+}
+
+/// Returns hex encoded digest of the data.
+pub fn hash_to_hex(hash_type: HashType, data: &[u8]) -> String {
+    use sha2::Digest;
+
+    match hash_type {
+        HashType::MD5 => to_hex(&md5::compute(data)[..]),
+        HashType::SHA1 => to_hex(&sha1::Sha1::digest(data)[..]),
+        HashType::SHA256 => to_hex(&sha2::Sha256::digest(data)[..]),
+    }
+}
+
+pub fn to_hex(data: &[u8]) -> String {
+    use std::fmt::Write;
+
+    data.iter().fold(String::with_capacity(data.len() * 2), |mut s, b| {
+        write!(s, "{:02x}", b).unwrap();
+        s
+    })
+}
+
+/// Returns `length` bytes starting from `offset`. `None` length means till the end of the body.
+pub fn slice(body: &[u8], offset: usize, length: Option<usize>) -> Result<&[u8]> {
+    if offset > body.len() {
+        bail!("Offset {} is out of the body bounds {}", offset, body.len());
+    }
+
+    let end = length.map_or(body.len(), |length| {
+        std::cmp::min(offset.saturating_add(length), body.len())
+    });
+
+    Ok(&body[offset..end])
+}
+
+/// Length of the longest prefix, which is not larger than `max` and doesn't split UTF-8 character.
+/// Bytes which are not part of the valid UTF-8 sequence are never treated as split character.
+pub fn utf8_prefix_len(bytes: &[u8], max: usize) -> usize {
+    if bytes.len() <= max {
+        return bytes.len();
+    }
+
+    // Walk back over continuation bytes to find the leading byte of the last character.
+    let mut start = max;
+    while start > 0 && max - start < 3 && bytes[start] & 0b1100_0000 == 0b1000_0000 {
+        start -= 1;
+    }
+
+    let char_len = match bytes[start] {
+        b if b & 0b1110_0000 == 0b1100_0000 => 2,
+        b if b & 0b1111_0000 == 0b1110_0000 => 3,
+        b if b & 0b1111_1000 == 0b1111_0000 => 4,
+        _ => 1,
+    };
+
+    if start < max && start + char_len > max {
+        start
+    } else {
+        max
+    }
+}
+
+/// Finds the line starting at `offset`, which is not longer than `max` bytes.
+/// Returns line without line terminator and offset of the next line,
+/// or `None` when the end of the body is reached.
+/// Lines longer than `max` are split into several parts.
+/// Fails, when the next character doesn't fit into `max` bytes, because offset wouldn't advance.
+pub fn next_line(body: &[u8], offset: usize, max: usize) -> Result<Option<(&[u8], usize)>> {
+    if offset >= body.len() {
+        return Ok(None);
+    }
+
+    let rest = &body[offset..];
+    match rest.iter().position(|&b| b == b'\n') {
+        // The `\r` of a `\r\n` terminator doesn't count against the buffer.
+        Some(end) if end <= max || (end == max + 1 && rest[max] == b'\r') => {
+            let line = &rest[..end];
+            let line = if line.last() == Some(&b'\r') {
+                &line[..line.len() - 1]
+            } else {
+                line
+            };
+
+            Ok(Some((line, offset + end + 1)))
+        }
+        _ => {
+            let length = utf8_prefix_len(rest, max);
+            if length == 0 {
+                bail!(
+                    "Buffer of {} bytes is too small for the character at offset {}",
+                    max,
+                    offset
+                );
+            }
+
+            Ok(Some((&rest[..length], offset + length)))
+        }
+    }
+}
+
+fn decoding_error<T: Into<String>>(t: T) -> Error {
+    ErrorKind::DecodingError(t.into()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+    use std::io::Write;
+
+    fn headers(name: reqwest::header::HeaderName, value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn test_decompress() {
+        let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(&[b'a'; 1000]).unwrap();
+        let gzip = encoder.finish().unwrap();
+
+        let gzip_headers = headers(CONTENT_ENCODING, "gzip");
+        assert_eq!(
+            decompress(&gzip_headers, gzip.clone(), 1000).unwrap(),
+            vec![b'a'; 1000]
+        );
+
+        match decompress(&gzip_headers, gzip, 999).unwrap_err().kind() {
+            ErrorKind::ResponseBodyTooLarge(999) => {}
+            _ => unreachable!(),
+        }
+
+        let mut encoder =
+            flate2::write::DeflateEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(b"raw deflate").unwrap();
+        assert_eq!(
+            decompress(
+                &headers(CONTENT_ENCODING, "deflate"),
+                encoder.finish().unwrap(),
+                100
+            )
+            .unwrap(),
+            b"raw deflate".to_vec()
+        );
+
+        assert_eq!(
+            decompress(&HeaderMap::new(), b"plain".to_vec(), 1).unwrap(),
+            b"plain".to_vec()
+        );
+        assert!(decompress(&headers(CONTENT_ENCODING, "compress"), vec![], 1).is_err());
+    }
+
+    #[test]
+    fn test_decode_text() {
+        assert_eq!(
+            decode_text(
+                &headers(CONTENT_TYPE, "text/plain; charset=windows-1251"),
+                &[0xcf, 0xf0, 0xe8, 0xe2, 0xe5, 0xf2]
+            )
+            .unwrap(),
+            "Привет"
+        );
+
+        assert_eq!(decode_text(&HeaderMap::new(), b"hello").unwrap(), "hello");
+        assert!(decode_text(&HeaderMap::new(), &[0xff, 0xfe]).is_err());
+        assert!(decode_text(&headers(CONTENT_TYPE, "text/plain; charset=nope"), b"").is_err());
+    }
+
+    #[test]
+    fn test_hash_and_slice() {
+        assert_eq!(
+            hash_to_hex(HashType::MD5, b"abc"),
+            "900150983cd24fb0d6963f7d28e17f72"
+        );
+        assert_eq!(
+            hash_to_hex(HashType::SHA1, b"abc"),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+        assert_eq!(
+            hash_to_hex(HashType::SHA256, b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+
+        assert_eq!(slice(b"abcdef", 2, Some(2)).unwrap(), b"cd");
+        assert_eq!(slice(b"abcdef", 2, None).unwrap(), b"cdef");
+        assert_eq!(slice(b"abcdef", 4, Some(100)).unwrap(), b"ef");
+        assert_eq!(slice(b"abcdef", 6, None).unwrap(), b"");
+        assert!(slice(b"abcdef", 7, None).is_err());
+    }
+
+    #[test]
+    fn test_utf8_prefix_len() {
+        let text = "aПривет".as_bytes();
+        assert_eq!(utf8_prefix_len(text, 100), text.len());
+        assert_eq!(utf8_prefix_len(text, 3), 3);
+        assert_eq!(utf8_prefix_len(text, 4), 3);
+        assert_eq!(utf8_prefix_len(text, 0), 0);
+        assert_eq!(utf8_prefix_len(&[0xff, 0xff, 0xff], 2), 2);
+    }
+
+    #[test]
+    fn test_next_line() {
+        let body = b"first\r\nsecond\n\nvery long line";
+
+        let (line, offset) = next_line(body, 0, 100).unwrap().unwrap();
+        assert_eq!((line, offset), (&b"first"[..], 7));
+
+        let (line, offset) = next_line(body, offset, 100).unwrap().unwrap();
+        assert_eq!((line, offset), (&b"second"[..], 14));
+
+        let (line, offset) = next_line(body, offset, 100).unwrap().unwrap();
+        assert_eq!((line, offset), (&b""[..], 15));
+
+        let (line, offset) = next_line(body, offset, 4).unwrap().unwrap();
+        assert_eq!((line, offset), (&b"very"[..], 19));
+
+        let (line, offset) = next_line(body, offset, 100).unwrap().unwrap();
+        assert_eq!((line, offset), (&b" long line"[..], body.len()));
+
+        assert!(next_line(body, offset, 100).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_next_line_small_buffer() {
+        assert!(next_line(b"line", 0, 0).is_err());
+        // Empty line fits into any buffer.
+        assert_eq!(next_line(b"\nline", 0, 0).unwrap(), Some((&b""[..], 1)));
+        assert_eq!(next_line(b"\r\nline", 0, 0).unwrap(), Some((&b""[..], 2)));
+
+        // Terminator right past the buffer belongs to the same line.
+        let body = b"abcd\r\nef";
+        assert_eq!(next_line(body, 0, 4).unwrap(), Some((&b"abcd"[..], 6)));
+        assert_eq!(next_line(body, 6, 4).unwrap(), Some((&b"ef"[..], 8)));
+        assert_eq!(next_line(body, 0, 3).unwrap(), Some((&b"abc"[..], 3)));
+
+        let body = "😀 smile".as_bytes();
+        assert!(next_line(body, 0, 2).is_err());
+        assert_eq!(next_line(body, 0, 5).unwrap(), Some((&body[..5], 5)));
+    }
+}
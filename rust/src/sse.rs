@@ -0,0 +1,241 @@
+/*
+ * gRIP
+ * Copyright (c) 2018 Alik Aslanyan <cplusplus256@gmail.com>
+ *
+ *
+ *    This program is free software; you can redistribute it and/or modify it
+ *    under the terms of the GNU General Public License as published by the
+ *    Free Software Foundation; either version 3 of the License, or (at
+ *    your option) any later version.
+ *
+ *    This program is distributed in the hope that it will be useful, but
+ *    WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ *    General Public License for more details.
+ *
+ *    You should have received a copy of the GNU General Public License
+ *    along with this program; if not, write to the Free Software Foundation,
+ *    Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ *    In addition, as a special exception, the author gives permission to
+ *    link the code of this program with the Half-Life Game Engine ("HL
+ *    Engine") and Modified Game Libraries ("MODs") developed by Valve,
+ *    L.L.C ("Valve").  You must obey the GNU General Public License in all
+ *    respects for all of the code used other than the HL Engine and MODs
+ *    from Valve.  If you modify this file, you may extend this exception
+ *    to your version of the file, but you are not obligated to do so.  If
+ *    you do not wish to do so, delete this exception statement from your
+ *    version.
+ *
+ */
+
+//! Parser of the `text/event-stream` format.
+//! https://html.spec.whatwg.org/multipage/server-sent-events.html#event-stream-interpretation
+
+use crate::errors::*;
+use std::time::Duration;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Event {
+    /// Event type, `message` when not specified.
+    pub name: String,
+    /// Last event ID at the moment of dispatch.
+    pub id: String,
+    pub data: String,
+}
+
+/// Events delivered to the subscription callback.
+#[derive(Debug)]
+pub enum SubscriptionEvent {
+    /// Connection was (re)established.
+    Open,
+    Event(Event),
+    /// Connection failed or was lost. Reconnect is done automatically.
+    Error(Error),
+}
+
+#[derive(Debug, Default)]
+pub struct Parser {
+    line: Vec<u8>,
+    /// Previous chunk ended with `\r`, so the `\n` in the beginning of the next one should be skipped.
+    skip_line_feed: bool,
+    bom_checked: bool,
+    name: String,
+    data: String,
+    last_event_id: String,
+    retry: Option<Duration>,
+}
+
+impl Parser {
+    pub fn new() -> Self {
+        Parser::default()
+    }
+
+    /// ID which should be sent in the `Last-Event-ID` header on reconnect.
+    pub fn last_event_id(&self) -> &str {
+        &self.last_event_id
+    }
+
+    /// Reconnection time requested by the server.
+    pub fn retry(&self) -> Option<Duration> {
+        self.retry
+    }
+
+    /// Should be called on reconnect, because events can't span several connections.
+    pub fn reset_connection(&mut self) {
+        self.line.clear();
+        self.skip_line_feed = false;
+        self.bom_checked = false;
+        self.name.clear();
+        self.data.clear();
+    }
+
+    /// Parses part of the stream and returns all completed events.
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<Event> {
+        let mut events = vec![];
+
+        for &byte in chunk {
+            if self.skip_line_feed {
+                self.skip_line_feed = false;
+                if byte == b'\n' {
+                    continue;
+                }
+            }
+
+            match byte {
+                b'\r' | b'\n' => {
+                    self.skip_line_feed = byte == b'\r';
+
+                    let line = std::mem::replace(&mut self.line, vec![]);
+                    if let Some(event) = self.process_line(&line) {
+                        events.push(event);
+                    }
+                }
+                byte => {
+                    self.line.push(byte);
+
+                    if !self.bom_checked && self.line.len() == 3 {
+                        self.bom_checked = true;
+                        if self.line == b"\xEF\xBB\xBF" {
+                            self.line.clear();
+                        }
+                    }
+                }
+            }
+        }
+
+        events
+    }
+
+    fn process_line(&mut self, line: &[u8]) -> Option<Event> {
+        self.bom_checked = true;
+
+        if line.is_empty() {
+            return self.dispatch();
+        }
+
+        let line = String::from_utf8_lossy(line);
+        if line.starts_with(':') {
+            return None;
+        }
+
+        let (field, value) = match line.find(':') {
+            Some(position) => {
+                let value = &line[position + 1..];
+                (
+                    &line[..position],
+                    if value.starts_with(' ') {
+                        &value[1..]
+                    } else {
+                        value
+                    },
+                )
+            }
+            None => (&line[..], ""),
+        };
+
+        match field {
+            "event" => self.name = value.to_owned(),
+            "data" => {
+                self.data.push_str(value);
+                self.data.push('\n');
+            }
+            "id" if !value.contains('\0') => self.last_event_id = value.to_owned(),
+            "retry" => {
+                if let Ok(milliseconds) = value.parse() {
+                    self.retry = Some(Duration::from_millis(milliseconds));
+                }
+            }
+            _ => {}
+        }
+
+        None
+    }
+
+    fn dispatch(&mut self) -> Option<Event> {
+        let name = std::mem::replace(&mut self.name, String::new());
+        let mut data = std::mem::replace(&mut self.data, String::new());
+
+        if data.is_empty() {
+            return None;
+        }
+
+        data.pop();
+
+        Some(Event {
+            name: if name.is_empty() {
+                "message".to_owned()
+            } else {
+                name
+            },
+            id: self.last_event_id.clone(),
+            data,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(name: &str, id: &str, data: &str) -> Event {
+        Event {
+            name: name.to_owned(),
+            id: id.to_owned(),
+            data: data.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let mut parser = Parser::new();
+
+        assert_eq!(
+            parser.feed(
+                b"\xEF\xBB\xBFdata: first\n\n: comment\nevent: ban\nid: 7\ndata: a\ndata:b\n\n"
+            ),
+            vec![event("message", "", "first"), event("ban", "7", "a\nb")]
+        );
+        assert_eq!(parser.last_event_id(), "7");
+
+        assert_eq!(parser.feed(b"retry: 1500\nevent: x\n\n"), vec![]);
+        assert_eq!(parser.retry(), Some(Duration::from_millis(1500)));
+
+        assert_eq!(parser.feed(b"data"), vec![]);
+        assert_eq!(parser.feed(b"\r"), vec![]);
+        assert_eq!(parser.feed(b"\n\r\n"), vec![event("message", "7", "")]);
+    }
+
+    #[test]
+    fn test_reset_connection() {
+        let mut parser = Parser::new();
+
+        assert_eq!(parser.feed(b"id: 1\ndata: incomplete\n"), vec![]);
+        parser.reset_connection();
+
+        assert_eq!(
+            parser.feed(b"data: complete\n\n"),
+            vec![event("message", "1", "complete")]
+        );
+    }
+}
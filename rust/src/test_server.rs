@@ -0,0 +1,320 @@
+/*
+ * gRIP
+ * Copyright (c) 2018 Alik Aslanyan <cplusplus256@gmail.com>
+ *
+ *
+ *    This program is free software; you can redistribute it and/or modify it
+ *    under the terms of the GNU General Public License as published by the
+ *    Free Software Foundation; either version 3 of the License, or (at
+ *    your option) any later version.
+ *
+ *    This program is distributed in the hope that it will be useful, but
+ *    WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ *    General Public License for more details.
+ *
+ *    You should have received a copy of the GNU General Public License
+ *    along with this program; if not, write to the Free Software Foundation,
+ *    Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ *    In addition, as a special exception, the author gives permission to
+ *    link the code of this program with the Half-Life Game Engine ("HL
+ *    Engine") and Modified Game Libraries ("MODs") developed by Valve,
+ *    L.L.C ("Valve").  You must obey the GNU General Public License in all
+ *    respects for all of the code used other than the HL Engine and MODs
+ *    from Valve.  If you modify this file, you may extend this exception
+ *    to your version of the file, but you are not obligated to do so.  If
+ *    you do not wish to do so, delete this exception statement from your
+ *    version.
+ *
+ */
+
+//! Loopback HTTP server with programmable routes, used by the test suite instead of the network.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Self-signed certificate for `localhost` and `127.0.0.1`, which is not trusted by default.
+pub const CERTIFICATE_PEM: &[u8] = include_bytes!("test_data/localhost.pem");
+
+const IDENTITY_PKCS12: &[u8] = include_bytes!("test_data/localhost.p12");
+const IDENTITY_PASSWORD: &str = "grip";
+
+#[derive(Clone, Debug)]
+enum RouteBody {
+    Fixed(Vec<u8>),
+    Chunked(Vec<Vec<u8>>),
+}
+
+/// Scripted reply of the server for a single path.
+#[derive(Clone, Debug)]
+pub struct Route {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: RouteBody,
+    delay: Duration,
+    chunk_delay: Duration,
+}
+
+impl Route {
+    pub fn new<B: Into<Vec<u8>>>(status: u16, body: B) -> Self {
+        Route {
+            status,
+            headers: vec![],
+            body: RouteBody::Fixed(body.into()),
+            delay: Duration::from_secs(0),
+            chunk_delay: Duration::from_secs(0),
+        }
+    }
+
+    /// Body is sent with `Transfer-Encoding: chunked`, so its size is unknown in advance.
+    pub fn chunked(status: u16, chunks: Vec<Vec<u8>>) -> Self {
+        Route {
+            body: RouteBody::Chunked(chunks),
+            ..Route::new(status, vec![])
+        }
+    }
+
+    pub fn redirect(status: u16, location: &str) -> Self {
+        Route::new(status, vec![]).with_header("Location", location)
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    /// Delay before the status line is sent.
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Delay before each chunk of the chunked body.
+    pub fn with_chunk_delay(mut self, delay: Duration) -> Self {
+        self.chunk_delay = delay;
+        self
+    }
+}
+
+/// Request as it was seen by the server. Header names are lowercase.
+#[derive(Clone, Debug)]
+pub struct ReceivedRequest {
+    pub method: String,
+    pub path: String,
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+impl ReceivedRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(&name.to_lowercase()).map(String::as_str)
+    }
+}
+
+#[derive(Default)]
+struct State {
+    routes: HashMap<String, Route>,
+    received: Vec<ReceivedRequest>,
+}
+
+/// Serves each connection in its own thread and closes it after the reply.
+/// Unknown paths are answered with `404`. Server is stopped, when dropped.
+pub struct TestServer {
+    address: SocketAddr,
+    tls: bool,
+    state: Arc<Mutex<State>>,
+    stopped: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl TestServer {
+    pub fn start() -> Self {
+        TestServer::start_with_acceptor(None)
+    }
+
+    /// Server, which accepts only TLS connections with `CERTIFICATE_PEM`.
+    pub fn start_tls() -> Self {
+        let identity = native_tls::Identity::from_pkcs12(IDENTITY_PKCS12, IDENTITY_PASSWORD)
+            .expect("Test identity should be valid");
+        TestServer::start_with_acceptor(Some(native_tls::TlsAcceptor::new(identity).unwrap()))
+    }
+
+    fn start_with_acceptor(acceptor: Option<native_tls::TlsAcceptor>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+
+        let address = listener.local_addr().unwrap();
+        let tls = acceptor.is_some();
+        let state = Arc::new(Mutex::new(State::default()));
+        let stopped = Arc::new(AtomicBool::new(false));
+
+        let thread = {
+            let state = Arc::clone(&state);
+            let stopped = Arc::clone(&stopped);
+            thread::spawn(move || {
+                while !stopped.load(Ordering::SeqCst) {
+                    let stream = match listener.accept() {
+                        Ok((stream, _)) => stream,
+                        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                            thread::sleep(Duration::from_millis(5));
+                            continue;
+                        }
+                        Err(_) => break,
+                    };
+
+                    let state = Arc::clone(&state);
+                    let acceptor = acceptor.clone();
+                    thread::spawn(move || {
+                        // Errors are expected, when client cancels request or rejects certificate.
+                        serve_connection(stream, acceptor, &state).ok();
+                    });
+                }
+            })
+        };
+
+        TestServer {
+            address,
+            tls,
+            state,
+            stopped,
+            thread: Some(thread),
+        }
+    }
+
+    /// Replaces the route for the `path`. Query string is not part of the path.
+    pub fn route(&self, path: &str, route: Route) -> &Self {
+        self.state
+            .lock()
+            .unwrap()
+            .routes
+            .insert(path.to_owned(), route);
+        self
+    }
+
+    pub fn url(&self, path: &str) -> reqwest::Url {
+        format!(
+            "{}://localhost:{}{}",
+            if self.tls { "https" } else { "http" },
+            self.address.port(),
+            path
+        )
+        .parse()
+        .unwrap()
+    }
+
+    pub fn received_requests(&self) -> Vec<ReceivedRequest> {
+        self.state.lock().unwrap().received.clone()
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+fn serve_connection(
+    stream: TcpStream,
+    acceptor: Option<native_tls::TlsAcceptor>,
+    state: &Mutex<State>,
+) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    match acceptor {
+        Some(acceptor) => serve(
+            acceptor
+                .accept(stream)
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?,
+            state,
+        ),
+        None => serve(stream, state),
+    }
+}
+
+fn serve<S: Read + Write>(stream: S, state: &Mutex<State>) -> io::Result<()> {
+    let mut reader = BufReader::new(stream);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_owned();
+    let target = parts.next().unwrap_or_default();
+    let path = target.split('?').next().unwrap().to_owned();
+
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some(colon) = line.find(':') {
+            headers.insert(
+                line[..colon].trim().to_lowercase(),
+                line[colon + 1..].trim().to_owned(),
+            );
+        }
+    }
+
+    let content_length = headers
+        .get("content-length")
+        .and_then(|length| length.parse().ok())
+        .unwrap_or(0);
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+
+    let route = {
+        let mut state = state.lock().unwrap();
+        state.received.push(ReceivedRequest {
+            method,
+            path: path.clone(),
+            headers,
+            body,
+        });
+        state
+            .routes
+            .get(&path)
+            .cloned()
+            .unwrap_or_else(|| Route::new(404, "Not Found"))
+    };
+
+    thread::sleep(route.delay);
+
+    let stream = reader.get_mut();
+    write!(
+        stream,
+        "HTTP/1.1 {} Test\r\nConnection: close\r\n",
+        route.status
+    )?;
+    for (name, value) in &route.headers {
+        write!(stream, "{}: {}\r\n", name, value)?;
+    }
+
+    match route.body {
+        RouteBody::Fixed(body) => {
+            write!(stream, "Content-Length: {}\r\n\r\n", body.len())?;
+            stream.write_all(&body)?;
+        }
+        RouteBody::Chunked(chunks) => {
+            write!(stream, "Transfer-Encoding: chunked\r\n\r\n")?;
+            stream.flush()?;
+            for chunk in chunks.iter().filter(|chunk| !chunk.is_empty()) {
+                thread::sleep(route.chunk_delay);
+                write!(stream, "{:x}\r\n", chunk.len())?;
+                stream.write_all(chunk)?;
+                write!(stream, "\r\n")?;
+                stream.flush()?;
+            }
+            write!(stream, "0\r\n\r\n")?;
+        }
+    }
+
+    stream.flush()
+}
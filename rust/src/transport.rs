@@ -0,0 +1,348 @@
+/*
+ * gRIP
+ * Copyright (c) 2018 Alik Aslanyan <cplusplus256@gmail.com>
+ *
+ *
+ *    This program is free software; you can redistribute it and/or modify it
+ *    under the terms of the GNU General Public License as published by the
+ *    Free Software Foundation; either version 3 of the License, or (at
+ *    your option) any later version.
+ *
+ *    This program is distributed in the hope that it will be useful, but
+ *    WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ *    General Public License for more details.
+ *
+ *    You should have received a copy of the GNU General Public License
+ *    along with this program; if not, write to the Free Software Foundation,
+ *    Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ *    In addition, as a special exception, the author gives permission to
+ *    link the code of this program with the Half-Life Game Engine ("HL
+ *    Engine") and Modified Game Libraries ("MODs") developed by Valve,
+ *    L.L.C ("Valve").  You must obey the GNU General Public License in all
+ *    respects for all of the code used other than the HL Engine and MODs
+ *    from Valve.  If you modify this file, you may extend this exception
+ *    to your version of the file, but you are not obligated to do so.  If
+ *    you do not wish to do so, delete this exception statement from your
+ *    version.
+ *
+ */
+
+//! Ways, in which queue exchanges requests for responses.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use futures::future;
+use futures::prelude::*;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::r#async as reqwest_async;
+use reqwest::StatusCode;
+
+use crate::error_category::ErrorCategory;
+use crate::errors::*;
+use crate::networking_queue::{Request, RequestType, ResponseLimits};
+use crate::response_body;
+
+/// Status, headers and decompressed body of the response.
+pub type Exchange = (StatusCode, HeaderMap, Vec<u8>);
+
+pub type ExchangeFuture = Box<dyn Future<Item = Exchange, Error = Error> + Send>;
+
+pub trait Transport: Send + Sync {
+    /// Sends the request. Limits are already narrowed by the options of the request.
+    /// Timeout and cancellation are handled by the queue.
+    fn send(&self, request: &Request, limits: ResponseLimits) -> ExchangeFuture;
+}
+
+/// Transport, which sends requests to the network.
+pub struct ReqwestTransport {
+    client: reqwest_async::Client,
+}
+
+impl Default for ReqwestTransport {
+    fn default() -> Self {
+        ReqwestTransport::new()
+    }
+}
+
+impl ReqwestTransport {
+    pub fn new() -> Self {
+        ReqwestTransport::with_client_builder(reqwest_async::Client::builder())
+    }
+
+    /// Transport, which additionally trusts the `certificate`, e.g. self-signed one.
+    pub fn with_root_certificate(certificate: reqwest::Certificate) -> Self {
+        ReqwestTransport::with_client_builder(
+            reqwest_async::Client::builder().add_root_certificate(certificate),
+        )
+    }
+
+    fn with_client_builder(builder: reqwest_async::ClientBuilder) -> Self {
+        ReqwestTransport {
+            // Decompression is done manually, because reqwest doesn't limit decompressed size.
+            client: builder.gzip(false).build().unwrap(),
+        }
+    }
+}
+
+impl Transport for ReqwestTransport {
+    fn send(&self, request: &Request, limits: ResponseLimits) -> ExchangeFuture {
+        let max_body_size = limits.max_body_size;
+        let max_decompressed_body_size = limits.max_decompressed_body_size;
+
+        let mut headers = request.options.headers.clone(); // TODO: Optimize clone away
+        if !headers.contains_key(reqwest::header::ACCEPT_ENCODING) {
+            headers.insert(
+                reqwest::header::ACCEPT_ENCODING,
+                HeaderValue::from_static(response_body::ACCEPT_ENCODING),
+            );
+        }
+
+        Box::new(
+            match request.http_type {
+                RequestType::Post => self.client.post(request.uri.clone()),
+                RequestType::Get => self.client.get(request.uri.clone()),
+                RequestType::Delete => self.client.delete(request.uri.clone()),
+                RequestType::Put => self.client.put(request.uri.clone()),
+            }
+            .body(reqwest_async::Body::from(request.body.clone()))
+            .headers(headers)
+            .send()
+            .map_err(|e| -> Error { ErrorKind::HTTPError(e).into() })
+            .and_then(move |res| {
+                let status = res.status();
+                let headers = res.headers().clone();
+
+                // Fail fast, if server is honest about the size.
+                future::result(match res.content_length() {
+                    Some(length) if length > max_body_size as u64 => {
+                        Err(ErrorKind::ResponseBodyTooLarge(max_body_size).into())
+                    }
+                    _ => Ok(()),
+                })
+                .and_then(move |_| {
+                    res.into_body()
+                        .map_err(|e| -> Error { ErrorKind::HTTPError(e).into() })
+                        .fold(vec![], move |mut body, chunk| -> Result<Vec<u8>> {
+                            if body.len() + chunk.len() > max_body_size {
+                                bail!(ErrorKind::ResponseBodyTooLarge(max_body_size));
+                            }
+                            body.extend_from_slice(&chunk);
+                            Ok(body)
+                        })
+                })
+                .and_then(move |body| {
+                    response_body::decompress(&headers, body, max_decompressed_body_size)
+                        .map(|body| (status, headers, body))
+                })
+            }),
+        )
+    }
+}
+
+#[derive(Clone, Debug)]
+enum MockOutcome {
+    Response(StatusCode, HeaderMap, Vec<u8>),
+    Failure(ErrorCategory),
+}
+
+/// Scripted reply of the `MockTransport`.
+#[derive(Clone, Debug)]
+pub struct MockReply {
+    outcome: MockOutcome,
+    delay: Duration,
+}
+
+impl MockReply {
+    pub fn new(status: StatusCode, body: Vec<u8>) -> Self {
+        MockReply {
+            outcome: MockOutcome::Response(status, HeaderMap::new(), body),
+            delay: Duration::from_secs(0),
+        }
+    }
+
+    /// Request fails with the error of this kind.
+    pub fn failure(kind: ErrorCategory) -> Self {
+        MockReply {
+            outcome: MockOutcome::Failure(kind),
+            delay: Duration::from_secs(0),
+        }
+    }
+
+    pub fn with_header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        if let MockOutcome::Response(_, ref mut headers, _) = self.outcome {
+            headers.append(name, value);
+        }
+        self
+    }
+
+    pub fn with_headers(mut self, new_headers: &HeaderMap) -> Self {
+        if let MockOutcome::Response(_, ref mut headers, _) = self.outcome {
+            for (name, value) in new_headers {
+                headers.append(name, value.clone());
+            }
+        }
+        self
+    }
+
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+}
+
+type MockKey = (&'static str, String);
+
+#[derive(Default)]
+struct MockState {
+    replies: HashMap<MockKey, Vec<MockReply>>,
+    received: HashMap<MockKey, usize>,
+}
+
+/// In-memory transport. Replies are served by method and URL in the order they were added,
+/// the last one is repeated, when they are exhausted. Unknown requests receive `404 Not Found`.
+#[derive(Default)]
+pub struct MockTransport {
+    state: Mutex<MockState>,
+}
+
+impl MockTransport {
+    pub fn new() -> Self {
+        MockTransport::default()
+    }
+
+    pub fn add_reply(&self, http_type: RequestType, url: &reqwest::Url, reply: MockReply) {
+        self.state
+            .lock()
+            .unwrap()
+            .replies
+            .entry((http_type.as_str(), url.as_str().to_owned()))
+            .or_insert_with(Vec::new)
+            .push(reply);
+    }
+
+    /// Number of requests received with this method and URL.
+    pub fn received_count(&self, http_type: RequestType, url: &reqwest::Url) -> usize {
+        self.state
+            .lock()
+            .unwrap()
+            .received
+            .get(&(http_type.as_str(), url.as_str().to_owned()))
+            .cloned()
+            .unwrap_or(0)
+    }
+
+    /// Forgets all replies and received requests.
+    pub fn reset(&self) {
+        *self.state.lock().unwrap() = MockState::default();
+    }
+}
+
+impl Transport for MockTransport {
+    fn send(&self, request: &Request, limits: ResponseLimits) -> ExchangeFuture {
+        let key = (request.http_type.as_str(), request.uri.as_str().to_owned());
+
+        let reply = {
+            let mut state = self.state.lock().unwrap();
+
+            let index = {
+                let received = state.received.entry(key.clone()).or_insert(0);
+                *received += 1;
+                *received - 1
+            };
+
+            match state.replies.get(&key) {
+                Some(replies) => replies[std::cmp::min(index, replies.len() - 1)].clone(),
+                None => MockReply::new(StatusCode::NOT_FOUND, vec![]),
+            }
+        };
+
+        let result = match reply.outcome {
+            MockOutcome::Response(_, _, ref body) if body.len() > limits.max_body_size => {
+                Err(ErrorKind::ResponseBodyTooLarge(limits.max_body_size).into())
+            }
+            MockOutcome::Response(status, headers, body) => Ok((status, headers, body)),
+            MockOutcome::Failure(kind) => Err(ErrorKind::MockFailure(kind).into()),
+        };
+
+        if reply.delay == Duration::from_secs(0) {
+            Box::new(future::result(result))
+        } else {
+            Box::new(
+                tokio::timer::Delay::new(Instant::now() + reply.delay)
+                    .then(move |_| future::result(result)),
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::networking_queue::RequestBuilder;
+
+    fn send(transport: &MockTransport, http_type: RequestType, url: &str) -> Result<Exchange> {
+        transport
+            .send(
+                &RequestBuilder::default()
+                    .http_type(http_type)
+                    .uri(url.parse().unwrap())
+                    .build()
+                    .unwrap(),
+                ResponseLimits {
+                    max_body_size: 8,
+                    max_decompressed_body_size: 8,
+                },
+            )
+            .wait()
+    }
+
+    #[test]
+    fn test_mock_transport() {
+        let transport = MockTransport::new();
+        let url = "http://mock/a".parse().unwrap();
+
+        transport.add_reply(
+            RequestType::Get,
+            &url,
+            MockReply::new(StatusCode::OK, b"first".to_vec())
+                .with_header(reqwest::header::ETAG, HeaderValue::from_static("1")),
+        );
+        transport.add_reply(
+            RequestType::Get,
+            &url,
+            MockReply::failure(ErrorCategory::ConnectionRefused),
+        );
+        transport.add_reply(
+            RequestType::Post,
+            &url,
+            MockReply::new(StatusCode::OK, b"too large body".to_vec()),
+        );
+
+        let (status, headers, body) = send(&transport, RequestType::Get, "http://mock/a").unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers.get(reqwest::header::ETAG).unwrap(), "1");
+        assert_eq!(body, b"first");
+
+        for _ in 0..2 {
+            let error = send(&transport, RequestType::Get, "http://mock/a").unwrap_err();
+            assert_eq!(
+                ErrorCategory::from(&error),
+                ErrorCategory::ConnectionRefused
+            );
+        }
+
+        let error = send(&transport, RequestType::Post, "http://mock/a").unwrap_err();
+        assert_eq!(ErrorCategory::from(&error), ErrorCategory::BodyTooLarge);
+
+        let (status, _, _) = send(&transport, RequestType::Get, "http://mock/b").unwrap();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        assert_eq!(transport.received_count(RequestType::Get, &url), 3);
+        transport.reset();
+        assert_eq!(transport.received_count(RequestType::Get, &url), 0);
+    }
+}
@@ -0,0 +1,520 @@
+/*
+ * gRIP
+ * Copyright (c) 2018 Alik Aslanyan <cplusplus256@gmail.com>
+ *
+ *
+ *    This program is free software; you can redistribute it and/or modify it
+ *    under the terms of the GNU General Public License as published by the
+ *    Free Software Foundation; either version 3 of the License, or (at
+ *    your option) any later version.
+ *
+ *    This program is distributed in the hope that it will be useful, but
+ *    WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ *    General Public License for more details.
+ *
+ *    You should have received a copy of the GNU General Public License
+ *    along with this program; if not, write to the Free Software Foundation,
+ *    Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ *    In addition, as a special exception, the author gives permission to
+ *    link the code of this program with the Half-Life Game Engine ("HL
+ *    Engine") and Modified Game Libraries ("MODs") developed by Valve,
+ *    L.L.C ("Valve").  You must obey the GNU General Public License in all
+ *    respects for all of the code used other than the HL Engine and MODs
+ *    from Valve.  If you modify this file, you may extend this exception
+ *    to your version of the file, but you are not obligated to do so.  If
+ *    you do not wish to do so, delete this exception statement from your
+ *    version.
+ *
+ */
+
+use crate::errors::*;
+
+use std::io;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tungstenite::protocol::frame::coding::CloseCode;
+use tungstenite::protocol::CloseFrame;
+use tungstenite::stream::Stream;
+
+type SocketStream = Stream<TcpStream, native_tls::TlsStream<TcpStream>>;
+
+/// How long worker waits for the incoming frames, before checking outgoing commands.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Close code reported, when connection was lost without close frame.
+pub const ABNORMAL_CLOSE_CODE: u16 = 1006;
+
+#[derive(Clone, Debug)]
+pub struct WebSocketOptions {
+    pub headers: reqwest::header::HeaderMap,
+    /// Timeout of the TCP connect, TLS and WebSocket handshakes.
+    pub connect_timeout: Duration,
+    pub reconnect: bool,
+    /// Delay before the first reconnect attempt. Doubled after every failed attempt.
+    pub min_reconnect_delay: Duration,
+    pub max_reconnect_delay: Duration,
+}
+
+impl Default for WebSocketOptions {
+    fn default() -> Self {
+        WebSocketOptions {
+            headers: reqwest::header::HeaderMap::new(),
+            connect_timeout: Duration::from_secs(30),
+            reconnect: true,
+            min_reconnect_delay: Duration::from_secs(1),
+            max_reconnect_delay: Duration::from_secs(60),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+#[derive(Debug)]
+pub enum Event {
+    Connected,
+    Message(Message),
+    Ping(Vec<u8>),
+    Disconnected { code: u16, reason: String },
+    Error(Error),
+}
+
+enum Command {
+    Send(Message),
+    Close { code: u16, reason: String },
+}
+
+/// WebSocket connection, which lives in its own thread and reconnects automatically.
+/// Events are buffered until they are received on the main thread.
+pub struct WebSocket {
+    command_sender: crossbeam_channel::Sender<Command>,
+    event_receiver: crossbeam_channel::Receiver<Event>,
+    connected: Arc<AtomicBool>,
+    quit: Arc<AtomicBool>,
+}
+
+impl Drop for WebSocket {
+    fn drop(&mut self) {
+        // Worker is not joined, because it can be blocked by the connect for a long time.
+        self.quit.store(true, Ordering::SeqCst);
+    }
+}
+
+impl WebSocket {
+    pub fn connect(url: reqwest::Url, options: WebSocketOptions) -> Result<WebSocket> {
+        match url.scheme() {
+            "ws" | "wss" => {}
+            scheme => bail!(ErrorKind::WebSocketURLError(format!(
+                "Unsupported scheme {}",
+                scheme
+            ))),
+        }
+
+        let (command_sender, command_receiver) = crossbeam_channel::unbounded();
+        let (event_sender, event_receiver) = crossbeam_channel::unbounded();
+        let connected = Arc::new(AtomicBool::new(false));
+        let quit = Arc::new(AtomicBool::new(false));
+
+        let worker = Worker {
+            url,
+            options,
+            command_receiver,
+            event_sender,
+            connected: Arc::clone(&connected),
+            quit: Arc::clone(&quit),
+        };
+        thread::spawn(move || worker.run());
+
+        Ok(WebSocket {
+            command_sender,
+            event_receiver,
+            connected,
+            quit,
+        })
+    }
+
+    pub fn send(&self, message: Message) {
+        self.command_sender.send(Command::Send(message)).ok();
+    }
+
+    pub fn close(&self, code: u16, reason: String) {
+        self.command_sender
+            .send(Command::Close { code, reason })
+            .ok();
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::SeqCst)
+    }
+
+    pub fn try_recv_event(&self) -> Option<Event> {
+        self.event_receiver.try_recv().ok()
+    }
+}
+
+/// Returns delay before the reconnect attempt, starting from zero.
+pub fn reconnect_delay(options: &WebSocketOptions, attempt: u32) -> Duration {
+    options
+        .min_reconnect_delay
+        .checked_mul(1 << std::cmp::min(attempt, 16))
+        .map_or(options.max_reconnect_delay, |delay| {
+            std::cmp::min(delay, options.max_reconnect_delay)
+        })
+}
+
+struct Worker {
+    url: reqwest::Url,
+    options: WebSocketOptions,
+    command_receiver: crossbeam_channel::Receiver<Command>,
+    event_sender: crossbeam_channel::Sender<Event>,
+    connected: Arc<AtomicBool>,
+    quit: Arc<AtomicBool>,
+}
+
+enum SessionEnd {
+    /// Closed by the user. No reconnect should be done.
+    Closed,
+    Lost,
+}
+
+impl Worker {
+    fn run(self) {
+        let mut attempt = 0;
+
+        while !self.quit.load(Ordering::SeqCst) {
+            match self.connect() {
+                Ok(socket) => {
+                    attempt = 0;
+                    self.connected.store(true, Ordering::SeqCst);
+                    self.emit(Event::Connected);
+
+                    let end = self.session(socket);
+                    self.connected.store(false, Ordering::SeqCst);
+
+                    if let SessionEnd::Closed = end {
+                        return;
+                    }
+                }
+                Err(e) => self.emit(Event::Error(e)),
+            }
+
+            if !self.options.reconnect || !self.wait_before_reconnect(attempt) {
+                return;
+            }
+
+            attempt += 1;
+        }
+    }
+
+    fn emit(&self, event: Event) {
+        self.event_sender.send(event).ok();
+    }
+
+    fn connect(&self) -> Result<tungstenite::WebSocket<SocketStream>> {
+        let host = self
+            .url
+            .host_str()
+            .chain_err(|| ErrorKind::WebSocketURLError("URL has no host".to_owned()))?;
+        let port = self
+            .url
+            .port_or_known_default()
+            .unwrap_or(if self.url.scheme() == "wss" { 443 } else { 80 });
+
+        let deadline = Instant::now() + self.options.connect_timeout;
+        let remaining = || -> Result<Duration> {
+            let now = Instant::now();
+            if now >= deadline {
+                bail!(ErrorKind::RequestTimeout);
+            }
+            Ok(deadline - now)
+        };
+
+        let stream = (host, port)
+            .to_socket_addrs()
+            .chain_err(|| ErrorKind::HostResolutionError(host.to_owned()))?
+            .fold(
+                None,
+                |connected: Option<io::Result<TcpStream>>, addr| match connected {
+                    Some(Ok(stream)) => Some(Ok(stream)),
+                    _ => Some(match remaining() {
+                        Ok(timeout) => TcpStream::connect_timeout(&addr, timeout),
+                        Err(_) => Err(io::ErrorKind::TimedOut.into()),
+                    }),
+                },
+            )
+            .chain_err(|| ErrorKind::HostResolutionError(host.to_owned()))?
+            .chain_err(|| format!("Unable to connect to {}:{}", host, port))?;
+
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(remaining()?))?;
+        stream.set_write_timeout(Some(remaining()?))?;
+
+        let stream = if self.url.scheme() == "wss" {
+            Stream::Tls(
+                native_tls::TlsConnector::new()
+                    .chain_err(|| "Unable to create TLS connector")?
+                    .connect(host, stream)
+                    .map_err(|e| -> Error {
+                        match e {
+                            native_tls::HandshakeError::Failure(e) => {
+                                ErrorKind::WebSocketHandshakeError(tungstenite::Error::Tls(e))
+                                    .into()
+                            }
+                            native_tls::HandshakeError::WouldBlock(_) => {
+                                ErrorKind::RequestTimeout.into()
+                            }
+                        }
+                    })?,
+            )
+        } else {
+            Stream::Plain(stream)
+        };
+
+        let request = self
+            .options
+            .headers
+            .iter()
+            .fold(
+                tungstenite::http::Request::get(self.url.as_str()),
+                |request, (name, value)| request.header(name.as_str(), value.as_bytes()),
+            )
+            .body(())
+            .chain_err(|| ErrorKind::WebSocketURLError(self.url.to_string()))?;
+
+        let (socket, _) = tungstenite::client(request, stream).map_err(|e| -> Error {
+            match e {
+                tungstenite::HandshakeError::Failure(e) => {
+                    ErrorKind::WebSocketHandshakeError(e).into()
+                }
+                tungstenite::HandshakeError::Interrupted(_) => ErrorKind::RequestTimeout.into(),
+            }
+        })?;
+
+        let tcp = match socket.get_ref() {
+            Stream::Plain(stream) => stream,
+            Stream::Tls(stream) => stream.get_ref(),
+        };
+        tcp.set_read_timeout(Some(POLL_INTERVAL))?;
+        tcp.set_write_timeout(None)?;
+
+        Ok(socket)
+    }
+
+    fn session(&self, mut socket: tungstenite::WebSocket<SocketStream>) -> SessionEnd {
+        let mut closed_by_user = false;
+        let mut close_frame = None;
+
+        let end = |closed_by_user| {
+            if closed_by_user {
+                SessionEnd::Closed
+            } else {
+                SessionEnd::Lost
+            }
+        };
+
+        loop {
+            if self.quit.load(Ordering::SeqCst) {
+                return SessionEnd::Closed;
+            }
+
+            while let Ok(command) = self.command_receiver.try_recv() {
+                let result = match command {
+                    Command::Send(Message::Text(text)) => {
+                        socket.write_message(tungstenite::Message::Text(text))
+                    }
+                    Command::Send(Message::Binary(data)) => {
+                        socket.write_message(tungstenite::Message::Binary(data))
+                    }
+                    Command::Close { code, reason } => {
+                        closed_by_user = true;
+                        socket.close(Some(CloseFrame {
+                            code: CloseCode::from(code),
+                            reason: reason.into(),
+                        }))
+                    }
+                };
+
+                match result {
+                    Ok(()) => {}
+                    Err(ref e) if is_would_block(e) => {}
+                    Err(e) => {
+                        self.emit(Event::Error(e.into()));
+                        self.emit_disconnected(None);
+                        return end(closed_by_user);
+                    }
+                }
+            }
+
+            match socket.read_message() {
+                Ok(tungstenite::Message::Text(text)) => {
+                    self.emit(Event::Message(Message::Text(text)))
+                }
+                Ok(tungstenite::Message::Binary(data)) => {
+                    self.emit(Event::Message(Message::Binary(data)))
+                }
+                Ok(tungstenite::Message::Ping(data)) => self.emit(Event::Ping(data)),
+                Ok(tungstenite::Message::Pong(_)) => {}
+                Ok(tungstenite::Message::Close(frame)) => close_frame = frame,
+                Err(ref e) if is_would_block(e) => {
+                    // Replies to pings and close frames are sent only on the next read/write.
+                    match socket.write_pending() {
+                        Ok(()) => {}
+                        Err(ref e) if is_would_block(e) => {}
+                        Err(tungstenite::Error::ConnectionClosed) => {
+                            self.emit_disconnected(close_frame);
+                            return end(closed_by_user);
+                        }
+                        Err(e) => {
+                            self.emit(Event::Error(e.into()));
+                            self.emit_disconnected(None);
+                            return end(closed_by_user);
+                        }
+                    }
+                }
+                Err(tungstenite::Error::ConnectionClosed)
+                | Err(tungstenite::Error::AlreadyClosed) => {
+                    self.emit_disconnected(close_frame);
+                    return end(closed_by_user);
+                }
+                Err(e) => {
+                    self.emit(Event::Error(e.into()));
+                    self.emit_disconnected(close_frame);
+                    return end(closed_by_user);
+                }
+            }
+        }
+    }
+
+    fn emit_disconnected(&self, frame: Option<CloseFrame>) {
+        self.emit(match frame {
+            Some(frame) => Event::Disconnected {
+                code: frame.code.into(),
+                reason: frame.reason.into_owned(),
+            },
+            None => Event::Disconnected {
+                code: ABNORMAL_CLOSE_CODE,
+                reason: String::new(),
+            },
+        });
+    }
+
+    /// Returns false, if connection should not be reestablished anymore.
+    fn wait_before_reconnect(&self, attempt: u32) -> bool {
+        let deadline = Instant::now() + reconnect_delay(&self.options, attempt);
+
+        loop {
+            if self.quit.load(Ordering::SeqCst) {
+                return false;
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return true;
+            }
+
+            // Nothing can be sent while disconnected, so only close matters here.
+            match self
+                .command_receiver
+                .recv_timeout(std::cmp::min(deadline - now, POLL_INTERVAL))
+            {
+                Ok(Command::Close { .. }) => return false,
+                Ok(Command::Send(_)) => {
+                    self.emit(Event::Error(ErrorKind::WebSocketNotConnected.into()))
+                }
+                Err(crossbeam_channel::RecvTimeoutError::Timeout) => {}
+                Err(crossbeam_channel::RecvTimeoutError::Disconnected) => return false,
+            }
+        }
+    }
+}
+
+fn is_would_block(error: &tungstenite::Error) -> bool {
+    match error {
+        tungstenite::Error::Io(e) => {
+            e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reconnect_delay() {
+        let options = WebSocketOptions {
+            min_reconnect_delay: Duration::from_millis(500),
+            max_reconnect_delay: Duration::from_secs(5),
+            ..Default::default()
+        };
+
+        assert_eq!(reconnect_delay(&options, 0), Duration::from_millis(500));
+        assert_eq!(reconnect_delay(&options, 1), Duration::from_secs(1));
+        assert_eq!(reconnect_delay(&options, 3), Duration::from_secs(4));
+        assert_eq!(reconnect_delay(&options, 4), Duration::from_secs(5));
+        assert_eq!(reconnect_delay(&options, 100), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_echo() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        thread::spawn(move || {
+            let mut server = tungstenite::accept(listener.accept().unwrap().0).unwrap();
+            loop {
+                match server.read_message() {
+                    Ok(message @ tungstenite::Message::Text(_))
+                    | Ok(message @ tungstenite::Message::Binary(_)) => {
+                        server.write_message(message).unwrap()
+                    }
+                    Ok(_) => {}
+                    Err(_) => break,
+                }
+            }
+        });
+
+        let socket = WebSocket::connect(
+            format!("ws://127.0.0.1:{}/", port).parse().unwrap(),
+            WebSocketOptions {
+                reconnect: false,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        socket.send(Message::Text("hello".to_owned()));
+        socket.send(Message::Binary(vec![0, 1, 2]));
+        socket.close(1000, "bye".to_owned());
+
+        let events: Vec<Event> = socket.event_receiver.iter().take(4).collect();
+
+        match &events[..] {
+            [Event::Connected, Event::Message(Message::Text(text)), Event::Message(Message::Binary(data)), Event::Disconnected { code: 1000, .. }] =>
+            {
+                assert_eq!(text, "hello");
+                assert_eq!(data, &vec![0, 1, 2]);
+            }
+            events => panic!("Unexpected events {:?}", events),
+        }
+    }
+
+    #[test]
+    fn test_unsupported_scheme() {
+        assert!(WebSocket::connect(
+            "http://localhost/".parse().unwrap(),
+            WebSocketOptions::default()
+        )
+        .is_err());
+    }
+}